@@ -0,0 +1,292 @@
+//! Derive macros for the message traits of [quic-rpc](https://docs.rs/quic-rpc)
+//!
+//! Use them through the `derive` feature of quic-rpc, which re-exports them from the crate root.
+//!
+//! # Example
+//! ```
+//! use quic_rpc::{MsgEnum, Msg, RpcMsg, Service};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, Serialize, Deserialize, RpcMsg)]
+//! #[rpc(service = Calculator, response = SqrResponse)]
+//! struct Sqr(u64);
+//!
+//! #[derive(Debug, Serialize, Deserialize)]
+//! struct SqrResponse(u128);
+//!
+//! #[derive(Debug, Serialize, Deserialize, Msg)]
+//! #[msg(service = Calculator, pattern = ClientStreaming, update = SumUpdate, response = SumResponse)]
+//! struct Sum;
+//!
+//! #[derive(Debug, Serialize, Deserialize)]
+//! struct SumUpdate(u64);
+//!
+//! #[derive(Debug, Serialize, Deserialize)]
+//! struct SumResponse(u128);
+//!
+//! #[derive(Debug, Serialize, Deserialize, MsgEnum)]
+//! enum Request {
+//!     Sqr(Sqr),
+//!     Sum(Sum),
+//!     SumUpdate(SumUpdate),
+//! }
+//!
+//! #[derive(Debug, Serialize, Deserialize, MsgEnum)]
+//! enum Response {
+//!     SqrResponse(SqrResponse),
+//!     SumResponse(SumResponse),
+//! }
+//!
+//! #[derive(Debug, Clone)]
+//! struct Calculator;
+//!
+//! impl Service for Calculator {
+//!     type Req = Request;
+//!     type Res = Response;
+//! }
+//! ```
+//!
+//! Missing attributes are reported at compile time:
+//! ```compile_fail
+//! # use quic_rpc::RpcMsg;
+//! #[derive(RpcMsg)]
+//! #[rpc(response = SqrResponse)]
+//! struct Sqr(u64);
+//! ```
+//!
+//! The pattern is given by its plain name, paths are rejected:
+//! ```compile_fail
+//! # use quic_rpc::{Msg, MsgEnum, Service};
+//! # use serde::{Deserialize, Serialize};
+//! # #[derive(Debug, Serialize, Deserialize)]
+//! # struct SqrResponse(u128);
+//! # #[derive(Debug, Serialize, Deserialize, MsgEnum)]
+//! # enum Request { Sqr(Sqr) }
+//! # #[derive(Debug, Serialize, Deserialize, MsgEnum)]
+//! # enum Response { SqrResponse(SqrResponse) }
+//! # #[derive(Debug, Clone)]
+//! # struct Calculator;
+//! # impl Service for Calculator {
+//! #     type Req = Request;
+//! #     type Res = Response;
+//! # }
+//! #[derive(Debug, Serialize, Deserialize, Msg)]
+//! #[msg(service = Calculator, pattern = quic_rpc::message::ServerStreaming, response = SqrResponse)]
+//! struct Sqr(u64);
+//! ```
+#![deny(missing_docs)]
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    spanned::Spanned,
+    Data, DeriveInput, Error, Fields, Ident, Result, Token, Type,
+};
+
+/// Implement `RpcMsg` for a request message
+///
+/// The service and the response type are given with
+/// `#[rpc(service = MyService, response = MyResponse)]`.
+#[proc_macro_derive(RpcMsg, attributes(rpc))]
+pub fn derive_rpc_msg(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_rpc_msg(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Implement `Msg` for a request message with a streaming interaction pattern
+///
+/// The service, the pattern, the response type and the update type are given with
+/// `#[msg(service = MyService, pattern = BidiStreaming, update = MyUpdate, response = MyResponse)]`.
+/// The pattern is one of `ServerStreaming`, `ClientStreaming` and `BidiStreaming`, and the
+/// update type is required for the patterns with updates. For the rpc pattern, derive `RpcMsg`
+/// instead.
+#[proc_macro_derive(Msg, attributes(msg))]
+pub fn derive_msg(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_msg(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Implement the conversions between a request or response enum and the messages it wraps
+///
+/// Every variant needs to wrap a single message, like `Sqr(Sqr)`. This generates `From` for
+/// wrapping a message into the enum, and `TryFrom` for unwrapping it, which gives back the enum
+/// if it holds another variant.
+#[proc_macro_derive(MsgEnum)]
+pub fn derive_msg_enum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_msg_enum(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// A `key = Type` pair in a derive attribute
+struct Arg {
+    key: Ident,
+    value: Type,
+}
+
+impl Parse for Arg {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let key = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let value = input.parse()?;
+        Ok(Self { key, value })
+    }
+}
+
+/// The arguments of the attribute `name`, checked against the allowed keys
+struct Args {
+    name: &'static str,
+    args: Vec<Arg>,
+}
+
+impl Args {
+    fn parse(input: &DeriveInput, name: &'static str, allowed: &[&str]) -> Result<Self> {
+        let mut args = Vec::new();
+        for attr in input.attrs.iter().filter(|attr| attr.path.is_ident(name)) {
+            let parsed = attr.parse_args_with(Punctuated::<Arg, Token![,]>::parse_terminated)?;
+            for arg in parsed {
+                if !allowed.iter().any(|key| arg.key == key) {
+                    let msg = format!(
+                        "unknown argument `{}`, expected one of {}",
+                        arg.key,
+                        allowed.join(", ")
+                    );
+                    return Err(Error::new(arg.key.span(), msg));
+                }
+                if args.iter().any(|other: &Arg| other.key == arg.key) {
+                    let msg = format!("duplicate argument `{}`", arg.key);
+                    return Err(Error::new(arg.key.span(), msg));
+                }
+                args.push(arg);
+            }
+        }
+        Ok(Self { name, args })
+    }
+
+    fn get(&self, key: &str) -> Option<&Type> {
+        self.args
+            .iter()
+            .find(|arg| arg.key == key)
+            .map(|arg| &arg.value)
+    }
+
+    fn require(&self, key: &str) -> Result<&Type> {
+        self.get(key).ok_or_else(|| {
+            let msg = format!("missing `#[{}({} = ...)]` attribute", self.name, key);
+            Error::new(Span::call_site(), msg)
+        })
+    }
+}
+
+fn expand_rpc_msg(input: &DeriveInput) -> Result<TokenStream2> {
+    let args = Args::parse(input, "rpc", &["service", "response"])?;
+    let service = args.require("service")?;
+    let response = args.require("response")?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::quic_rpc::message::RpcMsg<#service> for #name #ty_generics #where_clause {
+            type Response = #response;
+        }
+    })
+}
+
+fn expand_msg(input: &DeriveInput) -> Result<TokenStream2> {
+    let args = Args::parse(input, "msg", &["service", "pattern", "update", "response"])?;
+    let service = args.require("service")?;
+    let response = args.require("response")?;
+    let pattern = pattern_ident(args.require("pattern")?)?;
+    let update = match (pattern.to_string().as_str(), args.get("update")) {
+        ("ClientStreaming" | "BidiStreaming", _) => {
+            let update = args.require("update")?;
+            quote!(#update)
+        }
+        ("ServerStreaming", None) => quote!(Self),
+        ("ServerStreaming", Some(update)) => {
+            let msg = "server streaming requests do not take updates";
+            return Err(Error::new(update.span(), msg));
+        }
+        ("Rpc", _) => {
+            let msg = "use `#[derive(RpcMsg)]` for the rpc pattern";
+            return Err(Error::new(pattern.span(), msg));
+        }
+        _ => {
+            let msg = "expected `ServerStreaming`, `ClientStreaming` or `BidiStreaming`";
+            return Err(Error::new(pattern.span(), msg));
+        }
+    };
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::quic_rpc::message::Msg<#service> for #name #ty_generics #where_clause {
+            type Update = #update;
+            type Response = #response;
+            type Pattern = ::quic_rpc::message::#pattern;
+        }
+    })
+}
+
+/// The name of an interaction pattern, which has to be a plain name like `ServerStreaming`
+fn pattern_ident(pattern: &Type) -> Result<&Ident> {
+    match pattern {
+        Type::Path(path) if path.qself.is_none() => path.path.get_ident(),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        let msg = "expected `ServerStreaming`, `ClientStreaming` or `BidiStreaming`";
+        Error::new(pattern.span(), msg)
+    })
+}
+
+fn expand_msg_enum(input: &DeriveInput) -> Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new(
+            input.span(),
+            "`MsgEnum` can only be derived for enums",
+        ));
+    };
+    if !input.generics.params.is_empty() {
+        let msg = "`MsgEnum` can not be derived for generic enums";
+        return Err(Error::new(input.generics.span(), msg));
+    }
+    let name = &input.ident;
+    let mut impls = Vec::new();
+    for variant in &data.variants {
+        let ty = match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
+            _ => {
+                let msg = "every variant needs to wrap a single message, like `Sqr(Sqr)`";
+                return Err(Error::new(variant.span(), msg));
+            }
+        };
+        let variant = &variant.ident;
+        impls.push(quote! {
+            impl ::std::convert::From<#ty> for #name {
+                fn from(msg: #ty) -> Self {
+                    Self::#variant(msg)
+                }
+            }
+
+            impl ::std::convert::TryFrom<#name> for #ty {
+                type Error = #name;
+
+                #[allow(unreachable_patterns)]
+                fn try_from(msg: #name) -> ::std::result::Result<Self, #name> {
+                    match msg {
+                        #name::#variant(msg) => Ok(msg),
+                        msg => Err(msg),
+                    }
+                }
+            }
+        });
+    }
+    Ok(quote!(#(#impls)*))
+}
@@ -0,0 +1,94 @@
+use futures::{SinkExt, StreamExt};
+use quic_rpc::{
+    mem::{self, MemChannelTypes},
+    Msg, MsgEnum, RpcClient, RpcMsg, RpcServer, Service,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone)]
+struct Calculator;
+
+impl Service for Calculator {
+    type Req = Request;
+    type Res = Response;
+}
+
+#[derive(Debug, Serialize, Deserialize, MsgEnum)]
+enum Request {
+    Sqr(Sqr),
+    Sum(Sum),
+    SumUpdate(SumUpdate),
+}
+
+#[derive(Debug, Serialize, Deserialize, MsgEnum)]
+enum Response {
+    SqrResponse(SqrResponse),
+    SumResponse(SumResponse),
+}
+
+#[derive(Debug, Serialize, Deserialize, RpcMsg)]
+#[rpc(service = Calculator, response = SqrResponse)]
+struct Sqr(u64);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SqrResponse(u128);
+
+#[derive(Debug, Serialize, Deserialize, Msg)]
+#[msg(service = Calculator, pattern = ClientStreaming, update = SumUpdate, response = SumResponse)]
+struct Sum;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SumUpdate(u64);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SumResponse(u128);
+
+#[test]
+fn msg_enum_conversions() {
+    let req: Request = Sqr(2).into();
+    assert!(matches!(Sqr::try_from(req), Ok(Sqr(2))));
+    let req: Request = Sum.into();
+    assert!(matches!(Sqr::try_from(req), Err(Request::Sum(Sum))));
+}
+
+/// derived impls are usable for rpc and client streaming calls
+#[tokio::test]
+async fn derive_roundtrip() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<Response, Request>(1);
+    let mut server = RpcServer::<Calculator, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(async move {
+        for _ in 0..2 {
+            let (req, chan) = server.accept_one().await?;
+            match req {
+                Request::Sqr(msg) => {
+                    server
+                        .rpc(msg, chan, (), |_, Sqr(x)| async move {
+                            SqrResponse(x as u128 * x as u128)
+                        })
+                        .await?
+                }
+                Request::Sum(msg) => {
+                    server
+                        .client_streaming(msg, chan, (), |_, _, updates| async move {
+                            let sum =
+                                updates.fold(0, |sum, SumUpdate(x)| async move { sum + x as u128 });
+                            SumResponse(sum.await)
+                        })
+                        .await?
+                }
+                req => anyhow::bail!("unexpected request {:?}", req),
+            }
+        }
+        anyhow::Ok(())
+    });
+    let mut client = RpcClient::<Calculator, MemChannelTypes>::new(client);
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    let (mut send, recv) = client.client_streaming(Sum).await?;
+    for i in 1..=4 {
+        send.send(SumUpdate(i)).await?;
+    }
+    drop(send);
+    assert_eq!(recv.await?, SumResponse(10));
+    server_handle.await??;
+    Ok(())
+}
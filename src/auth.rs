@@ -0,0 +1,228 @@
+//! Channel that sends an auth token with the first message of every stream
+//!
+//! TLS authenticates the connection, but applications often need to authorize single requests,
+//! e.g. using bearer tokens. This channel sends the token set with [Channel::with_token] along
+//! with the first message of every stream it opens, so the token does not have to be part of
+//! every request message. The server reads it using [ChannelTypes::auth_token], and an
+//! [crate::server::Authenticator] checks it before the request is handed to a handler. Both
+//! sides need to use an auth channel.
+//!
+//! The token is sent as is, so it should only be used over an encrypted transport.
+use crate::{message::PatternKind, ChannelTypes, ConnectionInfo, RpcMessage};
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt, Sink, SinkExt, Stream, TryFutureExt};
+use pin_project::pin_project;
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+    pin::Pin,
+    result,
+    task::{Context, Poll},
+};
+
+/// A message together with the auth token of its stream
+///
+/// The token is only sent with the first message of a stream opened by a channel with a token.
+pub type WithToken<M> = (Option<Vec<u8>>, M);
+
+/// A channel that sends an auth token on every stream it opens, wrapping a channel for
+/// [WithToken] messages
+pub struct Channel<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> {
+    inner: C::Channel<WithToken<In>, WithToken<Out>>,
+    token: Option<Bytes>,
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Channel<C, In, Out> {
+    /// Wrap a channel, without a token
+    ///
+    /// This is what the server uses.
+    pub fn new(inner: C::Channel<WithToken<In>, WithToken<Out>>) -> Self {
+        Self { inner, token: None }
+    }
+
+    /// Send `token` on every stream opened by this channel
+    ///
+    /// The token is shared by all clones of the channel. To use another token for some requests,
+    /// e.g. after refreshing it, create a client for a clone of the channel with the new token.
+    pub fn with_token(mut self, token: impl Into<Bytes>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn wrap(
+        token: Option<Bytes>,
+        (send, recv): (C::SendSink<WithToken<Out>>, C::RecvStream<WithToken<In>>),
+    ) -> Socket<C, In, Out> {
+        (
+            SendSink { inner: send, token },
+            RecvStream {
+                inner: recv,
+                token: None,
+            },
+        )
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Clone for Channel<C, In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            token: self.token.clone(),
+        }
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Debug for Channel<C, In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the token is a secret, so it is not printed
+        f.debug_struct("Channel")
+            .field("has_token", &self.token.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// SendSink for auth channels
+pub struct SendSink<C: ChannelTypes, Out: RpcMessage> {
+    inner: C::SendSink<WithToken<Out>>,
+    /// The token to send with the next message, taken by the first message
+    token: Option<Bytes>,
+}
+
+impl<C: ChannelTypes, Out: RpcMessage> Sink<Out> for SendSink<C, Out> {
+    type Error = C::SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        let token = self.token.take().map(|token| token.to_vec());
+        self.inner.start_send_unpin((token, item))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_close_unpin(cx)
+    }
+}
+
+/// RecvStream for auth channels
+#[pin_project]
+pub struct RecvStream<C: ChannelTypes, In: RpcMessage> {
+    #[pin]
+    inner: C::RecvStream<WithToken<In>>,
+    token: Option<Bytes>,
+}
+
+impl<C: ChannelTypes, In: RpcMessage> RecvStream<C, In> {
+    /// The token the remote sent on this stream, if any was received yet
+    pub fn token(&self) -> Option<&Bytes> {
+        self.token.as_ref()
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage> Stream for RecvStream<C, In> {
+    type Item = Result<In, C::RecvError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        Poll::Ready(match futures::ready!(this.inner.poll_next(cx)) {
+            Some(Ok((token, msg))) => {
+                if let Some(token) = token {
+                    this.token.get_or_insert_with(|| token.into());
+                }
+                Some(Ok(msg))
+            }
+            Some(Err(e)) => Some(Err(e)),
+            None => None,
+        })
+    }
+}
+
+/// A bidirectional stream of an auth channel: a sink for outgoing and a stream of incoming
+/// messages
+pub type Socket<C, In, Out> = (self::SendSink<C, Out>, self::RecvStream<C, In>);
+
+/// Future returned by open_bi
+pub type OpenBiFuture<'a, C, In, Out> =
+    BoxFuture<'a, result::Result<Socket<C, In, Out>, <C as ChannelTypes>::OpenBiError>>;
+
+/// Future returned by accept_bi
+pub type AcceptBiFuture<'a, C, In, Out> =
+    BoxFuture<'a, result::Result<Socket<C, In, Out>, <C as ChannelTypes>::AcceptBiError>>;
+
+/// Channel types for auth channels
+///
+/// `C` is the channel type of the wrapped channel. Errors are passed through unchanged.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthChannelTypes<C: ChannelTypes>(PhantomData<C>);
+
+impl<C: ChannelTypes> ChannelTypes for AuthChannelTypes<C> {
+    type SendSink<M: RpcMessage> = self::SendSink<C, M>;
+
+    type RecvStream<M: RpcMessage> = self::RecvStream<C, M>;
+
+    type SendError = C::SendError;
+
+    type RecvError = C::RecvError;
+
+    type OpenBiError = C::OpenBiError;
+
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::OpenBiFuture<'a, C, In, Out>;
+
+    type AcceptBiError = C::AcceptBiError;
+
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::AcceptBiFuture<'a, C, In, Out>;
+
+    type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<C, In, Out>;
+
+    fn reset<M: RpcMessage>(send: &mut Self::SendSink<M>, code: u32) {
+        C::reset(&mut send.inner, code)
+    }
+
+    fn set_pattern<M: RpcMessage>(send: &mut Self::SendSink<M>, pattern: PatternKind) {
+        C::set_pattern(&mut send.inner, pattern)
+    }
+
+    fn set_priority<M: RpcMessage>(send: &mut Self::SendSink<M>, priority: i32) {
+        C::set_priority(&mut send.inner, priority)
+    }
+
+    fn correlation_id<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<u64> {
+        C::correlation_id(&recv.inner)
+    }
+
+    fn auth_token<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<Bytes> {
+        recv.token().cloned()
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> crate::Channel<In, Out, AuthChannelTypes<C>>
+    for Channel<C, In, Out>
+{
+    fn open_bi(&self) -> OpenBiFuture<'_, C, In, Out> {
+        let token = self.token.clone();
+        self.inner
+            .open_bi()
+            .map_ok(move |socket| Self::wrap(token, socket))
+            .boxed()
+    }
+
+    fn accept_bi(&self) -> AcceptBiFuture<'_, C, In, Out> {
+        self.inner
+            .accept_bi()
+            .map_ok(|socket| Self::wrap(None, socket))
+            .boxed()
+    }
+
+    fn connection_info(&self) -> ConnectionInfo {
+        self.inner.connection_info()
+    }
+
+    fn close(&self, code: u32, reason: &[u8]) {
+        self.inner.close(code, reason)
+    }
+}
@@ -0,0 +1,246 @@
+//! Client side load balancing of rpc calls over several servers
+//!
+//! A [BalancedClient] holds a client per server and picks one for every call according to a
+//! [BalancePolicy]. A server whose connection failed is marked unhealthy and skipped for
+//! [BalancedClient::with_cooldown], so a single server going away does not fail calls as long as
+//! other servers are up.
+use crate::{
+    client::RpcClientError,
+    message::{Msg, Rpc},
+    ChannelError, ChannelTypes, RpcClient, Service,
+};
+use std::{
+    fmt, result,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How a [BalancedClient] picks the server for a call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BalancePolicy {
+    /// Use the servers one after the other
+    #[default]
+    RoundRobin,
+    /// Use the server with the fewest calls in flight, the first one on ties
+    LeastOutstanding,
+    /// Use a random server
+    Random,
+}
+
+/// State of a single server of a [BalancedClient]
+#[derive(Debug, Default)]
+struct Backend {
+    /// Number of calls currently in flight
+    outstanding: AtomicUsize,
+    /// Whether and for how long the server is skipped
+    health: Mutex<Health>,
+}
+
+/// Health of a single server of a [BalancedClient]
+#[derive(Debug, Default, Clone, Copy)]
+enum Health {
+    /// The server is used
+    #[default]
+    Healthy,
+    /// The server is skipped until this instant
+    UnhealthyUntil(Instant),
+    /// The server is skipped for good, since the cooldown does not fit the clock
+    Unhealthy,
+}
+
+impl Backend {
+    fn is_healthy(&self) -> bool {
+        match *self.health.lock().unwrap() {
+            Health::Healthy => true,
+            Health::UnhealthyUntil(until) => Instant::now() >= until,
+            Health::Unhealthy => false,
+        }
+    }
+
+    /// Skip the server for `cooldown`
+    fn set_unhealthy(&self, cooldown: Duration) {
+        *self.health.lock().unwrap() = match Instant::now().checked_add(cooldown) {
+            Some(until) => Health::UnhealthyUntil(until),
+            None => Health::Unhealthy,
+        };
+    }
+}
+
+/// Decrements the outstanding calls of a backend when the call completes or is dropped
+struct Outstanding<'a>(&'a AtomicUsize);
+
+impl<'a> Outstanding<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for Outstanding<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A client that spreads rpc calls over several servers, see the [module docs](self)
+///
+/// The channel type defaults to [crate::quinn::QuinnChannelTypes], but any channel type works.
+pub struct BalancedClient<S: Service, C: ChannelTypes = crate::quinn::QuinnChannelTypes> {
+    clients: Arc<[RpcClient<S, C>]>,
+    backends: Arc<[Backend]>,
+    policy: BalancePolicy,
+    cooldown: Duration,
+    next: Arc<AtomicUsize>,
+}
+
+impl<S: Service, C: ChannelTypes> BalancedClient<S, C> {
+    /// Default time a server is skipped after its connection failed
+    pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(5);
+
+    /// Create a balanced client from a client per server
+    ///
+    /// Panics if `clients` is empty.
+    pub fn new(clients: Vec<RpcClient<S, C>>, policy: BalancePolicy) -> Self {
+        assert!(
+            !clients.is_empty(),
+            "a balanced client needs at least one server"
+        );
+        let backends = clients.iter().map(|_| Backend::default()).collect();
+        Self {
+            clients: clients.into(),
+            backends,
+            policy,
+            cooldown: Self::DEFAULT_COOLDOWN,
+            next: Default::default(),
+        }
+    }
+
+    /// Skip a server for `cooldown` after its connection failed
+    ///
+    /// Once the cooldown elapsed, the server is used again. A cooldown too long for the clock,
+    /// e.g. [Duration::MAX], skips the server for good. If all servers are unhealthy, calls
+    /// still try each of them.
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Number of servers
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Always false, since a balanced client has at least one server
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// True unless the server at `index` is skipped because its connection failed
+    pub fn is_healthy(&self, index: usize) -> bool {
+        self.backends[index].is_healthy()
+    }
+
+    /// Number of calls in flight to the server at `index`
+    pub fn outstanding(&self, index: usize) -> usize {
+        self.backends[index].outstanding.load(Ordering::Relaxed)
+    }
+
+    /// RPC call to one of the servers
+    ///
+    /// If the stream to the chosen server can not be opened, the server is marked unhealthy and
+    /// the call is tried on the next one, so every server is tried at most once. The request was
+    /// not sent in that case, so this is safe for any request. Servers whose connection fails
+    /// later in the call are marked unhealthy as well, but since the request might have been
+    /// handled, the error is returned. Returns the error of the last server if all failed.
+    pub async fn rpc<M>(&self, msg: M) -> result::Result<M::Response, RpcClientError<C>>
+    where
+        M: Msg<S, Pattern = Rpc> + Into<S::Req> + Clone,
+    {
+        let mut tried = vec![false; self.clients.len()];
+        loop {
+            let index = self.pick(&tried);
+            tried[index] = true;
+            let backend = &self.backends[index];
+            let res = {
+                let _outstanding = Outstanding::new(&backend.outstanding);
+                self.clients[index].rpc(msg.clone()).await
+            };
+            let err = match res {
+                Ok(res) => return Ok(res),
+                Err(err) => err,
+            };
+            let (connection_lost, failover) = match &err {
+                RpcClientError::Open(_) => (true, true),
+                RpcClientError::Send(e) => (e.is_connection_lost(), false),
+                RpcClientError::RecvError(e) => (e.is_connection_lost(), false),
+                _ => (false, false),
+            };
+            if connection_lost {
+                backend.set_unhealthy(self.cooldown);
+            }
+            if !failover || tried.iter().all(|t| *t) {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Pick a server that was not tried yet, preferring healthy ones
+    fn pick(&self, tried: &[bool]) -> usize {
+        let untried = || (0..self.clients.len()).filter(|i| !tried[*i]);
+        let mut candidates: Vec<usize> = untried()
+            .filter(|i| self.backends[*i].is_healthy())
+            .collect();
+        if candidates.is_empty() {
+            candidates = untried().collect();
+        }
+        match self.policy {
+            BalancePolicy::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed) % self.clients.len();
+                // the first candidate at or after the current position, wrapping around
+                *candidates
+                    .iter()
+                    .find(|i| **i >= start)
+                    .unwrap_or(&candidates[0])
+            }
+            BalancePolicy::LeastOutstanding => *candidates
+                .iter()
+                .min_by_key(|i| self.outstanding(**i))
+                .unwrap(),
+            BalancePolicy::Random => candidates[random_index(candidates.len())],
+        }
+    }
+}
+
+impl<S: Service, C: ChannelTypes> Clone for BalancedClient<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            clients: self.clients.clone(),
+            backends: self.backends.clone(),
+            policy: self.policy,
+            cooldown: self.cooldown,
+            next: self.next.clone(),
+        }
+    }
+}
+
+impl<S: Service, C: ChannelTypes> fmt::Debug for BalancedClient<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BalancedClient")
+            .field("backends", &self.backends)
+            .field("policy", &self.policy)
+            .field("cooldown", &self.cooldown)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A random number in `[0, n)`, good enough to spread calls
+fn random_index(n: usize) -> usize {
+    use std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+    };
+    (RandomState::new().build_hasher().finish() % n as u64) as usize
+}
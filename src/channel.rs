@@ -0,0 +1,406 @@
+//! The traits a transport implements to be used as a channel, and a test suite for them
+//!
+//! A transport provides a [ChannelTypes] impl with the types of its streams and errors, and a
+//! [Channel] impl for its connections. The client and server only use these traits, so any
+//! type that implements them can be used with [crate::RpcClient] and [crate::RpcServer]. The
+//! traits are reexported at the crate root.
+//!
+//! The contract of a channel:
+//!
+//! - Every interaction uses its own bidirectional stream, opened by the connecting side with
+//!   [Channel::open_bi] and accepted by the other side with [Channel::accept_bi]. Streams are
+//!   expected to be cheap.
+//! - Messages on a stream arrive in order, and closing the sink of a stream ends the stream of
+//!   the remote. There are no ordering guarantees between streams, except that streams are
+//!   accepted in the order of their first messages.
+//! - [Channel::accept_bi] is cancel safe, [Channel::open_bi] is not.
+//! - Opening streams from the accepting side, and unidirectional streams, are optional. The
+//!   default implementations of [Channel::open_uni_with] and [Channel::accept_uni] fall back to
+//!   bidirectional streams.
+//!
+//! New channel types can check that they follow the contract using [test_suite].
+use crate::{message::PatternKind, ChannelError, Retryable, RpcMessage};
+use bytes::Bytes;
+use futures::{
+    future::{self, BoxFuture, Either},
+    Future, FutureExt, Sink, SinkExt, Stream, StreamExt,
+};
+use std::{fmt::Debug, io, net::SocketAddr, pin::Pin, result, time::Duration};
+
+/// Defines a set of types for a kind of channel
+///
+/// Every distinct kind of channel has its own ChannelType. See e.g.
+/// [crate::mem::MemChannelTypes] and [crate::quinn::QuinnChannelTypes].
+pub trait ChannelTypes: Debug + Sized + Send + Sync + Unpin + Clone + 'static {
+    /// The sink used for sending either requests or responses on this channel
+    ///
+    /// Messages arrive at the remote in the order they were sent. Closing the sink ends the
+    /// [ChannelTypes::RecvStream] of the remote after the last message.
+    type SendSink<M: RpcMessage>: Sink<M, Error = Self::SendError> + Send + Unpin + 'static;
+    /// The stream used for receiving either requests or responses on this channel
+    ///
+    /// The stream does not need to be [Unpin], the client and server pin it where needed.
+    type RecvStream<M: RpcMessage>: Stream<Item = result::Result<M, Self::RecvError>>
+        + Send
+        + 'static;
+    /// Error you might get while sending messages to a sink
+    type SendError: ChannelError + Retryable;
+    /// Error you might get while receiving messages from a stream
+    type RecvError: ChannelError + Retryable;
+    /// Error you might get when opening a new connection to the server
+    type OpenBiError: ChannelError + Retryable;
+    /// Future returned by open_bi
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage>: Future<
+            Output = result::Result<(Self::SendSink<Out>, Self::RecvStream<In>), Self::OpenBiError>,
+        > + Send
+        + 'a
+    where
+        Self: 'a;
+
+    /// Error you might get when waiting for new streams on the server side
+    type AcceptBiError: ChannelError;
+    /// Future returned by accept_bi
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage>: Future<
+            Output = result::Result<
+                (Self::SendSink<Out>, Self::RecvStream<In>),
+                Self::AcceptBiError,
+            >,
+        > + Send
+        + 'a
+    where
+        Self: 'a;
+
+    /// Channel type
+    type Channel<In: RpcMessage, Out: RpcMessage>: crate::Channel<In, Out, Self>;
+
+    /// Abort sending on a stream with an application error code
+    ///
+    /// The server uses this to tell the client why a call failed, see
+    /// [crate::server::RpcServerErrorKind::reset_code]. Data that was not sent yet may be discarded.
+    /// The default implementation does nothing, so the stream is just closed when the sink is
+    /// dropped.
+    fn reset<M: RpcMessage>(_send: &mut Self::SendSink<M>, _code: u32) {}
+
+    /// Tell a sink which interaction pattern its stream is used for
+    ///
+    /// The server calls this before it sends the responses of a call. Channel types can use it to
+    /// treat the patterns differently, e.g. quinn channels can compress only the responses of
+    /// streaming calls. The default implementation does nothing.
+    fn set_pattern<M: RpcMessage>(_send: &mut Self::SendSink<M>, _pattern: PatternKind) {}
+
+    /// Set the priority of a stream before anything is written to it
+    ///
+    /// Data of streams with a higher priority is sent first. The client calls this for
+    /// [crate::RpcClient::rpc_with_priority]. The default implementation does nothing, so all
+    /// streams are treated the same.
+    fn set_priority<M: RpcMessage>(_send: &mut Self::SendSink<M>, _priority: i32) {}
+
+    /// Id that the client assigned to a stream, to correlate the calls on both sides in logs
+    ///
+    /// The client and the server record the id in the span of the call. The default
+    /// implementation returns `None`. With the `tracing` feature, the correlated channel wraps
+    /// another channel to send such an id along with every message.
+    fn correlation_id<M: RpcMessage>(_recv: &Self::RecvStream<M>) -> Option<u64> {
+        None
+    }
+
+    /// Auth token that the client sent with the first message of a stream
+    ///
+    /// The server checks the token before handing the request to a handler, see
+    /// [crate::server::Authenticator]. The default implementation returns `None`. The
+    /// [crate::auth] channel wraps another channel to send a token with every request.
+    fn auth_token<M: RpcMessage>(_recv: &Self::RecvStream<M>) -> Option<Bytes> {
+        None
+    }
+}
+
+/// An abstract channel with typed input and output
+///
+/// This assumes cheap streams, so every interaction uses a new stream.
+///
+/// Heavily inspired by quinn, but uses concrete `In` and `Out` types instead of bytes. The reason for this is that
+/// we want to be able to write a memory channel that does not serialize and deserialize.
+pub trait Channel<In: RpcMessage, Out: RpcMessage, T: ChannelTypes>:
+    Send + Sync + Clone + 'static
+{
+    /// Open a bidirectional stream
+    ///
+    /// The remote may only see the stream once the first message is sent on it. This is not
+    /// required to be cancel safe: if the future is dropped, the remote may get a stream that
+    /// ends without any messages.
+    fn open_bi(&self) -> T::OpenBiFuture<'_, In, Out>;
+    /// Open a bidirectional stream and send the first message on it
+    ///
+    /// The default implementation opens the stream and then sends the message. Channel types
+    /// that can do better, e.g. by writing the message together with the stream header, can
+    /// override this.
+    fn open_bi_with(&self, first: Out) -> OpenBiWithFuture<'_, T, In, Out> {
+        let open = self.open_bi();
+        async move {
+            let (mut send, recv) = open.await.map_err(OpenBiWithError::Open)?;
+            send.send(first).await.map_err(OpenBiWithError::Send)?;
+            Ok((send, recv))
+        }
+        .boxed()
+    }
+    /// Accept a bidirectional stream
+    ///
+    /// Streams whose first messages were sent one after the other are accepted in that order.
+    /// This is cancel safe: dropping the future before it completes does not lose a stream, so
+    /// it can be raced against other futures. Channel types whose accepting side can not open
+    /// streams return a future that never completes on the connecting side.
+    fn accept_bi(&self) -> T::AcceptBiFuture<'_, In, Out>;
+    /// Open a unidirectional stream and send the first message on it
+    ///
+    /// This is used for messages that do not get a response, see [crate::message::NotifyMsg]. The
+    /// default implementation opens a bidirectional stream and drops the receiving side, so the
+    /// remote gets the stream from [Channel::accept_bi]. Channel types with native
+    /// unidirectional streams override this together with [Channel::accept_uni].
+    fn open_uni_with(&self, first: Out) -> OpenUniWithFuture<'_, T, Out> {
+        let open = self.open_bi_with(first);
+        async move { open.await.map(|(send, _recv)| send) }.boxed()
+    }
+    /// Accept a unidirectional stream opened with [Channel::open_uni_with]
+    ///
+    /// The default implementation never completes, since the default implementation of
+    /// [Channel::open_uni_with] uses bidirectional streams.
+    fn accept_uni(&self) -> AcceptUniFuture<'_, T, In> {
+        future::pending().boxed()
+    }
+    /// Information about the connection to the remote, as far as the channel type knows it
+    ///
+    /// The default implementation returns an empty [ConnectionInfo].
+    fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo::default()
+    }
+    /// Close the connection with an application defined error `code` and `reason`
+    ///
+    /// Pending and future operations on the channel and its clones fail, and so do the ones of
+    /// the remote. Transports that can not send the code and reason to the remote just close the
+    /// connection.
+    fn close(&self, code: u32, reason: &[u8]);
+}
+
+/// Closing state of a channel and its clones, for transports without a native close
+///
+/// Closing drops the only sender, which wakes all futures returned by [CloseSignal::closed].
+#[derive(Debug)]
+pub(crate) struct CloseSignal {
+    sender: std::sync::Mutex<Option<flume::Sender<()>>>,
+    receiver: flume::Receiver<()>,
+}
+
+impl Default for CloseSignal {
+    fn default() -> Self {
+        let (sender, receiver) = flume::bounded(1);
+        Self {
+            sender: std::sync::Mutex::new(Some(sender)),
+            receiver,
+        }
+    }
+}
+
+impl CloseSignal {
+    pub(crate) fn close(&self) {
+        self.sender.lock().unwrap().take();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.receiver.is_disconnected()
+    }
+
+    /// Completes once the channel is closed
+    pub(crate) fn closed(&self) -> flume::r#async::RecvFut<'_, ()> {
+        self.receiver.recv_async()
+    }
+}
+
+/// Information about the connection of a channel, see [Channel::connection_info]
+///
+/// Fields are `None` if the channel type does not provide them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Address of the remote
+    pub peer: Option<SocketAddr>,
+    /// Certificate chain presented by the remote during the TLS handshake, DER encoded
+    pub peer_certificates: Option<Vec<Vec<u8>>>,
+    /// Current estimate of the round trip time
+    pub rtt: Option<Duration>,
+}
+
+/// Future returned by [Channel::open_bi_with]
+pub type OpenBiWithFuture<'a, T, In, Out> = BoxFuture<
+    'a,
+    result::Result<
+        (
+            <T as ChannelTypes>::SendSink<Out>,
+            <T as ChannelTypes>::RecvStream<In>,
+        ),
+        OpenBiWithError<T>,
+    >,
+>;
+
+/// Future returned by [Channel::open_uni_with]
+pub type OpenUniWithFuture<'a, T, Out> =
+    BoxFuture<'a, result::Result<<T as ChannelTypes>::SendSink<Out>, OpenBiWithError<T>>>;
+
+/// Future returned by [Channel::accept_uni]
+pub type AcceptUniFuture<'a, T, In> = BoxFuture<
+    'a,
+    result::Result<<T as ChannelTypes>::RecvStream<In>, <T as ChannelTypes>::AcceptBiError>,
+>;
+
+/// Error when opening a stream and sending the first message, see [Channel::open_bi_with]
+#[derive(Debug, thiserror::Error)]
+pub enum OpenBiWithError<T: ChannelTypes> {
+    /// Unable to open the stream
+    #[error("failed to open a stream")]
+    Open(#[source] T::OpenBiError),
+    /// Unable to send the first message
+    #[error("failed to send the first message")]
+    Send(#[source] T::SendError),
+}
+
+impl<T: ChannelTypes> ChannelError for OpenBiWithError<T> {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::Open(e) => e.into_io(),
+            Self::Send(e) => e.into_io(),
+        }
+    }
+}
+
+impl<T: ChannelTypes> Retryable for OpenBiWithError<T> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Open(e) => e.is_retryable(),
+            Self::Send(e) => e.is_retryable(),
+        }
+    }
+}
+
+/// Check that the channels of `C` follow the contract of [Channel]
+///
+/// `connect` is called for every check and returns a connected pair of channels: the first one
+/// opens streams, the second one accepts them. The checks panic if the channel type does not
+/// behave as expected, so this is meant to be called from a test:
+///
+/// ```ignore
+/// #[tokio::test]
+/// async fn my_channel_conformance() {
+///     quic_rpc::channel::test_suite::<MyChannelTypes, _, _>(|| async {
+///         my_channel::connection(16)
+///     })
+///     .await;
+/// }
+/// ```
+pub async fn test_suite<C, F, Fut>(mut connect: F)
+where
+    C: ChannelTypes,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = (C::Channel<u64, u64>, C::Channel<u64, u64>)>,
+{
+    // a request and a response on a stream
+    let (client, server) = connect().await;
+    let open = async {
+        let (mut send, recv) = client.open_bi().await.expect("open_bi failed");
+        futures::pin_mut!(recv);
+        send.send(1).await.expect("sending the request failed");
+        let res = next(recv.as_mut()).await;
+        assert_eq!(res, Some(2), "did not get the response");
+    };
+    let accept = async {
+        let (mut send, recv) = server.accept_bi().await.expect("accept_bi failed");
+        futures::pin_mut!(recv);
+        assert_eq!(
+            next(recv.as_mut()).await,
+            Some(1),
+            "did not get the request"
+        );
+        send.send(2).await.expect("sending the response failed");
+    };
+    future::join(open, accept).await;
+
+    // messages arrive in order, and closing the sink ends the remote stream
+    let (client, server) = connect().await;
+    let open = async {
+        let (mut send, _recv) = client.open_bi_with(0).await.expect("open_bi_with failed");
+        for i in 1..100 {
+            send.send(i).await.expect("sending failed");
+        }
+        send.close().await.expect("closing the sink failed");
+    };
+    let accept = async {
+        let (_send, recv) = server.accept_bi().await.expect("accept_bi failed");
+        let received = recv
+            .map(|msg| msg.expect("receiving failed"))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            received,
+            (0..100).collect::<Vec<_>>(),
+            "messages out of order"
+        );
+    };
+    future::join(open, accept).await;
+
+    // streams are accepted in the order of their first messages
+    let (client, server) = connect().await;
+    let open = async {
+        let mut streams = Vec::new();
+        for i in 0..3 {
+            streams.push(client.open_bi_with(i).await.expect("open_bi_with failed"));
+        }
+        streams
+    };
+    let accept = async {
+        for i in 0..3 {
+            let (_send, recv) = server.accept_bi().await.expect("accept_bi failed");
+            futures::pin_mut!(recv);
+            assert_eq!(
+                next(recv.as_mut()).await,
+                Some(i),
+                "streams accepted out of order"
+            );
+        }
+    };
+    // keep the streams open until the remote got the messages
+    let (_streams, ()) = future::join(open, accept).await;
+
+    // dropping an accept future does not lose the next stream
+    let (client, server) = connect().await;
+    assert!(
+        server.accept_bi().now_or_never().is_none(),
+        "accepted a stream that was never opened"
+    );
+    let open = async { client.open_bi_with(7).await.expect("open_bi_with failed") };
+    let accept = async {
+        let (_send, recv) = server.accept_bi().await.expect("accept_bi failed");
+        futures::pin_mut!(recv);
+        assert_eq!(next(recv.as_mut()).await, Some(7), "lost a stream");
+    };
+    let (_stream, ()) = future::join(open, accept).await;
+
+    // a unidirectional stream arrives on one of the accept methods
+    let (client, server) = connect().await;
+    let open = async { client.open_uni_with(9).await.expect("open_uni_with failed") };
+    let accept = async {
+        let bi = server.accept_bi().map(|res| res.map(|(_send, recv)| recv));
+        futures::pin_mut!(bi);
+        let recv = match future::select(server.accept_uni(), bi).await {
+            Either::Left((res, _)) | Either::Right((res, _)) => res.expect("accept failed"),
+        };
+        futures::pin_mut!(recv);
+        assert_eq!(next(recv.as_mut()).await, Some(9), "lost the message");
+    };
+    let (_stream, ()) = future::join(open, accept).await;
+}
+
+/// The next message of a stream, panicking on errors
+async fn next<S, E>(mut recv: Pin<&mut S>) -> Option<u64>
+where
+    S: Stream<Item = result::Result<u64, E>>,
+    E: Debug,
+{
+    recv.next().await.map(|msg| msg.expect("receiving failed"))
+}
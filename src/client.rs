@@ -1,43 +1,93 @@
 //! [RpcClient] and support types
 //!
 //! This defines the RPC client DSL
+#[cfg(feature = "tokio")]
+use crate::message::{Idempotent, Keepalive};
 use crate::{
-    message::{BidiStreaming, ClientStreaming, Msg, Rpc, ServerStreaming},
-    Channel, ChannelTypes, Service,
+    mapped::{self, MappedChannelTypes},
+    message::{
+        BidiStreaming, Cancel, ClientStreaming, ControlFrame, Frame, Indexed, InteractionPattern,
+        Msg, NotifyMsg, PatternKind, Rpc, Sequenced, ServerStreaming, SplitControl, StreamControl,
+    },
+    server::RpcServerErrorKind,
+    tagged::variant_name,
+    trace::{CallSpan, Hooks},
+    ByteCounted, ByteCounts, Channel, ChannelError, ChannelTypes, OpenBiWithError, Retryable,
+    RpcMessage, Service, SubService,
 };
+use bytes::Bytes;
 use futures::{
-    future::BoxFuture, stream::BoxStream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt,
+    future::{self, BoxFuture, Either},
+    lock::Mutex,
+    stream::BoxStream,
+    Future, FutureExt, Sink, SinkExt, Stream, StreamExt, TryStreamExt,
 };
 use pin_project::pin_project;
+use serde::Serialize;
+#[cfg(feature = "tokio")]
+use std::time::Duration;
 use std::{
-    error, fmt,
+    error, fmt, io,
     marker::PhantomData,
     pin::Pin,
     result,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
 };
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 /// A client for a specific service
 ///
 /// This is a wrapper around a [crate::Channel] that serves as the entry point for the client DSL.
 /// `S` is the service type, `C` is the channel type.
-#[derive(Debug)]
 pub struct RpcClient<S: Service, C: ChannelTypes> {
-    channel: C::Channel<S::Res, S::Req>,
+    pub(crate) channel: C::Channel<S::Res, S::Req>,
+    hooks: Hooks,
+    /// Check that rpc calls get no more than one response, see [RpcClient::with_strict_rpc]
+    strict: bool,
     _s: PhantomData<S>,
 }
 
+impl<S: Service, C: ChannelTypes> fmt::Debug for RpcClient<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the channel is not required to implement Debug
+        f.debug_struct("RpcClient")
+            .field("service", &std::any::type_name::<S>())
+            .finish_non_exhaustive()
+    }
+}
+
 impl<S: Service, C: ChannelTypes> Clone for RpcClient<S, C> {
     fn clone(&self) -> Self {
         Self {
             channel: self.channel.clone(),
+            hooks: self.hooks.clone(),
+            strict: self.strict,
             _s: self._s,
         }
     }
 }
 
+/// The bidirectional stream of a request on the client side
+///
+/// This is a sink for the request and updates and a stream of the responses sent by the server.
+pub type ClientSocket<S, C> = (
+    <C as ChannelTypes>::SendSink<<S as Service>::Req>,
+    <C as ChannelTypes>::RecvStream<<S as Service>::Res>,
+);
+
 /// Sink that can be used to send updates to the server for the two interaction patterns
 /// that support it, [ClientStreaming] and [BidiStreaming].
+///
+/// Updates passed to [SinkExt::feed] may be buffered by the channel, and are only guaranteed
+/// to be written to the stream once the sink is flushed. [SinkExt::send] flushes after every
+/// update. When feeding several updates, call [SinkExt::flush] at a logical boundary, e.g.
+/// before waiting for the reaction of the server, so the updates do not wait for more data or
+/// for the sink to be closed. For quinn, flushing hands the data to the connection, which
+/// sends it as soon as flow and congestion control allow.
 #[pin_project]
 #[derive(Debug)]
 pub struct UpdateSink<S: Service, C: ChannelTypes, M: Msg<S>>(
@@ -45,6 +95,52 @@ pub struct UpdateSink<S: Service, C: ChannelTypes, M: Msg<S>>(
     PhantomData<M>,
 );
 
+/// [UpdateSink] with a bounded buffer, see [RpcClient::client_streaming_bounded]
+pub type BoundedUpdateSink<S, C, M> =
+    futures::sink::Buffer<UpdateSink<S, C, M>, <M as Msg<S>>::Update>;
+
+/// Stream of responses of a server streaming request, see [RpcClient::server_streaming]
+pub type ServerStreamingResponses<S, C, M> =
+    BoxStream<'static, result::Result<<M as Msg<S>>::Response, StreamingResponseItemError<C>>>;
+
+/// Future for the response of a client streaming request, see [RpcClient::client_streaming]
+pub type ClientStreamingResponse<S, C, M> =
+    BoxFuture<'static, result::Result<<M as Msg<S>>::Response, ClientStreamingItemError<C>>>;
+
+/// Stream of responses of a bidi streaming request, see [RpcClient::bidi]
+pub type BidiResponses<S, C, M> =
+    BoxStream<'static, result::Result<<M as Msg<S>>::Response, BidiItemError<C>>>;
+
+impl<S: Service, C: ChannelTypes, M: Msg<S>> UpdateSink<S, C, M> {
+    /// Send a control frame to the server, in order with the updates
+    pub async fn send_control(&mut self, frame: ControlFrame) -> result::Result<(), C::SendError>
+    where
+        ControlFrame: Into<S::Req>,
+    {
+        self.0.send(frame.into()).await
+    }
+
+    /// The [ByteCounts] of the stream, for channel types whose sinks implement [ByteCounted]
+    pub fn byte_counts(&self) -> ByteCounts
+    where
+        C::SendSink<S::Req>: ByteCounted,
+    {
+        self.0.byte_counts()
+    }
+
+    /// Cancel the interaction and close the sink
+    ///
+    /// The server aborts the handler, see [Cancel]. This works on all channel types, unlike
+    /// relying on dropping the sink to reset the stream.
+    pub async fn cancel(mut self) -> result::Result<(), C::SendError>
+    where
+        Cancel: Into<S::Req>,
+    {
+        self.0.send(Cancel.into()).await?;
+        self.0.close().await
+    }
+}
+
 impl<S: Service, C: ChannelTypes, M: Msg<S>> Sink<M::Update> for UpdateSink<S, C, M> {
     type Error = C::SendError;
 
@@ -66,69 +162,673 @@ impl<S: Service, C: ChannelTypes, M: Msg<S>> Sink<M::Update> for UpdateSink<S, C
     }
 }
 
+/// The update sink and the responses of a bidi request as a single object, see
+/// [RpcClient::bidi_duplex]
+///
+/// This is a [Sink] for the updates and a [Stream] of the responses. The two halves are
+/// independent, so updates can be sent while waiting for responses, e.g. using
+/// [Duplex::parts_mut] to borrow both at once. [Duplex::split] turns it back into the halves.
+pub struct Duplex<S: Service, C: ChannelTypes, M: Msg<S>> {
+    sink: UpdateSink<S, C, M>,
+    responses: BidiResponses<S, C, M>,
+}
+
+impl<S: Service, C: ChannelTypes, M: Msg<S>> Duplex<S, C, M> {
+    /// Combine the halves returned by [RpcClient::bidi]
+    pub fn new(sink: UpdateSink<S, C, M>, responses: BidiResponses<S, C, M>) -> Self {
+        Self { sink, responses }
+    }
+
+    /// Borrow the update sink and the responses at the same time
+    pub fn parts_mut(&mut self) -> (&mut UpdateSink<S, C, M>, &mut BidiResponses<S, C, M>) {
+        (&mut self.sink, &mut self.responses)
+    }
+
+    /// Split into the update sink and the responses
+    pub fn split(self) -> (UpdateSink<S, C, M>, BidiResponses<S, C, M>) {
+        (self.sink, self.responses)
+    }
+}
+
+impl<S: Service, C: ChannelTypes, M: Msg<S>> fmt::Debug for Duplex<S, C, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Duplex").finish_non_exhaustive()
+    }
+}
+
+impl<S: Service, C: ChannelTypes, M: Msg<S>> Sink<M::Update> for Duplex<S, C, M> {
+    type Error = C::SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: M::Update) -> Result<(), Self::Error> {
+        self.sink.start_send_unpin(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink.poll_close_unpin(cx)
+    }
+}
+
+impl<S: Service, C: ChannelTypes, M: Msg<S>> Stream for Duplex<S, C, M> {
+    type Item = result::Result<M::Response, BidiItemError<C>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.responses.poll_next_unpin(cx)
+    }
+}
+
 impl<S: Service, C: ChannelTypes> RpcClient<S, C> {
     /// Create a new client channel from a channel and a service type
     pub fn new(channel: C::Channel<S::Res, S::Req>) -> Self {
         Self {
             channel,
+            hooks: Hooks::default(),
+            strict: false,
+            _s: PhantomData,
+        }
+    }
+
+    /// Turn this client into a client for service `S2`, whose messages are embedded in the
+    /// messages of `S`
+    ///
+    /// Requests of `S2` are converted using `to`, responses using `from`, which returns `None`
+    /// for responses that do not belong to `S2`. See [crate::mapped].
+    pub fn map_service<S2: Service>(
+        self,
+        to: impl Fn(S2::Req) -> S::Req + Send + Sync + 'static,
+        from: impl Fn(S::Res) -> Option<S2::Res> + Send + Sync + 'static,
+    ) -> RpcClient<S2, MappedChannelTypes<C, S::Res, S::Req>> {
+        RpcClient {
+            channel: mapped::Channel::new(self.channel, to, from),
+            hooks: self.hooks,
+            strict: self.strict,
             _s: PhantomData,
         }
     }
 
+    /// A client for the sub-service `Child`, using the connection of this client
+    ///
+    /// Requests are wrapped in the envelope of `S`, responses unwrapped, see [SubService].
+    pub fn sub<Child: SubService<S>>(
+        &self,
+    ) -> RpcClient<Child, MappedChannelTypes<C, S::Res, S::Req>> {
+        self.clone()
+            .map_service(Child::wrap_req, |res| Child::unwrap_res(res).ok())
+    }
+
+    /// Check that the server sends no more than one response to an rpc call
+    ///
+    /// With strict rpc enabled, [RpcClient::rpc] closes the request stream after the response and
+    /// waits for the server to close its side as well. It fails with [RpcClientError::UnexpectedExtraResponse] if it sends another
+    /// message instead. This catches servers that handle the request with another interaction
+    /// pattern than the client, at the cost of an extra await per call, so it is off by default.
+    pub fn with_strict_rpc(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Report every call of this client to `metrics`
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Arc<dyn crate::metrics::Metrics>) -> Self {
+        self.hooks.metrics = Some(metrics);
+        self
+    }
+
     /// RPC call to the server, single request, single response
+    ///
+    /// If the call is made within [Deadline::scope], it fails with [RpcClientError::Timeout] once
+    /// the deadline has passed, without opening a stream if it already passed.
+    ///
+    /// The call is cancel safe: if the future is dropped before the response arrived, e.g.
+    /// because it lost a `select!` against a timeout, the stream is reset with the code of
+    /// [RpcServerErrorKind::Cancelled], so the server stops the handler instead of computing a
+    /// response nobody reads. Channel types that do not support resets, see [ChannelTypes::reset],
+    /// close the stream instead. If the future is dropped while the stream is being opened, the
+    /// server may still get the request.
     pub async fn rpc<M>(&self, msg: M) -> result::Result<M::Response, RpcClientError<C>>
+    where
+        M: Msg<S, Pattern = Rpc> + Into<S::Req>,
+    {
+        within_current_deadline(self.rpc_inner(msg, None)).await
+    }
+
+    /// RPC call to the server on a stream with the given priority, single request, single response
+    ///
+    /// The priority is set before the request is written, so latency sensitive calls can get
+    /// ahead of bulk transfers on the same connection, see [ChannelTypes::set_priority]. Otherwise
+    /// this is the same as [RpcClient::rpc], and the server handles the request like any other
+    /// rpc.
+    pub async fn rpc_with_priority<M>(
+        &self,
+        msg: M,
+        priority: i32,
+    ) -> result::Result<M::Response, RpcClientError<C>>
+    where
+        M: Msg<S, Pattern = Rpc> + Into<S::Req>,
+    {
+        within_current_deadline(self.rpc_inner(msg, Some(priority))).await
+    }
+
+    /// RPC call to the server that fails with [RpcClientError::Timeout] if it does not complete
+    /// within `timeout`
+    ///
+    /// The timeout covers the whole call, from opening the stream to receiving the response. A
+    /// [Deadline] that expires earlier takes precedence.
+    #[cfg(feature = "tokio")]
+    pub async fn rpc_with_timeout<M>(
+        &self,
+        msg: M,
+        timeout: Duration,
+    ) -> result::Result<M::Response, RpcClientError<C>>
+    where
+        M: Msg<S, Pattern = Rpc> + Into<S::Req>,
+    {
+        Deadline::after(timeout)
+            .min(Deadline::current())
+            .run(self.rpc_inner(msg, None))
+            .await
+    }
+
+    /// Run a call that bypasses the typed messages of the service, like the raw calls of some
+    /// transports, in a span for `M` and within the current [Deadline]
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) async fn untyped_call<M, T>(
+        &self,
+        pattern: PatternKind,
+        fut: impl Future<Output = result::Result<T, RpcClientError<C>>>,
+    ) -> result::Result<T, RpcClientError<C>> {
+        within_current_deadline(CallSpan::new::<M>("client", pattern, &self.hooks).call(fut)).await
+    }
+
+    async fn rpc_inner<M>(
+        &self,
+        msg: M,
+        priority: Option<i32>,
+    ) -> result::Result<M::Response, RpcClientError<C>>
     where
         M: Msg<S, Pattern = Rpc> + Into<S::Req>,
     {
         let msg = msg.into();
-        let (mut send, mut recv) = self.channel.open_bi().await.map_err(RpcClientError::Open)?;
-        send.send(msg).await.map_err(RpcClientError::Send)?;
-        let res = recv
+        let span = CallSpan::for_msg::<S, M>("client", &self.hooks);
+        span.clone()
+            .call(async move {
+                let (send, recv) = match priority {
+                    None => self.channel.open_bi_with(msg).await?,
+                    Some(priority) => {
+                        let (mut send, recv) =
+                            self.channel.open_bi().await.map_err(RpcClientError::Open)?;
+                        C::set_priority(&mut send, priority);
+                        send.send(msg).await.map_err(RpcClientError::Send)?;
+                        (send, recv)
+                    }
+                };
+                span.correlate::<C, S::Res>(&recv);
+                // keep send alive until we have the answer, and reset it if the call is dropped
+                let mut send = ResetOnDrop::<C, S::Req>(Some(send));
+                tokio::pin!(recv);
+                let res = recv
+                    .next()
+                    .await
+                    .ok_or(RpcClientError::EarlyClose)?
+                    .map_err(RpcClientError::RecvError)?;
+                if self.strict {
+                    // finish the request stream first, since the server might only close its
+                    // side once the client did. Errors after the response do not affect the
+                    // result of the call
+                    if let Some(send) = send.0.as_mut() {
+                        send.close().await.ok();
+                    }
+                    if let Some(Ok(_)) = recv.next().await {
+                        return Err(RpcClientError::UnexpectedExtraResponse);
+                    }
+                }
+                send.disarm();
+                UnexpectedResponse::downcast::<M::Response, _>(res)
+                    .map_err(RpcClientError::DowncastError)
+            })
+            .await
+    }
+
+    /// Send several rpc requests on a single stream and collect the responses
+    ///
+    /// This saves opening a stream per request, which matters for many small calls. The server
+    /// needs to handle `M` using [crate::RpcServer::rpc_pipeline], which processes the requests
+    /// one after the other, so the responses are in the order of the requests. Requests are sent
+    /// while the responses are read, so the batch can be larger than the buffers of the channel.
+    pub async fn rpc_batch<M>(
+        &self,
+        msgs: Vec<M>,
+    ) -> result::Result<Vec<M::Response>, RpcClientError<C>>
+    where
+        M: Msg<S, Pattern = Rpc> + Into<S::Req>,
+    {
+        let n = msgs.len();
+        let mut msgs = msgs.into_iter().map(Into::into);
+        let Some(first) = msgs.next() else {
+            return Ok(Vec::new());
+        };
+        let span = CallSpan::for_msg::<S, M>("client", &self.hooks);
+        span.clone()
+            .call(async move {
+                let (mut send, recv) = self.channel.open_bi_with(first).await?;
+                span.correlate::<C, S::Res>(&recv);
+                let send_rest = async move {
+                    let mut rest = futures::stream::iter(msgs.map(Ok));
+                    send.send_all(&mut rest)
+                        .await
+                        .map_err(RpcClientError::Send)?;
+                    // finish the stream, so the server knows that the batch is complete
+                    send.close().await.map_err(RpcClientError::Send)
+                };
+                let recv_all = async move {
+                    tokio::pin!(recv);
+                    let mut res = Vec::with_capacity(n);
+                    while res.len() < n {
+                        let item = recv
+                            .next()
+                            .await
+                            .ok_or(RpcClientError::EarlyClose)?
+                            .map_err(RpcClientError::RecvError)?;
+                        res.push(
+                            UnexpectedResponse::downcast::<M::Response, _>(item)
+                                .map_err(RpcClientError::DowncastError)?,
+                        );
+                    }
+                    Ok(res)
+                };
+                let ((), res) = futures::future::try_join(send_rest, recv_all).await?;
+                Ok(res)
+            })
+            .await
+    }
+
+    /// Like [RpcClient::rpc_batch], but for a server that processes the requests concurrently
+    ///
+    /// The server needs to handle `M` using [crate::RpcServer::rpc_pipeline_concurrent], which
+    /// sends every response as soon as it is ready, tagged with the index of its request in an
+    /// [Indexed]. This puts the responses back into the order of the requests, so a slow request
+    /// does not delay the responses after it. A response with an index that is out of range or
+    /// was already seen is reported as [RpcClientError::DowncastError].
+    pub async fn rpc_batch_concurrent<M>(
+        &self,
+        msgs: Vec<M>,
+    ) -> result::Result<Vec<M::Response>, RpcClientError<C>>
+    where
+        M: Msg<S, Pattern = Rpc> + Into<S::Req>,
+        Indexed<M::Response>: TryFrom<S::Res>,
+    {
+        let n = msgs.len();
+        let mut msgs = msgs.into_iter().map(Into::into);
+        let Some(first) = msgs.next() else {
+            return Ok(Vec::new());
+        };
+        let span = CallSpan::for_msg::<S, M>("client", &self.hooks);
+        span.clone()
+            .call(async move {
+                let (mut send, recv) = self.channel.open_bi_with(first).await?;
+                span.correlate::<C, S::Res>(&recv);
+                let send_rest = async move {
+                    let mut rest = futures::stream::iter(msgs.map(Ok));
+                    send.send_all(&mut rest)
+                        .await
+                        .map_err(RpcClientError::Send)?;
+                    send.close().await.map_err(RpcClientError::Send)
+                };
+                let recv_all = async move {
+                    tokio::pin!(recv);
+                    let mut res = (0..n).map(|_| None).collect::<Vec<_>>();
+                    for _ in 0..n {
+                        let item = recv
+                            .next()
+                            .await
+                            .ok_or(RpcClientError::EarlyClose)?
+                            .map_err(RpcClientError::RecvError)?;
+                        let received = variant_name(&item);
+                        let Indexed { index, item } =
+                            UnexpectedResponse::downcast::<Indexed<M::Response>, _>(item)
+                                .map_err(RpcClientError::DowncastError)?;
+                        match res.get_mut(index as usize) {
+                            Some(slot @ None) => *slot = Some(item),
+                            _ => {
+                                return Err(RpcClientError::DowncastError(
+                                    UnexpectedResponse::new::<Indexed<M::Response>>(received),
+                                ))
+                            }
+                        }
+                    }
+                    // all n slots are filled, since every index was accepted only once
+                    Ok(res.into_iter().flatten().collect())
+                };
+                let ((), res) = futures::future::try_join(send_rest, recv_all).await?;
+                Ok(res)
+            })
+            .await
+    }
+
+    /// Notification to the server, single request, no response
+    ///
+    /// Returns once the message is sent. This uses a unidirectional stream if the channel type
+    /// supports them, see [Channel::open_uni_with].
+    pub async fn notify<M>(&self, msg: M) -> result::Result<(), NotifyError<C>>
+    where
+        M: NotifyMsg<S>,
+    {
+        let mut send = self.channel.open_uni_with(msg.into()).await?;
+        send.close().await.map_err(NotifyError::Send)
+    }
+
+    /// Accept a message pushed by the server, see [crate::RpcServer::push]
+    ///
+    /// Pushes arrive on streams opened by the server, which are unidirectional or bidirectional
+    /// depending on the channel type, so this accepts both. The message is converted from the
+    /// response type of the service to `M`, and a push of another message fails with
+    /// [PushError::DowncastError].
+    pub async fn accept_push<M>(&self) -> result::Result<M, PushError<C>>
+    where
+        M: TryFrom<S::Res>,
+    {
+        let uni = self.channel.accept_uni();
+        let bi = self
+            .channel
+            .accept_bi()
+            .map(|res| res.map(|(_send, recv)| recv));
+        futures::pin_mut!(bi);
+        let recv = match future::select(uni, bi).await {
+            Either::Left((res, _)) | Either::Right((res, _)) => res.map_err(PushError::Accept)?,
+        };
+        futures::pin_mut!(recv);
+        let msg = recv
             .next()
             .await
-            .ok_or(RpcClientError::EarlyClose)?
-            .map_err(RpcClientError::RecvError)?;
-        // keep send alive until we have the answer
-        drop(send);
-        M::Response::try_from(res).map_err(|_| RpcClientError::DowncastError)
+            .ok_or(PushError::EarlyClose)?
+            .map_err(PushError::RecvError)?;
+        UnexpectedResponse::downcast::<M, _>(msg).map_err(PushError::DowncastError)
+    }
+
+    /// Accept pushes from the server and call `handler` for each of them
+    ///
+    /// The next push is accepted once the handler completes. Runs until accepting a push fails,
+    /// e.g. because the connection was closed, and returns that error.
+    pub async fn accept_push_loop<M, F, Fut>(
+        &self,
+        mut handler: F,
+    ) -> result::Result<(), PushError<C>>
+    where
+        M: TryFrom<S::Res>,
+        F: FnMut(M) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        loop {
+            handler(self.accept_push().await?).await;
+        }
     }
 
     /// Bidi call to the server, request opens a stream, response is a stream
+    ///
+    /// To read responses without waiting, wrap the stream in a [PollStream].
     pub async fn server_streaming<M>(
         &mut self,
         msg: M,
+    ) -> result::Result<ServerStreamingResponses<S, C, M>, StreamingResponseError<C>>
+    where
+        M: Msg<S, Pattern = ServerStreaming> + Into<S::Req>,
+    {
+        let msg = msg.into();
+        let span = CallSpan::for_msg::<S, M>("client", &self.hooks);
+        let (send, recv) = span.start(self.channel.open_bi_with(msg)).await?;
+        span.correlate::<C, S::Res>(&recv);
+        Ok(streaming_responses::<S, C, M>(&span, send, recv))
+    }
+
+    /// Subscribe to the events of the server that match `filter`
+    ///
+    /// The filter is a server streaming message that the server handles with
+    /// [crate::RpcServer::subscription]. To unsubscribe, drop the returned stream. The server
+    /// then stops sending events and completes the subscription without an error.
+    pub async fn subscribe<M>(
+        &mut self,
+        filter: M,
+    ) -> result::Result<ServerStreamingResponses<S, C, M>, StreamingResponseError<C>>
+    where
+        M: Msg<S, Pattern = ServerStreaming> + Into<S::Req>,
+    {
+        self.server_streaming(filter).await
+    }
+
+    /// Like [RpcClient::server_streaming], but also returns the [ByteCounts] of the stream
+    ///
+    /// The counts are updated while the responses are received, e.g. for showing progress. This
+    /// is supported by channel types whose streams implement [ByteCounted].
+    pub async fn server_streaming_counted<M>(
+        &mut self,
+        msg: M,
+    ) -> result::Result<
+        (
+            BoxStream<'static, result::Result<M::Response, StreamingResponseItemError<C>>>,
+            ByteCounts,
+        ),
+        StreamingResponseError<C>,
+    >
+    where
+        M: Msg<S, Pattern = ServerStreaming> + Into<S::Req>,
+        C::RecvStream<S::Res>: ByteCounted,
+    {
+        let msg = msg.into();
+        let span = CallSpan::for_msg::<S, M>("client", &self.hooks);
+        let (send, recv) = span.start(self.channel.open_bi_with(msg)).await?;
+        span.correlate::<C, S::Res>(&recv);
+        let counts = recv.byte_counts();
+        Ok((streaming_responses::<S, C, M>(&span, send, recv), counts))
+    }
+
+    /// Like [RpcClient::server_streaming], but sends a [Keepalive] if no response arrived for
+    /// `interval`
+    ///
+    /// This is for streams that produce data rarely, where a dead connection would otherwise go
+    /// unnoticed for a long time. Sending the keepalive makes the transport check that the
+    /// server is still there. If sending fails, the stream yields
+    /// [StreamingResponseItemError::KeepaliveTimeout] and ends. How fast a dead peer is noticed
+    /// depends on the transport, e.g. the idle timeout of quinn. For quinn, setting
+    /// [quinn::TransportConfig::keep_alive_interval] on the endpoint detects dead connections
+    /// for all calls without involving the server.
+    ///
+    /// The request enum of the service needs to contain a variant for [Keepalive], and the
+    /// service needs to recognize it in [Service::is_keepalive], so the server ignores it.
+    #[cfg(feature = "tokio")]
+    pub async fn server_streaming_with_keepalive<M>(
+        &mut self,
+        msg: M,
+        interval: Duration,
     ) -> result::Result<
         BoxStream<'static, result::Result<M::Response, StreamingResponseItemError<C>>>,
         StreamingResponseError<C>,
     >
     where
         M: Msg<S, Pattern = ServerStreaming> + Into<S::Req>,
+        Keepalive: Into<S::Req>,
     {
         let msg = msg.into();
-        let (mut send, recv) = self
-            .channel
-            .open_bi()
-            .map_err(StreamingResponseError::Open)
-            .await?;
-        send.send(msg).map_err(StreamingResponseError::Send).await?;
-        let recv = recv.map(move |x| match x {
-            Ok(x) => {
-                M::Response::try_from(x).map_err(|_| StreamingResponseItemError::DowncastError)
+        let span = CallSpan::for_msg::<S, M>("client", &self.hooks);
+        let (send, recv) = span.start(self.channel.open_bi_with(msg)).await?;
+        span.correlate::<C, S::Res>(&recv);
+        let recv = span.stream(recv.map(move |x| {
+            match x {
+                Ok(x) => UnexpectedResponse::downcast::<M::Response, _>(x)
+                    .map_err(StreamingResponseItemError::DowncastError),
+                Err(e) => Err(StreamingResponseItemError::RecvError(e)),
             }
-            Err(e) => Err(StreamingResponseItemError::RecvError(e)),
+        }));
+        let on_failure = || Err(StreamingResponseItemError::KeepaliveTimeout);
+        Ok(KeepaliveStream::new(recv, send, interval, on_failure).boxed())
+    }
+
+    /// Server streaming call where the server can end the stream with an application error
+    ///
+    /// The response type of the message is a `Result`, see
+    /// [crate::RpcServer::try_server_streaming]. An error reported by the handler is yielded as
+    /// `Ok(Err(_))` and ends the stream, while failures to receive the responses are yielded as
+    /// `Err(_)` like for [RpcClient::server_streaming].
+    pub async fn try_server_streaming<M, R, E>(
+        &mut self,
+        msg: M,
+    ) -> result::Result<
+        BoxStream<'static, result::Result<result::Result<R, E>, StreamingResponseItemError<C>>>,
+        StreamingResponseError<C>,
+    >
+    where
+        M: Msg<S, Pattern = ServerStreaming, Response = result::Result<R, E>> + Into<S::Req>,
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        let recv = self.server_streaming(msg).await?;
+        let recv = recv.scan(false, |done, item| {
+            let stop = std::mem::replace(done, matches!(item, Ok(Err(_))));
+            futures::future::ready((!stop).then_some(item))
         });
-        // keep send alive so the request on the server side does not get cancelled
-        let recv = DeferDrop(recv, send).boxed();
-        Ok(recv)
+        Ok(recv.boxed())
+    }
+
+    /// Server streaming call that re-opens the stream after a transient failure
+    ///
+    /// If receiving fails with an error that is [Retryable], or the server reset the stream because
+    /// the client was too slow or the call exceeded its max duration, see
+    /// [ChannelError::server_error_kind], the request is sent again on a new stream. The stream
+    /// then yields [ResumableItem::Reconnected] before the items of the new stream, since items
+    /// may have been missed or repeated in between. `resume` builds the request to resume from
+    /// the original request and the last received item, e.g. using [crate::message::ResumeFrom]
+    /// with the index of an [crate::message::Indexed] response. It is called for every item. If
+    /// no item was received yet, the original request is sent again.
+    ///
+    /// Reconnecting is retried according to `policy`, which limits the number of streams that
+    /// are opened in a row without receiving an item. Once it gives up, the stream yields the
+    /// error that ended the last stream and ends. Non-retryable errors end the stream right away.
+    /// All streams are opened on the channel of this client, so for quinn this survives failed
+    /// streams but not a failed connection.
+    #[cfg(feature = "tokio")]
+    pub async fn server_streaming_resumable<M, F>(
+        &mut self,
+        msg: M,
+        policy: RetryPolicy,
+        resume: F,
+    ) -> result::Result<
+        BoxStream<
+            'static,
+            result::Result<ResumableItem<M::Response>, StreamingResponseItemError<C>>,
+        >,
+        StreamingResponseError<C>,
+    >
+    where
+        M: Msg<S, Pattern = ServerStreaming> + Into<S::Req> + Clone,
+        F: FnMut(&M, &M::Response) -> M + Send + 'static,
+    {
+        let stream = self.server_streaming(msg.clone()).await?;
+        let state = ResumeState {
+            client: self.clone(),
+            msg,
+            next: None,
+            resume,
+            policy,
+            attempts: 1,
+            stream: Some(stream),
+        };
+        Ok(futures::stream::unfold(state, ResumeState::next_item).boxed())
+    }
+
+    /// Server streaming call that can be paused, resumed or cancelled using the returned
+    /// [StreamController]
+    ///
+    /// To pause and resume, the server needs to handle the request with
+    /// [crate::RpcServer::server_streaming_controlled], and the request enum of the service needs
+    /// to contain a variant for [StreamControl]. Cancelling works with all server streaming
+    /// handlers, see [Cancel].
+    pub async fn server_streaming_controlled<M>(
+        &mut self,
+        msg: M,
+    ) -> result::Result<
+        (
+            StreamController<S, C>,
+            BoxStream<'static, result::Result<M::Response, StreamingResponseItemError<C>>>,
+        ),
+        StreamingResponseError<C>,
+    >
+    where
+        M: Msg<S, Pattern = ServerStreaming> + Into<S::Req>,
+    {
+        let msg = msg.into();
+        let span = CallSpan::for_msg::<S, M>("client", &self.hooks);
+        let (send, recv) = span.start(self.channel.open_bi_with(msg)).await?;
+        span.correlate::<C, S::Res>(&recv);
+        let recv = span.stream(recv.map(move |x| {
+            match x {
+                Ok(x) => UnexpectedResponse::downcast::<M::Response, _>(x)
+                    .map_err(StreamingResponseItemError::DowncastError),
+                Err(e) => Err(StreamingResponseItemError::RecvError(e)),
+            }
+        }));
+        let send = Arc::new(Mutex::new(send));
+        // keep send alive even if the controller is dropped, since closing it cancels the request
+        let recv = DeferDrop(recv, send.clone()).boxed();
+        Ok((StreamController(send, PhantomData), recv))
     }
 
     /// Call to the server that allows the client to stream, single response
+    ///
+    /// To finish the input, close the sink using [SinkExt::close]. This signals the end of the
+    /// updates to the server on all channel types, so the response future resolves even if the
+    /// sink is kept alive. Dropping the sink also ends the updates, but without a way to wait
+    /// for the pending updates to be sent.
     pub async fn client_streaming<M>(
         &mut self,
         msg: M,
+    ) -> result::Result<
+        (UpdateSink<S, C, M>, ClientStreamingResponse<S, C, M>),
+        ClientStreamingError<C>,
+    >
+    where
+        M: Msg<S, Pattern = ClientStreaming> + Into<S::Req>,
+    {
+        let msg = msg.into();
+        let span = CallSpan::for_msg::<S, M>("client", &self.hooks);
+        let (send, recv) = span.start(self.channel.open_bi_with(msg)).await?;
+        span.correlate::<C, S::Res>(&recv);
+        let send = UpdateSink::<S, C, M>(send, PhantomData);
+        let recv = span
+            .call(async move {
+                tokio::pin!(recv);
+                let item = recv
+                    .next()
+                    .await
+                    .ok_or(ClientStreamingItemError::EarlyClose)?;
+
+                match item {
+                    Ok(x) => UnexpectedResponse::downcast::<M::Response, _>(x)
+                        .map_err(ClientStreamingItemError::DowncastError),
+                    Err(e) => Err(ClientStreamingItemError::RecvError(e)),
+                }
+            })
+            .boxed();
+        Ok((send, recv))
+    }
+
+    /// Like [RpcClient::client_streaming], but with a sink that buffers up to `capacity` updates
+    ///
+    /// Once the buffer is full, the sink applies backpressure: `poll_ready`, and therefore `feed`
+    /// and `send`, only complete once the channel accepted some of the buffered updates. This
+    /// keeps memory use bounded no matter how fast updates are produced.
+    pub async fn client_streaming_bounded<M>(
+        &mut self,
+        msg: M,
+        capacity: usize,
     ) -> result::Result<
         (
-            UpdateSink<S, C, M>,
+            BoundedUpdateSink<S, C, M>,
             BoxFuture<'static, result::Result<M::Response, ClientStreamingItemError<C>>>,
         ),
         ClientStreamingError<C>,
@@ -136,185 +836,1446 @@ impl<S: Service, C: ChannelTypes> RpcClient<S, C> {
     where
         M: Msg<S, Pattern = ClientStreaming> + Into<S::Req>,
     {
-        let msg = msg.into();
-        let (mut send, mut recv) = self
-            .channel
-            .open_bi()
-            .map_err(ClientStreamingError::Open)
-            .await?;
-        send.send(msg).map_err(ClientStreamingError::Send).await?;
-        let send = UpdateSink::<S, C, M>(send, PhantomData);
-        let recv = async move {
-            let item = recv
-                .next()
-                .await
-                .ok_or(ClientStreamingItemError::EarlyClose)?;
-
-            match item {
-                Ok(x) => {
-                    M::Response::try_from(x).map_err(|_| ClientStreamingItemError::DowncastError)
+        let (send, recv) = self.client_streaming(msg).await?;
+        Ok((send.buffer(capacity), recv))
+    }
+
+    /// Upload the contents of `reader` using a client streaming call
+    ///
+    /// The reader is split into updates of `chunk_size` bytes, only the last update can be
+    /// smaller. Short reads are combined until a chunk is full, so the updates do not depend on
+    /// how the reader returns the data. At the end of the reader the sink is closed, and the
+    /// response of the server is returned.
+    pub async fn upload<M, R>(
+        &mut self,
+        msg: M,
+        reader: R,
+        chunk_size: usize,
+    ) -> result::Result<M::Response, UploadError<C>>
+    where
+        M: Msg<S, Pattern = ClientStreaming> + Into<S::Req>,
+        M::Update: From<Bytes>,
+        R: AsyncRead,
+    {
+        let chunk_size = chunk_size.max(1);
+        let (mut send, recv) = self.client_streaming(msg).await?;
+        tokio::pin!(reader);
+        loop {
+            let mut chunk = vec![0u8; chunk_size];
+            let mut filled = 0;
+            while filled < chunk_size {
+                match reader.read(&mut chunk[filled..]).await {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(UploadError::Read(e)),
                 }
-                Err(e) => Err(ClientStreamingItemError::RecvError(e)),
+            }
+            chunk.truncate(filled);
+            if filled > 0 {
+                let update = M::Update::from(Bytes::from(chunk));
+                send.feed(update).await.map_err(UploadError::Send)?;
+            }
+            if filled < chunk_size {
+                break;
             }
         }
-        .boxed();
-        Ok((send, recv))
+        send.close().await.map_err(UploadError::Send)?;
+        Ok(recv.await?)
     }
 
     /// Bidi call to the server, request opens a stream, response is a stream
     pub async fn bidi<M>(
         &mut self,
         msg: M,
+    ) -> result::Result<(UpdateSink<S, C, M>, BidiResponses<S, C, M>), BidiError<C>>
+    where
+        M: Msg<S, Pattern = BidiStreaming> + Into<S::Req>,
+    {
+        let msg = msg.into();
+        let span = CallSpan::for_msg::<S, M>("client", &self.hooks);
+        let (send, recv) = span.start(self.channel.open_bi_with(msg)).await?;
+        span.correlate::<C, S::Res>(&recv);
+        let send = UpdateSink(send, PhantomData);
+        let recv = span
+            .stream(recv.map(|x| {
+                match x {
+                    Ok(x) => UnexpectedResponse::downcast::<M::Response, _>(x)
+                        .map_err(BidiItemError::DowncastError),
+                    Err(e) => Err(BidiItemError::RecvError(e)),
+                }
+            }))
+            .boxed();
+        Ok((send, recv))
+    }
+
+    /// Bidi call to the server, returning the updates and responses as a single [Duplex]
+    pub async fn bidi_duplex<M>(&mut self, msg: M) -> result::Result<Duplex<S, C, M>, BidiError<C>>
+    where
+        M: Msg<S, Pattern = BidiStreaming> + Into<S::Req>,
+    {
+        let (sink, responses) = self.bidi(msg).await?;
+        Ok(Duplex::new(sink, responses))
+    }
+
+    /// Bidi call to the server that can also exchange [ControlFrame]s with the server
+    ///
+    /// Control frames are sent using [UpdateSink::send_control], and arrive in order with the
+    /// responses. The server needs to handle the request with
+    /// [crate::RpcServer::bidi_streaming_with_control].
+    pub async fn bidi_with_control<M>(
+        &mut self,
+        msg: M,
     ) -> result::Result<
         (
             UpdateSink<S, C, M>,
-            BoxStream<'static, result::Result<M::Response, BidiItemError<C>>>,
+            BoxStream<'static, result::Result<Frame<M::Response>, BidiItemError<C>>>,
         ),
         BidiError<C>,
     >
     where
         M: Msg<S, Pattern = BidiStreaming> + Into<S::Req>,
+        S::Res: SplitControl,
     {
         let msg = msg.into();
-        let (mut send, recv) = self.channel.open_bi().await.map_err(BidiError::Open)?;
-        send.send(msg).await.map_err(BidiError::Send)?;
+        let span = CallSpan::for_msg::<S, M>("client", &self.hooks);
+        let (send, recv) = span.start(self.channel.open_bi_with(msg)).await?;
+        span.correlate::<C, S::Res>(&recv);
         let send = UpdateSink(send, PhantomData);
-        let recv = recv
-            .map(|x| match x {
-                Ok(x) => M::Response::try_from(x).map_err(|_| BidiItemError::DowncastError),
-                Err(e) => Err(BidiItemError::RecvError(e)),
-            })
+        let recv = span
+            .stream(recv.map(|x| {
+                match x.map_err(BidiItemError::RecvError)?.split_control() {
+                    Ok(frame) => Ok(Frame::Control(frame)),
+                    Err(x) => UnexpectedResponse::downcast::<M::Response, _>(x)
+                        .map(Frame::Data)
+                        .map_err(BidiItemError::DowncastError),
+                }
+            }))
             .boxed();
         Ok((send, recv))
     }
+
+    /// Call to the server using the interaction pattern of the message
+    ///
+    /// Depending on `M::Pattern`, this is the same as [RpcClient::rpc],
+    /// [RpcClient::server_streaming], [RpcClient::client_streaming] or [RpcClient::bidi], and
+    /// returns the same result. See [CallPattern].
+    pub async fn call<M>(&mut self, msg: M) -> <M::Pattern as CallPattern<S, C, M>>::Output
+    where
+        M: Msg<S>,
+        M::Pattern: CallPattern<S, C, M>,
+    {
+        <M::Pattern as CallPattern<S, C, M>>::call(self, msg).await
+    }
 }
 
-/// Client error. All client DSL methods return a `Result` with this error type.
-#[derive(Debug)]
-pub enum RpcClientError<C: ChannelTypes> {
-    /// Unable to open a stream to the server
-    Open(C::OpenBiError),
-    /// Unable to send the request to the server
-    Send(C::SendError),
-    /// Server closed the stream before sending a response
-    EarlyClose,
-    /// Unable to receive the response from the server
-    RecvError(C::RecvError),
-    /// Unexpected response from the server
-    DowncastError,
+/// Client side of an [InteractionPattern], see [RpcClient::call]
+///
+/// This is implemented for all patterns that can be used with [Msg].
+pub trait CallPattern<S: Service, C: ChannelTypes, M: Msg<S>>: InteractionPattern {
+    /// Result of a call with this pattern
+    type Output;
+
+    /// Call to the server with this pattern
+    fn call(client: &mut RpcClient<S, C>, msg: M) -> BoxFuture<'_, Self::Output>;
 }
 
-impl<C: ChannelTypes> fmt::Display for RpcClientError<C> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<S: Service, C: ChannelTypes, M: Msg<S, Pattern = Rpc>> CallPattern<S, C, M> for Rpc {
+    type Output = result::Result<M::Response, RpcClientError<C>>;
+
+    fn call(client: &mut RpcClient<S, C>, msg: M) -> BoxFuture<'_, Self::Output> {
+        client.rpc(msg).boxed()
     }
 }
 
-impl<C: ChannelTypes> error::Error for RpcClientError<C> {}
+impl<S: Service, C: ChannelTypes, M: Msg<S, Pattern = ServerStreaming>> CallPattern<S, C, M>
+    for ServerStreaming
+{
+    type Output = result::Result<
+        BoxStream<'static, result::Result<M::Response, StreamingResponseItemError<C>>>,
+        StreamingResponseError<C>,
+    >;
+
+    fn call(client: &mut RpcClient<S, C>, msg: M) -> BoxFuture<'_, Self::Output> {
+        client.server_streaming(msg).boxed()
+    }
+}
+
+impl<S: Service, C: ChannelTypes, M: Msg<S, Pattern = ClientStreaming>> CallPattern<S, C, M>
+    for ClientStreaming
+{
+    type Output = result::Result<
+        (
+            UpdateSink<S, C, M>,
+            BoxFuture<'static, result::Result<M::Response, ClientStreamingItemError<C>>>,
+        ),
+        ClientStreamingError<C>,
+    >;
+
+    fn call(client: &mut RpcClient<S, C>, msg: M) -> BoxFuture<'_, Self::Output> {
+        client.client_streaming(msg).boxed()
+    }
+}
+
+impl<S: Service, C: ChannelTypes, M: Msg<S, Pattern = BidiStreaming>> CallPattern<S, C, M>
+    for BidiStreaming
+{
+    type Output = result::Result<
+        (
+            UpdateSink<S, C, M>,
+            BoxStream<'static, result::Result<M::Response, BidiItemError<C>>>,
+        ),
+        BidiError<C>,
+    >;
+
+    fn call(client: &mut RpcClient<S, C>, msg: M) -> BoxFuture<'_, Self::Output> {
+        client.bidi(msg).boxed()
+    }
+}
+
+/// A client that numbers its rpc calls, so the server can process them in order
+///
+/// The server needs to handle the requests with [crate::RpcServer::rpc_ordered]. Use a single
+/// ordered client per connection and clone it to share it, since the server expects a single
+/// sequence. A call that is dropped before its request was sent leaves a gap in the sequence,
+/// which the server skips after the gap timeout of its [crate::server::OrderedQueue].
+pub struct OrderedClient<S: Service, C: ChannelTypes> {
+    client: RpcClient<S, C>,
+    next_seq: Arc<AtomicU64>,
+}
+
+impl<S: Service, C: ChannelTypes> fmt::Debug for OrderedClient<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OrderedClient")
+            .field("next_seq", &self.next_seq)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: Service, C: ChannelTypes> Clone for OrderedClient<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            next_seq: self.next_seq.clone(),
+        }
+    }
+}
+
+impl<S: Service, C: ChannelTypes> OrderedClient<S, C> {
+    /// Create an ordered client from a client, starting the sequence at 0
+    pub fn new(client: RpcClient<S, C>) -> Self {
+        Self {
+            client,
+            next_seq: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// RPC call that the server processes after all previously issued ordered calls
+    ///
+    /// The sequence number is assigned once the stream to the server is ready to send the
+    /// request, so the order is the order in which the requests got sent.
+    pub async fn rpc<M, R>(&self, msg: M) -> result::Result<R, RpcClientError<C>>
+    where
+        M: Send + 'static,
+        Sequenced<M>: Msg<S, Pattern = Rpc, Response = R>,
+        R: TryFrom<S::Res>,
+    {
+        let (mut send, recv) = self
+            .client
+            .channel
+            .open_bi()
+            .await
+            .map_err(RpcClientError::Open)?;
+        tokio::pin!(recv);
+        // no await between assigning the number and queueing the request, so dropping the call
+        // only leaves a gap if the request was never flushed
+        future::poll_fn(|cx| send.poll_ready_unpin(cx))
+            .await
+            .map_err(RpcClientError::Send)?;
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst);
+        send.start_send_unpin(Sequenced { seq, msg }.into())
+            .map_err(RpcClientError::Send)?;
+        send.flush().await.map_err(RpcClientError::Send)?;
+        let res = recv
+            .next()
+            .await
+            .ok_or(RpcClientError::EarlyClose)?
+            .map_err(RpcClientError::RecvError)?;
+        // keep send alive until we have the answer
+        drop(send);
+        UnexpectedResponse::downcast::<R, _>(res).map_err(RpcClientError::DowncastError)
+    }
+}
+
+/// Item of a stream returned by [RpcClient::server_streaming_resumable]
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResumableItem<T> {
+    /// A response item
+    Item(T),
+    /// The stream was re-opened after a failure, so items may have been missed or repeated
+    Reconnected,
+}
+
+/// State of a stream returned by [RpcClient::server_streaming_resumable]
+#[cfg(feature = "tokio")]
+struct ResumeState<S: Service, C: ChannelTypes, M: Msg<S>, F> {
+    client: RpcClient<S, C>,
+    /// The original request
+    msg: M,
+    /// The request to resume after the last received item
+    next: Option<M>,
+    resume: F,
+    policy: RetryPolicy,
+    /// Number of streams opened since the last received item
+    attempts: u32,
+    stream: Option<BoxStream<'static, result::Result<M::Response, StreamingResponseItemError<C>>>>,
+}
+
+#[cfg(feature = "tokio")]
+impl<S, C, M, F> ResumeState<S, C, M, F>
+where
+    S: Service,
+    C: ChannelTypes,
+    M: Msg<S, Pattern = ServerStreaming> + Into<S::Req> + Clone,
+    F: FnMut(&M, &M::Response) -> M + Send + 'static,
+{
+    async fn next_item(
+        mut self,
+    ) -> Option<(
+        result::Result<ResumableItem<M::Response>, StreamingResponseItemError<C>>,
+        Self,
+    )> {
+        let item = match self.stream.as_mut()?.next().await {
+            Some(Ok(item)) => {
+                self.next = Some((self.resume)(&self.msg, &item));
+                self.attempts = 1;
+                Ok(ResumableItem::Item(item))
+            }
+            Some(Err(cause)) => {
+                self.stream = None;
+                if cause.is_retryable() || cause.is_given_up() {
+                    self.stream = self.reopen().await;
+                }
+                match self.stream {
+                    Some(_) => Ok(ResumableItem::Reconnected),
+                    None => Err(cause),
+                }
+            }
+            None => return None,
+        };
+        Some((item, self))
+    }
+
+    /// Open a new stream with the resume request, according to the retry policy
+    async fn reopen(
+        &mut self,
+    ) -> Option<BoxStream<'static, result::Result<M::Response, StreamingResponseItemError<C>>>>
+    {
+        while self.attempts < self.policy.max_attempts {
+            tokio::time::sleep(self.policy.delay(self.attempts)).await;
+            self.attempts += 1;
+            let msg = self.next.clone().unwrap_or_else(|| self.msg.clone());
+            match self.client.server_streaming(msg).await {
+                Ok(stream) => return Some(stream),
+                Err(cause) if cause.is_retryable() => continue,
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+}
+
+/// How often and after which delays [RetryingClient] retries a failed call
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every further retry
+    pub base_delay: Duration,
+    /// Maximum random delay added to every retry, so clients don't retry in lockstep
+    pub jitter: Duration,
+}
+
+#[cfg(feature = "tokio")]
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            jitter: Duration::from_millis(100),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl RetryPolicy {
+    /// Delay before the given retry, starting at 1 for the first retry
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(1u32.checked_shl(retry - 1).unwrap_or(u32::MAX));
+        backoff + self.jitter.mul_f64(random_fraction())
+    }
+}
+
+/// A random number in `[0, 1)`, good enough for jitter
+#[cfg(feature = "tokio")]
+fn random_fraction() -> f64 {
+    use std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+    };
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+/// A client that retries idempotent rpc calls that failed due to transient errors
+///
+/// Every attempt opens a new stream. Only failures to open the stream or to send the request
+/// that the channel considers [Retryable], and streams closed before the response arrived are
+/// retried. All other errors, in particular unexpected responses, are returned right away.
+#[cfg(feature = "tokio")]
+pub struct RetryingClient<S: Service, C: ChannelTypes> {
+    client: RpcClient<S, C>,
+    policy: RetryPolicy,
+}
+
+#[cfg(feature = "tokio")]
+impl<S: Service, C: ChannelTypes> fmt::Debug for RetryingClient<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryingClient")
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: Service, C: ChannelTypes> Clone for RetryingClient<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            policy: self.policy,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: Service, C: ChannelTypes> RetryingClient<S, C> {
+    /// Create a retrying client from a client and a retry policy
+    pub fn new(client: RpcClient<S, C>, policy: RetryPolicy) -> Self {
+        Self { client, policy }
+    }
+
+    /// RPC call that is retried according to the [RetryPolicy]
+    ///
+    /// Returns the error of the last attempt if all attempts failed.
+    pub async fn rpc<M>(&self, msg: M) -> result::Result<M::Response, RpcClientError<C>>
+    where
+        M: Msg<S, Pattern = Rpc> + Idempotent,
+    {
+        let mut attempt = 1;
+        loop {
+            let err = match self.client.rpc_inner(msg.clone(), None).await {
+                Ok(res) => return Ok(res),
+                Err(err) => err,
+            };
+            let transient = match &err {
+                RpcClientError::Open(e) => e.is_retryable(),
+                RpcClientError::Send(e) => e.is_retryable(),
+                RpcClientError::EarlyClose => true,
+                _ => false,
+            };
+            if !transient || attempt >= self.policy.max_attempts {
+                return Err(err);
+            }
+            tokio::time::sleep(self.policy.delay(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// A client that applies a default timeout to all calls, see [RpcClient::with_default_timeout]
+///
+/// Rpc calls fail with [RpcClientError::Timeout] if they do not complete within the timeout.
+/// Streaming calls fail with the `Timeout` variant of their error if the stream is not opened
+/// within the timeout, and their responses can be limited with
+/// [TimeoutClient::with_item_timeout]. A [Deadline] that expires earlier takes precedence. To
+/// use another timeout for a single call, use [TimeoutClient::rpc_with_timeout] or call the
+/// client returned by [TimeoutClient::inner] directly.
+#[cfg(feature = "tokio")]
+pub struct TimeoutClient<S: Service, C: ChannelTypes> {
+    client: RpcClient<S, C>,
+    timeout: Duration,
+    item_timeout: Option<Duration>,
+}
+
+#[cfg(feature = "tokio")]
+impl<S: Service, C: ChannelTypes> fmt::Debug for TimeoutClient<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeoutClient")
+            .field("timeout", &self.timeout)
+            .field("item_timeout", &self.item_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: Service, C: ChannelTypes> Clone for TimeoutClient<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            timeout: self.timeout,
+            item_timeout: self.item_timeout,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: Service, C: ChannelTypes> RpcClient<S, C> {
+    /// Apply `timeout` to all calls made using the returned client, see [TimeoutClient]
+    pub fn with_default_timeout(self, timeout: Duration) -> TimeoutClient<S, C> {
+        TimeoutClient {
+            client: self,
+            timeout,
+            item_timeout: None,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: Service, C: ChannelTypes> TimeoutClient<S, C> {
+    /// Also fail response streams if the next response does not arrive within `timeout`
+    ///
+    /// This applies to server streaming and bidi calls. The stream then yields a `Timeout` error
+    /// and ends. By default, only opening the stream is limited.
+    pub fn with_item_timeout(mut self, timeout: Duration) -> Self {
+        self.item_timeout = Some(timeout);
+        self
+    }
+
+    /// The client without a default timeout
+    pub fn inner(&self) -> &RpcClient<S, C> {
+        &self.client
+    }
+
+    /// RPC call that fails with [RpcClientError::Timeout] if it does not complete within the
+    /// default timeout, see [RpcClient::rpc_with_timeout]
+    pub async fn rpc<M>(&self, msg: M) -> result::Result<M::Response, RpcClientError<C>>
+    where
+        M: Msg<S, Pattern = Rpc> + Into<S::Req>,
+    {
+        self.client.rpc_with_timeout(msg, self.timeout).await
+    }
+
+    /// RPC call with another timeout than the default, see [RpcClient::rpc_with_timeout]
+    pub async fn rpc_with_timeout<M>(
+        &self,
+        msg: M,
+        timeout: Duration,
+    ) -> result::Result<M::Response, RpcClientError<C>>
+    where
+        M: Msg<S, Pattern = Rpc> + Into<S::Req>,
+    {
+        self.client.rpc_with_timeout(msg, timeout).await
+    }
+
+    /// Server streaming call, see [RpcClient::server_streaming]
+    pub async fn server_streaming<M>(
+        &mut self,
+        msg: M,
+    ) -> result::Result<ServerStreamingResponses<S, C, M>, StreamingResponseError<C>>
+    where
+        M: Msg<S, Pattern = ServerStreaming> + Into<S::Req>,
+    {
+        let deadline = self.deadline();
+        let responses = deadline
+            .within(self.client.server_streaming(msg))
+            .await
+            .ok_or(StreamingResponseError::Timeout)??;
+        Ok(match self.item_timeout {
+            Some(timeout) => items_within(responses, timeout, || {
+                Err(StreamingResponseItemError::Timeout)
+            }),
+            None => responses,
+        })
+    }
+
+    /// Client streaming call, see [RpcClient::client_streaming]
+    ///
+    /// The timeout only applies to opening the stream, since the updates are up to the caller.
+    #[allow(clippy::type_complexity)]
+    pub async fn client_streaming<M>(
+        &mut self,
+        msg: M,
+    ) -> result::Result<
+        (UpdateSink<S, C, M>, ClientStreamingResponse<S, C, M>),
+        ClientStreamingError<C>,
+    >
+    where
+        M: Msg<S, Pattern = ClientStreaming> + Into<S::Req>,
+    {
+        let deadline = self.deadline();
+        deadline
+            .within(self.client.client_streaming(msg))
+            .await
+            .ok_or(ClientStreamingError::Timeout)?
+    }
+
+    /// Bidi call, see [RpcClient::bidi]
+    pub async fn bidi<M>(
+        &mut self,
+        msg: M,
+    ) -> result::Result<(UpdateSink<S, C, M>, BidiResponses<S, C, M>), BidiError<C>>
+    where
+        M: Msg<S, Pattern = BidiStreaming> + Into<S::Req>,
+    {
+        let deadline = self.deadline();
+        let (send, responses) = deadline
+            .within(self.client.bidi(msg))
+            .await
+            .ok_or(BidiError::Timeout)??;
+        let responses = match self.item_timeout {
+            Some(timeout) => items_within(responses, timeout, || Err(BidiItemError::Timeout)),
+            None => responses,
+        };
+        Ok((send, responses))
+    }
+
+    fn deadline(&self) -> Deadline {
+        Deadline::after(self.timeout).min(Deadline::current())
+    }
+}
+
+/// Pass on the items of `stream`, yielding `on_timeout` and ending if the next item does not
+/// arrive within `timeout`
+#[cfg(feature = "tokio")]
+fn items_within<T: Send + 'static>(
+    stream: BoxStream<'static, T>,
+    timeout: Duration,
+    on_timeout: fn() -> T,
+) -> BoxStream<'static, T> {
+    futures::stream::unfold(Some(stream), move |stream| async move {
+        let mut stream = stream?;
+        match tokio::time::timeout(timeout, stream.next()).await {
+            Ok(Some(item)) => Some((item, Some(stream))),
+            Ok(None) => None,
+            Err(_) => Some((on_timeout(), None)),
+        }
+    })
+    .boxed()
+}
+
+/// Close the update sink of a bidi call and collect all remaining responses
+///
+/// The sink is closed and then dropped before reading the responses, so the server sees the end
+/// of the updates on all channel types. Collecting stops at the first error.
+pub async fn bidi_drain<S, C, M, R>(
+    mut sink: UpdateSink<S, C, M>,
+    responses: R,
+) -> result::Result<Vec<M::Response>, BidiItemError<C>>
+where
+    S: Service,
+    C: ChannelTypes,
+    M: Msg<S, Pattern = BidiStreaming>,
+    R: Stream<Item = result::Result<M::Response, BidiItemError<C>>>,
+{
+    // the server may already have stopped reading updates, in which case closing fails.
+    // Any real problem with the connection will show up in the responses.
+    sink.close().await.ok();
+    drop(sink);
+    responses.try_collect().await
+}
+
+#[cfg(feature = "tokio")]
+tokio::task_local! {
+    static DEADLINE: Deadline;
+}
+
+/// A point in time by which a chain of rpc calls has to complete
+///
+/// A deadline is established for a future using [Deadline::scope], and applies to all
+/// [RpcClient::rpc] calls made while polling it, including calls made by nested handlers, e.g.
+/// in a gateway that calls downstream services while handling a request. Since the deadline is
+/// stored in a tokio task local, it does not carry over to spawned tasks, which need their own
+/// scope, e.g. using [Deadline::current].
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline(tokio::time::Instant);
+
+#[cfg(feature = "tokio")]
+impl Deadline {
+    /// A deadline `duration` from now
+    ///
+    /// A duration too long for the clock, e.g. [Duration::MAX], gives a deadline that never
+    /// expires in practice, as if there was no deadline.
+    pub fn after(duration: Duration) -> Self {
+        // same as tokio does for sleeps that overflow the clock
+        const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+        let now = tokio::time::Instant::now();
+        Self(
+            now.checked_add(duration)
+                .unwrap_or_else(|| now + FAR_FUTURE),
+        )
+    }
+
+    /// The deadline of the current scope, if any
+    pub fn current() -> Option<Self> {
+        DEADLINE.try_with(|deadline| *deadline).ok()
+    }
+
+    /// Run `fut` with a deadline `duration` from now
+    ///
+    /// Within an existing scope, the earlier of the two deadlines applies, so a nested call can
+    /// only shorten the remaining budget.
+    pub async fn scope<F: Future>(duration: Duration, fut: F) -> F::Output {
+        Self::after(duration)
+            .min(Self::current())
+            .scope_at(fut)
+            .await
+    }
+
+    /// Run `fut` with this deadline
+    ///
+    /// Unlike [Deadline::scope], this replaces the deadline of an enclosing scope.
+    pub async fn scope_at<F: Future>(self, fut: F) -> F::Output {
+        DEADLINE.scope(self, fut).await
+    }
+
+    /// The instant of the deadline
+    pub fn instant(&self) -> tokio::time::Instant {
+        self.0
+    }
+
+    /// Time left until the deadline, zero if it has passed
+    pub fn remaining(&self) -> Duration {
+        self.0
+            .saturating_duration_since(tokio::time::Instant::now())
+    }
+
+    fn min(self, other: Option<Self>) -> Self {
+        other.map_or(self, |other| Ord::min(self, other))
+    }
+
+    /// Run `fut`, returning `None` if the deadline passes first
+    async fn within<F: Future>(self, fut: F) -> Option<F::Output> {
+        tokio::time::timeout_at(self.0, fut).await.ok()
+    }
+
+    /// Run a call, failing with [RpcClientError::Timeout] once the deadline has passed
+    async fn run<T, C: ChannelTypes>(
+        self,
+        fut: impl Future<Output = result::Result<T, RpcClientError<C>>>,
+    ) -> result::Result<T, RpcClientError<C>> {
+        if self.remaining().is_zero() {
+            return Err(RpcClientError::Timeout);
+        }
+        tokio::time::timeout_at(self.0, fut)
+            .await
+            .map_err(|_| RpcClientError::Timeout)?
+    }
+}
+
+/// Run an rpc call, failing with [RpcClientError::Timeout] once the current deadline expires
+///
+/// Deadlines need the `tokio` feature, without it the call is just awaited.
+async fn within_current_deadline<T, C: ChannelTypes>(
+    fut: impl Future<Output = result::Result<T, RpcClientError<C>>>,
+) -> result::Result<T, RpcClientError<C>> {
+    #[cfg(feature = "tokio")]
+    if let Some(deadline) = Deadline::current() {
+        return deadline.run(fut).await;
+    }
+    fut.await
+}
+
+/// Turn the stream of a server streaming call into the stream of its responses
+fn streaming_responses<S, C, M>(
+    span: &CallSpan,
+    send: C::SendSink<S::Req>,
+    recv: C::RecvStream<S::Res>,
+) -> BoxStream<'static, result::Result<M::Response, StreamingResponseItemError<C>>>
+where
+    S: Service,
+    C: ChannelTypes,
+    M: Msg<S, Pattern = ServerStreaming>,
+{
+    let recv = span.stream(recv.map(move |x| {
+        match x {
+            Ok(x) => UnexpectedResponse::downcast::<M::Response, _>(x)
+                .map_err(StreamingResponseItemError::DowncastError),
+            Err(e) => Err(StreamingResponseItemError::RecvError(e)),
+        }
+    }));
+    // keep send alive so the request on the server side does not get cancelled
+    DeferDrop(recv, send).boxed()
+}
+
+/// Extension trait to map the errors of response streams to an application error type
+///
+/// This is implemented for the response streams returned by [RpcClient::server_streaming] and
+/// [RpcClient::bidi], so transport errors such as [StreamingResponseItemError] don't have to
+/// leak into business logic. The mapped stream owns the original stream, so the request stays
+/// alive until the mapped stream is dropped.
+pub trait ResponseStreamExt<T, E>: Stream<Item = result::Result<T, E>> + Sized {
+    /// Map every error of the stream to an application error using `f`
+    fn into_app_errors<A, F>(self, f: F) -> futures::stream::MapErr<Self, F>
+    where
+        F: FnMut(E) -> A;
+}
+
+impl<T, E, St> ResponseStreamExt<T, E> for St
+where
+    St: Stream<Item = result::Result<T, E>>,
+{
+    fn into_app_errors<A, F>(self, f: F) -> futures::stream::MapErr<Self, F>
+    where
+        F: FnMut(E) -> A,
+    {
+        self.map_err(f)
+    }
+}
+
+/// Response stream that also supports non-blocking reads, for event loop style clients
+///
+/// Wraps the stream returned by e.g. [RpcClient::server_streaming]. That stream keeps the send
+/// side of the request alive until it is dropped, so the request on the server side is not
+/// cancelled. Since the wrapper owns the stream, this still holds: the request stays alive until
+/// the `PollStream` is dropped, no matter how the items are read.
+pub struct PollStream<T> {
+    inner: BoxStream<'static, T>,
+    done: bool,
+}
+
+impl<T> fmt::Debug for PollStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PollStream")
+            .field("done", &self.done)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> PollStream<T> {
+    /// Wrap a response stream
+    pub fn new(inner: BoxStream<'static, T>) -> Self {
+        Self { inner, done: false }
+    }
+
+    /// Get the next item if it is available right away, without waiting
+    ///
+    /// Unlike awaiting the next item, this does not register the current task to be woken up
+    /// when an item becomes available.
+    pub fn try_recv(&mut self) -> result::Result<T, TryRecvError> {
+        if self.done {
+            return Err(TryRecvError::Closed);
+        }
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        match self.inner.poll_next_unpin(&mut cx) {
+            Poll::Ready(Some(item)) => Ok(item),
+            Poll::Ready(None) => {
+                self.done = true;
+                Err(TryRecvError::Closed)
+            }
+            Poll::Pending => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Stream for PollStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let res = self.inner.poll_next_unpin(cx);
+        if let Poll::Ready(None) = res {
+            self.done = true;
+        }
+        res
+    }
+}
+
+/// Error for [PollStream::try_recv]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum TryRecvError {
+    /// No item is available right now
+    #[error("no item is available right now")]
+    Empty,
+    /// The stream has ended
+    #[error("the stream has ended")]
+    Closed,
+}
+
+/// Handle to pause, resume or cancel a server streaming response
+///
+/// See [RpcClient::server_streaming_controlled].
+pub struct StreamController<S: Service, C: ChannelTypes>(
+    Arc<Mutex<C::SendSink<S::Req>>>,
+    PhantomData<S>,
+);
+
+impl<S: Service, C: ChannelTypes> fmt::Debug for StreamController<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("StreamController").finish()
+    }
+}
+
+impl<S: Service, C: ChannelTypes> StreamController<S, C>
+where
+    StreamControl: Into<S::Req>,
+{
+    /// Ask the server to stop sending responses
+    pub async fn pause(&self) -> result::Result<(), C::SendError> {
+        self.send(StreamControl::Pause).await
+    }
+
+    /// Ask the server to continue sending responses
+    pub async fn resume(&self) -> result::Result<(), C::SendError> {
+        self.send(StreamControl::Resume).await
+    }
+
+    async fn send(&self, msg: StreamControl) -> result::Result<(), C::SendError> {
+        self.0.lock().await.send(msg.into()).await
+    }
+}
+
+impl<S: Service, C: ChannelTypes> StreamController<S, C>
+where
+    Cancel: Into<S::Req>,
+{
+    /// Ask the server to stop the response stream and abort the handler, see [Cancel]
+    pub async fn cancel(&self) -> result::Result<(), C::SendError> {
+        self.0.lock().await.send(Cancel.into()).await
+    }
+}
+
+/// Details about a response that did not have the type expected for the request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnexpectedResponse {
+    /// Type name of the expected response
+    pub expected: &'static str,
+    /// Variant of the response enum that arrived instead, see [variant_name]
+    pub received: Option<&'static str>,
+}
+
+impl UnexpectedResponse {
+    pub(crate) fn new<T>(received: Option<&'static str>) -> Self {
+        Self {
+            expected: std::any::type_name::<T>(),
+            received,
+        }
+    }
+
+    /// Convert a response to the expected type, remembering its variant if it does not match
+    pub(crate) fn downcast<T: TryFrom<R>, R: Serialize>(res: R) -> result::Result<T, Self> {
+        // converting consumes the response, so get the variant up front
+        let received = variant_name(&res);
+        T::try_from(res).map_err(|_| Self::new::<T>(received))
+    }
+}
+
+impl fmt::Display for UnexpectedResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.received {
+            Some(received) => write!(
+                f,
+                "unexpected response {received}, expected {}",
+                self.expected
+            ),
+            None => write!(f, "unexpected response, expected {}", self.expected),
+        }
+    }
+}
+
+impl error::Error for UnexpectedResponse {}
+
+/// Client error. All client DSL methods return a `Result` with this error type.
+#[derive(Debug, thiserror::Error)]
+pub enum RpcClientError<C: ChannelTypes> {
+    /// Unable to open a stream to the server
+    #[error("failed to open a stream")]
+    Open(#[source] C::OpenBiError),
+    /// Unable to send the request to the server
+    #[error("failed to send the request")]
+    Send(#[source] C::SendError),
+    /// Server closed the stream before sending a response
+    #[error("the server closed the stream before sending a response")]
+    EarlyClose,
+    /// Unable to receive the response from the server
+    #[error("failed to receive the response")]
+    RecvError(#[source] C::RecvError),
+    /// Unexpected response from the server
+    #[error(transparent)]
+    DowncastError(UnexpectedResponse),
+    /// The call did not complete in time, see [RpcClient::rpc_with_timeout] and [Deadline]
+    #[error("the call did not complete in time")]
+    Timeout,
+    /// The server sent more than one response, see [RpcClient::with_strict_rpc]
+    #[error("the server sent more than one response to an rpc call")]
+    UnexpectedExtraResponse,
+}
+
+impl<C: ChannelTypes> From<OpenBiWithError<C>> for RpcClientError<C> {
+    fn from(e: OpenBiWithError<C>) -> Self {
+        match e {
+            OpenBiWithError::Open(e) => Self::Open(e),
+            OpenBiWithError::Send(e) => Self::Send(e),
+        }
+    }
+}
+
+impl<C: ChannelTypes> RpcClientError<C> {
+    /// True if retrying the call might succeed
+    ///
+    /// Errors from the underlying channel are classified by the channel type, see [Retryable].
+    /// An unexpected response is never retryable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Open(e) => e.is_retryable(),
+            Self::Send(e) => e.is_retryable(),
+            Self::EarlyClose => true,
+            Self::RecvError(e) => e.is_retryable(),
+            Self::DowncastError(_) | Self::UnexpectedExtraResponse => false,
+            Self::Timeout => true,
+        }
+    }
+
+    /// Convert the error into an io error, independent of the channel type
+    ///
+    /// Errors from the underlying channel are converted by the channel type, see
+    /// [ChannelError].
+    pub fn into_io(self) -> io::Error {
+        match self {
+            Self::Open(e) => e.into_io(),
+            Self::Send(e) => e.into_io(),
+            Self::EarlyClose => io::Error::new(io::ErrorKind::UnexpectedEof, self),
+            Self::RecvError(e) => e.into_io(),
+            Self::DowncastError(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+            Self::Timeout => io::Error::new(io::ErrorKind::TimedOut, self),
+            Self::UnexpectedExtraResponse => io::Error::new(io::ErrorKind::InvalidData, self),
+        }
+    }
+}
+
+impl<C: ChannelTypes> From<RpcClientError<C>> for io::Error {
+    fn from(e: RpcClientError<C>) -> Self {
+        e.into_io()
+    }
+}
 
 /// Server error when accepting a bidi request
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum BidiError<C: ChannelTypes> {
     /// Unable to open a stream to the server
-    Open(C::OpenBiError),
+    #[error("failed to open a stream")]
+    Open(#[source] C::OpenBiError),
     /// Unable to send the request to the server
-    Send(C::SendError),
+    #[error("failed to send the request")]
+    Send(#[source] C::SendError),
+    /// The stream was not opened in time, see [TimeoutClient]
+    #[error("the stream was not opened in time")]
+    Timeout,
 }
 
-impl<C: ChannelTypes> fmt::Display for BidiError<C> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<C: ChannelTypes> From<OpenBiWithError<C>> for BidiError<C> {
+    fn from(e: OpenBiWithError<C>) -> Self {
+        match e {
+            OpenBiWithError::Open(e) => Self::Open(e),
+            OpenBiWithError::Send(e) => Self::Send(e),
+        }
     }
 }
 
-impl<C: ChannelTypes> error::Error for BidiError<C> {}
+impl<C: ChannelTypes> BidiError<C> {
+    /// True if retrying the call might succeed
+    ///
+    /// Errors from the underlying channel are classified by the channel type, see [Retryable].
+    /// An unexpected response is never retryable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Open(e) => e.is_retryable(),
+            Self::Send(e) => e.is_retryable(),
+            Self::Timeout => true,
+        }
+    }
+}
 
 /// Server error when receiving an item for a bidi request
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum BidiItemError<C: ChannelTypes> {
     /// Unable to receive the response from the server
-    RecvError(C::RecvError),
+    #[error("failed to receive a response")]
+    RecvError(#[source] C::RecvError),
     /// Unexpected response from the server
-    DowncastError,
+    #[error(transparent)]
+    DowncastError(UnexpectedResponse),
+    /// No response arrived within the item timeout, see [TimeoutClient::with_item_timeout]
+    #[error("no response arrived in time")]
+    Timeout,
 }
 
-impl<C: ChannelTypes> fmt::Display for BidiItemError<C> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<C: ChannelTypes> BidiItemError<C> {
+    /// True if retrying the call might succeed
+    ///
+    /// Errors from the underlying channel are classified by the channel type, see [Retryable].
+    /// An unexpected response is never retryable, a timeout always is.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::RecvError(e) => e.is_retryable(),
+            Self::DowncastError(_) => false,
+            Self::Timeout => true,
+        }
     }
 }
 
-impl<C: ChannelTypes> error::Error for BidiItemError<C> {}
+/// Client error for notifications
+#[derive(Debug, thiserror::Error)]
+pub enum NotifyError<C: ChannelTypes> {
+    /// Unable to open a stream to the server
+    #[error("failed to open a stream")]
+    Open(#[source] C::OpenBiError),
+    /// Unable to send the notification to the server
+    #[error("failed to send the notification")]
+    Send(#[source] C::SendError),
+}
+
+/// Client error for uploads, see [RpcClient::upload]
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError<C: ChannelTypes> {
+    /// Unable to open a stream to the server
+    #[error("failed to open a stream")]
+    Open(#[source] C::OpenBiError),
+    /// Unable to send the request or an update to the server
+    #[error("failed to send the upload")]
+    Send(#[source] C::SendError),
+    /// Unable to read the data to upload
+    #[error("failed to read the data to upload")]
+    Read(#[source] io::Error),
+    /// Server closed the stream before sending a response
+    #[error("the server closed the stream before sending a response")]
+    EarlyClose,
+    /// Unable to receive the response from the server
+    #[error("failed to receive the response")]
+    RecvError(#[source] C::RecvError),
+    /// Unexpected response from the server
+    #[error(transparent)]
+    DowncastError(UnexpectedResponse),
+    /// The stream was not opened in time, see [ClientStreamingError::Timeout]
+    #[error("the stream was not opened in time")]
+    Timeout,
+}
+
+impl<C: ChannelTypes> From<ClientStreamingError<C>> for UploadError<C> {
+    fn from(e: ClientStreamingError<C>) -> Self {
+        match e {
+            ClientStreamingError::Open(e) => Self::Open(e),
+            ClientStreamingError::Send(e) => Self::Send(e),
+            ClientStreamingError::Timeout => Self::Timeout,
+        }
+    }
+}
+
+impl<C: ChannelTypes> From<ClientStreamingItemError<C>> for UploadError<C> {
+    fn from(e: ClientStreamingItemError<C>) -> Self {
+        match e {
+            ClientStreamingItemError::EarlyClose => Self::EarlyClose,
+            ClientStreamingItemError::RecvError(e) => Self::RecvError(e),
+            ClientStreamingItemError::DowncastError(e) => Self::DowncastError(e),
+        }
+    }
+}
+
+/// Client error for pushes from the server, see [RpcClient::accept_push]
+#[derive(Debug, thiserror::Error)]
+pub enum PushError<C: ChannelTypes> {
+    /// Unable to accept a stream from the server
+    #[error("failed to accept a stream")]
+    Accept(#[source] C::AcceptBiError),
+    /// Server closed the stream before sending the message
+    #[error("the server closed the stream before sending a message")]
+    EarlyClose,
+    /// Unable to receive the message from the server
+    #[error("failed to receive the message")]
+    RecvError(#[source] C::RecvError),
+    /// The server pushed a different message
+    #[error(transparent)]
+    DowncastError(UnexpectedResponse),
+}
+
+impl<C: ChannelTypes> From<OpenBiWithError<C>> for NotifyError<C> {
+    fn from(e: OpenBiWithError<C>) -> Self {
+        match e {
+            OpenBiWithError::Open(e) => Self::Open(e),
+            OpenBiWithError::Send(e) => Self::Send(e),
+        }
+    }
+}
+
+impl<C: ChannelTypes> NotifyError<C> {
+    /// True if retrying the notification might succeed
+    ///
+    /// Errors from the underlying channel are classified by the channel type, see [Retryable].
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Open(e) => e.is_retryable(),
+            Self::Send(e) => e.is_retryable(),
+        }
+    }
+}
 
 /// Server error when accepting a client streaming request
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum ClientStreamingError<C: ChannelTypes> {
     /// Unable to open a stream to the server
-    Open(C::OpenBiError),
+    #[error("failed to open a stream")]
+    Open(#[source] C::OpenBiError),
     /// Unable to send the request to the server
-    Send(C::SendError),
+    #[error("failed to send the request")]
+    Send(#[source] C::SendError),
+    /// The stream was not opened in time, see [TimeoutClient]
+    #[error("the stream was not opened in time")]
+    Timeout,
 }
 
-impl<C: ChannelTypes> fmt::Display for ClientStreamingError<C> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<C: ChannelTypes> From<OpenBiWithError<C>> for ClientStreamingError<C> {
+    fn from(e: OpenBiWithError<C>) -> Self {
+        match e {
+            OpenBiWithError::Open(e) => Self::Open(e),
+            OpenBiWithError::Send(e) => Self::Send(e),
+        }
     }
 }
 
-impl<C: ChannelTypes> error::Error for ClientStreamingError<C> {}
+impl<C: ChannelTypes> ClientStreamingError<C> {
+    /// True if retrying the call might succeed
+    ///
+    /// Errors from the underlying channel are classified by the channel type, see [Retryable].
+    /// An unexpected response is never retryable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Open(e) => e.is_retryable(),
+            Self::Send(e) => e.is_retryable(),
+            Self::Timeout => true,
+        }
+    }
+}
 
 /// Server error when receiving an item for a client streaming request
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum ClientStreamingItemError<C: ChannelTypes> {
     /// Connection was closed before receiving the first message
+    #[error("the server closed the stream before sending a response")]
     EarlyClose,
     /// Unable to receive the response from the server
-    RecvError(C::RecvError),
+    #[error("failed to receive the response")]
+    RecvError(#[source] C::RecvError),
     /// Unexpected response from the server
-    DowncastError,
+    #[error(transparent)]
+    DowncastError(UnexpectedResponse),
 }
 
-impl<C: ChannelTypes> fmt::Display for ClientStreamingItemError<C> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<C: ChannelTypes> ClientStreamingItemError<C> {
+    /// True if retrying the call might succeed
+    ///
+    /// Errors from the underlying channel are classified by the channel type, see [Retryable].
+    /// An unexpected response is never retryable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::EarlyClose => true,
+            Self::RecvError(e) => e.is_retryable(),
+            Self::DowncastError(_) => false,
+        }
     }
 }
 
-impl<C: ChannelTypes> error::Error for ClientStreamingItemError<C> {}
-
 /// Server error when accepting a server streaming request
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum StreamingResponseError<C: ChannelTypes> {
     /// Unable to open a stream to the server
-    Open(C::OpenBiError),
+    #[error("failed to open a stream")]
+    Open(#[source] C::OpenBiError),
     /// Unable to send the request to the server
-    Send(C::SendError),
+    #[error("failed to send the request")]
+    Send(#[source] C::SendError),
+    /// The stream was not opened in time, see [TimeoutClient]
+    #[error("the stream was not opened in time")]
+    Timeout,
 }
 
-impl<C: ChannelTypes> fmt::Display for StreamingResponseError<C> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<C: ChannelTypes> From<OpenBiWithError<C>> for StreamingResponseError<C> {
+    fn from(e: OpenBiWithError<C>) -> Self {
+        match e {
+            OpenBiWithError::Open(e) => Self::Open(e),
+            OpenBiWithError::Send(e) => Self::Send(e),
+        }
     }
 }
 
-impl<C: ChannelTypes> error::Error for StreamingResponseError<C> {}
+impl<C: ChannelTypes> StreamingResponseError<C> {
+    /// True if retrying the call might succeed
+    ///
+    /// Errors from the underlying channel are classified by the channel type, see [Retryable].
+    /// An unexpected response is never retryable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Open(e) => e.is_retryable(),
+            Self::Send(e) => e.is_retryable(),
+            Self::Timeout => true,
+        }
+    }
+}
 
 /// Client error when handling responses from a server streaming request
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum StreamingResponseItemError<C: ChannelTypes> {
     /// Unable to receive the response from the server
-    RecvError(C::RecvError),
+    #[error("failed to receive a response")]
+    RecvError(#[source] C::RecvError),
     /// Unexpected response from the server
-    DowncastError,
+    #[error(transparent)]
+    DowncastError(UnexpectedResponse),
+    /// Sending a keepalive failed, so the connection is probably dead, see
+    /// [RpcClient::server_streaming_with_keepalive]
+    #[error("sending a keepalive failed")]
+    KeepaliveTimeout,
+    /// No response arrived within the item timeout, see [TimeoutClient::with_item_timeout]
+    #[error("no response arrived in time")]
+    Timeout,
 }
 
-impl<C: ChannelTypes> fmt::Display for StreamingResponseItemError<C> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<C: ChannelTypes> StreamingResponseItemError<C> {
+    /// True if retrying the call might succeed
+    ///
+    /// Errors from the underlying channel are classified by the channel type, see [Retryable].
+    /// An unexpected response is never retryable, a failed keepalive or a timeout always is.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::RecvError(e) => e.is_retryable(),
+            Self::DowncastError(_) => false,
+            Self::KeepaliveTimeout | Self::Timeout => true,
+        }
+    }
+
+    /// True if the server reset the stream because it gave up on a slow call
+    ///
+    /// This is not retryable in general, since sending the request again starts the same call
+    /// from scratch, but a resumable stream continues after the last received item.
+    #[cfg(feature = "tokio")]
+    fn is_given_up(&self) -> bool {
+        match self {
+            Self::RecvError(e) => matches!(
+                e.server_error_kind(),
+                Some(RpcServerErrorKind::ClientTooSlow | RpcServerErrorKind::MaxDurationExceeded)
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// A response stream that sends a keepalive on `send` if it did not get an item for `interval`
+///
+/// If sending fails, the stream yields `on_failure` and ends.
+#[cfg(feature = "tokio")]
+#[pin_project]
+struct KeepaliveStream<St: Stream, Si, R> {
+    #[pin]
+    inner: St,
+    send: Si,
+    #[pin]
+    sleep: tokio::time::Sleep,
+    interval: Duration,
+    /// True while a keepalive is being flushed
+    sending: bool,
+    failed: bool,
+    on_failure: fn() -> St::Item,
+    _r: PhantomData<fn(R)>,
+}
+
+#[cfg(feature = "tokio")]
+impl<St: Stream, Si, R> KeepaliveStream<St, Si, R> {
+    fn new(inner: St, send: Si, interval: Duration, on_failure: fn() -> St::Item) -> Self {
+        Self {
+            inner,
+            send,
+            sleep: tokio::time::sleep(interval),
+            interval,
+            sending: false,
+            failed: false,
+            on_failure,
+            _r: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<St, Si, R> Stream for KeepaliveStream<St, Si, R>
+where
+    St: Stream,
+    Si: Sink<R> + Unpin,
+    Keepalive: Into<R>,
+{
+    type Item = St::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if *this.failed {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(item) = this.inner.poll_next(cx) {
+            let deadline = tokio::time::Instant::now() + *this.interval;
+            this.sleep.as_mut().reset(deadline);
+            return Poll::Ready(item);
+        }
+        loop {
+            if *this.sending {
+                match this.send.poll_flush_unpin(cx) {
+                    Poll::Ready(Ok(())) => {
+                        *this.sending = false;
+                        let deadline = tokio::time::Instant::now() + *this.interval;
+                        this.sleep.as_mut().reset(deadline);
+                    }
+                    Poll::Ready(Err(_)) => break,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            if this.sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            match this.send.poll_ready_unpin(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(_)) => break,
+                Poll::Pending => return Poll::Pending,
+            }
+            if this.send.start_send_unpin(Keepalive.into()).is_err() {
+                break;
+            }
+            *this.sending = true;
+        }
+        *this.failed = true;
+        Poll::Ready(Some((this.on_failure)()))
+    }
+}
+
+/// Resets a stream with the code for [RpcServerErrorKind::Cancelled] when dropped, unless disarmed
+struct ResetOnDrop<C: ChannelTypes, M: RpcMessage>(Option<C::SendSink<M>>);
+
+impl<C: ChannelTypes, M: RpcMessage> ResetOnDrop<C, M> {
+    /// Drop the stream without resetting it
+    fn disarm(mut self) {
+        self.0.take();
     }
 }
 
-impl<C: ChannelTypes> error::Error for StreamingResponseItemError<C> {}
+impl<C: ChannelTypes, M: RpcMessage> Drop for ResetOnDrop<C, M> {
+    fn drop(&mut self) {
+        if let (Some(send), Some(code)) = (&mut self.0, RpcServerErrorKind::Cancelled.reset_code())
+        {
+            C::reset(send, code);
+        }
+    }
+}
 
 /// Wrap a stream with an additional item that is kept alive until the stream is dropped
 #[pin_project]
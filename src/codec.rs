@@ -0,0 +1,99 @@
+//! Serialization formats for channels that send messages over the network
+//!
+//! A [Codec] turns a single message into bytes and back. Framing, i.e. finding the message
+//! boundaries in a stream of bytes, is up to the channel. Codecs that need state can implement
+//! [ChannelCodec], and types that don't implement serde can use a [MessageCodec] instead.
+use bytes::BytesMut;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt::Debug, io};
+
+/// A serialization format for messages
+///
+/// Codecs are selected using a type parameter, e.g. `QuinnChannelTypes<PostcardCodec>`, so they
+/// don't carry any state. Both sides of a connection need to use the same codec.
+pub trait Codec: Debug + Clone + Copy + Default + Send + Sync + Unpin + 'static {
+    /// Serialize a message
+    fn encode<T: Serialize>(item: &T) -> io::Result<Vec<u8>>;
+
+    /// Deserialize a message
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> io::Result<T>;
+}
+
+/// The [bincode] format, the default for all channels
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+    fn encode<T: Serialize>(item: &T) -> io::Result<Vec<u8>> {
+        bincode::serialize(item).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> io::Result<T> {
+        bincode::deserialize(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// The [postcard] format, which is more compact and suitable for embedded peers
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PostcardCodec;
+
+impl Codec for PostcardCodec {
+    fn encode<T: Serialize>(item: &T) -> io::Result<Vec<u8>> {
+        postcard::to_stdvec(item).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> io::Result<T> {
+        postcard::from_bytes(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// A serialization format for all messages of a channel, which can carry state
+///
+/// Every [Codec] is a channel codec. Implement this directly for formats that need
+/// configuration, e.g. a schema registry or a compression dictionary. Channels that support it
+/// take one instance per direction, see [crate::quinn::Channel::new_with_codec].
+pub trait ChannelCodec: Debug + Clone + Send + Sync + Unpin + 'static {
+    /// Append the encoding of `item` to `buf`
+    fn encode_into<T: Serialize>(&self, item: &T, buf: &mut BytesMut) -> io::Result<()>;
+
+    /// Decode a message from `buf`, which contains exactly one frame
+    fn decode_from<T: DeserializeOwned>(&self, buf: &mut BytesMut) -> io::Result<T>;
+}
+
+impl<K: Codec> ChannelCodec for K {
+    fn encode_into<T: Serialize>(&self, item: &T, buf: &mut BytesMut) -> io::Result<()> {
+        buf.extend_from_slice(&K::encode(item)?);
+        Ok(())
+    }
+
+    fn decode_from<T: DeserializeOwned>(&self, buf: &mut BytesMut) -> io::Result<T> {
+        K::decode(buf)
+    }
+}
+
+/// Encoding of messages of type `T`, for types that don't use serde
+///
+/// Unlike a [Codec], this is implemented for specific message types and can carry state, so it
+/// can be used for hand written wire formats, or to plug in e.g. protobuf. Channels that support
+/// it take one instance per direction, see [crate::quinn::Channel::open_with_codec].
+pub trait MessageCodec<T>: Send + Sync + Unpin + 'static {
+    /// Append the encoding of `item` to `buf`
+    fn encode(&self, item: &T, buf: &mut BytesMut) -> io::Result<()>;
+
+    /// Decode a message from `buf`, which contains exactly one frame
+    fn decode(&self, buf: &mut BytesMut) -> io::Result<T>;
+}
+
+/// A [MessageCodec] for all serde types, using the serialization format `K`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerdeCodec<K: ChannelCodec = BincodeCodec>(pub K);
+
+impl<T: Serialize + DeserializeOwned, K: ChannelCodec> MessageCodec<T> for SerdeCodec<K> {
+    fn encode(&self, item: &T, buf: &mut BytesMut) -> io::Result<()> {
+        self.0.encode_into(item, buf)
+    }
+
+    fn decode(&self, buf: &mut BytesMut) -> io::Result<T> {
+        self.0.decode_from(buf)
+    }
+}
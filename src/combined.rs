@@ -1,13 +1,14 @@
 //! Channel that combines two other channels
-use crate::{ChannelTypes, RpcMessage};
+use crate::{message::PatternKind, ChannelError, ChannelTypes, Retryable, RpcMessage};
+use bytes::Bytes;
 use futures::{
     future::{self, BoxFuture},
     FutureExt, Sink, Stream, TryFutureExt,
 };
 use pin_project::pin_project;
 use std::{
-    fmt,
     fmt::Debug,
+    io,
     marker::PhantomData,
     pin::Pin,
     result,
@@ -108,64 +109,121 @@ impl<A: ChannelTypes, B: ChannelTypes, In: RpcMessage> Stream for RecvStream<A,
 }
 
 /// SendError for combined channels
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum SendError<A: ChannelTypes, B: ChannelTypes> {
     /// A variant
+    #[error(transparent)]
     A(A::SendError),
     /// B variant
+    #[error(transparent)]
     B(B::SendError),
 }
 
-impl<A: ChannelTypes, B: ChannelTypes> fmt::Display for SendError<A, B> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<A: ChannelTypes, B: ChannelTypes> ChannelError for SendError<A, B> {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::A(e) => e.into_io(),
+            Self::B(e) => e.into_io(),
+        }
+    }
+}
+
+impl<A: ChannelTypes, B: ChannelTypes> Retryable for SendError<A, B> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::A(e) => e.is_retryable(),
+            Self::B(e) => e.is_retryable(),
+        }
     }
 }
 
 /// RecvError for combined channels
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum RecvError<A: ChannelTypes, B: ChannelTypes> {
     /// A variant
+    #[error(transparent)]
     A(A::RecvError),
     /// B variant
+    #[error(transparent)]
     B(B::RecvError),
 }
 
-impl<A: ChannelTypes, B: ChannelTypes> fmt::Display for RecvError<A, B> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<A: ChannelTypes, B: ChannelTypes> ChannelError for RecvError<A, B> {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::A(e) => e.into_io(),
+            Self::B(e) => e.into_io(),
+        }
+    }
+
+    fn is_connection_lost(&self) -> bool {
+        match self {
+            Self::A(e) => e.is_connection_lost(),
+            Self::B(e) => e.is_connection_lost(),
+        }
+    }
+}
+
+impl<A: ChannelTypes, B: ChannelTypes> Retryable for RecvError<A, B> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::A(e) => e.is_retryable(),
+            Self::B(e) => e.is_retryable(),
+        }
     }
 }
 
 /// OpenBiError for combined channels
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum OpenBiError<A: ChannelTypes, B: ChannelTypes> {
     /// A variant
+    #[error(transparent)]
     A(A::OpenBiError),
     /// B variant
+    #[error(transparent)]
     B(B::OpenBiError),
     /// None of the two channels is configured
+    #[error("none of the two channels is configured")]
     NoChannel,
 }
 
-impl<A: ChannelTypes, B: ChannelTypes> fmt::Display for OpenBiError<A, B> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<A: ChannelTypes, B: ChannelTypes> ChannelError for OpenBiError<A, B> {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::A(e) => e.into_io(),
+            Self::B(e) => e.into_io(),
+            Self::NoChannel => io::Error::new(io::ErrorKind::NotConnected, "no channel"),
+        }
+    }
+}
+
+impl<A: ChannelTypes, B: ChannelTypes> Retryable for OpenBiError<A, B> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::A(e) => e.is_retryable(),
+            Self::B(e) => e.is_retryable(),
+            Self::NoChannel => false,
+        }
     }
 }
 
 /// AcceptBiError for combined channels
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum AcceptBiError<A: ChannelTypes, B: ChannelTypes> {
     /// A variant
+    #[error(transparent)]
     A(A::AcceptBiError),
     /// B variant
+    #[error(transparent)]
     B(B::AcceptBiError),
 }
 
-impl<A: ChannelTypes, B: ChannelTypes> fmt::Display for AcceptBiError<A, B> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl<A: ChannelTypes, B: ChannelTypes> ChannelError for AcceptBiError<A, B> {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::A(e) => e.into_io(),
+            Self::B(e) => e.into_io(),
+        }
     }
 }
 
@@ -177,7 +235,8 @@ pub type OpenBiFuture<'a, A, B, In, Out> =
 pub type AcceptBiFuture<'a, A, B, In, Out> =
     BoxFuture<'a, result::Result<self::Socket<A, B, In, Out>, self::AcceptBiError<A, B>>>;
 
-type Socket<A, B, In, Out> = (self::SendSink<A, B, Out>, self::RecvStream<A, B, In>);
+/// A bidirectional stream of a combined channel: a sink for outgoing and a stream of incoming messages
+pub type Socket<A, B, In, Out> = (self::SendSink<A, B, Out>, self::RecvStream<A, B, In>);
 
 /// Channel types for combined channels
 ///
@@ -205,6 +264,41 @@ impl<A: ChannelTypes, B: ChannelTypes> crate::ChannelTypes for CombinedChannelTy
         self::AcceptBiFuture<'a, A, B, In, Out>;
 
     type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<A, B, In, Out>;
+
+    fn reset<M: RpcMessage>(send: &mut Self::SendSink<M>, code: u32) {
+        match send {
+            SendSink::A(send) => A::reset(send, code),
+            SendSink::B(send) => B::reset(send, code),
+        }
+    }
+
+    fn set_pattern<M: RpcMessage>(send: &mut Self::SendSink<M>, pattern: PatternKind) {
+        match send {
+            SendSink::A(send) => A::set_pattern(send, pattern),
+            SendSink::B(send) => B::set_pattern(send, pattern),
+        }
+    }
+
+    fn set_priority<M: RpcMessage>(send: &mut Self::SendSink<M>, priority: i32) {
+        match send {
+            SendSink::A(send) => A::set_priority(send, priority),
+            SendSink::B(send) => B::set_priority(send, priority),
+        }
+    }
+
+    fn correlation_id<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<u64> {
+        match recv {
+            RecvStream::A(recv) => A::correlation_id(recv),
+            RecvStream::B(recv) => B::correlation_id(recv),
+        }
+    }
+
+    fn auth_token<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<Bytes> {
+        match recv {
+            RecvStream::A(recv) => A::auth_token(recv),
+            RecvStream::B(recv) => B::auth_token(recv),
+        }
+    }
 }
 
 impl<A: ChannelTypes, B: ChannelTypes, In: RpcMessage, Out: RpcMessage>
@@ -254,16 +348,23 @@ impl<A: ChannelTypes, B: ChannelTypes, In: RpcMessage, Out: RpcMessage>
             future::pending().right_future()
         };
         async move {
-            tokio::select! {
-                res = a_fut => res,
-                res = b_fut => res,
-            }
+            futures::pin_mut!(a_fut, b_fut);
+            future::select(a_fut, b_fut).await.factor_first().0
         }
         .boxed()
     }
+
+    fn close(&self, code: u32, reason: &[u8]) {
+        if let Some(a) = &self.a {
+            a.close(code, reason);
+        }
+        if let Some(b) = &self.b {
+            b.close(code, reason);
+        }
+    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "tokio"))]
 mod tests {
     use super::*;
     use crate::{combined, mem, quinn, Channel};
@@ -0,0 +1,229 @@
+//! Channel that sends a correlation id along with every message, to match calls across logs
+//!
+//! On a busy connection, it is hard to tell which span on the server belongs to which span on
+//! the client. This channel assigns an id to every stream it opens, counting up from 0 for every
+//! channel and its clones, and sends it along with every message. The remote echoes the id on
+//! its messages on the same stream. [crate::RpcClient] and [crate::RpcServer] record the id in
+//! the `correlation_id` field of the span of the call, see [ChannelTypes::correlation_id]. Both
+//! sides need to use a correlated channel.
+//!
+//! This is purely for observability, so it is only available with the `tracing` feature.
+use crate::{message::PatternKind, ChannelTypes, ConnectionInfo, RpcMessage};
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt, Sink, SinkExt, Stream, TryFutureExt};
+use pin_project::pin_project;
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+    pin::Pin,
+    result,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+/// A message together with the correlation id of its stream
+///
+/// The id is `None` for messages on an accepted stream before the first message of the remote
+/// arrived.
+pub type Correlated<M> = (Option<u64>, M);
+
+/// Correlation id of a stream, shared by its sink and stream
+type StreamId = Arc<Mutex<Option<u64>>>;
+
+/// A channel that sends a correlation id with every message, wrapping a channel for
+/// [Correlated] messages
+pub struct Channel<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> {
+    inner: C::Channel<Correlated<In>, Correlated<Out>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Channel<C, In, Out> {
+    /// Wrap a channel
+    pub fn new(inner: C::Channel<Correlated<In>, Correlated<Out>>) -> Self {
+        Self {
+            inner,
+            next_id: Default::default(),
+        }
+    }
+
+    fn wrap(
+        id: Option<u64>,
+        (send, recv): (C::SendSink<Correlated<Out>>, C::RecvStream<Correlated<In>>),
+    ) -> Socket<C, In, Out> {
+        let id = Arc::new(Mutex::new(id));
+        (
+            SendSink {
+                inner: send,
+                id: id.clone(),
+            },
+            RecvStream { inner: recv, id },
+        )
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Clone for Channel<C, In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Debug for Channel<C, In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel")
+            .field("next_id", &self.next_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// SendSink for correlated channels
+pub struct SendSink<C: ChannelTypes, Out: RpcMessage> {
+    inner: C::SendSink<Correlated<Out>>,
+    id: StreamId,
+}
+
+impl<C: ChannelTypes, Out: RpcMessage> Sink<Out> for SendSink<C, Out> {
+    type Error = C::SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        let id = *self.id.lock().unwrap();
+        self.inner.start_send_unpin((id, item))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_close_unpin(cx)
+    }
+}
+
+/// RecvStream for correlated channels
+#[pin_project]
+pub struct RecvStream<C: ChannelTypes, In: RpcMessage> {
+    #[pin]
+    inner: C::RecvStream<Correlated<In>>,
+    id: StreamId,
+}
+
+impl<C: ChannelTypes, In: RpcMessage> RecvStream<C, In> {
+    /// Correlation id of the stream, if it is known yet
+    pub fn id(&self) -> Option<u64> {
+        *self.id.lock().unwrap()
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage> Stream for RecvStream<C, In> {
+    type Item = Result<In, C::RecvError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        Poll::Ready(match futures::ready!(this.inner.poll_next(cx)) {
+            Some(Ok((id, msg))) => {
+                // an accepted stream takes the id of the remote that opened it
+                let mut current = this.id.lock().unwrap();
+                if current.is_none() {
+                    *current = id;
+                }
+                Some(Ok(msg))
+            }
+            Some(Err(e)) => Some(Err(e)),
+            None => None,
+        })
+    }
+}
+
+/// A bidirectional stream of a correlated channel: a sink for outgoing and a stream of incoming
+/// messages
+pub type Socket<C, In, Out> = (self::SendSink<C, Out>, self::RecvStream<C, In>);
+
+/// Future returned by open_bi
+pub type OpenBiFuture<'a, C, In, Out> =
+    BoxFuture<'a, result::Result<Socket<C, In, Out>, <C as ChannelTypes>::OpenBiError>>;
+
+/// Future returned by accept_bi
+pub type AcceptBiFuture<'a, C, In, Out> =
+    BoxFuture<'a, result::Result<Socket<C, In, Out>, <C as ChannelTypes>::AcceptBiError>>;
+
+/// Channel types for correlated channels
+///
+/// `C` is the channel type of the wrapped channel. Errors are passed through unchanged.
+#[derive(Debug, Clone, Copy)]
+pub struct CorrelatedChannelTypes<C: ChannelTypes>(PhantomData<C>);
+
+impl<C: ChannelTypes> ChannelTypes for CorrelatedChannelTypes<C> {
+    type SendSink<M: RpcMessage> = self::SendSink<C, M>;
+
+    type RecvStream<M: RpcMessage> = self::RecvStream<C, M>;
+
+    type SendError = C::SendError;
+
+    type RecvError = C::RecvError;
+
+    type OpenBiError = C::OpenBiError;
+
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::OpenBiFuture<'a, C, In, Out>;
+
+    type AcceptBiError = C::AcceptBiError;
+
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::AcceptBiFuture<'a, C, In, Out>;
+
+    type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<C, In, Out>;
+
+    fn reset<M: RpcMessage>(send: &mut Self::SendSink<M>, code: u32) {
+        C::reset(&mut send.inner, code)
+    }
+
+    fn set_pattern<M: RpcMessage>(send: &mut Self::SendSink<M>, pattern: PatternKind) {
+        C::set_pattern(&mut send.inner, pattern)
+    }
+
+    fn set_priority<M: RpcMessage>(send: &mut Self::SendSink<M>, priority: i32) {
+        C::set_priority(&mut send.inner, priority)
+    }
+
+    fn correlation_id<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<u64> {
+        recv.id()
+    }
+
+    fn auth_token<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<Bytes> {
+        C::auth_token(&recv.inner)
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage>
+    crate::Channel<In, Out, CorrelatedChannelTypes<C>> for Channel<C, In, Out>
+{
+    fn open_bi(&self) -> OpenBiFuture<'_, C, In, Out> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.inner
+            .open_bi()
+            .map_ok(move |socket| Self::wrap(Some(id), socket))
+            .boxed()
+    }
+
+    fn accept_bi(&self) -> AcceptBiFuture<'_, C, In, Out> {
+        self.inner
+            .accept_bi()
+            .map_ok(|socket| Self::wrap(None, socket))
+            .boxed()
+    }
+
+    fn connection_info(&self) -> ConnectionInfo {
+        self.inner.connection_info()
+    }
+
+    fn close(&self, code: u32, reason: &[u8]) {
+        self.inner.close(code, reason)
+    }
+}
@@ -56,19 +56,55 @@
 //! ```
 #![deny(missing_docs)]
 #![deny(rustdoc::broken_intra_doc_links)]
-use futures::{Future, Sink, Stream};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
+    error,
     fmt::{Debug, Display},
-    result,
+    io, result,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
+pub mod auth;
+#[cfg(feature = "tokio")]
+pub mod balancer;
+pub mod channel;
 pub mod client;
+pub mod codec;
 pub mod combined;
+#[cfg(feature = "tracing")]
+pub mod correlated;
+pub mod logging;
+mod macros;
+pub mod mapped;
 pub mod mem;
 pub mod message;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "tokio")]
+pub mod pool;
+pub mod probe;
+#[cfg(feature = "tokio")]
 pub mod quinn;
+pub mod reflect;
+pub mod router;
+pub mod runtime;
+pub use channel::{
+    AcceptUniFuture, Channel, ChannelTypes, ConnectionInfo, OpenBiWithError, OpenBiWithFuture,
+    OpenUniWithFuture,
+};
 pub use client::RpcClient;
 pub mod server;
+pub mod tagged;
+#[cfg(feature = "tokio")]
+pub mod tcp;
+pub mod testing;
+mod trace;
+#[cfg(feature = "tokio")]
+pub mod ws;
+#[cfg(feature = "derive")]
+pub use quic_rpc_derive::{Msg, MsgEnum, RpcMsg};
 pub use server::RpcServer;
 
 /// requirements for a RPC message
@@ -89,68 +125,164 @@ pub trait RpcError: Debug + Display + Send + Sync + Unpin + 'static {}
 
 impl<T> RpcError for T where T: Debug + Display + Send + Sync + Unpin + 'static {}
 
+/// Classification of transport errors into retryable and fatal ones
+///
+/// This is implemented by the errors of each channel type, so the client error types can provide
+/// an `is_retryable` method without knowing the details of the transport.
+pub trait Retryable {
+    /// True if retrying the failed operation, possibly after reconnecting, might succeed
+    fn is_retryable(&self) -> bool;
+}
+
+impl Retryable for std::io::Error {
+    fn is_retryable(&self) -> bool {
+        use std::io::ErrorKind::*;
+        matches!(
+            self.kind(),
+            ConnectionReset
+                | ConnectionAborted
+                | NotConnected
+                | BrokenPipe
+                | TimedOut
+                | Interrupted
+                | UnexpectedEof
+        )
+    }
+}
+
+/// Conversion of transport errors into [std::io::Error]
+///
+/// This is implemented by the errors of each channel type, so application code can store the
+/// errors of different transports as a single error type, see [client::RpcClientError::into_io].
+/// Channel errors are the [error::Error::source] of the client and server errors, so they need
+/// to implement [error::Error] with a meaningful message, e.g. using `thiserror`.
+pub trait ChannelError: RpcError + error::Error {
+    /// Convert the error, mapping it to the closest [io::ErrorKind]
+    fn into_io(self) -> io::Error;
+
+    /// True if the error means that the connection is gone, as opposed to e.g. a malformed
+    /// message or a reset of a single stream
+    ///
+    /// The server uses this to tell a client that disconnected apart from other receive errors,
+    /// see [server::RpcServerError::ConnectionLost]. The default implementation returns false.
+    fn is_connection_lost(&self) -> bool {
+        false
+    }
+
+    /// The kind of server error the remote reset the stream for, if the channel reports resets
+    ///
+    /// The client uses this to resume streams the server gave up on, see
+    /// [client::RpcClient::server_streaming_resumable]. The default implementation returns None.
+    fn server_error_kind(&self) -> Option<server::RpcServerErrorKind> {
+        None
+    }
+}
+
+impl ChannelError for io::Error {
+    fn into_io(self) -> io::Error {
+        self
+    }
+
+    fn is_connection_lost(&self) -> bool {
+        use std::io::ErrorKind::*;
+        matches!(
+            self.kind(),
+            ConnectionReset | ConnectionAborted | NotConnected | BrokenPipe
+        )
+    }
+}
+
 /// A service
 pub trait Service: Send + Sync + Debug + Clone + 'static {
     /// Type of request messages
     type Req: RpcMessage;
     /// Type of response messages
     type Res: RpcMessage;
+
+    /// The interaction pattern of a request that starts an interaction
+    ///
+    /// This allows a dispatcher, see [RpcServer::accept_one_classified], to know the pattern of
+    /// a request without duplicating the [message::Msg] impls. Use [message::PatternKind::of]
+    /// to derive the result from them. Returns `None` for updates and other requests that do
+    /// not start an interaction.
+    ///
+    /// Services declared with [declare_service] or [compose_service] implement this for all
+    /// their messages. Hand-written services opt out by keeping the default, which returns `None`
+    /// for all requests, so [RpcServer::accept_one_classified] rejects them with
+    /// [server::RpcServerError::UnexpectedStartMessage]. The other accept methods do not use it.
+    fn interaction(_req: &Self::Req) -> Option<message::PatternKind> {
+        None
+    }
+
+    /// True if the request is a [message::Cancel]
+    ///
+    /// Servers check every update against this, so clients can cancel an interaction
+    /// cooperatively, without relying on the channel to reset streams. Returns `false` by
+    /// default, so services need to override it to support cancellation.
+    fn is_cancel(_req: &Self::Req) -> bool {
+        false
+    }
+
+    /// True if the request is a [message::Keepalive]
+    ///
+    /// Server streaming handlers ignore keepalives instead of treating them as unexpected
+    /// updates. Returns `false` by default.
+    fn is_keepalive(_req: &Self::Req) -> bool {
+        false
+    }
 }
 
-/// Defines a set of types for a kind of channel
+/// A service whose messages are embedded in the messages of the service `Parent`
 ///
-/// Every distinct kind of channel has its own ChannelType. See e.g.
-/// [crate::mem::MemChannelTypes] and [crate::quinn::QuinnChannelTypes].
-pub trait ChannelTypes: Debug + Sized + Send + Sync + Unpin + Clone + 'static {
-    /// The sink used for sending either requests or responses on this channel
-    type SendSink<M: RpcMessage>: Sink<M, Error = Self::SendError> + Send + Unpin + 'static;
-    /// The stream used for receiving either requests or responses on this channel
-    type RecvStream<M: RpcMessage>: Stream<Item = result::Result<M, Self::RecvError>>
-        + Send
-        + Unpin
-        + 'static;
-    /// Error you might get while sending messages to a sink
-    type SendError: RpcError;
-    /// Error you might get while receiving messages from a stream
-    type RecvError: RpcError;
-    /// Error you might get when opening a new connection to the server
-    type OpenBiError: RpcError;
-    /// Future returned by open_bi
-    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage>: Future<
-            Output = result::Result<(Self::SendSink<Out>, Self::RecvStream<In>), Self::OpenBiError>,
-        > + Send
-        + 'a
-    where
-        Self: 'a;
-
-    /// Error you might get when waiting for new streams on the server side
-    type AcceptBiError: RpcError;
-    /// Future returned by accept_bi
-    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage>: Future<
-            Output = result::Result<
-                (Self::SendSink<Out>, Self::RecvStream<In>),
-                Self::AcceptBiError,
-            >,
-        > + Send
-        + 'a
-    where
-        Self: 'a;
-
-    /// Channel type
-    type Channel<In: RpcMessage, Out: RpcMessage>: crate::Channel<In, Out, Self>;
+/// This allows splitting a large service into modules, each of which is a service of its own.
+/// Clients get a client for the sub-service using [RpcClient::sub], servers route requests
+/// using [RpcServer::route]. The [compose_service] macro generates the envelope messages of
+/// the parent and the impls of this trait.
+pub trait SubService<Parent: Service>: Service {
+    /// Wrap a request in the request of the parent
+    fn wrap_req(req: Self::Req) -> Parent::Req;
+    /// Unwrap a request of the parent, or return it if it belongs to another sub-service
+    fn unwrap_req(req: Parent::Req) -> result::Result<Self::Req, Parent::Req>;
+    /// Wrap a response in the response of the parent
+    fn wrap_res(res: Self::Res) -> Parent::Res;
+    /// Unwrap a response of the parent, or return it if it belongs to another sub-service
+    fn unwrap_res(res: Parent::Res) -> result::Result<Self::Res, Parent::Res>;
 }
 
-/// An abstract channel with typed input and output
+/// Number of bytes sent and received on a stream, including framing
 ///
-/// This assumes cheap streams, so every interaction uses a new stream.
+/// The counters are shared by the sink and the stream of a bidirectional stream, and can be read
+/// from any task while the stream is in use, e.g. to show progress.
+#[derive(Debug, Clone, Default)]
+pub struct ByteCounts(Arc<(AtomicU64, AtomicU64)>);
+
+impl ByteCounts {
+    /// Bytes sent so far
+    pub fn bytes_sent(&self) -> u64 {
+        self.0 .0.load(Ordering::Relaxed)
+    }
+
+    /// Bytes received so far
+    pub fn bytes_received(&self) -> u64 {
+        self.0 .1.load(Ordering::Relaxed)
+    }
+
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn add_sent(&self, n: usize) {
+        self.0 .0.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn add_received(&self, n: usize) {
+        self.0 .1.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+/// Sinks and streams that count the bytes they send and receive
 ///
-/// Heavily inspired by quinn, but uses concrete `In` and `Out` types instead of bytes. The reason for this is that
-/// we want to be able to write a memory channel that does not serialize and deserialize.
-pub trait Channel<In: RpcMessage, Out: RpcMessage, T: ChannelTypes>:
-    Send + Sync + Clone + 'static
-{
-    /// Open a bidirectional stream
-    fn open_bi(&self) -> T::OpenBiFuture<'_, In, Out>;
-    /// Accept a bidirectional stream
-    fn accept_bi(&self) -> T::AcceptBiFuture<'_, In, Out>;
+/// Implemented by the sinks and streams of [crate::quinn::QuinnChannelTypes]. See
+/// [RpcClient::server_streaming_counted] and [client::UpdateSink::byte_counts].
+pub trait ByteCounted {
+    /// The counters of the bidirectional stream this belongs to
+    fn byte_counts(&self) -> ByteCounts;
 }
@@ -0,0 +1,291 @@
+//! Channel that logs all messages sent and received over another channel
+//!
+//! Every message is logged at DEBUG level using the [log] crate, with the stream id, the method,
+//! the serialized size and optionally a payload snippet. Since payloads frequently contain
+//! secrets, what ends up in the log is controlled by a [Redactor].
+use crate::{message::PatternKind, ChannelTypes, ConnectionInfo, RpcMessage};
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt, Sink, SinkExt, Stream, TryFutureExt};
+use pin_project::pin_project;
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+    pin::Pin,
+    result,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+/// Decides what gets logged about a message
+pub trait Redactor<M>: Send + Sync + 'static {
+    /// The method the message belongs to, if known
+    fn method(&self, _msg: &M) -> Option<String> {
+        None
+    }
+
+    /// Payload snippet to log, or `None` to not log the payload at all
+    ///
+    /// This is the place to strip or truncate sensitive fields.
+    fn payload(&self, msg: &M) -> Option<String>;
+}
+
+/// Redactor that never logs payloads
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoPayload;
+
+impl<M> Redactor<M> for NoPayload {
+    fn payload(&self, _msg: &M) -> Option<String> {
+        None
+    }
+}
+
+/// Redactor that logs the [Debug] representation of the message, truncated to `max_len` bytes
+///
+/// The method is taken to be the variant name at the start of the debug output, which works
+/// for the usual request and response enums.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugPayload {
+    /// Maximum length of the logged payload
+    pub max_len: usize,
+}
+
+impl<M: Debug> Redactor<M> for DebugPayload {
+    fn method(&self, msg: &M) -> Option<String> {
+        let text = format!("{msg:?}");
+        let end = text
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(text.len());
+        Some(text[..end].to_string()).filter(|x| !x.is_empty())
+    }
+
+    fn payload(&self, msg: &M) -> Option<String> {
+        Some(truncate(format!("{msg:?}"), self.max_len))
+    }
+}
+
+/// Redactor from a function that returns the payload snippet
+impl<M, F> Redactor<M> for F
+where
+    F: Fn(&M) -> Option<String> + Send + Sync + 'static,
+{
+    fn payload(&self, msg: &M) -> Option<String> {
+        self(msg)
+    }
+}
+
+fn truncate(mut text: String, max_len: usize) -> String {
+    if text.len() > max_len {
+        let mut end = max_len;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push('…');
+    }
+    text
+}
+
+fn log_msg<M: RpcMessage>(redactor: &dyn Redactor<M>, id: u64, direction: &str, msg: &M) {
+    if !log::log_enabled!(log::Level::Debug) {
+        return;
+    }
+    let size = bincode::serialized_size(msg).unwrap_or_default();
+    let method = redactor.method(msg).unwrap_or_else(|| "?".into());
+    match redactor.payload(msg) {
+        Some(payload) => log::debug!("stream {id} {direction} {method} ({size} bytes): {payload}"),
+        None => log::debug!("stream {id} {direction} {method} ({size} bytes)"),
+    }
+}
+
+/// A channel that logs messages, wrapping another channel
+pub struct Channel<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> {
+    inner: C::Channel<In, Out>,
+    recv: Arc<dyn Redactor<In>>,
+    send: Arc<dyn Redactor<Out>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Channel<C, In, Out> {
+    /// Wrap a channel, logging messages without payloads
+    pub fn new(inner: C::Channel<In, Out>) -> Self {
+        Self::with_redactors(inner, NoPayload, NoPayload)
+    }
+
+    /// Wrap a channel, using the given redactors for incoming and outgoing messages
+    pub fn with_redactors(
+        inner: C::Channel<In, Out>,
+        recv: impl Redactor<In>,
+        send: impl Redactor<Out>,
+    ) -> Self {
+        Self {
+            inner,
+            recv: Arc::new(recv),
+            send: Arc::new(send),
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn wrap(&self, (send, recv): (C::SendSink<Out>, C::RecvStream<In>)) -> Socket<C, In, Out> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        (
+            SendSink {
+                inner: send,
+                redactor: self.send.clone(),
+                id,
+            },
+            RecvStream {
+                inner: recv,
+                redactor: self.recv.clone(),
+                id,
+            },
+        )
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Clone for Channel<C, In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            recv: self.recv.clone(),
+            send: self.send.clone(),
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Debug for Channel<C, In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel").finish_non_exhaustive()
+    }
+}
+
+/// SendSink for logging channels
+pub struct SendSink<C: ChannelTypes, Out: RpcMessage> {
+    inner: C::SendSink<Out>,
+    redactor: Arc<dyn Redactor<Out>>,
+    id: u64,
+}
+
+impl<C: ChannelTypes, Out: RpcMessage> Sink<Out> for SendSink<C, Out> {
+    type Error = C::SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        log_msg(self.redactor.as_ref(), self.id, "send", &item);
+        self.inner.start_send_unpin(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_close_unpin(cx)
+    }
+}
+
+/// RecvStream for logging channels
+#[pin_project]
+pub struct RecvStream<C: ChannelTypes, In: RpcMessage> {
+    #[pin]
+    inner: C::RecvStream<In>,
+    redactor: Arc<dyn Redactor<In>>,
+    id: u64,
+}
+
+impl<C: ChannelTypes, In: RpcMessage> Stream for RecvStream<C, In> {
+    type Item = Result<In, C::RecvError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let res = this.inner.poll_next(cx);
+        if let Poll::Ready(Some(Ok(msg))) = &res {
+            log_msg(this.redactor.as_ref(), *this.id, "recv", msg);
+        }
+        res
+    }
+}
+
+/// A bidirectional stream of a logging channel: a sink for outgoing and a stream of incoming messages
+pub type Socket<C, In, Out> = (self::SendSink<C, Out>, self::RecvStream<C, In>);
+
+/// Future returned by open_bi
+pub type OpenBiFuture<'a, C, In, Out> =
+    BoxFuture<'a, result::Result<Socket<C, In, Out>, <C as ChannelTypes>::OpenBiError>>;
+
+/// Future returned by accept_bi
+pub type AcceptBiFuture<'a, C, In, Out> =
+    BoxFuture<'a, result::Result<Socket<C, In, Out>, <C as ChannelTypes>::AcceptBiError>>;
+
+/// Channel types for logging channels
+///
+/// `C` is the channel type of the wrapped channel. Errors are passed through unchanged.
+#[derive(Debug, Clone, Copy)]
+pub struct LoggingChannelTypes<C: ChannelTypes>(PhantomData<C>);
+
+impl<C: ChannelTypes> ChannelTypes for LoggingChannelTypes<C> {
+    type SendSink<M: RpcMessage> = self::SendSink<C, M>;
+
+    type RecvStream<M: RpcMessage> = self::RecvStream<C, M>;
+
+    type SendError = C::SendError;
+
+    type RecvError = C::RecvError;
+
+    type OpenBiError = C::OpenBiError;
+
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::OpenBiFuture<'a, C, In, Out>;
+
+    type AcceptBiError = C::AcceptBiError;
+
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::AcceptBiFuture<'a, C, In, Out>;
+
+    type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<C, In, Out>;
+
+    fn reset<M: RpcMessage>(send: &mut Self::SendSink<M>, code: u32) {
+        log::debug!("stream {} reset with code {code}", send.id);
+        C::reset(&mut send.inner, code)
+    }
+
+    fn set_pattern<M: RpcMessage>(send: &mut Self::SendSink<M>, pattern: PatternKind) {
+        C::set_pattern(&mut send.inner, pattern)
+    }
+
+    fn set_priority<M: RpcMessage>(send: &mut Self::SendSink<M>, priority: i32) {
+        C::set_priority(&mut send.inner, priority)
+    }
+
+    fn correlation_id<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<u64> {
+        C::correlation_id(&recv.inner)
+    }
+
+    fn auth_token<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<Bytes> {
+        C::auth_token(&recv.inner)
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage>
+    crate::Channel<In, Out, LoggingChannelTypes<C>> for Channel<C, In, Out>
+{
+    fn open_bi(&self) -> OpenBiFuture<'_, C, In, Out> {
+        self.inner.open_bi().map_ok(|s| self.wrap(s)).boxed()
+    }
+
+    fn accept_bi(&self) -> AcceptBiFuture<'_, C, In, Out> {
+        self.inner.accept_bi().map_ok(|s| self.wrap(s)).boxed()
+    }
+
+    fn connection_info(&self) -> ConnectionInfo {
+        self.inner.connection_info()
+    }
+
+    fn close(&self, code: u32, reason: &[u8]) {
+        self.inner.close(code, reason)
+    }
+}
@@ -0,0 +1,542 @@
+//! Macros to reduce the boilerplate of defining services
+
+/// Declare a service, the interaction patterns of its messages and a dispatch function
+///
+/// This generates the [crate::Service] impl, a [crate::message::Msg] impl for every message,
+/// and a `dispatch` method on the service type that calls the matching handler method on the
+/// service for a request accepted with [crate::RpcServer::accept_one]. The generated
+/// [crate::Service::interaction] returns the pattern of every listed message, so the service
+/// works with [crate::RpcServer::accept_one_classified].
+///
+/// Each request message needs to be wrapped in a variant of the request enum with the same name
+/// as the message type. Handler methods take `self` and have the signature expected by the
+/// corresponding [crate::RpcServer] method. Requests that are not listed, such as updates, make
+/// `dispatch` fail with [crate::server::RpcServerError::UnexpectedStartMessage].
+///
+/// For a typed client with one method per message, see [crate::declare_client].
+///
+/// # Example
+/// ```
+/// # use futures::{Stream, StreamExt};
+/// # use quic_rpc::server::UpdateStream;
+/// # use serde::{Deserialize, Serialize};
+/// # use derive_more::{From, TryInto};
+/// #[derive(Debug, Serialize, Deserialize)]
+/// struct Sqr(u64);
+/// #[derive(Debug, Serialize, Deserialize)]
+/// struct SqrResponse(u128);
+/// #[derive(Debug, Serialize, Deserialize)]
+/// struct Sum;
+/// #[derive(Debug, Serialize, Deserialize)]
+/// struct SumUpdate(u64);
+/// #[derive(Debug, Serialize, Deserialize)]
+/// struct SumResponse(u128);
+///
+/// #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+/// enum Request {
+///     Sqr(Sqr),
+///     Sum(Sum),
+///     SumUpdate(SumUpdate),
+/// }
+///
+/// #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+/// enum Response {
+///     SqrResponse(SqrResponse),
+///     SumResponse(SumResponse),
+/// }
+///
+/// #[derive(Debug, Clone)]
+/// struct Calculator;
+///
+/// quic_rpc::declare_service! {
+///     Calculator: Request => Response;
+///     rpc Sqr -> SqrResponse: sqr;
+///     client_streaming Sum(SumUpdate) -> SumResponse: sum;
+/// }
+///
+/// impl Calculator {
+///     async fn sqr(self, Sqr(x): Sqr) -> SqrResponse {
+///         SqrResponse(x as u128 * x as u128)
+///     }
+///
+///     async fn sum(self, _: Sum, updates: impl Stream<Item = SumUpdate>) -> SumResponse {
+///         SumResponse(updates.fold(0, |sum, SumUpdate(x)| async move { sum + x as u128 }).await)
+///     }
+/// }
+///
+/// async fn serve<C: quic_rpc::ChannelTypes>(
+///     mut server: quic_rpc::RpcServer<Calculator, C>,
+/// ) -> Result<(), quic_rpc::server::RpcServerError<C>> {
+///     loop {
+///         let (req, chan) = server.accept_one().await?;
+///         Calculator.dispatch(&server, req, chan).await?;
+///     }
+/// }
+///
+/// // in a test, check that the enums convert back to the messages
+/// Calculator::assert_service_roundtrip::<quic_rpc::codec::BincodeCodec>();
+/// ```
+///
+/// Every message can only have one interaction pattern, so listing a message twice is a
+/// compile error:
+/// ```compile_fail
+/// # use serde::{Deserialize, Serialize};
+/// # use derive_more::{From, TryInto};
+/// # #[derive(Debug, Serialize, Deserialize)]
+/// # struct Sqr(u64);
+/// # #[derive(Debug, Serialize, Deserialize)]
+/// # struct SqrResponse(u128);
+/// # #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+/// # enum Request { Sqr(Sqr) }
+/// # #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+/// # enum Response { SqrResponse(SqrResponse) }
+/// # #[derive(Debug, Clone)]
+/// # struct Calculator;
+/// # impl Calculator {
+/// #     async fn sqr(self, Sqr(x): Sqr) -> SqrResponse { SqrResponse(x as u128 * x as u128) }
+/// #     fn sqrs(self, _: Sqr) -> futures::stream::Empty<SqrResponse> { futures::stream::empty() }
+/// # }
+/// quic_rpc::declare_service! {
+///     Calculator: Request => Response;
+///     rpc Sqr -> SqrResponse: sqr;
+///     server_streaming Sqr -> SqrResponse: sqrs;
+/// }
+/// ```
+#[macro_export]
+macro_rules! declare_service {
+    (
+        $service:ident : $req:ident => $res:ident;
+        $($pattern:ident $msg:ident $(($update:ty))? -> $response:ty : $handler:ident;)*
+    ) => {
+        impl $crate::Service for $service {
+            type Req = $req;
+            type Res = $res;
+
+            fn interaction(req: &$req) -> Option<$crate::message::PatternKind> {
+                #[allow(unreachable_patterns)]
+                match req {
+                    $($req::$msg(_) => Some($crate::message::PatternKind::of::<Self, $msg>()),)*
+                    _ => None,
+                }
+            }
+        }
+
+        $(
+            $crate::declare_service!(@msg $service, $pattern, $msg, ($($update)?), $response);
+        )*
+
+        impl $service {
+            /// Handle a request accepted with `RpcServer::accept_one` using the handler method
+            /// for its message
+            #[allow(dead_code)]
+            pub async fn dispatch<C: $crate::ChannelTypes>(
+                self,
+                server: &$crate::RpcServer<Self, C>,
+                req: $req,
+                chan: $crate::server::ServerSocket<Self, C>,
+            ) -> ::std::result::Result<(), $crate::server::RpcServerError<C>> {
+                #[allow(unreachable_patterns)]
+                match req {
+                    $(
+                        $req::$msg(msg) => {
+                            $crate::declare_service!(@call $pattern, server, msg, chan, self, Self::$handler)
+                        }
+                    )*
+                    _ => Err($crate::server::RpcServerError::UnexpectedStartMessage),
+                }
+            }
+
+            /// Check that a sample of every message, update and response survives the round
+            /// trip through the request and response enums and the codec `C`, see
+            /// `quic_rpc::testing::assert_sample_roundtrip`
+            ///
+            /// This is meant to be called from a test.
+            #[allow(dead_code)]
+            pub fn assert_service_roundtrip<C: $crate::codec::Codec>() {
+                $(
+                    $crate::testing::assert_sample_roundtrip::<C, $req, $msg>();
+                    $($crate::testing::assert_sample_roundtrip::<C, $req, $update>();)?
+                    $crate::testing::assert_sample_roundtrip::<C, $res, $response>();
+                )*
+            }
+        }
+    };
+    (@msg $service:ident, rpc, $msg:ident, (), $response:ty) => {
+        impl $crate::message::RpcMsg<$service> for $msg {
+            type Response = $response;
+        }
+    };
+    (@msg $service:ident, server_streaming, $msg:ident, (), $response:ty) => {
+        impl $crate::message::Msg<$service> for $msg {
+            type Update = Self;
+            type Response = $response;
+            type Pattern = $crate::message::ServerStreaming;
+        }
+    };
+    (@msg $service:ident, client_streaming, $msg:ident, ($update:ty), $response:ty) => {
+        impl $crate::message::Msg<$service> for $msg {
+            type Update = $update;
+            type Response = $response;
+            type Pattern = $crate::message::ClientStreaming;
+        }
+    };
+    (@msg $service:ident, bidi_streaming, $msg:ident, ($update:ty), $response:ty) => {
+        impl $crate::message::Msg<$service> for $msg {
+            type Update = $update;
+            type Response = $response;
+            type Pattern = $crate::message::BidiStreaming;
+        }
+    };
+    (@msg $service:ident, $pattern:ident, $msg:ident, ($($update:ty)?), $response:ty) => {
+        compile_error!(concat!(
+            "invalid pattern for ",
+            stringify!($msg),
+            ": expected `rpc` or `server_streaming` without updates, ",
+            "or `client_streaming` or `bidi_streaming` with an update type"
+        ));
+    };
+    (@call rpc, $server:ident, $msg:ident, $chan:ident, $target:expr, $f:expr) => {
+        $server.rpc($msg, $chan, $target, $f).await
+    };
+    (@call server_streaming, $server:ident, $msg:ident, $chan:ident, $target:expr, $f:expr) => {
+        $server.server_streaming($msg, $chan, $target, $f).await
+    };
+    (@call client_streaming, $server:ident, $msg:ident, $chan:ident, $target:expr, $f:expr) => {
+        $server.client_streaming($msg, $chan, $target, $f).await
+    };
+    (@call bidi_streaming, $server:ident, $msg:ident, $chan:ident, $target:expr, $f:expr) => {
+        $server.bidi_streaming($msg, $chan, $target, $f).await
+    };
+    (@call $pattern:ident, $server:ident, $msg:ident, $chan:ident, $target:expr, $f:expr) => {
+        unreachable!()
+    };
+}
+
+/// Declare a request or response enum, with a round trip check for all of its variants
+///
+/// The enum is declared as written, so the conversions can be derived as usual. In addition,
+/// this generates an `assert_variants_roundtrip` method that checks a sample of every wrapped
+/// type using [crate::testing::assert_sample_roundtrip]. Unlike a list of checks written by
+/// hand, it covers variants that are added later. This is useful for services that do not use
+/// [crate::declare_service], which generates the check from the listed messages instead.
+///
+/// Every variant needs to wrap a single type, like `Sqr(Sqr)`.
+///
+/// # Example
+/// ```
+/// # use serde::{Deserialize, Serialize};
+/// # use derive_more::{From, TryInto};
+/// # #[derive(Debug, Serialize, Deserialize)]
+/// # struct Sqr(u64);
+/// # #[derive(Debug, Serialize, Deserialize)]
+/// # struct Sum;
+/// quic_rpc::declare_msg_enum! {
+///     #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+///     enum Request {
+///         Sqr(Sqr),
+///         Sum(Sum),
+///     }
+/// }
+///
+/// // in a test
+/// Request::assert_variants_roundtrip::<quic_rpc::codec::BincodeCodec>();
+/// ```
+#[macro_export]
+macro_rules! declare_msg_enum {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident {
+            $($(#[$variant_attr:meta])* $variant:ident($ty:ty)),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $name {
+            $($(#[$variant_attr])* $variant($ty),)*
+        }
+
+        impl $name {
+            /// Check that a sample of every wrapped type survives the round trip through this
+            /// enum and the codec `C`, see `quic_rpc::testing::assert_sample_roundtrip`
+            ///
+            /// This is meant to be called from a test.
+            #[allow(dead_code)]
+            pub fn assert_variants_roundtrip<C: $crate::codec::Codec>() {
+                $($crate::testing::assert_sample_roundtrip::<C, Self, $ty>();)*
+            }
+        }
+    };
+}
+
+/// Declare a typed client for a service, with one method per message
+///
+/// This generates a struct wrapping a [crate::RpcClient], with a method for each listed message
+/// that calls the client method for its interaction pattern. The messages need to implement
+/// [crate::message::Msg] for the service already, e.g. using [crate::declare_service], and the return
+/// types are taken from those impls:
+///
+/// - `rpc` methods return the response
+/// - `server_streaming` methods return a [crate::client::ServerStreamingResponses]
+/// - `client_streaming` methods return an [crate::client::UpdateSink] and a
+///   [crate::client::ClientStreamingResponse]
+/// - `bidi_streaming` methods return an [crate::client::UpdateSink] and a
+///   [crate::client::BidiResponses]
+///
+/// # Example
+/// ```
+/// # use futures::SinkExt;
+/// # use serde::{Deserialize, Serialize};
+/// # use derive_more::{From, TryInto};
+/// # #[derive(Debug, Serialize, Deserialize)]
+/// # struct Sqr(u64);
+/// # #[derive(Debug, Serialize, Deserialize)]
+/// # struct SqrResponse(u128);
+/// # #[derive(Debug, Serialize, Deserialize)]
+/// # struct Sum;
+/// # #[derive(Debug, Serialize, Deserialize)]
+/// # struct SumUpdate(u64);
+/// # #[derive(Debug, Serialize, Deserialize)]
+/// # struct SumResponse(u128);
+/// # #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+/// # enum Request { Sqr(Sqr), Sum(Sum), SumUpdate(SumUpdate) }
+/// # #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+/// # enum Response { SqrResponse(SqrResponse), SumResponse(SumResponse) }
+/// # #[derive(Debug, Clone)]
+/// # struct Calculator;
+/// # impl quic_rpc::Service for Calculator { type Req = Request; type Res = Response; }
+/// # impl quic_rpc::message::RpcMsg<Calculator> for Sqr { type Response = SqrResponse; }
+/// # impl quic_rpc::message::Msg<Calculator> for Sum {
+/// #     type Update = SumUpdate;
+/// #     type Response = SumResponse;
+/// #     type Pattern = quic_rpc::message::ClientStreaming;
+/// # }
+/// quic_rpc::declare_client! {
+///     /// Client for the calculator
+///     pub CalculatorClient for Calculator;
+///     rpc Sqr: sqr;
+///     client_streaming Sum: sum;
+/// }
+///
+/// async fn call(
+///     client: quic_rpc::RpcClient<Calculator, quic_rpc::mem::MemChannelTypes>,
+/// ) -> anyhow::Result<u128> {
+///     let mut client = CalculatorClient::new(client);
+///     let SqrResponse(x) = client.sqr(Sqr(3)).await?;
+///     let (mut updates, res) = client.sum(Sum).await?;
+///     updates.send(SumUpdate(4)).await?;
+///     drop(updates);
+///     let SumResponse(sum) = res.await?;
+///     Ok(x + sum)
+/// }
+/// ```
+#[macro_export]
+macro_rules! declare_client {
+    (
+        $(#[$attr:meta])*
+        $vis:vis $client:ident for $service:ty;
+        $($pattern:ident $msg:ty : $method:ident;)*
+    ) => {
+        $(#[$attr])*
+        $vis struct $client<C: $crate::ChannelTypes>(pub $crate::RpcClient<$service, C>);
+
+        impl<C: $crate::ChannelTypes> ::std::clone::Clone for $client<C> {
+            fn clone(&self) -> Self {
+                Self(self.0.clone())
+            }
+        }
+
+        impl<C: $crate::ChannelTypes> ::std::fmt::Debug for $client<C> {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.debug_tuple(stringify!($client)).finish()
+            }
+        }
+
+        impl<C: $crate::ChannelTypes> ::std::convert::From<$crate::RpcClient<$service, C>>
+            for $client<C>
+        {
+            fn from(client: $crate::RpcClient<$service, C>) -> Self {
+                Self(client)
+            }
+        }
+
+        #[allow(dead_code)]
+        impl<C: $crate::ChannelTypes> $client<C> {
+            /// Wrap a client of the service
+            pub fn new(client: $crate::RpcClient<$service, C>) -> Self {
+                Self(client)
+            }
+
+            $(
+                $crate::declare_client!(@method $pattern, $service, $msg, $method);
+            )*
+        }
+    };
+    (@method rpc, $service:ty, $msg:ty, $method:ident) => {
+        #[doc = concat!("Send a `", stringify!($msg), "` request and wait for the response")]
+        pub async fn $method(
+            &self,
+            msg: $msg,
+        ) -> ::std::result::Result<
+            <$msg as $crate::message::Msg<$service>>::Response,
+            $crate::client::RpcClientError<C>,
+        > {
+            self.0.rpc(msg).await
+        }
+    };
+    (@method server_streaming, $service:ty, $msg:ty, $method:ident) => {
+        #[doc = concat!("Send a `", stringify!($msg), "` request and return the stream of responses")]
+        pub async fn $method(
+            &mut self,
+            msg: $msg,
+        ) -> ::std::result::Result<
+            $crate::client::ServerStreamingResponses<$service, C, $msg>,
+            $crate::client::StreamingResponseError<C>,
+        > {
+            self.0.server_streaming(msg).await
+        }
+    };
+    (@method client_streaming, $service:ty, $msg:ty, $method:ident) => {
+        #[doc = concat!("Send a `", stringify!($msg), "` request, returning the sink for the updates and the future for the response")]
+        pub async fn $method(
+            &mut self,
+            msg: $msg,
+        ) -> ::std::result::Result<
+            (
+                $crate::client::UpdateSink<$service, C, $msg>,
+                $crate::client::ClientStreamingResponse<$service, C, $msg>,
+            ),
+            $crate::client::ClientStreamingError<C>,
+        > {
+            self.0.client_streaming(msg).await
+        }
+    };
+    (@method bidi_streaming, $service:ty, $msg:ty, $method:ident) => {
+        #[doc = concat!("Send a `", stringify!($msg), "` request, returning the sink for the updates and the stream of responses")]
+        pub async fn $method(
+            &mut self,
+            msg: $msg,
+        ) -> ::std::result::Result<
+            (
+                $crate::client::UpdateSink<$service, C, $msg>,
+                $crate::client::BidiResponses<$service, C, $msg>,
+            ),
+            $crate::client::BidiError<C>,
+        > {
+            self.0.bidi(msg).await
+        }
+    };
+    (@method $pattern:ident, $service:ty, $msg:ty, $method:ident) => {
+        compile_error!(concat!(
+            "invalid pattern for ",
+            stringify!($msg),
+            ": expected `rpc`, `server_streaming`, `client_streaming` or `bidi_streaming`"
+        ));
+    };
+}
+
+/// Declare a service that is composed of sub-services
+///
+/// This generates the request and response enums of the service, with a variant wrapping the
+/// messages of each sub-service, the [crate::Service] impl, and a [crate::SubService] impl for
+/// every sub-service. Clients use [crate::RpcClient::sub] to call a sub-service, servers use
+/// [crate::RpcServer::route] to hand requests to the handlers of the sub-service.
+///
+/// The generated enums derive serde, so the crate using the macro needs to depend on serde.
+///
+/// # Example
+/// ```
+/// # #[derive(Debug, Clone)]
+/// # struct Compute;
+/// # impl quic_rpc::Service for Compute { type Req = u64; type Res = u128; }
+/// # #[derive(Debug, Clone)]
+/// # struct Echo;
+/// # impl quic_rpc::Service for Echo { type Req = String; type Res = String; }
+/// #[derive(Debug, Clone)]
+/// struct App;
+///
+/// quic_rpc::compose_service! {
+///     App: AppRequest => AppResponse;
+///     Compute(Compute),
+///     Echo(Echo),
+/// }
+///
+/// fn clients<C: quic_rpc::ChannelTypes>(client: quic_rpc::RpcClient<App, C>) {
+///     let compute = client.sub::<Compute>();
+///     let echo = client.sub::<Echo>();
+/// }
+/// ```
+#[macro_export]
+macro_rules! compose_service {
+    (
+        $vis:vis $service:ident : $req:ident => $res:ident;
+        $($variant:ident($child:ty)),* $(,)?
+    ) => {
+        #[doc = concat!("Requests of [", stringify!($service), "], wrapping the requests of its sub-services")]
+        #[derive(Debug, ::serde::Serialize, ::serde::Deserialize)]
+        $vis enum $req {
+            $(
+                #[allow(missing_docs)]
+                $variant(<$child as $crate::Service>::Req),
+            )*
+        }
+
+        #[doc = concat!("Responses of [", stringify!($service), "], wrapping the responses of its sub-services")]
+        #[derive(Debug, ::serde::Serialize, ::serde::Deserialize)]
+        $vis enum $res {
+            $(
+                #[allow(missing_docs)]
+                $variant(<$child as $crate::Service>::Res),
+            )*
+        }
+
+        impl $crate::Service for $service {
+            type Req = $req;
+            type Res = $res;
+
+            fn interaction(req: &$req) -> Option<$crate::message::PatternKind> {
+                match req {
+                    $($req::$variant(req) => <$child as $crate::Service>::interaction(req),)*
+                }
+            }
+
+            fn is_cancel(req: &$req) -> bool {
+                match req {
+                    $($req::$variant(req) => <$child as $crate::Service>::is_cancel(req),)*
+                }
+            }
+
+            fn is_keepalive(req: &$req) -> bool {
+                match req {
+                    $($req::$variant(req) => <$child as $crate::Service>::is_keepalive(req),)*
+                }
+            }
+        }
+
+        $(
+            impl $crate::SubService<$service> for $child {
+                fn wrap_req(req: Self::Req) -> $req {
+                    $req::$variant(req)
+                }
+
+                fn unwrap_req(req: $req) -> ::std::result::Result<Self::Req, $req> {
+                    #[allow(unreachable_patterns)]
+                    match req {
+                        $req::$variant(req) => Ok(req),
+                        req => Err(req),
+                    }
+                }
+
+                fn wrap_res(res: Self::Res) -> $res {
+                    $res::$variant(res)
+                }
+
+                fn unwrap_res(res: $res) -> ::std::result::Result<Self::Res, $res> {
+                    #[allow(unreachable_patterns)]
+                    match res {
+                        $res::$variant(res) => Ok(res),
+                        res => Err(res),
+                    }
+                }
+            }
+        )*
+    };
+}
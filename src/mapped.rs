@@ -0,0 +1,305 @@
+//! Channel that translates the messages of another channel, to adapt between service types
+//!
+//! This allows embedding the messages of one service in the messages of another one, e.g. for a
+//! gateway that multiplexes several services over one connection. Outgoing messages are
+//! converted using a function that wraps them, incoming messages using a function that unwraps
+//! them, or returns `None` if the message does not belong to the embedded service. Clients can
+//! use [crate::RpcClient::map_service], see also [crate::SubService].
+use crate::{
+    message::PatternKind, ChannelError, ChannelTypes, ConnectionInfo, Retryable, RpcMessage,
+};
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
+use std::{
+    fmt::{self, Debug},
+    io,
+    marker::PhantomData,
+    pin::Pin,
+    result,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+type MapOut<Out, InnerOut> = Arc<dyn Fn(Out) -> InnerOut + Send + Sync>;
+type MapIn<InnerIn, In> = Arc<dyn Fn(InnerIn) -> Option<In> + Send + Sync>;
+
+/// A channel that translates messages, wrapping a channel for `InnerIn` and `InnerOut`
+pub struct Channel<C: ChannelTypes, InnerIn, InnerOut, In, Out>
+where
+    InnerIn: RpcMessage,
+    InnerOut: RpcMessage,
+{
+    inner: C::Channel<InnerIn, InnerOut>,
+    to: MapOut<Out, InnerOut>,
+    from: MapIn<InnerIn, In>,
+}
+
+impl<C, InnerIn, InnerOut, In, Out> Channel<C, InnerIn, InnerOut, In, Out>
+where
+    C: ChannelTypes,
+    InnerIn: RpcMessage,
+    InnerOut: RpcMessage,
+    In: RpcMessage,
+    Out: RpcMessage,
+{
+    /// Wrap a channel, converting outgoing messages using `to` and incoming messages using
+    /// `from`
+    pub fn new(
+        inner: C::Channel<InnerIn, InnerOut>,
+        to: impl Fn(Out) -> InnerOut + Send + Sync + 'static,
+        from: impl Fn(InnerIn) -> Option<In> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner,
+            to: Arc::new(to),
+            from: Arc::new(from),
+        }
+    }
+
+    fn wrap(
+        &self,
+        (send, recv): (C::SendSink<InnerOut>, C::RecvStream<InnerIn>),
+    ) -> Socket<C, InnerIn, InnerOut, In, Out> {
+        self.wrap_pinned(send, Box::pin(recv))
+    }
+
+    /// Wrap a sink and a stream that were opened or accepted on the wrapped channel
+    pub(crate) fn wrap_pinned(
+        &self,
+        send: C::SendSink<InnerOut>,
+        recv: Pin<Box<C::RecvStream<InnerIn>>>,
+    ) -> Socket<C, InnerIn, InnerOut, In, Out> {
+        (
+            SendSink {
+                inner: send,
+                to: self.to.clone(),
+            },
+            RecvStream {
+                inner: recv,
+                from: self.from.clone(),
+            },
+        )
+    }
+}
+
+impl<C, InnerIn, InnerOut, In, Out> Clone for Channel<C, InnerIn, InnerOut, In, Out>
+where
+    C: ChannelTypes,
+    InnerIn: RpcMessage,
+    InnerOut: RpcMessage,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            to: self.to.clone(),
+            from: self.from.clone(),
+        }
+    }
+}
+
+impl<C, InnerIn, InnerOut, In, Out> Debug for Channel<C, InnerIn, InnerOut, In, Out>
+where
+    C: ChannelTypes,
+    InnerIn: RpcMessage,
+    InnerOut: RpcMessage,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel").finish_non_exhaustive()
+    }
+}
+
+/// SendSink for mapped channels
+pub struct SendSink<C: ChannelTypes, InnerOut: RpcMessage, Out> {
+    inner: C::SendSink<InnerOut>,
+    to: MapOut<Out, InnerOut>,
+}
+
+impl<C: ChannelTypes, InnerOut: RpcMessage, Out> Sink<Out> for SendSink<C, InnerOut, Out> {
+    type Error = C::SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        let item = (self.to)(item);
+        self.inner.start_send_unpin(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_close_unpin(cx)
+    }
+}
+
+/// RecvStream for mapped channels
+pub struct RecvStream<C: ChannelTypes, InnerIn: RpcMessage, In> {
+    inner: Pin<Box<C::RecvStream<InnerIn>>>,
+    from: MapIn<InnerIn, In>,
+}
+
+impl<C: ChannelTypes, InnerIn: RpcMessage, In> Stream for RecvStream<C, InnerIn, In> {
+    type Item = Result<In, RecvError<C>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(match futures::ready!(self.inner.poll_next_unpin(cx)) {
+            Some(Ok(msg)) => Some((self.from)(msg).ok_or(RecvError::Unmapped)),
+            Some(Err(e)) => Some(Err(RecvError::Inner(e))),
+            None => None,
+        })
+    }
+}
+
+/// RecvError for mapped channels
+#[derive(Debug, thiserror::Error)]
+pub enum RecvError<C: ChannelTypes> {
+    /// Error of the wrapped channel
+    #[error(transparent)]
+    Inner(C::RecvError),
+    /// The received message does not belong to the embedded service
+    #[error("the received message does not belong to the embedded service")]
+    Unmapped,
+}
+
+impl<C: ChannelTypes> ChannelError for RecvError<C> {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::Inner(e) => e.into_io(),
+            Self::Unmapped => io::Error::new(io::ErrorKind::InvalidData, self),
+        }
+    }
+
+    fn is_connection_lost(&self) -> bool {
+        match self {
+            Self::Inner(e) => e.is_connection_lost(),
+            Self::Unmapped => false,
+        }
+    }
+}
+
+impl<C: ChannelTypes> Retryable for RecvError<C> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Inner(e) => e.is_retryable(),
+            Self::Unmapped => false,
+        }
+    }
+}
+
+/// A bidirectional stream of a mapped channel: a sink for outgoing and a stream of incoming messages
+pub type Socket<C, InnerIn, InnerOut, In, Out> = (
+    self::SendSink<C, InnerOut, Out>,
+    self::RecvStream<C, InnerIn, In>,
+);
+
+/// Future returned by open_bi
+pub type OpenBiFuture<'a, C, InnerIn, InnerOut, In, Out> = BoxFuture<
+    'a,
+    result::Result<Socket<C, InnerIn, InnerOut, In, Out>, <C as ChannelTypes>::OpenBiError>,
+>;
+
+/// Future returned by accept_bi
+pub type AcceptBiFuture<'a, C, InnerIn, InnerOut, In, Out> = BoxFuture<
+    'a,
+    result::Result<Socket<C, InnerIn, InnerOut, In, Out>, <C as ChannelTypes>::AcceptBiError>,
+>;
+
+/// Channel types for mapped channels
+///
+/// `C` is the channel type of the wrapped channel, which carries `InnerIn` and `InnerOut`
+/// messages. Errors are passed through unchanged, except for incoming messages that could not be
+/// converted, see [RecvError::Unmapped].
+pub struct MappedChannelTypes<C: ChannelTypes, InnerIn, InnerOut>(
+    PhantomData<(C, InnerIn, InnerOut)>,
+);
+
+impl<C: ChannelTypes, InnerIn, InnerOut> Clone for MappedChannelTypes<C, InnerIn, InnerOut> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: ChannelTypes, InnerIn, InnerOut> Copy for MappedChannelTypes<C, InnerIn, InnerOut> {}
+
+impl<C: ChannelTypes, InnerIn, InnerOut> Debug for MappedChannelTypes<C, InnerIn, InnerOut> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MappedChannelTypes").field(&self.0).finish()
+    }
+}
+
+impl<C, InnerIn, InnerOut> ChannelTypes for MappedChannelTypes<C, InnerIn, InnerOut>
+where
+    C: ChannelTypes,
+    InnerIn: RpcMessage,
+    InnerOut: RpcMessage,
+{
+    type SendSink<M: RpcMessage> = self::SendSink<C, InnerOut, M>;
+
+    type RecvStream<M: RpcMessage> = self::RecvStream<C, InnerIn, M>;
+
+    type SendError = C::SendError;
+
+    type RecvError = self::RecvError<C>;
+
+    type OpenBiError = C::OpenBiError;
+
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> =
+        self::OpenBiFuture<'a, C, InnerIn, InnerOut, In, Out>;
+
+    type AcceptBiError = C::AcceptBiError;
+
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> =
+        self::AcceptBiFuture<'a, C, InnerIn, InnerOut, In, Out>;
+
+    type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<C, InnerIn, InnerOut, In, Out>;
+
+    fn reset<M: RpcMessage>(send: &mut Self::SendSink<M>, code: u32) {
+        C::reset(&mut send.inner, code)
+    }
+
+    fn set_pattern<M: RpcMessage>(send: &mut Self::SendSink<M>, pattern: PatternKind) {
+        C::set_pattern(&mut send.inner, pattern)
+    }
+
+    fn set_priority<M: RpcMessage>(send: &mut Self::SendSink<M>, priority: i32) {
+        C::set_priority(&mut send.inner, priority)
+    }
+
+    fn correlation_id<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<u64> {
+        C::correlation_id(&recv.inner)
+    }
+
+    fn auth_token<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<Bytes> {
+        C::auth_token(&recv.inner)
+    }
+}
+
+impl<C, InnerIn, InnerOut, In, Out>
+    crate::Channel<In, Out, MappedChannelTypes<C, InnerIn, InnerOut>>
+    for Channel<C, InnerIn, InnerOut, In, Out>
+where
+    C: ChannelTypes,
+    InnerIn: RpcMessage,
+    InnerOut: RpcMessage,
+    In: RpcMessage,
+    Out: RpcMessage,
+{
+    fn open_bi(&self) -> OpenBiFuture<'_, C, InnerIn, InnerOut, In, Out> {
+        self.inner.open_bi().map_ok(|s| self.wrap(s)).boxed()
+    }
+
+    fn accept_bi(&self) -> AcceptBiFuture<'_, C, InnerIn, InnerOut, In, Out> {
+        self.inner.accept_bi().map_ok(|s| self.wrap(s)).boxed()
+    }
+
+    fn connection_info(&self) -> ConnectionInfo {
+        self.inner.connection_info()
+    }
+
+    fn close(&self, code: u32, reason: &[u8]) {
+        self.inner.close(code, reason)
+    }
+}
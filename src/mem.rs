@@ -3,27 +3,38 @@
 //! This is currently based on [flume], but since no flume types are exposed it can be changed to another
 //! mpmc channel implementation, like [crossbeam].
 //!
+//! [MemChannelTypes] passes messages by value, so it does not catch messages that fail to
+//! serialize or deserialize. [SerializedMemChannelTypes] round-trips every message through a
+//! [Codec] like the network transports, and is the recommended channel for integration tests.
+//!
 //! [flume]: https://docs.rs/flume/
 //! [crossbeam]: https://docs.rs/crossbeam/
-use crate::RpcMessage;
-use core::fmt;
-use futures::{Future, FutureExt, Sink, SinkExt, StreamExt};
+use crate::{
+    channel::CloseSignal,
+    codec::{BincodeCodec, Codec},
+    ChannelError, Retryable, RpcClient, RpcMessage, RpcServer, Service,
+};
+use futures::{future::BoxFuture, Future, FutureExt, Sink, SinkExt, StreamExt, TryFutureExt};
 use pin_project::pin_project;
-use std::{error, fmt::Display, pin::Pin, result, task::Poll};
+use std::{io, marker::PhantomData, pin::Pin, result, sync::Arc, task::Poll};
 
 /// Error when receiving from a channel
 ///
 /// This type has zero inhabitants, so it is always safe to unwrap a result with this error type.
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum RecvError {}
 
-impl fmt::Display for RecvError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl ChannelError for RecvError {
+    fn into_io(self) -> io::Error {
+        match self {}
     }
 }
 
-impl error::Error for RecvError {}
+impl Retryable for RecvError {
+    fn is_retryable(&self) -> bool {
+        match *self {}
+    }
+}
 
 /// RecvStream for mem channels
 pub struct RecvStream<Res: RpcMessage>(flume::r#async::RecvStream<'static, Res>);
@@ -49,12 +60,15 @@ impl<Res: RpcMessage> futures::Stream for RecvStream<Res> {
     }
 }
 
-type Socket<In, Out> = (self::SendSink<Out>, self::RecvStream<In>);
+/// A bidirectional stream of a mem channel: a sink for outgoing and a stream of incoming messages
+pub type Socket<In, Out> = (self::SendSink<Out>, self::RecvStream<In>);
 
 /// A mem channel
 pub struct Channel<In: RpcMessage, Out: RpcMessage> {
     stream: flume::Receiver<Socket<In, Out>>,
     sink: flume::Sender<Socket<Out, In>>,
+    /// Shared by both ends of the connection
+    closed: Arc<CloseSignal>,
 }
 
 impl<In: RpcMessage, Out: RpcMessage> Clone for Channel<In, Out> {
@@ -62,6 +76,7 @@ impl<In: RpcMessage, Out: RpcMessage> Clone for Channel<In, Out> {
         Self {
             stream: self.stream.clone(),
             sink: self.sink.clone(),
+            closed: self.closed.clone(),
         }
     }
 }
@@ -69,32 +84,40 @@ impl<In: RpcMessage, Out: RpcMessage> Clone for Channel<In, Out> {
 /// AcceptBiError for mem channels.
 ///
 /// There is not much that can go wrong with mem channels.
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum AcceptBiError {
     /// The remote side of the channel was dropped
+    #[error("the remote side of the channel was dropped")]
     RemoteDropped,
+    /// The connection was closed, see [crate::Channel::close]
+    #[error("the connection was closed")]
+    Closed,
 }
 
-impl fmt::Display for AcceptBiError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl ChannelError for AcceptBiError {
+    fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::NotConnected, self)
     }
 }
 
-impl error::Error for AcceptBiError {}
-
 /// Future returned by accept_bi
 #[pin_project]
 pub struct OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> {
     #[pin]
     inner: flume::r#async::SendFut<'a, Socket<Out, In>>,
+    closed: flume::r#async::RecvFut<'a, ()>,
     res: Option<Socket<In, Out>>,
 }
 
 impl<'a, In: RpcMessage, Out: RpcMessage> OpenBiFuture<'a, In, Out> {
-    fn new(inner: flume::r#async::SendFut<'a, Socket<Out, In>>, res: Socket<In, Out>) -> Self {
+    fn new(
+        inner: flume::r#async::SendFut<'a, Socket<Out, In>>,
+        closed: flume::r#async::RecvFut<'a, ()>,
+        res: Socket<In, Out>,
+    ) -> Self {
         Self {
             inner,
+            closed,
             res: Some(res),
         }
     }
@@ -108,6 +131,9 @@ impl<'a, In: RpcMessage, Out: RpcMessage> Future for OpenBiFuture<'a, In, Out> {
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Self::Output> {
         let mut this = self.project();
+        if this.closed.poll_unpin(cx).is_ready() {
+            return Poll::Ready(Err(self::OpenBiError::Closed));
+        }
         match this.inner.poll_unpin(cx) {
             Poll::Ready(Ok(())) => this
                 .res
@@ -123,6 +149,7 @@ impl<'a, In: RpcMessage, Out: RpcMessage> Future for OpenBiFuture<'a, In, Out> {
 /// Future returned by accept_bi
 pub struct AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage>(
     flume::r#async::RecvFut<'a, Socket<In, Out>>,
+    flume::r#async::RecvFut<'a, ()>,
 );
 
 impl<'a, In: RpcMessage, Out: RpcMessage> Future for AcceptBiFuture<'a, In, Out> {
@@ -132,6 +159,9 @@ impl<'a, In: RpcMessage, Out: RpcMessage> Future for AcceptBiFuture<'a, In, Out>
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Self::Output> {
+        if self.1.poll_unpin(cx).is_ready() {
+            return Poll::Ready(Err(AcceptBiError::Closed));
+        }
         match self.0.poll_unpin(cx) {
             Poll::Ready(Ok(socket)) => Poll::Ready(Ok(socket)),
             Poll::Ready(Err(_)) => Poll::Ready(Err(AcceptBiError::RemoteDropped)),
@@ -141,7 +171,19 @@ impl<'a, In: RpcMessage, Out: RpcMessage> Future for AcceptBiFuture<'a, In, Out>
 }
 
 /// SendSink for mem channels
-pub struct SendSink<Out: RpcMessage>(flume::r#async::SendSink<'static, Out>);
+///
+/// Closing the sink drops the sender, so the remote stream ends even if the sink is kept.
+pub struct SendSink<Out: RpcMessage>(Option<flume::r#async::SendSink<'static, Out>>);
+
+impl<Out: RpcMessage> SendSink<Out> {
+    fn new(sender: flume::Sender<Out>) -> Self {
+        Self(Some(sender.into_sink()))
+    }
+
+    fn inner(&mut self) -> Result<&mut flume::r#async::SendSink<'static, Out>, SendError> {
+        self.0.as_mut().ok_or(SendError::Closed)
+    }
+}
 
 impl<Out: RpcMessage> Sink<Out> for SendSink<Out> {
     type Error = SendError;
@@ -150,13 +192,13 @@ impl<Out: RpcMessage> Sink<Out> for SendSink<Out> {
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Result<(), Self::Error>> {
-        self.0
+        self.inner()?
             .poll_ready_unpin(cx)
             .map_err(|_| SendError::ReceiverDropped)
     }
 
     fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
-        self.0
+        self.inner()?
             .start_send_unpin(item)
             .map_err(|_| SendError::ReceiverDropped)
     }
@@ -165,52 +207,80 @@ impl<Out: RpcMessage> Sink<Out> for SendSink<Out> {
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Result<(), Self::Error>> {
-        self.0
-            .poll_flush_unpin(cx)
-            .map_err(|_| SendError::ReceiverDropped)
+        match self.0.as_mut() {
+            Some(sink) => sink
+                .poll_flush_unpin(cx)
+                .map_err(|_| SendError::ReceiverDropped),
+            None => Poll::Ready(Ok(())),
+        }
     }
 
     fn poll_close(
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Result<(), Self::Error>> {
-        self.0
-            .poll_close_unpin(cx)
-            .map_err(|_| SendError::ReceiverDropped)
+        if let Some(sink) = self.0.as_mut() {
+            futures::ready!(sink.poll_close_unpin(cx)).map_err(|_| SendError::ReceiverDropped)?;
+            self.0 = None;
+        }
+        Poll::Ready(Ok(()))
     }
 }
 
 /// SendError for mem channels.
 ///
 /// There is not much that can go wrong with mem channels.
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum SendError {
     /// Receiver was dropped
+    #[error("the receiver was dropped")]
     ReceiverDropped,
+    /// The sink was already closed
+    #[error("the sink was already closed")]
+    Closed,
 }
 
-impl Display for SendError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl ChannelError for SendError {
+    fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, self)
     }
 }
 
-impl std::error::Error for SendError {}
+impl Retryable for SendError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            // only the receiver of this stream is gone, a new stream might work
+            Self::ReceiverDropped => true,
+            Self::Closed => false,
+        }
+    }
+}
 
 /// OpenBiError for mem channels.
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum OpenBiError {
     /// The remote side of the channel was dropped
+    #[error("the remote side of the channel was dropped")]
     RemoteDropped,
+    /// The connection was closed, see [crate::Channel::close]
+    #[error("the connection was closed")]
+    Closed,
 }
 
-impl Display for OpenBiError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+impl ChannelError for OpenBiError {
+    fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::NotConnected, self)
     }
 }
 
-impl std::error::Error for OpenBiError {}
+impl Retryable for OpenBiError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            // the server side of the channel is gone for good
+            Self::RemoteDropped | Self::Closed => false,
+        }
+    }
+}
 
 /// Types for mem channels.
 #[derive(Debug, Clone, Copy)]
@@ -244,14 +314,25 @@ impl<In: RpcMessage, Out: RpcMessage> crate::Channel<In, Out, MemChannelTypes>
         let (remote_send, local_recv) = flume::bounded::<In>(128);
         let remote_recv = RecvStream(remote_recv.into_stream());
         let local_recv = RecvStream(local_recv.into_stream());
-        let remote_send = SendSink(remote_send.into_sink());
-        let local_send = SendSink(local_send.into_sink());
+        let remote_send = SendSink::new(remote_send);
+        let local_send = SendSink::new(local_send);
         let inner = self.sink.send_async((remote_send, remote_recv));
-        OpenBiFuture::new(inner, (local_send, local_recv))
+        OpenBiFuture::new(inner, self.closed.closed(), (local_send, local_recv))
     }
 
     fn accept_bi(&self) -> AcceptBiFuture<'_, In, Out> {
-        AcceptBiFuture(self.stream.recv_async())
+        if self.closed.is_closed() {
+            // fail the streams that were opened before the close, but not accepted
+            self.stream.drain();
+        }
+        AcceptBiFuture(self.stream.recv_async(), self.closed.closed())
+    }
+
+    fn close(&self, _code: u32, _reason: &[u8]) {
+        // streams that are already open are not affected, and there is no remote to send the
+        // code and reason to
+        self.closed.close();
+        self.stream.drain();
     }
 }
 
@@ -263,14 +344,255 @@ pub fn connection<Req: RpcMessage, Res: RpcMessage>(
 ) -> (Channel<Req, Res>, Channel<Res, Req>) {
     let (send1, recv1) = flume::bounded::<Socket<Req, Res>>(buffer);
     let (send2, recv2) = flume::bounded::<Socket<Res, Req>>(buffer);
+    let closed = Arc::new(CloseSignal::default());
     (
         Channel {
             stream: recv1,
             sink: send2,
+            closed: closed.clone(),
         },
         Channel {
             stream: recv2,
             sink: send1,
+            closed,
         },
     )
 }
+
+/// Create a connected client and server for service `S` using mem channels
+///
+/// `buffer` the size of the buffer for each channel, see [connection].
+pub fn service_connection<S: Service>(
+    buffer: usize,
+) -> (RpcClient<S, MemChannelTypes>, RpcServer<S, MemChannelTypes>) {
+    let (server, client) = connection::<S::Req, S::Res>(buffer);
+    (RpcClient::new(client), RpcServer::new(server))
+}
+
+/// A mem channel that sends every message serialized with the codec `K`
+pub struct SerializedChannel<In: RpcMessage, Out: RpcMessage, K: Codec = BincodeCodec>(
+    Channel<Vec<u8>, Vec<u8>>,
+    PhantomData<(In, Out, K)>,
+);
+
+impl<In: RpcMessage, Out: RpcMessage, K: Codec> Clone for SerializedChannel<In, Out, K> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), PhantomData)
+    }
+}
+
+/// SendSink for serialized mem channels
+pub struct SerializedSendSink<Out: RpcMessage, K: Codec>(SendSink<Vec<u8>>, PhantomData<(Out, K)>);
+
+impl<Out: RpcMessage, K: Codec> Sink<Out> for SerializedSendSink<Out, K> {
+    type Error = SerializedSendError;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.0
+            .poll_ready_unpin(cx)
+            .map_err(SerializedSendError::Send)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        let data = K::encode(&item).map_err(SerializedSendError::Encode)?;
+        self.0
+            .start_send_unpin(data)
+            .map_err(SerializedSendError::Send)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.0
+            .poll_flush_unpin(cx)
+            .map_err(SerializedSendError::Send)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.0
+            .poll_close_unpin(cx)
+            .map_err(SerializedSendError::Send)
+    }
+}
+
+/// RecvStream for serialized mem channels
+pub struct SerializedRecvStream<In: RpcMessage, K: Codec>(
+    RecvStream<Vec<u8>>,
+    PhantomData<(In, K)>,
+);
+
+impl<In: RpcMessage, K: Codec> futures::Stream for SerializedRecvStream<In, K> {
+    type Item = Result<In, SerializedRecvError>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        Poll::Ready(match futures::ready!(self.0.poll_next_unpin(cx)) {
+            Some(Ok(data)) => Some(K::decode(&data).map_err(SerializedRecvError::Decode)),
+            Some(Err(e)) => match e {},
+            None => None,
+        })
+    }
+}
+
+/// SendError for serialized mem channels
+#[derive(Debug, thiserror::Error)]
+pub enum SerializedSendError {
+    /// Error of the underlying mem channel
+    #[error("failed to send the serialized message")]
+    Send(#[source] SendError),
+    /// The message could not be serialized
+    #[error("failed to serialize the message")]
+    Encode(#[source] io::Error),
+}
+
+impl ChannelError for SerializedSendError {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::Send(e) => e.into_io(),
+            Self::Encode(e) => e,
+        }
+    }
+}
+
+impl Retryable for SerializedSendError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Send(e) => e.is_retryable(),
+            // the message will not serialize any better next time
+            Self::Encode(_) => false,
+        }
+    }
+}
+
+/// RecvError for serialized mem channels
+#[derive(Debug, thiserror::Error)]
+pub enum SerializedRecvError {
+    /// The message could not be deserialized
+    #[error("failed to deserialize the message")]
+    Decode(#[source] io::Error),
+}
+
+impl ChannelError for SerializedRecvError {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::Decode(e) => e,
+        }
+    }
+}
+
+impl Retryable for SerializedRecvError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Decode(_) => false,
+        }
+    }
+}
+
+/// A bidirectional stream of a serialized mem channel
+pub type SerializedSocket<In, Out, K> = (SerializedSendSink<Out, K>, SerializedRecvStream<In, K>);
+
+/// Future returned by open_bi of serialized mem channels
+pub type SerializedOpenBiFuture<'a, In, Out, K> =
+    BoxFuture<'a, result::Result<SerializedSocket<In, Out, K>, OpenBiError>>;
+
+/// Future returned by accept_bi of serialized mem channels
+pub type SerializedAcceptBiFuture<'a, In, Out, K> =
+    BoxFuture<'a, result::Result<SerializedSocket<In, Out, K>, AcceptBiError>>;
+
+/// Types for mem channels that serialize every message with the codec `K`
+///
+/// Messages are serialized when they are sent and deserialized when they are received, like
+/// with the network transports, so messages that don't survive the wire format fail with
+/// [SerializedSendError::Encode] or [SerializedRecvError::Decode]. Use [serialized_connection]
+/// or [serialized_service_connection] to create a connection.
+#[derive(Debug, Clone, Copy)]
+pub struct SerializedMemChannelTypes<K: Codec = BincodeCodec>(PhantomData<K>);
+
+impl<K: Codec> crate::ChannelTypes for SerializedMemChannelTypes<K> {
+    type SendSink<M: RpcMessage> = self::SerializedSendSink<M, K>;
+
+    type RecvStream<M: RpcMessage> = self::SerializedRecvStream<M, K>;
+
+    type SendError = self::SerializedSendError;
+
+    type RecvError = self::SerializedRecvError;
+
+    type OpenBiError = self::OpenBiError;
+
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> =
+        self::SerializedOpenBiFuture<'a, In, Out, K>;
+
+    type AcceptBiError = self::AcceptBiError;
+
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> =
+        self::SerializedAcceptBiFuture<'a, In, Out, K>;
+
+    type Channel<In: RpcMessage, Out: RpcMessage> = self::SerializedChannel<In, Out, K>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage, K: Codec>
+    crate::Channel<In, Out, SerializedMemChannelTypes<K>> for SerializedChannel<In, Out, K>
+{
+    fn open_bi(&self) -> SerializedOpenBiFuture<'_, In, Out, K> {
+        crate::Channel::open_bi(&self.0)
+            .map_ok(wrap_serialized)
+            .boxed()
+    }
+
+    fn accept_bi(&self) -> SerializedAcceptBiFuture<'_, In, Out, K> {
+        crate::Channel::accept_bi(&self.0)
+            .map_ok(wrap_serialized)
+            .boxed()
+    }
+
+    fn close(&self, code: u32, reason: &[u8]) {
+        crate::Channel::close(&self.0, code, reason)
+    }
+}
+
+fn wrap_serialized<In: RpcMessage, Out: RpcMessage, K: Codec>(
+    (send, recv): Socket<Vec<u8>, Vec<u8>>,
+) -> SerializedSocket<In, Out, K> {
+    (
+        SerializedSendSink(send, PhantomData),
+        SerializedRecvStream(recv, PhantomData),
+    )
+}
+
+/// Create a channel pair (server, client) for serialized mem channels
+///
+/// `buffer` the size of the buffer for each channel, see [connection].
+pub fn serialized_connection<Req: RpcMessage, Res: RpcMessage, K: Codec>(
+    buffer: usize,
+) -> (
+    SerializedChannel<Req, Res, K>,
+    SerializedChannel<Res, Req, K>,
+) {
+    let (server, client) = connection::<Vec<u8>, Vec<u8>>(buffer);
+    (
+        SerializedChannel(server, PhantomData),
+        SerializedChannel(client, PhantomData),
+    )
+}
+
+/// Create a connected client and server for service `S` using serialized mem channels
+///
+/// `buffer` the size of the buffer for each channel, see [connection].
+pub fn serialized_service_connection<S: Service, K: Codec>(
+    buffer: usize,
+) -> (
+    RpcClient<S, SerializedMemChannelTypes<K>>,
+    RpcServer<S, SerializedMemChannelTypes<K>>,
+) {
+    let (server, client) = serialized_connection::<S::Req, S::Res, K>(buffer);
+    (RpcClient::new(client), RpcServer::new(server))
+}
@@ -1,6 +1,7 @@
 //! Traits to define the behaviour of messages for services
 use crate::Service;
-use std::fmt::Debug;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Debug, result};
 
 /// Defines interaction pattern, update type and return type for a RPC message
 ///
@@ -37,29 +38,203 @@ impl<S: Service, T: RpcMsg<S>> Msg<S> for T {
 
 /// Trait defining interaction pattern.
 ///
-/// Currently there are 4 patterns:
+/// Currently there are 5 patterns:
 /// - `RPC`: 1 request, 1 response
+/// - `Notify`: 1 request, no response
 /// - `ClientStreaming`: 1 request, stream of updates, 1 response
 /// - `ServerStreaming`: 1 request, stream of responses
 /// - `BidiStreaming`: 1 request, stream of updates, stream of responses
-pub trait InteractionPattern: Debug + Clone + Send + Sync + 'static {}
+pub trait InteractionPattern: Debug + Clone + Send + Sync + 'static {
+    /// Runtime representation of this interaction pattern
+    const KIND: PatternKind;
+}
+
+/// Runtime representation of an [InteractionPattern]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PatternKind {
+    /// See [Rpc]
+    Rpc,
+    /// See [ClientStreaming]
+    ClientStreaming,
+    /// See [ServerStreaming]
+    ServerStreaming,
+    /// See [BidiStreaming]
+    BidiStreaming,
+    /// See [Notify]
+    Notify,
+}
+
+impl PatternKind {
+    /// The pattern of message `M` for service `S`
+    pub fn of<S: Service, M: Msg<S>>() -> Self {
+        M::Pattern::KIND
+    }
+
+    /// True if the client sends updates after the initial request for this pattern
+    pub fn has_updates(self) -> bool {
+        matches!(self, Self::ClientStreaming | Self::BidiStreaming)
+    }
+}
 
 /// RPC interaction pattern
 #[derive(Debug, Clone, Copy)]
 pub struct Rpc;
-impl InteractionPattern for Rpc {}
+impl InteractionPattern for Rpc {
+    const KIND: PatternKind = PatternKind::Rpc;
+}
 
 /// Client streaming interaction pattern
 #[derive(Debug, Clone, Copy)]
 pub struct ClientStreaming;
-impl InteractionPattern for ClientStreaming {}
+impl InteractionPattern for ClientStreaming {
+    const KIND: PatternKind = PatternKind::ClientStreaming;
+}
 
 /// Server streaming interaction pattern
 #[derive(Debug, Clone, Copy)]
 pub struct ServerStreaming;
-impl InteractionPattern for ServerStreaming {}
+impl InteractionPattern for ServerStreaming {
+    const KIND: PatternKind = PatternKind::ServerStreaming;
+}
 
 /// Bidirectional streaming interaction pattern
 #[derive(Debug, Clone, Copy)]
 pub struct BidiStreaming;
-impl InteractionPattern for BidiStreaming {}
+impl InteractionPattern for BidiStreaming {
+    const KIND: PatternKind = PatternKind::BidiStreaming;
+}
+
+/// Marker for requests that can safely be processed more than once
+///
+/// Only requests with this marker are retried by [crate::client::RetryingClient].
+pub trait Idempotent: Clone {}
+
+/// Notify interaction pattern, see [NotifyMsg]
+#[derive(Debug, Clone, Copy)]
+pub struct Notify;
+impl InteractionPattern for Notify {
+    const KIND: PatternKind = PatternKind::Notify;
+}
+
+/// A message that does not get a response, for fire and forget messages such as telemetry
+///
+/// Since there is no response type, this is not a [Msg]. Notifications are sent using
+/// [crate::RpcClient::notify] and handled using [crate::RpcServer::notify].
+pub trait NotifyMsg<S: Service>: Into<S::Req> + TryFrom<S::Req> + Send + 'static {}
+
+/// Control message a client can send on a server streaming interaction
+///
+/// See [crate::RpcClient::server_streaming_controlled] and
+/// [crate::RpcServer::server_streaming_controlled]. To use it, the request enum of the service
+/// needs to contain a variant for this type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StreamControl {
+    /// Stop sending responses until [StreamControl::Resume] is received
+    Pause,
+    /// Continue sending responses
+    Resume,
+}
+
+/// Request to cancel an interaction, sent by the client in place of an update
+///
+/// See [crate::client::UpdateSink::cancel] and [crate::client::StreamController::cancel]. The
+/// server aborts the handler and fails the call with
+/// [crate::server::RpcServerError::Cancelled]. To use it, the request enum of the service needs
+/// to contain a variant for this type, and the service needs to recognize it in
+/// [crate::Service::is_cancel].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cancel;
+
+/// Keepalive sent by the client on a server streaming call that did not get data for a while
+///
+/// See [crate::RpcClient::server_streaming_with_keepalive]. Sending it makes the transport notice
+/// if the server is gone, and the server ignores it. To use it, the request enum of the service
+/// needs to contain a variant for this type, and the service needs to recognize it in
+/// [crate::Service::is_keepalive].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keepalive;
+
+/// What a server does with the responses of a paused stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PausePolicy {
+    /// Stop polling the handler while paused, so no items are lost
+    #[default]
+    Buffer,
+    /// Keep polling the handler while paused and drop the items, e.g. for live views
+    Drop,
+}
+
+/// Request wrapper to resume a server streaming interaction from a given item index
+///
+/// To make a server streaming message `M` resumable, implement [Msg] for `ResumeFrom<M>` with
+/// [ServerStreaming] as the pattern and [Indexed] responses, and handle it on the server using
+/// [crate::RpcServer::server_streaming_resumable]. A client that lost the connection can then
+/// resubscribe with the index after the last [Indexed] response it has seen.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeFrom<M> {
+    /// Index of the first response item the client wants to receive
+    pub offset: u64,
+    /// The original request
+    pub msg: M,
+}
+
+impl<M> ResumeFrom<M> {
+    /// Request the whole stream
+    pub fn start(msg: M) -> Self {
+        Self { offset: 0, msg }
+    }
+}
+
+/// Response item of a resumable server streaming interaction, see [ResumeFrom]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Indexed<T> {
+    /// Index of the item within the stream, counting from the start of the stream
+    pub index: u64,
+    /// The response item
+    pub item: T,
+}
+
+/// Request wrapper for requests that need to be processed in the order they were issued
+///
+/// To make an rpc message `M` orderable, implement [RpcMsg] for `Sequenced<M>` and handle it on
+/// the server using [crate::RpcServer::rpc_ordered]. The sequence number is assigned by
+/// [crate::client::OrderedClient].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sequenced<M> {
+    /// Position of the request in the order of the client, starting at 0
+    pub seq: u64,
+    /// The original request
+    pub msg: M,
+}
+
+/// Application defined control frame that can be sent alongside the messages of a bidi stream
+///
+/// The crate does not interpret control frames, so they can be used to build protocol extensions
+/// such as acks or pings in user code. See [crate::RpcClient::bidi_with_control] and
+/// [crate::RpcServer::bidi_streaming_with_control]. To use them, the request and response enums
+/// of the service need to contain a variant for this type and implement [SplitControl].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ControlFrame {
+    /// Application defined frame type
+    pub code: u32,
+    /// Opaque payload
+    pub payload: Vec<u8>,
+}
+
+/// Item of a stream that carries both messages and [ControlFrame]s
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame<T> {
+    /// A regular message
+    Data(T),
+    /// A control frame
+    Control(ControlFrame),
+}
+
+/// Separates control frames from the other messages of a request or response enum
+///
+/// Unlike `TryFrom`, this gives back the message if it is not a control frame, so it can still
+/// be converted to the expected message type.
+pub trait SplitControl: Sized {
+    /// Returns the control frame, or the message itself if it is not a control frame
+    fn split_control(self) -> result::Result<ControlFrame, Self>;
+}
@@ -0,0 +1,141 @@
+//! Hooks to collect metrics about rpc calls, enabled with the `metrics` feature
+//!
+//! Set a [Metrics] implementation on a client or server using
+//! [crate::RpcClient::with_metrics] or [crate::RpcServer::with_metrics]. The hooks are called
+//! from the same places as the spans of the `tracing` feature, so a call counts as ended once
+//! its result is known, or as cancelled if it is dropped before that.
+use crate::message::PatternKind;
+use std::{
+    fmt::Debug,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// How a call ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    /// The call completed successfully
+    Ok,
+    /// The call failed
+    Err,
+    /// The call was dropped before it completed
+    Cancelled,
+}
+
+/// Callbacks for the start and end of every call
+///
+/// `message` is the type name of the request message. Implementations should be cheap, since
+/// they are called inline for every call.
+pub trait Metrics: Debug + Send + Sync + 'static {
+    /// A call was started
+    fn on_request_start(&self, message: &'static str, pattern: PatternKind);
+
+    /// A call ended after `duration`
+    fn on_request_end(
+        &self,
+        message: &'static str,
+        pattern: PatternKind,
+        duration: Duration,
+        outcome: Outcome,
+    );
+}
+
+/// Metrics that do nothing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {
+    fn on_request_start(&self, _message: &'static str, _pattern: PatternKind) {}
+
+    fn on_request_end(
+        &self,
+        _message: &'static str,
+        _pattern: PatternKind,
+        _duration: Duration,
+        _outcome: Outcome,
+    ) {
+    }
+}
+
+/// Counters for the calls of one pattern, see [AtomicMetrics::get]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PatternCounts {
+    /// Number of started calls
+    pub started: u64,
+    /// Number of calls that completed successfully
+    pub ok: u64,
+    /// Number of calls that failed
+    pub err: u64,
+    /// Number of calls that were dropped before they completed
+    pub cancelled: u64,
+    /// Sum of the durations of all ended calls
+    pub total_duration: Duration,
+}
+
+#[derive(Debug, Default)]
+struct AtomicCounts {
+    started: AtomicU64,
+    ok: AtomicU64,
+    err: AtomicU64,
+    cancelled: AtomicU64,
+    total_micros: AtomicU64,
+}
+
+/// Metrics that count the calls per pattern using atomics
+///
+/// This is meant as a simple starting point, e.g. to export totals to Prometheus periodically.
+#[derive(Debug, Default)]
+pub struct AtomicMetrics([AtomicCounts; 5]);
+
+impl AtomicMetrics {
+    /// Create new metrics with all counters at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current counters for calls with the given pattern
+    pub fn get(&self, pattern: PatternKind) -> PatternCounts {
+        let counts = self.counts(pattern);
+        PatternCounts {
+            started: counts.started.load(Ordering::Relaxed),
+            ok: counts.ok.load(Ordering::Relaxed),
+            err: counts.err.load(Ordering::Relaxed),
+            cancelled: counts.cancelled.load(Ordering::Relaxed),
+            total_duration: Duration::from_micros(counts.total_micros.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn counts(&self, pattern: PatternKind) -> &AtomicCounts {
+        &self.0[match pattern {
+            PatternKind::Rpc => 0,
+            PatternKind::ClientStreaming => 1,
+            PatternKind::ServerStreaming => 2,
+            PatternKind::BidiStreaming => 3,
+            PatternKind::Notify => 4,
+        }]
+    }
+}
+
+impl Metrics for AtomicMetrics {
+    fn on_request_start(&self, _message: &'static str, pattern: PatternKind) {
+        self.counts(pattern).started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_request_end(
+        &self,
+        _message: &'static str,
+        pattern: PatternKind,
+        duration: Duration,
+        outcome: Outcome,
+    ) {
+        let counts = self.counts(pattern);
+        let counter = match outcome {
+            Outcome::Ok => &counts.ok,
+            Outcome::Err => &counts.err,
+            Outcome::Cancelled => &counts.cancelled,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        counts.total_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,212 @@
+//! A pool of quinn connections to a single server
+//!
+//! Since quinn connections multiplex streams, all clients handed out by a [ClientPool] share a
+//! few connections instead of opening one each. The pool mostly takes care of replacing
+//! connections that failed or were closed, and of letting go of idle connections.
+use crate::{
+    codec::{BincodeCodec, Codec},
+    quinn::{Channel, QuinnChannelTypes},
+    Retryable, RpcClient, Service,
+};
+use std::{
+    fmt,
+    marker::PhantomData,
+    net::SocketAddr,
+    result,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+/// Configuration of a [ClientPool]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Maximum number of connections to open to the server
+    pub max_connections: usize,
+    /// A connection that was not handed out for this long is removed from the pool
+    ///
+    /// The connection is closed once the clients that are still using it are dropped.
+    pub idle_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 4,
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Error when getting a client from a [ClientPool]
+#[derive(Debug, thiserror::Error)]
+pub enum PoolError {
+    /// The connection could not be started, e.g. because of an invalid server name
+    #[error("failed to start the connection")]
+    Connect(#[source] quinn::ConnectError),
+    /// The connection failed during the handshake
+    #[error("the connection failed during the handshake")]
+    Connection(#[source] quinn::ConnectionError),
+}
+
+impl Retryable for PoolError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Connect(_) => false,
+            Self::Connection(e) => e.is_retryable(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    conn: quinn::Connection,
+    last_used: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: Vec<Entry>,
+    /// Number of connections that are being opened, each reserving a slot
+    connecting: usize,
+    next: usize,
+}
+
+/// The state of a pool, and a notification for when a connection attempt finished
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    connected: Notify,
+}
+
+/// A slot reserved for a connection that is being opened, released when dropped
+struct Reservation<'a>(&'a Shared);
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().connecting -= 1;
+        self.0.connected.notify_waiters();
+    }
+}
+
+/// A pool of quinn connections to a single server, handing out clients for service `S`
+///
+/// Connections are opened lazily, up to [PoolConfig::max_connections], and the clients are
+/// spread over them round robin. Connections that are closed, e.g. because the server went away,
+/// are replaced by new ones the next time a client is requested.
+pub struct ClientPool<S: Service, K: Codec = BincodeCodec> {
+    endpoint: quinn::Endpoint,
+    addr: SocketAddr,
+    server_name: Arc<str>,
+    config: PoolConfig,
+    shared: Arc<Shared>,
+    _s: PhantomData<(S, K)>,
+}
+
+impl<S: Service, K: Codec> ClientPool<S, K> {
+    /// Create a pool of connections to the server at `addr`
+    ///
+    /// `server_name` is the name used to verify the certificate of the server. No connection is
+    /// opened until the first client is requested.
+    pub fn new(
+        endpoint: quinn::Endpoint,
+        addr: SocketAddr,
+        server_name: &str,
+        config: PoolConfig,
+    ) -> Self {
+        Self {
+            endpoint,
+            addr,
+            server_name: server_name.into(),
+            config,
+            shared: Default::default(),
+            _s: PhantomData,
+        }
+    }
+
+    /// Get a client using one of the connections of the pool, connecting if needed
+    ///
+    /// The pool is not locked while connecting, so other callers can use the existing
+    /// connections in the meantime. If all slots are taken by connections that are still being
+    /// opened, this waits for one of them.
+    pub async fn get(&self) -> result::Result<RpcClient<S, QuinnChannelTypes<K>>, PoolError> {
+        let shared = &*self.shared;
+        let reservation = loop {
+            let connected = shared.connected.notified();
+            {
+                let mut state = shared.state.lock().unwrap();
+                let idle_timeout = self.config.idle_timeout;
+                state.entries.retain(|e| {
+                    e.conn.close_reason().is_none() && e.last_used.elapsed() < idle_timeout
+                });
+                if state.entries.len() + state.connecting < self.config.max_connections.max(1) {
+                    state.connecting += 1;
+                    break Reservation(shared);
+                }
+                if !state.entries.is_empty() {
+                    let index = state.next % state.entries.len();
+                    return Ok(Self::use_entry(&mut state, index));
+                }
+            }
+            connected.await;
+        };
+        let conn = self
+            .endpoint
+            .connect(self.addr, &self.server_name)
+            .map_err(PoolError::Connect)?
+            .await
+            .map_err(PoolError::Connection)?;
+        let client = {
+            let mut state = shared.state.lock().unwrap();
+            state.entries.push(Entry {
+                conn,
+                last_used: Instant::now(),
+            });
+            let index = state.entries.len() - 1;
+            Self::use_entry(&mut state, index)
+        };
+        drop(reservation);
+        Ok(client)
+    }
+
+    /// Hand out a client for the connection at `index`, and continue round robin after it
+    fn use_entry(state: &mut State, index: usize) -> RpcClient<S, QuinnChannelTypes<K>> {
+        state.next = index + 1;
+        let entry = &mut state.entries[index];
+        entry.last_used = Instant::now();
+        RpcClient::new(Channel::new(entry.conn.clone()))
+    }
+
+    /// Number of connections currently in the pool
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().unwrap().entries.len()
+    }
+
+    /// True if the pool currently has no connections
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<S: Service, K: Codec> Clone for ClientPool<S, K> {
+    fn clone(&self) -> Self {
+        Self {
+            endpoint: self.endpoint.clone(),
+            addr: self.addr,
+            server_name: self.server_name.clone(),
+            config: self.config,
+            shared: self.shared.clone(),
+            _s: PhantomData,
+        }
+    }
+}
+
+impl<S: Service, K: Codec> fmt::Debug for ClientPool<S, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientPool")
+            .field("addr", &self.addr)
+            .field("server_name", &self.server_name)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,97 @@
+//! Built-in probe RPC to measure round trip time and clock skew
+//!
+//! Like [reflection](crate::reflect), this is opt in. A service adds [Probe] to its request enum
+//! and [ProbeResponse] to its response enum, and answers [Probe] requests with
+//! [RpcServer::probe]. Clients can then call [RpcClient::probe] on any such service.
+use crate::{
+    client::RpcClientError,
+    message::{Msg, Rpc, RpcMsg},
+    server::{RpcServerError, ServerSocket},
+    ChannelTypes, RpcClient, RpcServer, Service,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    result,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Request to answer with the current time of the server
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Probe;
+
+/// Response to a [Probe] request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbeResponse {
+    /// Wall clock time of the server when answering the probe
+    pub server_time: SystemTime,
+}
+
+/// Result of [RpcClient::probe]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeResult {
+    /// Round trip time of the probe, including opening the stream
+    pub rtt: Duration,
+    /// Wall clock time of the server when answering the probe
+    pub server_time: SystemTime,
+    /// Wall clock time of the client halfway between sending the probe and getting the response
+    pub local_time: SystemTime,
+}
+
+impl ProbeResult {
+    /// Estimated clock skew of the server relative to the client
+    ///
+    /// Returns `Ok` if the server clock is ahead of the client clock, `Err` if it is behind.
+    pub fn clock_skew(&self) -> result::Result<Duration, Duration> {
+        match self.server_time.duration_since(self.local_time) {
+            Ok(ahead) => Ok(ahead),
+            Err(behind) => Err(behind.duration()),
+        }
+    }
+}
+
+impl<S: Service> RpcMsg<S> for Probe
+where
+    Probe: Into<S::Req> + TryFrom<S::Req>,
+    ProbeResponse: Into<S::Res> + TryFrom<S::Res>,
+{
+    type Response = ProbeResponse;
+}
+
+impl<S: Service, C: ChannelTypes> RpcClient<S, C>
+where
+    Probe: Msg<S, Pattern = Rpc, Response = ProbeResponse>,
+{
+    /// Measure the round trip time to the server and get its current time
+    ///
+    /// This only works if the server answers probes, see [RpcServer::probe].
+    pub async fn probe(&self) -> result::Result<ProbeResult, RpcClientError<C>> {
+        let start = Instant::now();
+        let start_time = SystemTime::now();
+        let ProbeResponse { server_time } = self.rpc(Probe).await?;
+        let rtt = start.elapsed();
+        Ok(ProbeResult {
+            rtt,
+            server_time,
+            local_time: start_time + rtt / 2,
+        })
+    }
+}
+
+impl<S: Service, C: ChannelTypes> RpcServer<S, C>
+where
+    Probe: Msg<S, Pattern = Rpc, Response = ProbeResponse>,
+{
+    /// Answer a [Probe] request with the current time
+    pub async fn probe(
+        &self,
+        req: Probe,
+        c: ServerSocket<S, C>,
+    ) -> result::Result<(), RpcServerError<C>> {
+        self.rpc(req, c, (), |_, _| async move {
+            ProbeResponse {
+                server_time: SystemTime::now(),
+            }
+        })
+        .await
+    }
+}
@@ -1,43 +1,938 @@
 //! QUIC channel implementation based on quinn
-use crate::RpcMessage;
-use futures::{Future, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use crate::{
+    client::RpcClientError,
+    codec::{BincodeCodec, ChannelCodec, MessageCodec, SerdeCodec},
+    message::{Idempotent, Msg, PatternKind, Rpc},
+    server::{RpcServerError, RpcServerErrorKind},
+    AcceptUniFuture, ByteCounted, ByteCounts, ChannelError, ConnectionInfo, OpenBiWithError,
+    OpenUniWithFuture, Retryable, RpcClient, RpcMessage, RpcServer, Service,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{
+    channel::oneshot, stream::BoxStream, Future, FutureExt, Sink, SinkExt, Stream, StreamExt,
+};
 use pin_project::pin_project;
-use serde::{de::DeserializeOwned, Serialize};
-use std::{io, marker::PhantomData, pin::Pin, result};
-use tokio_serde::{formats::SymmetricalBincode, SymmetricallyFramed};
-use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, io,
+    marker::PhantomData,
+    net::SocketAddr,
+    pin::Pin,
+    result,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    task::Waker,
+    time::Duration,
+};
+use tokio::io::AsyncWrite;
 
-type Socket<In, Out> = (SendSink<Out>, RecvStream<In>);
+pub use quinn_proto::ConnectionStats;
+use tokio_util::codec::{Decoder, FramedRead, FramedWrite, LengthDelimitedCodec};
+
+/// A bidirectional stream of a quinn channel: a sink for outgoing and a stream of incoming messages
+pub type Socket<In, Out, K = BincodeCodec> = (SendSink<Out, K>, RecvStream<In, K>);
+
+/// A bidirectional stream of a quinn channel that sends and receives frames without the codec
+pub type RawSocket = (RawSendSink, RawRecvStream);
+
+/// A bidirectional stream of a quinn channel for messages encoded using a
+/// [MessageCodec], see [Channel::open_with_codec]
+pub type CodecSocket<In, Out, D, E> = (CodecSendSink<Out, E>, CodecRecvStream<In, D>);
+
+fn raw_socket(
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+    config: &StreamConfig,
+) -> RawSocket {
+    let counts = ByteCounts::default();
+    (
+        RawSendSink::new(send, config, counts.clone()),
+        RawRecvStream::new(recv, config, counts),
+    )
+}
+
+fn codec_socket<In, Out, D, E>(
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+    config: &StreamConfig,
+    decoder: D,
+    encoder: E,
+) -> CodecSocket<In, Out, D, E> {
+    let counts = ByteCounts::default();
+    (
+        CodecSendSink::new(send, config, counts.clone(), encoder),
+        CodecRecvStream::new(recv, config, counts, decoder),
+    )
+}
+
+/// Settings for the streams of a channel
+#[derive(Debug, Clone)]
+struct StreamConfig {
+    max_frame_size: usize,
+    /// Limit for messages that span several frames, `None` if messages are not chunked
+    max_message_size: Option<usize>,
+    write_coalescing: usize,
+    scheduler: Option<FairScheduler>,
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_message_size: None,
+            write_coalescing: DEFAULT_WRITE_COALESCING,
+            scheduler: None,
+            #[cfg(feature = "compression")]
+            compression: None,
+        }
+    }
+}
 
 /// A channel using a quinn connection
+///
+/// Messages are serialized using the [ChannelCodec] `K`, with one instance for decoding incoming
+/// and one for encoding outgoing messages, see [Channel::new_with_codec].
 #[derive(Debug)]
-pub struct Channel<In: RpcMessage, Out: RpcMessage>(quinn::Connection, PhantomData<(In, Out)>);
+pub struct Channel<In: RpcMessage, Out: RpcMessage, K: ChannelCodec = BincodeCodec>(
+    quinn::Connection,
+    Arc<DatagramDemux>,
+    StreamConfig,
+    Codecs<K>,
+    PhantomData<(In, Out)>,
+);
 
-impl<In: RpcMessage, Out: RpcMessage> Channel<In, Out> {
+/// The codecs of a channel, one per direction
+#[derive(Debug, Clone)]
+struct Codecs<K> {
+    decoder: K,
+    encoder: K,
+}
+
+impl<K: ChannelCodec> Codecs<K> {
+    fn serde(&self) -> (SerdeCodec<K>, SerdeCodec<K>) {
+        (
+            SerdeCodec(self.decoder.clone()),
+            SerdeCodec(self.encoder.clone()),
+        )
+    }
+}
+
+/// Default for [Channel::with_max_frame_size]
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Default for [Channel::with_write_coalescing]
+pub const DEFAULT_WRITE_COALESCING: usize = 8 * 1024;
+
+impl<In: RpcMessage, Out: RpcMessage, K: ChannelCodec + Default> Channel<In, Out, K> {
     /// Create a new channel
     pub fn new(conn: quinn::Connection) -> Self {
-        Self(conn, PhantomData)
+        Self::new_with_codec(conn, K::default(), K::default())
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, K: ChannelCodec> Channel<In, Out, K> {
+    /// Create a new channel that decodes incoming messages using `decoder` and encodes outgoing
+    /// messages using `encoder`
+    ///
+    /// Both sides of the connection need to use matching codecs. The codecs are used for all
+    /// streams and datagrams of the channel, including the ones opened by an [RpcClient] or
+    /// accepted by an [RpcServer] using it.
+    pub fn new_with_codec(conn: quinn::Connection, decoder: K, encoder: K) -> Self {
+        let codecs = Codecs { decoder, encoder };
+        Self(
+            conn,
+            Default::default(),
+            Default::default(),
+            codecs,
+            PhantomData,
+        )
+    }
+
+    /// Set the maximum size of a received frame, in bytes
+    ///
+    /// Every message is sent as a single frame, unless chunking is enabled, see
+    /// [Channel::with_max_message_size]. Receiving a larger frame fails with
+    /// [RecvError::FrameTooLarge]. The size is checked using the length prefix of the frame,
+    /// before any memory for it is allocated. The default is [DEFAULT_MAX_FRAME_SIZE].
+    pub fn with_max_frame_size(mut self, limit: usize) -> Self {
+        self.2.max_frame_size = limit;
+        self
+    }
+
+    /// Split messages that are larger than a frame into several frames, up to `limit` bytes
+    ///
+    /// Without this, every message has to fit into a single frame, see
+    /// [Channel::with_max_frame_size]. With chunking, every frame starts with a header byte that
+    /// tells whether more frames of the same message follow, and the frames are reassembled
+    /// before the message is decoded. This keeps the frame size small, while allowing larger
+    /// messages that are bounded separately. Receiving a message larger than `limit` fails with
+    /// [RecvError::MessageTooLarge].
+    ///
+    /// Both sides need to enable chunking. Messages are split using the max frame size of the
+    /// sending side, so both sides should use the same max frame size. With compression, the
+    /// limit applies to the decompressed message.
+    pub fn with_max_message_size(mut self, limit: usize) -> Self {
+        self.2.max_message_size = Some(limit);
+        self
+    }
+
+    /// Set how many bytes of frames are buffered before they are written to the quinn stream
+    ///
+    /// Frames that are fed to a sink without flushing, e.g. using [futures::SinkExt::feed] or
+    /// [futures::SinkExt::send_all], are combined into a single write once `threshold` bytes
+    /// are buffered. Flushing the sink writes all buffered frames regardless of the threshold,
+    /// so [futures::SinkExt::send], which flushes after every item, does not coalesce. A
+    /// threshold of 0 writes every frame on its own. The default is [DEFAULT_WRITE_COALESCING].
+    pub fn with_write_coalescing(mut self, threshold: usize) -> Self {
+        self.2.write_coalescing = threshold;
+        self
+    }
+
+    /// Take turns writing to the streams of this channel, see [FairScheduler]
+    ///
+    /// The scheduler is shared with all clones of this channel.
+    pub fn with_fair_scheduler(mut self, scheduler: FairScheduler) -> Self {
+        self.2.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Compress large frames on the streams of this channel, see [Compression]
+    ///
+    /// The remote has to enable compression as well, since compressed channels add a header to
+    /// every frame. Datagrams are not compressed.
+    #[cfg(feature = "compression")]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.2.compression = Some(compression);
+        self
     }
 }
 
-impl<In: RpcMessage, Out: RpcMessage> Clone for Channel<In, Out> {
+impl<In: RpcMessage, Out: RpcMessage, K: ChannelCodec> Channel<In, Out, K> {
+    /// Open a bidirectional stream that sends and receives frames without the codec
+    ///
+    /// The frames use the same length prefix as messages, but their content is not interpreted.
+    /// The other side has to accept the stream using [Channel::accept_raw], since the frames
+    /// can not be decoded as messages.
+    pub async fn open_raw(&self) -> result::Result<RawSocket, OpenBiError> {
+        let (send, recv) = self.0.open_bi().await?;
+        Ok(raw_socket(send, recv, &self.2))
+    }
+
+    /// Accept a bidirectional stream opened using [Channel::open_raw]
+    pub async fn accept_raw(&self) -> result::Result<RawSocket, AcceptBiError> {
+        let (send, recv) = self.0.accept_bi().await?;
+        Ok(raw_socket(send, recv, &self.2))
+    }
+
+    /// Open a bidirectional stream for messages that are encoded using a [MessageCodec]
+    ///
+    /// Outgoing messages are encoded using `encoder`, incoming messages decoded using `decoder`,
+    /// so the messages don't need to implement serde. The frames use the same length prefix as
+    /// regular messages. The other side has to accept the stream using
+    /// [Channel::accept_with_codec], with matching codecs.
+    pub async fn open_with_codec<I, O, D, E>(
+        &self,
+        decoder: D,
+        encoder: E,
+    ) -> result::Result<CodecSocket<I, O, D, E>, OpenBiError>
+    where
+        D: MessageCodec<I>,
+        E: MessageCodec<O>,
+    {
+        let (send, recv) = self.0.open_bi().await?;
+        Ok(codec_socket(send, recv, &self.2, decoder, encoder))
+    }
+
+    /// Accept a bidirectional stream opened using [Channel::open_with_codec]
+    pub async fn accept_with_codec<I, O, D, E>(
+        &self,
+        decoder: D,
+        encoder: E,
+    ) -> result::Result<CodecSocket<I, O, D, E>, AcceptBiError>
+    where
+        D: MessageCodec<I>,
+        E: MessageCodec<O>,
+    {
+        let (send, recv) = self.0.accept_bi().await?;
+        Ok(codec_socket(send, recv, &self.2, decoder, encoder))
+    }
+
+    /// The underlying quinn connection
+    pub fn connection(&self) -> &quinn::Connection {
+        &self.0
+    }
+
+    /// Watch the connection for path changes, idle periods and closing
+    ///
+    /// quinn does not report these as they happen, so the connection is checked every
+    /// `interval`, and events are only detected with that granularity. Nothing is checked unless
+    /// the returned stream is polled.
+    pub fn events(&self, interval: Duration) -> ConnectionEvents {
+        ConnectionEvents::new(self.0.clone(), interval)
+    }
+
+    /// The application protocol negotiated using ALPN during the handshake, if any
+    pub fn alpn(&self) -> Option<Vec<u8>> {
+        negotiated_alpn(&self.0)
+    }
+
+    /// Close the connection unless one of the given application protocols was negotiated
+    ///
+    /// This is useful when a single endpoint accepts connections for several protocols. On a
+    /// mismatch, the connection is closed with [ALPN_MISMATCH] as the error code.
+    pub fn require_alpn(&self, protocols: &[&[u8]]) -> result::Result<(), AlpnMismatch> {
+        let negotiated = self.alpn();
+        match &negotiated {
+            Some(alpn) if protocols.contains(&alpn.as_slice()) => Ok(()),
+            _ => {
+                self.0.close(ALPN_MISMATCH, b"unexpected alpn");
+                Err(AlpnMismatch { negotiated })
+            }
+        }
+    }
+}
+
+/// Access to the quinn connection of a channel, and of clients and servers using one
+///
+/// This is meant for diagnostics like dashboards of congestion and loss, without having to keep
+/// the connection around separately.
+pub trait QuinnChannelExt {
+    /// The underlying quinn connection
+    fn quinn_connection(&self) -> &quinn::Connection;
+
+    /// Statistics of the connection, see [quinn::Connection::stats]
+    ///
+    /// This includes the bytes sent and received, lost packets and the congestion window.
+    fn stats(&self) -> ConnectionStats {
+        self.quinn_connection().stats()
+    }
+
+    /// Current best estimate of the round trip time of the connection
+    fn rtt(&self) -> Duration {
+        self.quinn_connection().rtt()
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, K: ChannelCodec> QuinnChannelExt for Channel<In, Out, K> {
+    fn quinn_connection(&self) -> &quinn::Connection {
+        &self.0
+    }
+}
+
+impl<S: Service, K: ChannelCodec> QuinnChannelExt for RpcClient<S, QuinnChannelTypes<K>> {
+    fn quinn_connection(&self) -> &quinn::Connection {
+        self.channel.quinn_connection()
+    }
+}
+
+impl<S: Service, K: ChannelCodec> QuinnChannelExt for RpcServer<S, QuinnChannelTypes<K>> {
+    fn quinn_connection(&self) -> &quinn::Connection {
+        self.channel.quinn_connection()
+    }
+}
+
+/// Something that happened to a quinn connection, see [Channel::events]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectionEvent {
+    /// The address of the remote changed, e.g. because a mobile client switched networks
+    PathChanged {
+        /// The previous address of the remote
+        old: SocketAddr,
+        /// The current address of the remote
+        new: SocketAddr,
+    },
+    /// No stream data was sent or received for a whole interval
+    ///
+    /// This is emitted once per idle period, keepalives do not count as activity.
+    Idle,
+    /// Stream data was sent or received again after [ConnectionEvent::Idle]
+    Active,
+    /// The connection was closed, this is the last event
+    Closed(quinn::ConnectionError),
+}
+
+/// Stream of [ConnectionEvent]s, see [Channel::events]
+pub struct ConnectionEvents(BoxStream<'static, ConnectionEvent>);
+
+impl ConnectionEvents {
+    fn new(conn: quinn::Connection, interval: Duration) -> Self {
+        let state = EventState {
+            remote: conn.remote_address(),
+            activity: stream_frames(&conn),
+            conn: Some(conn),
+            interval,
+            idle: false,
+        };
+        Self(futures::stream::unfold(state, EventState::next).boxed())
+    }
+}
+
+impl fmt::Debug for ConnectionEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionEvents").finish_non_exhaustive()
+    }
+}
+
+impl Stream for ConnectionEvents {
+    type Item = ConnectionEvent;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.0.poll_next_unpin(cx)
+    }
+}
+
+/// What [ConnectionEvents] saw at the previous check
+struct EventState {
+    /// `None` once the connection is closed
+    conn: Option<quinn::Connection>,
+    interval: Duration,
+    remote: SocketAddr,
+    activity: u64,
+    idle: bool,
+}
+
+impl EventState {
+    async fn next(mut self) -> Option<(ConnectionEvent, Self)> {
+        let conn = self.conn.clone()?;
+        loop {
+            tokio::select! {
+                error = conn.closed() => {
+                    self.conn = None;
+                    return Some((ConnectionEvent::Closed(error), self));
+                }
+                _ = tokio::time::sleep(self.interval) => {}
+            }
+            let remote = conn.remote_address();
+            if remote != self.remote {
+                let old = std::mem::replace(&mut self.remote, remote);
+                return Some((ConnectionEvent::PathChanged { old, new: remote }, self));
+            }
+            let activity = stream_frames(&conn);
+            let active = activity != self.activity;
+            self.activity = activity;
+            if active == self.idle {
+                self.idle = !active;
+                let event = if active {
+                    ConnectionEvent::Active
+                } else {
+                    ConnectionEvent::Idle
+                };
+                return Some((event, self));
+            }
+        }
+    }
+}
+
+/// Number of stream frames sent and received on the connection
+fn stream_frames(conn: &quinn::Connection) -> u64 {
+    let stats = conn.stats();
+    stats.frame_tx.stream + stats.frame_rx.stream
+}
+
+/// Application error code used to close a connection with an unexpected ALPN
+pub const ALPN_MISMATCH: quinn::VarInt = quinn::VarInt::from_u32(0x51);
+
+/// The negotiated ALPN did not match any of the expected ones, see [Channel::require_alpn]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("unexpected alpn {negotiated:?}")]
+pub struct AlpnMismatch {
+    /// The protocol that was actually negotiated, if any
+    pub negotiated: Option<Vec<u8>>,
+}
+
+fn negotiated_alpn(conn: &quinn::Connection) -> Option<Vec<u8>> {
+    conn.handshake_data()?
+        .downcast::<quinn::crypto::rustls::HandshakeData>()
+        .ok()?
+        .protocol
+}
+
+impl<In: RpcMessage, Out: RpcMessage, K: ChannelCodec> Clone for Channel<In, Out, K> {
     fn clone(&self) -> Self {
-        Self(self.0.clone(), PhantomData)
+        Self(
+            self.0.clone(),
+            self.1.clone(),
+            self.2.clone(),
+            self.3.clone(),
+            PhantomData,
+        )
     }
 }
 
-/// A sink that wraps a quinn SendStream with length delimiting and bincode
+/// A sink that wraps a quinn SendStream with length delimiting, sending frames as they are
+///
+/// This is the framing used by all quinn channels. [SendSink] adds the codec on top of it.
+///
+/// Frames are buffered until the threshold of [Channel::with_write_coalescing] is reached or
+/// the sink is flushed, and a flush writes all buffered frames to the quinn stream.
 #[pin_project]
-pub struct SendSink<Out>(
-    #[pin]
-    tokio_serde::SymmetricallyFramed<
-        FramedWrite<::quinn::SendStream, LengthDelimitedCodec>,
-        Out,
-        SymmetricalBincode<Out>,
-    >,
+pub struct RawSendSink(
+    #[pin] FramedWrite<FairWriter, LengthDelimitedCodec>,
+    ByteCounts,
+    FrameEncoder,
+    usize,
 );
 
-impl<Out: Serialize> Sink<Out> for SendSink<Out> {
+impl RawSendSink {
+    fn new(send: ::quinn::SendStream, config: &StreamConfig, counts: ByteCounts) -> Self {
+        // the size limit is enforced by the receiver, see Channel::with_max_frame_size
+        let codec = LengthDelimitedCodec::builder()
+            .max_frame_length(u32::MAX as usize)
+            .new_codec();
+        let send = FairWriter::new(send, config.scheduler.clone());
+        let encoder = FrameEncoder {
+            // one byte of every frame is used for the chunk header
+            chunk_size: config
+                .max_message_size
+                .map(|_| config.max_frame_size.saturating_sub(1).max(1)),
+            #[cfg(feature = "compression")]
+            compression: config.compression,
+            #[cfg(feature = "compression")]
+            pattern: None,
+        };
+        Self(
+            FramedWrite::new(send, codec),
+            counts,
+            encoder,
+            config.write_coalescing,
+        )
+    }
+}
+
+impl RawSendSink {
+    /// Set the priority of the stream, see [quinn::SendStream::set_priority]
+    ///
+    /// Data of streams with a higher priority is sent first, streams with the same priority
+    /// share the bandwidth. The default priority is 0. The priority should be set before writing
+    /// to the stream, since data that was already handed to quinn may be sent anyway.
+    pub fn set_priority(&self, priority: i32) -> result::Result<(), quinn::UnknownStream> {
+        self.0.get_ref().inner.set_priority(priority)
+    }
+
+    /// Abort the stream with an application error code, see [quinn::SendStream::reset]
+    ///
+    /// The remote gets [RecvError::PeerReset] with the code. Data that was not sent yet is
+    /// discarded.
+    pub fn reset(&mut self, code: u32) -> result::Result<(), quinn::UnknownStream> {
+        self.0.get_mut().inner.reset(quinn::VarInt::from_u32(code))
+    }
+
+    /// Set the interaction pattern of the stream, see [crate::ChannelTypes::set_pattern]
+    pub fn set_pattern(&mut self, pattern: PatternKind) {
+        #[cfg(feature = "compression")]
+        {
+            self.2.pattern = Some(pattern);
+        }
+        #[cfg(not(feature = "compression"))]
+        let _ = pattern;
+    }
+}
+
+impl ByteCounted for RawSendSink {
+    fn byte_counts(&self) -> ByteCounts {
+        self.1.clone()
+    }
+}
+
+impl Sink<Bytes> for RawSendSink {
+    type Error = io::Error;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        let mut this = self.project();
+        // the inner sink applies its own fixed backpressure boundary, which must never be
+        // skipped, or the buffer could grow without limit
+        std::task::ready!(this.0.as_mut().poll_ready(cx))?;
+        // on top of that, our own threshold is enforced. The flush keeps writing until the
+        // buffer is empty, even if the stream accepts only part of it at a time.
+        if this.0.write_buffer().len() >= *this.3 {
+            this.0.as_mut().poll_flush(cx)
+        } else {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let mut this = self.project();
+        let mut item = this.2.encode(item)?;
+        let Some(chunk_size) = this.2.chunk_size else {
+            this.1.add_sent(LENGTH_PREFIX_LEN + item.len());
+            return this.0.start_send(item);
+        };
+        // an empty message is sent as a single empty final chunk
+        loop {
+            let chunk = item.split_to(item.len().min(chunk_size));
+            let header = if item.is_empty() { FINAL } else { CONTINUED };
+            let frame = with_header(header, &chunk);
+            this.1.add_sent(LENGTH_PREFIX_LEN + frame.len());
+            this.0.as_mut().start_send(frame)?;
+            if item.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.project().0.poll_flush(cx)
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.project().0.poll_close(cx)
+    }
+}
+
+/// Default for [Compression::with_threshold]
+#[cfg(feature = "compression")]
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Compression of large frames using zstd, see [Channel::with_compression]
+///
+/// Every frame of a compressed channel starts with a header byte that tells whether the rest of
+/// the frame is compressed. Frames smaller than the threshold, and frames that do not get
+/// smaller, are sent uncompressed. Received frames are decompressed as needed, independent of
+/// the settings of the receiving side. The limit of [Channel::with_max_frame_size] applies to
+/// both the received frame and the decompressed frame.
+///
+/// The settings are per direction: each side compresses the frames it sends. To compress in one
+/// direction only, e.g. the large responses of a service but not its small requests, enable
+/// compression with [Compression::receive_only] on the side that should not compress. To
+/// compress only the responses of some interaction patterns, e.g. of server streaming calls,
+/// use [Compression::for_patterns] on the server.
+#[cfg(feature = "compression")]
+#[derive(Debug, Clone, Copy)]
+pub struct Compression {
+    level: i32,
+    threshold: usize,
+    send: bool,
+    /// Bit set of the patterns to compress, `None` to compress all streams
+    patterns: Option<u8>,
+}
+
+#[cfg(feature = "compression")]
+impl Compression {
+    /// Compress frames using the zstd compression `level`
+    ///
+    /// Level 0 selects the default level of zstd, higher levels compress better but slower.
+    pub fn new(level: i32) -> Self {
+        Self {
+            level,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            send: true,
+            patterns: None,
+        }
+    }
+
+    /// Only compress frames of at least `threshold` bytes
+    ///
+    /// The default is [DEFAULT_COMPRESSION_THRESHOLD].
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Decompress received frames, but send all frames uncompressed
+    pub fn receive_only(mut self) -> Self {
+        self.send = false;
+        self
+    }
+
+    /// Only compress the frames of streams used for one of `patterns`
+    ///
+    /// The pattern of a stream is only known on the server, which tells the sink before it sends
+    /// the responses, see [crate::ChannelTypes::set_pattern]. So with a pattern filter, the
+    /// client sends all frames uncompressed.
+    pub fn for_patterns(mut self, patterns: &[PatternKind]) -> Self {
+        self.patterns = Some(patterns.iter().fold(0, |set, p| set | 1 << *p as u8));
+        self
+    }
+
+    fn encode(&self, frame: Bytes, pattern: Option<PatternKind>) -> io::Result<Bytes> {
+        let selected = match (self.patterns, pattern) {
+            (None, _) => true,
+            (Some(set), Some(pattern)) => set & 1 << pattern as u8 != 0,
+            (Some(_), None) => false,
+        };
+        if self.send && selected && frame.len() >= self.threshold {
+            let compressed = zstd::bulk::compress(&frame, self.level)?;
+            if compressed.len() < frame.len() {
+                return Ok(with_header(COMPRESSED, &compressed));
+            }
+        }
+        Ok(with_header(UNCOMPRESSED, &frame))
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new(zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+}
+
+/// Header of frames of compressed channels that are sent as they are
+#[cfg(feature = "compression")]
+const UNCOMPRESSED: u8 = 0;
+
+/// Header of frames of compressed channels that are compressed using zstd
+#[cfg(feature = "compression")]
+const COMPRESSED: u8 = 1;
+
+/// Header of frames of chunked channels that end a message
+const FINAL: u8 = 0;
+
+/// Header of frames of chunked channels that are followed by more frames of the same message
+const CONTINUED: u8 = 1;
+
+fn with_header(header: u8, data: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(1 + data.len());
+    buf.put_u8(header);
+    buf.extend_from_slice(data);
+    buf.freeze()
+}
+
+/// Remove the header of a message of a compressed channel, and decompress it if needed
+#[cfg(feature = "compression")]
+fn decompress(mut frame: BytesMut, limit: usize) -> result::Result<BytesMut, RecvError> {
+    if frame.is_empty() {
+        return Err(invalid_data("frame without compression header"));
+    }
+    match frame.get_u8() {
+        UNCOMPRESSED => Ok(frame),
+        COMPRESSED => {
+            // fails if the decompressed frame would be larger than the limit
+            let data = zstd::bulk::decompress(&frame, limit)
+                .map_err(|_| invalid_data("invalid or too large compressed frame"))?;
+            Ok(BytesMut::from(&data[..]))
+        }
+        _ => Err(invalid_data("unknown compression header")),
+    }
+}
+
+fn invalid_data(msg: &'static str) -> RecvError {
+    RecvError::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+/// Default for [FairScheduler::new]
+pub const DEFAULT_FAIR_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Round robin scheduling of writes across the streams of a channel
+///
+/// Quinn interleaves the data of streams that are waiting to be sent, but a stream that writes
+/// a lot of data at once can fill up the send buffer of the connection, so a small message on
+/// another stream has to wait until that data is sent. With a fair scheduler, streams write at
+/// most `chunk_size` bytes at a time and then let the other streams of the channel that are
+/// writing take their turn. This improves latency for interactive streams that share a
+/// connection with bulk transfers.
+///
+/// The tradeoff is throughput: smaller chunks mean more context switches between the writing
+/// tasks, so a single bulk stream gets slower. Channels without a scheduler write as fast as
+/// quinn accepts the data. See [Channel::with_fair_scheduler].
+#[derive(Debug, Clone)]
+pub struct FairScheduler {
+    chunk_size: usize,
+    turns: Arc<Mutex<Turns>>,
+}
+
+/// Whose turn it is to write, shared by the streams of a [FairScheduler]
+#[derive(Debug, Default)]
+struct Turns {
+    next_id: u64,
+    /// The stream that is writing a chunk, if any
+    current: Option<u64>,
+    /// Streams waiting for their turn, in order
+    waiting: VecDeque<(u64, Waker)>,
+}
+
+impl Turns {
+    /// Hand the turn to the next waiting stream, or to whoever writes first
+    fn pass(&mut self) {
+        self.current = self.waiting.pop_front().map(|(id, waker)| {
+            waker.wake();
+            id
+        });
+    }
+
+    /// Give up the turn and the place in the queue of the stream `id`
+    fn leave(&mut self, id: u64) {
+        self.waiting.retain(|(other, _)| *other != id);
+        if self.current == Some(id) {
+            self.pass();
+        }
+    }
+}
+
+impl FairScheduler {
+    /// Create a scheduler that lets every stream write `chunk_size` bytes per turn
+    pub fn new(chunk_size: usize) -> Self {
+        Self {
+            chunk_size: chunk_size.max(1),
+            turns: Default::default(),
+        }
+    }
+
+    /// Maximum number of bytes a stream writes per turn
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    fn turns(&self) -> MutexGuard<'_, Turns> {
+        self.turns.lock().unwrap()
+    }
+}
+
+impl Default for FairScheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAIR_CHUNK_SIZE)
+    }
+}
+
+/// A quinn SendStream that takes turns with the other streams of a [FairScheduler]
+#[derive(Debug)]
+struct FairWriter {
+    inner: quinn::SendStream,
+    /// The scheduler and the id of this stream in it
+    scheduler: Option<(FairScheduler, u64)>,
+}
+
+impl FairWriter {
+    fn new(inner: quinn::SendStream, scheduler: Option<FairScheduler>) -> Self {
+        let scheduler = scheduler.map(|scheduler| {
+            let id = {
+                let mut turns = scheduler.turns();
+                turns.next_id += 1;
+                turns.next_id
+            };
+            (scheduler, id)
+        });
+        Self { inner, scheduler }
+    }
+
+    fn leave(&self) {
+        if let Some((scheduler, id)) = &self.scheduler {
+            scheduler.turns().leave(*id);
+        }
+    }
+}
+
+impl Drop for FairWriter {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+impl AsyncWrite for FairWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some((scheduler, id)) = &this.scheduler else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        {
+            let mut turns = scheduler.turns();
+            match turns.current {
+                Some(current) if current != *id => {
+                    // wait until the streams before this one wrote their chunk
+                    match turns.waiting.iter_mut().find(|(other, _)| other == id) {
+                        Some((_, waker)) => waker.clone_from(cx.waker()),
+                        None => turns.waiting.push_back((*id, cx.waker().clone())),
+                    }
+                    return std::task::Poll::Pending;
+                }
+                _ => turns.current = Some(*id),
+            }
+        }
+        let len = buf.len().min(scheduler.chunk_size);
+        let res = Pin::new(&mut this.inner).poll_write(cx, &buf[..len]);
+        // also if quinn can not take the chunk right now, e.g. because the remote does not read
+        // this stream, so it does not hold up the other streams
+        scheduler.turns().pass();
+        res
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.inner).poll_flush(cx);
+        if res.is_ready() {
+            this.leave();
+        }
+        res
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.leave();
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// A sink that wraps a quinn SendStream with length delimiting and the [MessageCodec] `E`
+#[pin_project]
+pub struct CodecSendSink<Out, E>(#[pin] RawSendSink, E, PhantomData<Out>);
+
+impl<Out, E> CodecSendSink<Out, E> {
+    fn new(
+        send: ::quinn::SendStream,
+        config: &StreamConfig,
+        counts: ByteCounts,
+        encoder: E,
+    ) -> Self {
+        Self(RawSendSink::new(send, config, counts), encoder, PhantomData)
+    }
+}
+
+impl<Out, E> CodecSendSink<Out, E> {
+    /// Set the priority of the stream, see [RawSendSink::set_priority]
+    pub fn set_priority(&self, priority: i32) -> result::Result<(), quinn::UnknownStream> {
+        self.0.set_priority(priority)
+    }
+
+    /// Abort the stream with an application error code, see [RawSendSink::reset]
+    pub fn reset(&mut self, code: u32) -> result::Result<(), quinn::UnknownStream> {
+        self.0.reset(code)
+    }
+
+    /// Set the interaction pattern of the stream, see [RawSendSink::set_pattern]
+    pub fn set_pattern(&mut self, pattern: PatternKind) {
+        self.0.set_pattern(pattern)
+    }
+}
+
+impl<Out, E> ByteCounted for CodecSendSink<Out, E> {
+    fn byte_counts(&self) -> ByteCounts {
+        self.0.byte_counts()
+    }
+}
+
+/// A sink that wraps a quinn SendStream with length delimiting and the codec `K`
+pub type SendSink<Out, K = BincodeCodec> = CodecSendSink<Out, SerdeCodec<K>>;
+
+impl<Out, E: MessageCodec<Out>> Sink<Out> for CodecSendSink<Out, E> {
     type Error = io::Error;
 
     fn poll_ready(
@@ -48,7 +943,10 @@ impl<Out: Serialize> Sink<Out> for SendSink<Out> {
     }
 
     fn start_send(self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
-        self.project().0.start_send_unpin(item)
+        let this = self.project();
+        let mut buf = BytesMut::new();
+        this.1.encode(&item, &mut buf)?;
+        this.0.start_send(buf.freeze())
     }
 
     fn poll_flush(
@@ -66,25 +964,321 @@ impl<Out: Serialize> Sink<Out> for SendSink<Out> {
     }
 }
 
-/// A stream that wraps a quinn RecvStream with length delimiting and bincode
+/// A stream that wraps a quinn RecvStream with length delimiting, yielding frames as they are
+///
+/// This is the framing used by all quinn channels. [RecvStream] adds the codec on top of it.
 #[pin_project]
-pub struct RecvStream<In>(
-    #[pin]
-    tokio_serde::SymmetricallyFramed<
-        FramedRead<::quinn::RecvStream, LengthDelimitedCodec>,
-        In,
-        SymmetricalBincode<In>,
-    >,
-);
+pub struct RawRecvStream(#[pin] FramedRead<::quinn::RecvStream, FrameDecoder>);
 
-impl<In: DeserializeOwned> Stream for RecvStream<In> {
-    type Item = result::Result<In, io::Error>;
+impl RawRecvStream {
+    fn new(recv: ::quinn::RecvStream, config: &StreamConfig, counts: ByteCounts) -> Self {
+        let decoder = FrameDecoder {
+            max_frame_size: config.max_frame_size,
+            max_message_size: config.max_message_size,
+            partial: BytesMut::new(),
+            counts,
+            #[cfg(feature = "compression")]
+            compressed: config.compression.is_some(),
+        };
+        Self(FramedRead::new(recv, decoder))
+    }
+}
+
+impl ByteCounted for RawRecvStream {
+    fn byte_counts(&self) -> ByteCounts {
+        self.0.decoder().counts.clone()
+    }
+}
+
+impl Stream for RawRecvStream {
+    type Item = result::Result<Bytes, RecvError>;
 
     fn poll_next(
         self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
-        self.project().0.poll_next_unpin(cx)
+        self.project()
+            .0
+            .poll_next(cx)
+            .map(|item| item.map(|data| Ok(data?.freeze())))
+    }
+}
+
+/// A stream that wraps a quinn RecvStream with length delimiting and the [MessageCodec] `D`
+#[pin_project]
+pub struct CodecRecvStream<In, D>(#[pin] RawRecvStream, D, PhantomData<In>);
+
+impl<In, D> CodecRecvStream<In, D> {
+    fn new(
+        recv: ::quinn::RecvStream,
+        config: &StreamConfig,
+        counts: ByteCounts,
+        decoder: D,
+    ) -> Self {
+        Self(
+            RawRecvStream::new(recv, config, counts),
+            decoder,
+            PhantomData,
+        )
+    }
+}
+
+impl<In, D> ByteCounted for CodecRecvStream<In, D> {
+    fn byte_counts(&self) -> ByteCounts {
+        self.0.byte_counts()
+    }
+}
+
+/// A stream that wraps a quinn RecvStream with length delimiting and the codec `K`
+pub type RecvStream<In, K = BincodeCodec> = CodecRecvStream<In, SerdeCodec<K>>;
+
+impl<In, D: MessageCodec<In>> Stream for CodecRecvStream<In, D> {
+    type Item = result::Result<In, RecvError>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        let this = self.project();
+        let decoder = this.1;
+        // decode from the frame buffer directly, without going through Bytes
+        this.0
+            .project()
+            .0
+            .poll_next(cx)
+            .map(|item| item.map(|data| Ok(decoder.decode(&mut data?)?)))
+    }
+}
+
+/// Length of the big endian length prefix of every message
+const LENGTH_PREFIX_LEN: usize = 4;
+
+/// Encoder for the content of outgoing frames, adding the header of compressed channels
+#[derive(Debug)]
+struct FrameEncoder {
+    /// Maximum size of the content of a frame of a chunked channel, without the header
+    chunk_size: Option<usize>,
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+    /// The pattern of the stream, see [crate::ChannelTypes::set_pattern]
+    #[cfg(feature = "compression")]
+    pattern: Option<PatternKind>,
+}
+
+impl FrameEncoder {
+    fn encode(&self, frame: Bytes) -> io::Result<Bytes> {
+        #[cfg(feature = "compression")]
+        if let Some(compression) = &self.compression {
+            return compression.encode(frame, self.pattern);
+        }
+        Ok(frame)
+    }
+}
+
+/// Decoder for length prefixed frames, compatible with the default [LengthDelimitedCodec]
+///
+/// Unlike [LengthDelimitedCodec], this reports the size of frames that are too large. On chunked
+/// channels, it yields messages reassembled from their frames.
+#[derive(Debug)]
+struct FrameDecoder {
+    max_frame_size: usize,
+    /// Limit for messages that span several frames, `None` if messages are not chunked
+    max_message_size: Option<usize>,
+    /// The frames of a chunked message received so far
+    partial: BytesMut,
+    counts: ByteCounts,
+    /// True if frames start with the header of compressed channels
+    #[cfg(feature = "compression")]
+    compressed: bool,
+}
+
+impl Decoder for FrameDecoder {
+    type Item = BytesMut;
+    type Error = RecvError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> result::Result<Option<BytesMut>, RecvError> {
+        // the buffer can hold several frames of a chunked message, and FramedRead only calls
+        // decode again once more data arrived
+        loop {
+            let Some(frame) = self.decode_frame(src)? else {
+                return Ok(None);
+            };
+            let message = match self.max_message_size {
+                Some(limit) => match self.reassemble(frame, limit)? {
+                    Some(message) => message,
+                    None => continue,
+                },
+                None => frame,
+            };
+            #[cfg(feature = "compression")]
+            if self.compressed {
+                let limit = self.max_message_size.unwrap_or(self.max_frame_size);
+                return decompress(message, limit).map(Some);
+            }
+            return Ok(Some(message));
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> result::Result<Option<BytesMut>, RecvError> {
+        match self.decode(src)? {
+            Some(message) => Ok(Some(message)),
+            // the stream ended in the middle of a frame or of a chunked message
+            None if !src.is_empty() || !self.partial.is_empty() => {
+                Err(RecvError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stream ended within a message",
+                )))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl FrameDecoder {
+    fn decode_frame(&mut self, src: &mut BytesMut) -> result::Result<Option<BytesMut>, RecvError> {
+        let Some(prefix) = src.get(..LENGTH_PREFIX_LEN) else {
+            return Ok(None);
+        };
+        let size = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+        if size > self.max_frame_size {
+            return Err(RecvError::FrameTooLarge {
+                size,
+                limit: self.max_frame_size,
+            });
+        }
+        if src.len() < LENGTH_PREFIX_LEN + size {
+            src.reserve(LENGTH_PREFIX_LEN + size - src.len());
+            return Ok(None);
+        }
+        src.advance(LENGTH_PREFIX_LEN);
+        self.counts.add_received(LENGTH_PREFIX_LEN + size);
+        Ok(Some(src.split_to(size)))
+    }
+
+    /// Add a frame of a chunked channel to the current message, returning it if it is complete
+    fn reassemble(
+        &mut self,
+        mut frame: BytesMut,
+        limit: usize,
+    ) -> result::Result<Option<BytesMut>, RecvError> {
+        if frame.is_empty() {
+            return Err(invalid_data("frame without chunk header"));
+        }
+        let header = frame.get_u8();
+        let size = self.partial.len() + frame.len();
+        if size > limit {
+            return Err(RecvError::MessageTooLarge { size, limit });
+        }
+        match header {
+            FINAL if self.partial.is_empty() => Ok(Some(frame)),
+            FINAL => {
+                self.partial.extend_from_slice(&frame);
+                Ok(Some(std::mem::take(&mut self.partial)))
+            }
+            CONTINUED => {
+                self.partial.extend_from_slice(&frame);
+                Ok(None)
+            }
+            _ => Err(invalid_data("unknown chunk header")),
+        }
+    }
+}
+
+/// Error for receiving messages on a quinn channel
+#[derive(Debug, thiserror::Error)]
+pub enum RecvError {
+    /// Reading from the stream or deserializing the message failed
+    #[error(transparent)]
+    Io(io::Error),
+    /// The length prefix announced a message larger than allowed, see
+    /// [Channel::with_max_frame_size]
+    #[error("frame of {size} bytes is larger than the limit of {limit} bytes")]
+    FrameTooLarge {
+        /// Announced size of the message
+        size: usize,
+        /// Maximum size of a message
+        limit: usize,
+    },
+    /// A message that spans several frames got larger than allowed, see
+    /// [Channel::with_max_message_size]
+    #[error("message of at least {size} bytes is larger than the limit of {limit} bytes")]
+    MessageTooLarge {
+        /// Size of the message when it was rejected, the full message may be larger
+        size: usize,
+        /// Maximum size of a message
+        limit: usize,
+    },
+    /// The remote aborted the stream with an application error code
+    ///
+    /// Servers reset streams of failed calls with the codes of
+    /// [crate::server::RpcServerErrorKind::reset_code].
+    #[error("the remote reset the stream with code {code}")]
+    PeerReset {
+        /// The error code the remote reset the stream with
+        code: u64,
+    },
+}
+
+impl RecvError {
+    /// The kind of server error the remote reset the stream for, if the code is a known one
+    pub fn server_error_kind(&self) -> Option<RpcServerErrorKind> {
+        match self {
+            // peers may reset with any varint, the known codes all fit in a u32
+            Self::PeerReset { code } => u32::try_from(*code)
+                .ok()
+                .and_then(RpcServerErrorKind::from_reset_code),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RecvError {
+    fn from(e: io::Error) -> Self {
+        // quinn reports resets as io errors, with the read error as the inner error
+        match e
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<quinn::ReadError>())
+        {
+            Some(quinn::ReadError::Reset(code)) => Self::PeerReset {
+                code: code.into_inner(),
+            },
+            _ => Self::Io(e),
+        }
+    }
+}
+
+impl ChannelError for RecvError {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::Io(e) => e,
+            Self::FrameTooLarge { .. } | Self::MessageTooLarge { .. } => {
+                io::Error::new(io::ErrorKind::InvalidData, self)
+            }
+            Self::PeerReset { .. } => io::Error::new(io::ErrorKind::ConnectionReset, self),
+        }
+    }
+
+    fn is_connection_lost(&self) -> bool {
+        match self {
+            Self::Io(e) => e.is_connection_lost(),
+            Self::FrameTooLarge { .. } | Self::MessageTooLarge { .. } | Self::PeerReset { .. } => {
+                false
+            }
+        }
+    }
+    fn server_error_kind(&self) -> Option<RpcServerErrorKind> {
+        RecvError::server_error_kind(self)
+    }
+}
+
+impl Retryable for RecvError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Io(e) => e.is_retryable(),
+            Self::FrameTooLarge { .. } | Self::MessageTooLarge { .. } => false,
+            // the server failed the call on purpose, e.g. because of a protocol error
+            Self::PeerReset { .. } => false,
+        }
     }
 }
 
@@ -94,73 +1288,377 @@ pub type OpenBiError = quinn::ConnectionError;
 /// Error for accept_bi. Currently just a quinn::ConnectionError
 pub type AcceptBiError = quinn::ConnectionError;
 
+impl ChannelError for quinn::ConnectionError {
+    fn into_io(self) -> io::Error {
+        use quinn::ConnectionError::*;
+        let kind = match self {
+            TimedOut => io::ErrorKind::TimedOut,
+            Reset => io::ErrorKind::ConnectionReset,
+            ApplicationClosed(_) | ConnectionClosed(_) | LocallyClosed => {
+                io::ErrorKind::ConnectionAborted
+            }
+            TransportError(_) | VersionMismatch => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, self)
+    }
+}
+
+impl Retryable for quinn::ConnectionError {
+    fn is_retryable(&self) -> bool {
+        use quinn::ConnectionError::*;
+        match self {
+            // handshake and certificate failures are reported using the crypto error codes
+            TransportError(e) => !is_crypto_error(e.code.into()),
+            ConnectionClosed(close) => !is_crypto_error(close.error_code.into()),
+            ApplicationClosed(_) | Reset | TimedOut => true,
+            VersionMismatch | LocallyClosed => false,
+        }
+    }
+}
+
+/// True if the transport error code is in the range reserved for TLS alerts
+fn is_crypto_error(code: u64) -> bool {
+    (0x100..0x200).contains(&code)
+}
+
 /// Types for quinn channels.
 ///
-/// This exposes the types from quinn directly without attempting to wrap them.
+/// This exposes the types from quinn directly without attempting to wrap them. Messages are
+/// serialized using the [ChannelCodec] `K`, which defaults to [BincodeCodec]. Codecs with state
+/// are passed to [Channel::new_with_codec] or [QuinnBuilder::with_codec].
 #[derive(Debug, Clone, Copy)]
-pub struct QuinnChannelTypes;
+pub struct QuinnChannelTypes<K: ChannelCodec = BincodeCodec>(PhantomData<K>);
+
+impl<K: ChannelCodec + Default> QuinnChannelTypes<K> {
+    /// Create a builder for the transport config and channels of quinn connections
+    pub fn builder() -> QuinnBuilder<K> {
+        QuinnBuilder::default()
+    }
+
+    /// Connect to a server, sending the first requests as 0-RTT data if possible
+    ///
+    /// 0-RTT is used if the endpoint has a session ticket from a previous connection to the same
+    /// server, which requires a client config with `enable_early_data` set on the rustls config.
+    /// Otherwise this falls back to a regular handshake, and only returns once it completed.
+    ///
+    /// 0-RTT data can be replayed by an attacker, so the returned [EarlyClient] only sends
+    /// [Idempotent] rpc requests until the handshake is confirmed.
+    pub async fn connect_0rtt<S: Service>(
+        endpoint: &quinn::Endpoint,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> result::Result<EarlyClient<S, K>, ConnectError> {
+        let connecting = endpoint
+            .connect(addr, server_name)
+            .map_err(ConnectError::Connect)?;
+        let (connection, accepted) = match connecting.into_0rtt() {
+            Ok((connection, accepted)) => (connection, Some(accepted)),
+            Err(connecting) => (connecting.await.map_err(ConnectError::Handshake)?, None),
+        };
+        Ok(EarlyClient {
+            client: RpcClient::new(self::Channel::new(connection)),
+            accepted,
+        })
+    }
+}
+
+/// Error when connecting using [QuinnChannelTypes::connect_0rtt] or [QuinnBuilder::connect]
+#[derive(Debug, thiserror::Error)]
+pub enum ConnectError {
+    /// The connection could not be started, e.g. because of an invalid server name
+    #[error("failed to connect")]
+    Connect(#[source] quinn::ConnectError),
+    /// The regular handshake failed, e.g. after 0-RTT was not possible
+    #[error("the handshake failed")]
+    Handshake(#[source] quinn::ConnectionError),
+}
+
+/// Builder for the transport config and channels of quinn connections, see
+/// [QuinnChannelTypes::builder]
+///
+/// This keeps the quinn transport parameters that affect rpc calls together with the settings of
+/// the channels. The defaults are tuned for rpc workloads, where every call is a stream:
+///
+/// - up to 1024 concurrent bidirectional and unidirectional streams per connection, instead of
+///   100, so a busy client is not limited to 100 calls in flight
+/// - keep alive packets every 5 seconds, so idle connections of long lived clients stay open
+/// - an idle timeout of 30 seconds, after which a connection to a remote that went away is
+///   closed
+/// - datagrams enabled, for datagram rpc calls such as [RpcClient::rpc_datagram]
+///
+/// The transport config is applied to quinn configs using [QuinnBuilder::client_config] and
+/// [QuinnBuilder::server_config]. The effective idle timeout of a connection is the smaller one
+/// of both sides.
+#[derive(Debug, Clone)]
+pub struct QuinnBuilder<K: ChannelCodec = BincodeCodec> {
+    max_concurrent_bidi_streams: u32,
+    max_concurrent_uni_streams: u32,
+    keep_alive_interval: Option<Duration>,
+    max_idle_timeout: Option<Duration>,
+    datagrams: bool,
+    stream: StreamConfig,
+    codecs: Codecs<K>,
+}
+
+impl<K: ChannelCodec + Default> Default for QuinnBuilder<K> {
+    fn default() -> Self {
+        Self {
+            max_concurrent_bidi_streams: 1024,
+            max_concurrent_uni_streams: 1024,
+            keep_alive_interval: Some(Duration::from_secs(5)),
+            max_idle_timeout: Some(Duration::from_secs(30)),
+            datagrams: true,
+            stream: Default::default(),
+            codecs: Codecs {
+                decoder: K::default(),
+                encoder: K::default(),
+            },
+        }
+    }
+}
+
+impl<K: ChannelCodec> QuinnBuilder<K> {
+    /// Set how many bidirectional streams the remote may open at the same time
+    ///
+    /// This limits the number of calls of the remote that are in flight.
+    pub fn with_max_concurrent_bidi_streams(mut self, n: u32) -> Self {
+        self.max_concurrent_bidi_streams = n;
+        self
+    }
+
+    /// Set how many unidirectional streams the remote may open at the same time
+    ///
+    /// This limits the number of notifications of the remote that are in flight.
+    pub fn with_max_concurrent_uni_streams(mut self, n: u32) -> Self {
+        self.max_concurrent_uni_streams = n;
+        self
+    }
+
+    /// Send keep alive packets at this interval, or never for `None`
+    ///
+    /// The interval needs to be shorter than the idle timeout to keep idle connections open.
+    pub fn with_keep_alive_interval(mut self, interval: Option<Duration>) -> Self {
+        self.keep_alive_interval = interval;
+        self
+    }
+
+    /// Close connections that were idle for this long, or never for `None`
+    pub fn with_max_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.max_idle_timeout = timeout;
+        self
+    }
+
+    /// Enable or disable receiving datagrams
+    pub fn with_datagrams(mut self, enabled: bool) -> Self {
+        self.datagrams = enabled;
+        self
+    }
+
+    /// Set the maximum size of a received frame of the channels, see
+    /// [Channel::with_max_frame_size]
+    pub fn with_max_frame_size(mut self, limit: usize) -> Self {
+        self.stream.max_frame_size = limit;
+        self
+    }
+
+    /// Split large messages of the channels into several frames, see
+    /// [Channel::with_max_message_size]
+    pub fn with_max_message_size(mut self, limit: usize) -> Self {
+        self.stream.max_message_size = Some(limit);
+        self
+    }
+
+    /// Use `decoder` for incoming and `encoder` for outgoing messages of the channels, see
+    /// [Channel::new_with_codec]
+    pub fn with_codec<K2: ChannelCodec>(self, decoder: K2, encoder: K2) -> QuinnBuilder<K2> {
+        QuinnBuilder {
+            max_concurrent_bidi_streams: self.max_concurrent_bidi_streams,
+            max_concurrent_uni_streams: self.max_concurrent_uni_streams,
+            keep_alive_interval: self.keep_alive_interval,
+            max_idle_timeout: self.max_idle_timeout,
+            datagrams: self.datagrams,
+            stream: self.stream,
+            codecs: Codecs { decoder, encoder },
+        }
+    }
+
+    /// The quinn transport config
+    pub fn transport_config(&self) -> quinn::TransportConfig {
+        let mut transport = quinn::TransportConfig::default();
+        transport
+            .max_concurrent_bidi_streams(self.max_concurrent_bidi_streams.into())
+            .max_concurrent_uni_streams(self.max_concurrent_uni_streams.into())
+            .keep_alive_interval(self.keep_alive_interval)
+            .max_idle_timeout(self.max_idle_timeout.map(|timeout| {
+                // timeouts that do not fit into a varint are as good as no timeout
+                timeout
+                    .try_into()
+                    .unwrap_or_else(|_| quinn::VarInt::MAX.into())
+            }));
+        if !self.datagrams {
+            transport.datagram_receive_buffer_size(None);
+        }
+        transport
+    }
+
+    /// Use the transport config for connections made with `config`
+    pub fn client_config(&self, mut config: quinn::ClientConfig) -> quinn::ClientConfig {
+        config.transport_config(Arc::new(self.transport_config()));
+        config
+    }
+
+    /// Use the transport config for connections accepted with `config`
+    pub fn server_config(&self, mut config: quinn::ServerConfig) -> quinn::ServerConfig {
+        config.transport_config(Arc::new(self.transport_config()));
+        config
+    }
+
+    /// Create a channel for an existing connection
+    ///
+    /// The channel uses the settings of the builder, but the transport parameters of the
+    /// connection were fixed when it was established.
+    pub fn channel<In: RpcMessage, Out: RpcMessage>(
+        &self,
+        conn: quinn::Connection,
+    ) -> self::Channel<In, Out, K> {
+        self::Channel(
+            conn,
+            Default::default(),
+            self.stream.clone(),
+            self.codecs.clone(),
+            PhantomData,
+        )
+    }
+
+    /// Connect to a server using `config` with the transport config, and create a channel for
+    /// the connection
+    pub async fn connect<In: RpcMessage, Out: RpcMessage>(
+        &self,
+        endpoint: &quinn::Endpoint,
+        config: quinn::ClientConfig,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> result::Result<self::Channel<In, Out, K>, ConnectError> {
+        let conn = endpoint
+            .connect_with(self.client_config(config), addr, server_name)
+            .map_err(ConnectError::Connect)?
+            .await
+            .map_err(ConnectError::Handshake)?;
+        Ok(self.channel(conn))
+    }
+}
+
+/// A client whose requests might be sent as 0-RTT data, see [QuinnChannelTypes::connect_0rtt]
+///
+/// 0-RTT data can be replayed by an attacker, so until the handshake is confirmed, this only
+/// allows [Idempotent] rpc requests. Use [EarlyClient::confirm] to get a client for all
+/// requests.
+pub struct EarlyClient<S: Service, K: ChannelCodec = BincodeCodec> {
+    client: RpcClient<S, QuinnChannelTypes<K>>,
+    accepted: Option<quinn::ZeroRttAccepted>,
+}
+
+impl<S: Service, K: ChannelCodec> fmt::Debug for EarlyClient<S, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EarlyClient")
+            .field("client", &self.client)
+            .field("is_0rtt", &self.is_0rtt())
+            .finish()
+    }
+}
+
+impl<S: Service, K: ChannelCodec> EarlyClient<S, K> {
+    /// True if the connection uses 0-RTT, so requests can be replayed until it is confirmed
+    ///
+    /// False if there was no session ticket for 0-RTT, and the handshake is already complete.
+    pub fn is_0rtt(&self) -> bool {
+        self.accepted.is_some()
+    }
+
+    /// Send an rpc request that is safe to replay, possibly as 0-RTT data
+    ///
+    /// If the server rejects the 0-RTT data, the call fails and needs to be sent again once the
+    /// connection is confirmed.
+    pub async fn rpc<M>(
+        &self,
+        msg: M,
+    ) -> result::Result<M::Response, RpcClientError<QuinnChannelTypes<K>>>
+    where
+        M: Msg<S, Pattern = Rpc> + Idempotent + Into<S::Req>,
+    {
+        self.client.rpc(msg).await
+    }
+
+    /// Wait until the handshake is complete, and return a client for all requests
+    ///
+    /// Also returns whether the server accepted the 0-RTT data. This is true if the connection
+    /// did not use 0-RTT, and false if the server rejected it or the handshake failed, in which
+    /// case all requests sent before failed.
+    pub async fn confirm(self) -> (RpcClient<S, QuinnChannelTypes<K>>, bool) {
+        let accepted = match self.accepted {
+            Some(accepted) => accepted.await,
+            None => true,
+        };
+        (self.client, accepted)
+    }
+}
 
 /// Future returned by open_bi
 #[pin_project]
-pub struct OpenBiFuture<'a, In, Out>(#[pin] quinn::OpenBi<'a>, PhantomData<(In, Out)>);
+pub struct OpenBiFuture<'a, In, Out, K = BincodeCodec>(
+    #[pin] quinn::OpenBi<'a>,
+    &'a StreamConfig,
+    &'a Codecs<K>,
+    PhantomData<(In, Out)>,
+);
 
-impl<'a, In, Out> Future for OpenBiFuture<'a, In, Out> {
-    type Output = result::Result<self::Socket<In, Out>, self::OpenBiError>;
+impl<'a, In, Out, K: ChannelCodec> Future for OpenBiFuture<'a, In, Out, K> {
+    type Output = result::Result<self::Socket<In, Out, K>, self::OpenBiError>;
 
     fn poll(
         self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Self::Output> {
-        self.project().0.poll_unpin(cx).map(|conn| {
+        let this = self.project();
+        let (config, codecs) = (*this.1, *this.2);
+        this.0.poll(cx).map(|conn| {
             let (send, recv) = conn?;
-            // turn chunks of bytes into a stream of messages using length delimited codec
-            let send = FramedWrite::new(send, LengthDelimitedCodec::new());
-            let recv = FramedRead::new(recv, LengthDelimitedCodec::new());
-            // now switch to streams of WantRequestUpdate and WantResponse
-            let recv = SymmetricallyFramed::new(recv, SymmetricalBincode::<In>::default());
-            let send = SymmetricallyFramed::new(send, SymmetricalBincode::<Out>::default());
-            // box so we don't have to write down the insanely long type
-            let send = SendSink(send);
-            let recv = RecvStream(recv);
-            Ok((send, recv))
+            let (decoder, encoder) = codecs.serde();
+            Ok(codec_socket(send, recv, config, decoder, encoder))
         })
     }
 }
 
 /// Future returned by accept_bi
 #[pin_project]
-pub struct AcceptBiFuture<'a, In, Out>(#[pin] quinn::AcceptBi<'a>, PhantomData<(In, Out)>);
+pub struct AcceptBiFuture<'a, In, Out, K = BincodeCodec>(
+    #[pin] quinn::AcceptBi<'a>,
+    &'a StreamConfig,
+    &'a Codecs<K>,
+    PhantomData<(In, Out)>,
+);
 
-impl<'a, In, Out> Future for AcceptBiFuture<'a, In, Out> {
-    type Output = result::Result<self::Socket<In, Out>, self::OpenBiError>;
+impl<'a, In, Out, K: ChannelCodec> Future for AcceptBiFuture<'a, In, Out, K> {
+    type Output = result::Result<self::Socket<In, Out, K>, self::OpenBiError>;
 
     fn poll(
         self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Self::Output> {
-        self.project().0.poll_unpin(cx).map(|conn| {
+        let this = self.project();
+        let (config, codecs) = (*this.1, *this.2);
+        this.0.poll(cx).map(|conn| {
             let (send, recv) = conn?;
-            // turn chunks of bytes into a stream of messages using length delimited codec
-            let send = FramedWrite::new(send, LengthDelimitedCodec::new());
-            let recv = FramedRead::new(recv, LengthDelimitedCodec::new());
-            // now switch to streams of WantRequestUpdate and WantResponse
-            let recv = SymmetricallyFramed::new(recv, SymmetricalBincode::<In>::default());
-            let send = SymmetricallyFramed::new(send, SymmetricalBincode::<Out>::default());
-            // box so we don't have to write down the insanely long type
-            let send = SendSink(send);
-            let recv = RecvStream(recv);
-            Ok((send, recv))
+            let (decoder, encoder) = codecs.serde();
+            Ok(codec_socket(send, recv, config, decoder, encoder))
         })
     }
 }
 
-// pub type AcceptBiFuture<'a, In, Out> =
-//     BoxFuture<'a, result::Result<self::Socket<In, Out>, self::AcceptBiError>>;
-
-impl crate::ChannelTypes for QuinnChannelTypes {
-    type SendSink<M: RpcMessage> = self::SendSink<M>;
+impl<K: ChannelCodec> crate::ChannelTypes for QuinnChannelTypes<K> {
+    type SendSink<M: RpcMessage> = self::SendSink<M, K>;
 
-    type RecvStream<M: RpcMessage> = self::RecvStream<M>;
+    type RecvStream<M: RpcMessage> = self::RecvStream<M, K>;
 
     type OpenBiError = self::OpenBiError;
 
@@ -168,23 +1666,431 @@ impl crate::ChannelTypes for QuinnChannelTypes {
 
     type SendError = io::Error;
 
-    type RecvError = io::Error;
+    type RecvError = self::RecvError;
 
-    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::OpenBiFuture<'a, In, Out>;
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::OpenBiFuture<'a, In, Out, K>;
 
-    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::AcceptBiFuture<'a, In, Out>;
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::AcceptBiFuture<'a, In, Out, K>;
 
-    type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<In, Out>;
+    type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<In, Out, K>;
+
+    fn reset<M: RpcMessage>(send: &mut Self::SendSink<M>, code: u32) {
+        // the stream is gone already if the connection is closed
+        send.reset(code).ok();
+    }
+
+    fn set_pattern<M: RpcMessage>(send: &mut Self::SendSink<M>, pattern: PatternKind) {
+        send.set_pattern(pattern)
+    }
+
+    fn set_priority<M: RpcMessage>(send: &mut Self::SendSink<M>, priority: i32) {
+        // only fails if the stream is already closed, which sending will report
+        send.set_priority(priority).ok();
+    }
 }
 
-impl<In: RpcMessage + Sync, Out: RpcMessage + Sync> crate::Channel<In, Out, QuinnChannelTypes>
-    for self::Channel<In, Out>
+impl<In: RpcMessage + Sync, Out: RpcMessage + Sync, K: ChannelCodec>
+    crate::Channel<In, Out, QuinnChannelTypes<K>> for self::Channel<In, Out, K>
 {
-    fn open_bi(&self) -> OpenBiFuture<'_, In, Out> {
-        OpenBiFuture(self.0.open_bi(), PhantomData)
+    fn open_bi(&self) -> OpenBiFuture<'_, In, Out, K> {
+        OpenBiFuture(self.0.open_bi(), &self.2, &self.3, PhantomData)
+    }
+
+    fn accept_bi(&self) -> AcceptBiFuture<'_, In, Out, K> {
+        AcceptBiFuture(self.0.accept_bi(), &self.2, &self.3, PhantomData)
+    }
+
+    fn open_uni_with(&self, first: Out) -> OpenUniWithFuture<'_, QuinnChannelTypes<K>, Out> {
+        async move {
+            let send = self.0.open_uni().await.map_err(OpenBiWithError::Open)?;
+            let encoder = SerdeCodec(self.3.encoder.clone());
+            let mut send = SendSink::new(send, &self.2, ByteCounts::default(), encoder);
+            send.send(first).await.map_err(OpenBiWithError::Send)?;
+            Ok(send)
+        }
+        .boxed()
+    }
+
+    fn accept_uni(&self) -> AcceptUniFuture<'_, QuinnChannelTypes<K>, In> {
+        async move {
+            let recv = self.0.accept_uni().await?;
+            let decoder = SerdeCodec(self.3.decoder.clone());
+            Ok(RecvStream::new(
+                recv,
+                &self.2,
+                ByteCounts::default(),
+                decoder,
+            ))
+        }
+        .boxed()
+    }
+
+    fn connection_info(&self) -> ConnectionInfo {
+        let peer_certificates = self
+            .0
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<rustls::Certificate>>().ok())
+            .map(|certs| certs.into_iter().map(|cert| cert.0).collect());
+        ConnectionInfo {
+            peer: Some(self.0.remote_address()),
+            peer_certificates,
+            rtt: Some(self.0.rtt()),
+        }
+    }
+
+    fn close(&self, code: u32, reason: &[u8]) {
+        self.0.close(quinn::VarInt::from_u32(code), reason)
+    }
+}
+
+/// The error code the remote closed the connection with, see [crate::Channel::close]
+///
+/// Returns `None` if the connection was not closed by the remote application, e.g. because it
+/// timed out.
+pub fn close_code(error: &quinn::ConnectionError) -> Option<u64> {
+    match error {
+        quinn::ConnectionError::ApplicationClosed(close) => Some(close.error_code.into_inner()),
+        _ => None,
+    }
+}
+
+/// Size of the correlation id that prefixes every rpc datagram
+const DATAGRAM_ID_LEN: usize = 8;
+
+/// Client side state for matching response datagrams to pending requests
+#[derive(Debug, Default)]
+pub struct DatagramDemux {
+    next_id: AtomicU64,
+    /// Dropping this stops the reader task, so it does not keep the connection alive
+    reader: Mutex<Option<oneshot::Sender<()>>>,
+    pending: Mutex<HashMap<u64, oneshot::Sender<Bytes>>>,
+}
+
+impl DatagramDemux {
+    /// Register a new pending request and return its correlation id
+    fn register(&self) -> (u64, oneshot::Receiver<Bytes>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        (id, rx)
+    }
+
+    /// Forget about a pending request, e.g. because it timed out
+    fn unregister(&self, id: u64) {
+        self.pending.lock().unwrap().remove(&id);
+    }
+
+    /// Spawn the task that reads response datagrams, unless it is already running
+    fn ensure_reader(self: &Arc<Self>, conn: &quinn::Connection) {
+        let mut reader = self.reader.lock().unwrap();
+        if reader.is_some() {
+            return;
+        }
+        let (stop_tx, mut stop_rx) = oneshot::channel::<()>();
+        *reader = Some(stop_tx);
+        let this = Arc::downgrade(self);
+        let conn = conn.clone();
+        tokio::spawn(async move {
+            loop {
+                let mut data = tokio::select! {
+                    data = conn.read_datagram() => match data {
+                        Ok(data) => data,
+                        Err(_) => break,
+                    },
+                    _ = &mut stop_rx => break,
+                };
+                let Some(this) = this.upgrade() else {
+                    break;
+                };
+                if data.len() < DATAGRAM_ID_LEN {
+                    continue;
+                }
+                let id = data.get_u64();
+                // responses for unknown ids (e.g. timed out requests) are just dropped
+                let tx = this.pending.lock().unwrap().remove(&id);
+                if let Some(tx) = tx {
+                    tx.send(data).ok();
+                }
+            }
+            // connection is gone, so fail all pending requests by dropping their senders
+            if let Some(this) = this.upgrade() {
+                this.pending.lock().unwrap().clear();
+            }
+        });
+    }
+}
+
+/// Encode a message into a datagram with the given correlation id
+fn encode_datagram<K: ChannelCodec, T: Serialize>(
+    conn: &quinn::Connection,
+    codec: &K,
+    id: u64,
+    msg: &T,
+) -> result::Result<Bytes, DatagramError> {
+    let max = conn.max_datagram_size().ok_or(DatagramError::Unsupported)?;
+    let mut buf = BytesMut::new();
+    buf.put_u64(id);
+    codec
+        .encode_into(msg, &mut buf)
+        .map_err(DatagramError::Serialize)?;
+    let size = buf.len();
+    if size > max {
+        return Err(DatagramError::TooLarge { size, max });
+    }
+    Ok(buf.freeze())
+}
+
+/// Error for rpc calls over QUIC datagrams
+#[derive(Debug, thiserror::Error)]
+pub enum DatagramError {
+    /// The peer does not support datagrams, or they are disabled locally
+    #[error("datagrams are not supported")]
+    Unsupported,
+    /// The encoded message does not fit into a single datagram
+    #[error("datagram of {size} bytes is larger than the maximum of {max} bytes")]
+    TooLarge {
+        /// Size of the encoded datagram, including the correlation id
+        size: usize,
+        /// Maximum datagram size for the connection
+        max: usize,
+    },
+    /// Unable to serialize the message
+    #[error("failed to serialize the message")]
+    Serialize(#[source] io::Error),
+    /// Unable to deserialize a received datagram
+    #[error("failed to deserialize the datagram")]
+    Deserialize(#[source] io::Error),
+    /// Unable to send the datagram
+    #[error("failed to send the datagram")]
+    Send(#[source] quinn::SendDatagramError),
+    /// Unable to receive a datagram
+    #[error("failed to receive a datagram")]
+    Recv(#[source] quinn::ConnectionError),
+    /// No response arrived within the timeout
+    #[error("no response arrived in time")]
+    Timeout,
+    /// The connection was closed before the response arrived
+    #[error("the connection was closed before the response arrived")]
+    Closed,
+    /// Unexpected response from the server
+    #[error("unexpected response from the server")]
+    DowncastError,
+}
+
+impl Retryable for DatagramError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Send(quinn::SendDatagramError::ConnectionLost(e)) | Self::Recv(e) => {
+                e.is_retryable()
+            }
+            // datagrams are unreliable, so a lost request or response is expected
+            Self::Timeout | Self::Closed => true,
+            Self::Unsupported
+            | Self::TooLarge { .. }
+            | Self::Serialize(_)
+            | Self::Deserialize(_)
+            | Self::Send(_)
+            | Self::DowncastError => false,
+        }
+    }
+}
+
+impl<S: Service, K: ChannelCodec> RpcClient<S, QuinnChannelTypes<K>> {
+    /// Watch the connection of this client, see [Channel::events]
+    pub fn events(&self, interval: Duration) -> ConnectionEvents {
+        self.channel.events(interval)
+    }
+
+    /// RPC call to the server with an opaque payload, single request, single response
+    ///
+    /// The request is sent as a single frame without going through the codec, and the first
+    /// frame of the response is returned as it is. This avoids deserializing and serializing
+    /// again for services that just forward payloads, like gateways. The server has to handle
+    /// the request using [RpcServer::accept_bytes] and [RpcServer::rpc_raw].
+    ///
+    /// Like the typed calls, the call gets a span and the hooks of the client, and fails with
+    /// [RpcClientError::Timeout] once the current [crate::client::Deadline] expires.
+    pub async fn rpc_raw(
+        &self,
+        req: Bytes,
+    ) -> result::Result<Bytes, RpcClientError<QuinnChannelTypes<K>>> {
+        self.untyped_call::<Bytes, _>(PatternKind::Rpc, async {
+            let (mut send, mut recv) = self
+                .channel
+                .open_raw()
+                .await
+                .map_err(RpcClientError::Open)?;
+            send.send(req).await.map_err(RpcClientError::Send)?;
+            let res = recv
+                .next()
+                .await
+                .ok_or(RpcClientError::EarlyClose)?
+                .map_err(RpcClientError::RecvError)?;
+            // keep send alive until we have the answer
+            drop(send);
+            Ok(res)
+        })
+        .await
+    }
+
+    /// RPC call to the server over QUIC datagrams, single request, single response
+    ///
+    /// The request is sent in a single datagram tagged with a correlation id, and the matching
+    /// response datagram is awaited for at most `timeout`. This avoids the cost of opening a stream,
+    /// but datagrams are unreliable, so it is only suitable for small idempotent requests.
+    ///
+    /// Both the request and the response must fit into [quinn::Connection::max_datagram_size].
+    pub async fn rpc_datagram<M>(
+        &self,
+        msg: M,
+        timeout: Duration,
+    ) -> result::Result<M::Response, DatagramError>
+    where
+        M: Msg<S, Pattern = Rpc>,
+    {
+        let Channel(conn, demux, _, codecs, _) = &self.channel;
+        let req: S::Req = msg.into();
+        demux.ensure_reader(conn);
+        let (id, recv) = demux.register();
+        let res = async {
+            let data = encode_datagram(conn, &codecs.encoder, id, &req)?;
+            conn.send_datagram(data).map_err(DatagramError::Send)?;
+            let data = tokio::time::timeout(timeout, recv)
+                .await
+                .map_err(|_| DatagramError::Timeout)?
+                .map_err(|_| DatagramError::Closed)?;
+            let res: S::Res = codecs
+                .decoder
+                .decode_from(&mut BytesMut::from(&data[..]))
+                .map_err(DatagramError::Deserialize)?;
+            M::Response::try_from(res).map_err(|_| DatagramError::DowncastError)
+        }
+        .await;
+        demux.unregister(id);
+        res
+    }
+}
+
+/// Handle to send the response for a request that was received as a datagram
+#[derive(Debug)]
+pub struct DatagramResponder<S: Service, K: ChannelCodec = BincodeCodec> {
+    conn: quinn::Connection,
+    encoder: K,
+    id: u64,
+    _s: PhantomData<S>,
+}
+
+impl<S: Service, K: ChannelCodec> DatagramResponder<S, K> {
+    /// Send the response datagram, tagged with the correlation id of the request
+    pub fn respond(self, res: S::Res) -> result::Result<(), DatagramError> {
+        let data = encode_datagram(&self.conn, &self.encoder, self.id, &res)?;
+        self.conn.send_datagram(data).map_err(DatagramError::Send)
+    }
+}
+
+impl<S: Service, K: ChannelCodec> RpcServer<S, QuinnChannelTypes<K>> {
+    /// The application protocol negotiated using ALPN during the handshake, if any
+    pub fn alpn(&self) -> Option<Vec<u8>> {
+        self.channel.alpn()
+    }
+
+    /// Close the connection unless one of the given application protocols was negotiated
+    ///
+    /// See [Channel::require_alpn].
+    pub fn require_alpn(&self, protocols: &[&[u8]]) -> result::Result<(), AlpnMismatch> {
+        self.channel.require_alpn(protocols)
+    }
+
+    /// Watch the connection of this server, see [Channel::events]
+    pub fn events(&self, interval: Duration) -> ConnectionEvents {
+        self.channel.events(interval)
+    }
+
+    /// Accept one stream opened using [RpcClient::rpc_raw] and read its request frame
+    ///
+    /// The frame is returned without going through the codec. Streams for regular requests can
+    /// not be handled this way, so a connection should either be used for raw requests only, or
+    /// use a separate connection for them.
+    pub async fn accept_bytes(
+        &self,
+    ) -> result::Result<(Bytes, RawSocket), RpcServerError<QuinnChannelTypes<K>>> {
+        let (send, mut recv) = self
+            .channel
+            .accept_raw()
+            .await
+            .map_err(RpcServerError::AcceptBiError)?;
+        let req = recv
+            .next()
+            .await
+            .ok_or(RpcServerError::EarlyClose)?
+            .map_err(RpcServerError::recv)?;
+        Ok((req, (send, recv)))
+    }
+
+    /// handle a raw request using the given function on the target object
+    ///
+    /// The response is sent as a single frame, see [RpcClient::rpc_raw].
+    pub async fn rpc_raw<F, Fut, T>(
+        &self,
+        req: Bytes,
+        (mut send, _recv): RawSocket,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<QuinnChannelTypes<K>>>
+    where
+        F: FnOnce(T, Bytes) -> Fut,
+        Fut: Future<Output = Bytes>,
+    {
+        self.untyped_call::<Bytes>(PatternKind::Rpc, async move {
+            let res = f(target, req).await;
+            send.send(res).await.map_err(RpcServerError::SendError)
+        })
+        .await
+    }
+
+    /// Accept one request datagram from the client
+    ///
+    /// Returns the request and a [DatagramResponder] to send the response. Datagrams that are too
+    /// short to contain a correlation id are skipped.
+    pub async fn accept_datagram(
+        &self,
+    ) -> result::Result<(S::Req, DatagramResponder<S, K>), DatagramError> {
+        let Channel(conn, _, _, codecs, _) = &self.channel;
+        loop {
+            let mut data = conn.read_datagram().await.map_err(DatagramError::Recv)?;
+            if data.len() < DATAGRAM_ID_LEN {
+                continue;
+            }
+            let id = data.get_u64();
+            let req = codecs
+                .decoder
+                .decode_from(&mut BytesMut::from(&data[..]))
+                .map_err(DatagramError::Deserialize)?;
+            let responder = DatagramResponder {
+                conn: conn.clone(),
+                encoder: codecs.encoder.clone(),
+                id,
+                _s: PhantomData,
+            };
+            return Ok((req, responder));
+        }
     }
 
-    fn accept_bi(&self) -> AcceptBiFuture<'_, In, Out> {
-        AcceptBiFuture(self.0.accept_bi(), PhantomData)
+    /// handle the datagram message M using the given function on the target object
+    pub async fn rpc_datagram<M, F, Fut, T>(
+        &self,
+        req: M,
+        responder: DatagramResponder<S, K>,
+        target: T,
+        f: F,
+    ) -> result::Result<(), DatagramError>
+    where
+        M: Msg<S, Pattern = Rpc>,
+        F: FnOnce(T, M) -> Fut,
+        Fut: Future<Output = M::Response>,
+        T: Send + 'static,
+    {
+        let res = f(target, req).await;
+        responder.respond(res.into())
     }
 }
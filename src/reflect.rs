@@ -0,0 +1,138 @@
+//! Built-in reflection RPC to discover the methods of a service at runtime
+//!
+//! A service opts in by adding [Reflect] to its request enum and [ServiceDescriptor] to its
+//! response enum, and by answering [Reflect] requests with [RpcServer::reflect]. Clients can then
+//! call [RpcClient::reflect] to get the [ServiceDescriptor] of the server.
+use crate::{
+    client::RpcClientError,
+    message::{InteractionPattern, Msg, Notify, NotifyMsg, PatternKind, RpcMsg},
+    server::{RpcServerError, ServerSocket},
+    ChannelTypes, RpcClient, RpcServer, Service,
+};
+use serde::{Deserialize, Serialize};
+use std::{any, result};
+
+/// Request for the [ServiceDescriptor] of a service
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Reflect;
+
+/// Description of a single method of a service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MethodDescriptor {
+    /// Name of the method
+    pub name: String,
+    /// Interaction pattern of the method
+    pub pattern: PatternKind,
+    /// Type name of the request message
+    pub request: String,
+    /// Type name of the update message, for patterns that have updates
+    pub update: Option<String>,
+    /// Type name of the response message
+    pub response: String,
+}
+
+impl MethodDescriptor {
+    /// Describe the message `M` of service `S` under the given method name
+    pub fn new<S: Service, M: Msg<S>>(name: impl Into<String>) -> Self {
+        let pattern = M::Pattern::KIND;
+        Self {
+            name: name.into(),
+            pattern,
+            request: any::type_name::<M>().to_string(),
+            update: pattern
+                .has_updates()
+                .then(|| any::type_name::<M::Update>().to_string()),
+            response: any::type_name::<M::Response>().to_string(),
+        }
+    }
+
+    /// Describe the notification `M` of service `S` under the given method name
+    ///
+    /// The response type of a notification is given as `()`.
+    pub fn notify<S: Service, M: NotifyMsg<S>>(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            pattern: Notify::KIND,
+            request: any::type_name::<M>().to_string(),
+            update: None,
+            response: any::type_name::<()>().to_string(),
+        }
+    }
+}
+
+/// Description of a service, as returned by the reflection RPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceDescriptor {
+    /// Type name of the service
+    pub name: String,
+    /// Methods of the service
+    pub methods: Vec<MethodDescriptor>,
+}
+
+impl ServiceDescriptor {
+    /// Create an empty descriptor for service `S`
+    pub fn new<S: Service>() -> Self {
+        Self {
+            name: any::type_name::<S>().to_string(),
+            methods: Vec::new(),
+        }
+    }
+
+    /// Add the message `M` under the given method name
+    pub fn method<S: Service, M: Msg<S>>(mut self, name: impl Into<String>) -> Self {
+        self.methods.push(MethodDescriptor::new::<S, M>(name));
+        self
+    }
+
+    /// Add the notification `M` under the given method name
+    pub fn notify<S: Service, M: NotifyMsg<S>>(mut self, name: impl Into<String>) -> Self {
+        self.methods.push(MethodDescriptor::notify::<S, M>(name));
+        self
+    }
+
+    /// Find a method by name
+    pub fn get(&self, name: &str) -> Option<&MethodDescriptor> {
+        self.methods.iter().find(|m| m.name == name)
+    }
+}
+
+impl<S: Service> RpcMsg<S> for Reflect
+where
+    Reflect: Into<S::Req> + TryFrom<S::Req>,
+    ServiceDescriptor: Into<S::Res> + TryFrom<S::Res>,
+{
+    type Response = ServiceDescriptor;
+}
+
+impl<S: Service, C: ChannelTypes> RpcClient<S, C>
+where
+    Reflect: Msg<S, Pattern = crate::message::Rpc, Response = ServiceDescriptor>,
+{
+    /// Query the [ServiceDescriptor] of the server
+    ///
+    /// This only works if the server has opted in to reflection, see [RpcServer::reflect].
+    pub async fn reflect(&self) -> result::Result<ServiceDescriptor, RpcClientError<C>> {
+        self.rpc(Reflect).await
+    }
+}
+
+impl<S: Service, C: ChannelTypes> RpcServer<S, C>
+where
+    Reflect: Msg<S, Pattern = crate::message::Rpc, Response = ServiceDescriptor>,
+{
+    /// Answer a [Reflect] request with the given descriptor
+    pub async fn reflect(
+        &self,
+        req: Reflect,
+        c: ServerSocket<S, C>,
+        descriptor: ServiceDescriptor,
+    ) -> result::Result<(), RpcServerError<C>> {
+        self.rpc(
+            req,
+            c,
+            descriptor,
+            |descriptor, _| async move { descriptor },
+        )
+        .await
+    }
+}
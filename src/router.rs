@@ -0,0 +1,303 @@
+//! Serve a service from a set of handler functions, without writing the dispatch by hand
+//!
+//! Handlers are either closures, see [FunctionRouter::on], or types that implement one of the
+//! handler traits for a message, see [FunctionRouter::rpc_handler] and the methods next to it. A
+//! type can implement the handler traits for several messages and be registered for each of
+//! them.
+use crate::{
+    message::{BidiStreaming, ClientStreaming, Msg, Rpc, ServerStreaming},
+    server::{RpcServerError, ServerSocket},
+    ChannelTypes, RpcServer, Service,
+};
+use futures::{
+    future::BoxFuture,
+    stream::{BoxStream, StreamExt},
+    Future, FutureExt,
+};
+use std::{
+    any::{self, TypeId},
+    collections::HashMap,
+    fmt,
+    mem::{self, Discriminant},
+    result,
+    sync::{Arc, Mutex},
+};
+
+type Handler<S, C> = Arc<
+    dyn Fn(
+            RpcServer<S, C>,
+            <S as Service>::Req,
+            ServerSocket<S, C>,
+        ) -> BoxFuture<'static, result::Result<(), RpcServerError<C>>>
+        + Send
+        + Sync,
+>;
+
+/// Handler for the rpc message `M`, see [FunctionRouter::rpc_handler]
+pub trait RpcHandler<S: Service, M: Msg<S, Pattern = Rpc>>: Send + Sync + 'static {
+    /// Compute the response to a request
+    fn handle_rpc(self: Arc<Self>, msg: M) -> BoxFuture<'static, M::Response>;
+}
+
+/// Handler for the server streaming message `M`, see [FunctionRouter::server_streaming_handler]
+pub trait ServerStreamingHandler<S: Service, M: Msg<S, Pattern = ServerStreaming>>:
+    Send + Sync + 'static
+{
+    /// Produce the responses to a request
+    fn handle_server_streaming(self: Arc<Self>, msg: M) -> BoxStream<'static, M::Response>;
+}
+
+/// Handler for the client streaming message `M`, see [FunctionRouter::client_streaming_handler]
+pub trait ClientStreamingHandler<S: Service, M: Msg<S, Pattern = ClientStreaming>>:
+    Send + Sync + 'static
+{
+    /// Compute the response to a request and the updates of the client
+    fn handle_client_streaming(
+        self: Arc<Self>,
+        msg: M,
+        updates: BoxStream<'static, M::Update>,
+    ) -> BoxFuture<'static, M::Response>;
+}
+
+/// Handler for the bidi streaming message `M`, see [FunctionRouter::bidi_streaming_handler]
+pub trait BidiStreamingHandler<S: Service, M: Msg<S, Pattern = BidiStreaming>>:
+    Send + Sync + 'static
+{
+    /// Produce the responses to a request and the updates of the client
+    fn handle_bidi_streaming(
+        self: Arc<Self>,
+        msg: M,
+        updates: BoxStream<'static, M::Update>,
+    ) -> BoxStream<'static, M::Response>;
+}
+
+/// A router that maps messages to handler functions
+///
+/// Register a handler for each message using [FunctionRouter::on] or one of the methods for
+/// handler types, then pass the router to [FunctionRouter::serve]. Requests for messages without
+/// a handler fail with [RpcServerError::UnexpectedStartMessage].
+///
+/// Routing needs to try the registered messages in turn, which consumes the request, so this
+/// requires the request type of the service to be [Clone]. The matching handler for each variant
+/// of the request enum is cached, so this happens only once per variant.
+pub struct FunctionRouter<S: Service, C: ChannelTypes> {
+    routes: Vec<Route<S, C>>,
+    cache: Mutex<HashMap<Discriminant<S::Req>, usize>>,
+}
+
+struct Route<S: Service, C: ChannelTypes> {
+    id: TypeId,
+    name: &'static str,
+    matches: fn(S::Req) -> bool,
+    handler: Handler<S, C>,
+}
+
+/// True if the request can be converted into the message `M`
+fn matches<S: Service, M: Msg<S>>(req: S::Req) -> bool {
+    M::try_from(req).is_ok()
+}
+
+impl<S: Service, C: ChannelTypes> fmt::Debug for FunctionRouter<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = self.routes.iter().map(|route| route.name);
+        f.debug_struct("FunctionRouter")
+            .field("handlers", &names.collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<S: Service, C: ChannelTypes> Default for FunctionRouter<S, C>
+where
+    S::Req: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Service, C: ChannelTypes> FunctionRouter<S, C>
+where
+    S::Req: Clone,
+{
+    /// Create a router without any handlers
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            cache: Default::default(),
+        }
+    }
+
+    /// Register the handler for the rpc message `M`
+    ///
+    /// The handler has to return the response type of the message, so a mismatch between
+    /// message and response is a compile time error.
+    ///
+    /// # Panics
+    ///
+    /// If a handler for `M` is already registered.
+    pub fn on<M, F, Fut>(self, f: F) -> Self
+    where
+        M: Msg<S, Pattern = Rpc>,
+        F: Fn(M) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = M::Response> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        self.add::<M>(Arc::new(move |server, req, chan| {
+            let f = f.clone();
+            async move {
+                let msg = M::try_from(req).map_err(|_| RpcServerError::UnexpectedStartMessage)?;
+                server.rpc(msg, chan, f, |f, msg| f(msg)).await
+            }
+            .boxed()
+        }))
+    }
+
+    /// Register a handler type for the rpc message `M`
+    ///
+    /// # Panics
+    ///
+    /// If a handler for `M` is already registered.
+    pub fn rpc_handler<M, H>(self, handler: Arc<H>) -> Self
+    where
+        M: Msg<S, Pattern = Rpc>,
+        H: RpcHandler<S, M>,
+    {
+        self.add::<M>(Arc::new(move |server, req, chan| {
+            let handler = handler.clone();
+            async move {
+                let msg = M::try_from(req).map_err(|_| RpcServerError::UnexpectedStartMessage)?;
+                server.rpc(msg, chan, handler, H::handle_rpc).await
+            }
+            .boxed()
+        }))
+    }
+
+    /// Register a handler type for the server streaming message `M`
+    ///
+    /// # Panics
+    ///
+    /// If a handler for `M` is already registered.
+    pub fn server_streaming_handler<M, H>(self, handler: Arc<H>) -> Self
+    where
+        M: Msg<S, Pattern = ServerStreaming>,
+        H: ServerStreamingHandler<S, M>,
+    {
+        self.add::<M>(Arc::new(move |server, req, chan| {
+            let handler = handler.clone();
+            async move {
+                let msg = M::try_from(req).map_err(|_| RpcServerError::UnexpectedStartMessage)?;
+                server
+                    .server_streaming(msg, chan, handler, H::handle_server_streaming)
+                    .await
+            }
+            .boxed()
+        }))
+    }
+
+    /// Register a handler type for the client streaming message `M`
+    ///
+    /// # Panics
+    ///
+    /// If a handler for `M` is already registered.
+    pub fn client_streaming_handler<M, H>(self, handler: Arc<H>) -> Self
+    where
+        M: Msg<S, Pattern = ClientStreaming>,
+        H: ClientStreamingHandler<S, M>,
+    {
+        self.add::<M>(Arc::new(move |server, req, chan| {
+            let handler = handler.clone();
+            async move {
+                let msg = M::try_from(req).map_err(|_| RpcServerError::UnexpectedStartMessage)?;
+                server
+                    .client_streaming(msg, chan, handler, |handler, msg, updates| {
+                        handler.handle_client_streaming(msg, updates.boxed())
+                    })
+                    .await
+            }
+            .boxed()
+        }))
+    }
+
+    /// Register a handler type for the bidi streaming message `M`
+    ///
+    /// # Panics
+    ///
+    /// If a handler for `M` is already registered.
+    pub fn bidi_streaming_handler<M, H>(self, handler: Arc<H>) -> Self
+    where
+        M: Msg<S, Pattern = BidiStreaming>,
+        H: BidiStreamingHandler<S, M>,
+    {
+        self.add::<M>(Arc::new(move |server, req, chan| {
+            let handler = handler.clone();
+            async move {
+                let msg = M::try_from(req).map_err(|_| RpcServerError::UnexpectedStartMessage)?;
+                server
+                    .bidi_streaming(msg, chan, handler, |handler, msg, updates| {
+                        handler.handle_bidi_streaming(msg, updates.boxed())
+                    })
+                    .await
+            }
+            .boxed()
+        }))
+    }
+
+    /// Add the route for the message `M`
+    fn add<M: Msg<S>>(mut self, handler: Handler<S, C>) -> Self {
+        let id = TypeId::of::<M>();
+        let name = any::type_name::<M>();
+        assert!(
+            self.routes.iter().all(|route| route.id != id),
+            "duplicate handler for {}",
+            name
+        );
+        self.routes.push(Route {
+            id,
+            name,
+            matches: matches::<S, M>,
+            handler,
+        });
+        self
+    }
+
+    /// Find the handler for a request
+    fn route(&self, req: &S::Req) -> Option<&Handler<S, C>> {
+        let key = mem::discriminant(req);
+        let mut cache = self.cache.lock().unwrap();
+        let index = match cache.get(&key) {
+            Some(index) => *index,
+            None => {
+                let index = self
+                    .routes
+                    .iter()
+                    .position(|route| (route.matches)(req.clone()))?;
+                cache.insert(key, index);
+                index
+            }
+        };
+        Some(&self.routes[index].handler)
+    }
+
+    /// Dispatch a single request to the matching handler
+    pub fn dispatch(
+        &self,
+        server: RpcServer<S, C>,
+        req: S::Req,
+        chan: ServerSocket<S, C>,
+    ) -> BoxFuture<'static, result::Result<(), RpcServerError<C>>> {
+        match self.route(&req) {
+            Some(handler) => handler(server, req, chan),
+            None => futures::future::err(RpcServerError::UnexpectedStartMessage).boxed(),
+        }
+    }
+
+    /// Serve requests using this router until accepting a new channel fails
+    ///
+    /// See [RpcServer::serve].
+    pub async fn serve(self, server: RpcServer<S, C>) -> result::Result<(), RpcServerError<C>> {
+        let router = Arc::new(self);
+        server
+            .serve(move |server, req, chan| router.dispatch(server, req, chan))
+            .await
+    }
+}
@@ -0,0 +1,121 @@
+//! Spawning tasks and timers for the server, independent of the async runtime
+//!
+//! The serve loops of [crate::RpcServer] spawn a task for each request, and the limits for
+//! durations and deadlines need a timer. Both use tokio by default. To run a server on another
+//! runtime, e.g. async-std or smol, set a [Spawner] with [crate::RpcServer::with_spawner] and a
+//! [Timer] with [crate::RpcServer::with_timer]. Closures can be used for both:
+//!
+//! ```ignore
+//! let server = RpcServer::new(channel)
+//!     .with_spawner(|fut| {
+//!         async_std::task::spawn(fut);
+//!     })
+//!     .with_timer(|duration| async_std::task::sleep(duration).boxed());
+//! ```
+//!
+//! The handler methods themselves do not depend on a runtime. The transports other than
+//! [crate::mem] and the client helpers that wait, like keepalives and retries, still use tokio.
+//! They are only available with the `tokio` feature, which is enabled by default. Without it,
+//! a server needs a spawner and a timer before it serves any requests.
+use futures::{
+    future::{self, BoxFuture, Either},
+    Future,
+};
+use std::{fmt, sync::Arc, time::Duration};
+
+/// Spawns the tasks of a serve loop
+pub trait Spawner: Send + Sync + 'static {
+    /// Run `fut` to completion in the background
+    fn spawn(&self, fut: BoxFuture<'static, ()>);
+}
+
+impl<F: Fn(BoxFuture<'static, ()>) + Send + Sync + 'static> Spawner for F {
+    fn spawn(&self, fut: BoxFuture<'static, ()>) {
+        self(fut)
+    }
+}
+
+/// Creates futures that complete after a duration
+pub trait Timer: Send + Sync + 'static {
+    /// A future that completes once `duration` has passed
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+impl<F: Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync + 'static> Timer for F {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self(duration)
+    }
+}
+
+/// The tokio runtime, which is the default [Spawner] and [Timer]
+///
+/// Tasks are spawned on the current runtime, so this panics if used outside of a tokio runtime.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tokio;
+
+#[cfg(feature = "tokio")]
+impl Spawner for Tokio {
+    fn spawn(&self, fut: BoxFuture<'static, ()>) {
+        tokio::spawn(fut);
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Timer for Tokio {
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// The spawner and timer of a server
+#[derive(Clone)]
+pub(crate) struct Runtime {
+    pub(crate) spawner: Arc<dyn Spawner>,
+    pub(crate) timer: Arc<dyn Timer>,
+}
+
+#[cfg(feature = "tokio")]
+impl Default for Runtime {
+    fn default() -> Self {
+        Self {
+            spawner: Arc::new(Tokio),
+            timer: Arc::new(Tokio),
+        }
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+impl Default for Runtime {
+    fn default() -> Self {
+        Self {
+            spawner: Arc::new(|_| panic!("no spawner, see RpcServer::with_spawner")),
+            timer: Arc::new(|_| panic!("no timer, see RpcServer::with_timer")),
+        }
+    }
+}
+
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Runtime").finish_non_exhaustive()
+    }
+}
+
+impl Runtime {
+    /// Run `fut`, giving up once `duration` has passed
+    pub(crate) async fn timeout<F: Future>(
+        &self,
+        duration: Duration,
+        fut: F,
+    ) -> Result<F::Output, Elapsed> {
+        futures::pin_mut!(fut);
+        match future::select(fut, self.timer.sleep(duration)).await {
+            Either::Left((res, _)) => Ok(res),
+            Either::Right(_) => Err(Elapsed),
+        }
+    }
+}
+
+/// Error of [Runtime::timeout]
+#[derive(Debug)]
+pub(crate) struct Elapsed;
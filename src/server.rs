@@ -2,27 +2,85 @@
 //!
 //! This defines the RPC server DSL
 use crate::{
-    message::{BidiStreaming, ClientStreaming, Msg, Rpc, ServerStreaming},
-    Channel, ChannelTypes, Service,
+    mapped::{self, MappedChannelTypes},
+    message::{
+        BidiStreaming, ClientStreaming, ControlFrame, Frame, Indexed, Msg, NotifyMsg, PatternKind,
+        PausePolicy, ResumeFrom, Rpc, Sequenced, ServerStreaming, SplitControl, StreamControl,
+    },
+    runtime::{Runtime, Spawner, Timer},
+    trace::{CallSpan, Hooks},
+    Channel, ChannelError, ChannelTypes, ConnectionInfo, OpenBiWithError, Service, SubService,
+};
+use bytes::Bytes;
+use futures::{
+    channel::oneshot, future, future::BoxFuture, stream::FuturesUnordered, task, task::Poll,
+    Future, FutureExt, SinkExt, Stream, StreamExt,
 };
-use futures::{channel::oneshot, task, task::Poll, Future, FutureExt, SinkExt, Stream, StreamExt};
 use pin_project::pin_project;
-use std::{error, fmt, fmt::Debug, marker::PhantomData, pin::Pin, result};
+use std::{
+    collections::VecDeque,
+    fmt,
+    fmt::Debug,
+    io,
+    marker::PhantomData,
+    pin::Pin,
+    result,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+
+/// The bidirectional stream of an accepted request
+///
+/// This is a sink for responses and a stream of the requests and updates sent by the client.
+/// The stream is pinned when the channel is accepted, so channel types can use streams that are
+/// not [Unpin].
+pub type ServerSocket<S, C> = (
+    <C as ChannelTypes>::SendSink<<S as Service>::Res>,
+    Pin<Box<<C as ChannelTypes>::RecvStream<<S as Service>::Req>>>,
+);
 
 /// A server channel for a specific service
 ///
 /// This is a wrapper around a [crate::Channel] that serves as the entry point for the server DSL.
 /// `S` is the service type, `C` is the channel type.
-#[derive(Debug)]
 pub struct RpcServer<S: Service, C: ChannelTypes> {
-    channel: C::Channel<S::Req, S::Res>,
+    pub(crate) channel: C::Channel<S::Req, S::Res>,
+    max_rpc_duration: Option<Duration>,
+    reject_code: u32,
+    authenticator: Option<Arc<dyn Authenticator<S>>>,
+    hooks: Hooks,
+    runtime: Runtime,
     _s: std::marker::PhantomData<(S, C)>,
 }
 
+/// Default error code for closing connections in [RpcServer::accept_filtered]
+pub const DEFAULT_REJECT_CODE: u32 = 1;
+
+impl<S: Service, C: ChannelTypes> fmt::Debug for RpcServer<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the channel is not required to implement Debug
+        f.debug_struct("RpcServer")
+            .field("service", &std::any::type_name::<S>())
+            .field("max_rpc_duration", &self.max_rpc_duration)
+            .field("reject_code", &self.reject_code)
+            .field("has_authenticator", &self.authenticator.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
 impl<S: Service, C: ChannelTypes> Clone for RpcServer<S, C> {
     fn clone(&self) -> Self {
         Self {
             channel: self.channel.clone(),
+            max_rpc_duration: self.max_rpc_duration,
+            reject_code: self.reject_code,
+            authenticator: self.authenticator.clone(),
+            hooks: self.hooks.clone(),
+            runtime: self.runtime.clone(),
             _s: std::marker::PhantomData,
         }
     }
@@ -33,9 +91,180 @@ impl<S: Service, C: ChannelTypes> RpcServer<S, C> {
     pub fn new(channel: C::Channel<S::Req, S::Res>) -> Self {
         Self {
             channel,
+            max_rpc_duration: None,
+            reject_code: DEFAULT_REJECT_CODE,
+            authenticator: None,
+            hooks: Hooks::default(),
+            runtime: Runtime::default(),
             _s: std::marker::PhantomData,
         }
     }
+
+    /// Report every request handled by this server to `metrics`
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Arc<dyn crate::metrics::Metrics>) -> Self {
+        self.hooks.metrics = Some(metrics);
+        self
+    }
+
+    /// Abort every request handled by this server once it has been running for `duration`
+    ///
+    /// Unlike a timeout for single items, this fires even if the request is actively sending or
+    /// receiving data. Aborted requests fail with [RpcServerError::MaxDurationExceeded], and their
+    /// streams get dropped. To limit only some methods, use a clone of the server with a limit
+    /// for dispatching those.
+    pub fn with_max_rpc_duration(mut self, duration: Duration) -> Self {
+        self.max_rpc_duration = Some(duration);
+        self
+    }
+
+    /// Spawn the tasks of the serve loops using `spawner` instead of tokio
+    ///
+    /// See [crate::runtime] for running a server on another runtime.
+    pub fn with_spawner(mut self, spawner: impl Spawner) -> Self {
+        self.runtime.spawner = Arc::new(spawner);
+        self
+    }
+
+    /// Use `timer` instead of tokio for durations and deadlines
+    ///
+    /// This is used for [RpcServer::with_max_rpc_duration], [SlowReaderPolicy::TimeoutAfter] and
+    /// the handler methods with deadlines. See [crate::runtime].
+    pub fn with_timer(mut self, timer: impl Timer) -> Self {
+        self.runtime.timer = Arc::new(timer);
+        self
+    }
+
+    /// A server for the sub-service `Child`, using the connection of this server
+    ///
+    /// Usually requests are accepted on the server of the parent and then handed to the
+    /// sub-service using [RpcServer::route].
+    pub fn sub<Child: SubService<S>>(
+        &self,
+    ) -> RpcServer<Child, MappedChannelTypes<C, S::Req, S::Res>> {
+        let channel = mapped::Channel::new(self.channel.clone(), Child::wrap_res, |req| {
+            Child::unwrap_req(req).ok()
+        });
+        RpcServer {
+            channel,
+            max_rpc_duration: self.max_rpc_duration,
+            reject_code: self.reject_code,
+            // requests of sub-services are checked when they are accepted on the parent
+            authenticator: None,
+            hooks: self.hooks.clone(),
+            runtime: self.runtime.clone(),
+            _s: PhantomData,
+        }
+    }
+
+    /// Hand a request accepted on this server to the sub-service `Child`
+    ///
+    /// Returns the server for the sub-service, see [RpcServer::sub], together with the request
+    /// and the socket converted for the sub-service, so they can be passed to its handlers. If
+    /// the request belongs to another sub-service, the request and the socket are returned
+    /// unchanged.
+    ///
+    /// ```ignore
+    /// let (req, chan) = server.accept_one().await?;
+    /// match server.route::<ComputeService>(req, chan) {
+    ///     Ok((server, req, chan)) => ComputeService.dispatch(&server, req, chan).await?,
+    ///     Err((req, chan)) => { /* try the next sub-service */ }
+    /// }
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn route<Child: SubService<S>>(
+        &self,
+        req: S::Req,
+        chan: ServerSocket<S, C>,
+    ) -> result::Result<
+        (
+            RpcServer<Child, MappedChannelTypes<C, S::Req, S::Res>>,
+            Child::Req,
+            ServerSocket<Child, MappedChannelTypes<C, S::Req, S::Res>>,
+        ),
+        (S::Req, ServerSocket<S, C>),
+    > {
+        let req = match Child::unwrap_req(req) {
+            Ok(req) => req,
+            Err(req) => return Err((req, chan)),
+        };
+        let server = self.sub::<Child>();
+        let (send, recv) = chan;
+        let (send, recv) = server.channel.wrap_pinned(send, recv);
+        Ok((server, req, (send, Box::pin(recv))))
+    }
+
+    /// Close connections rejected by [RpcServer::accept_filtered] with `code`
+    ///
+    /// The default is [DEFAULT_REJECT_CODE]. For quinn, clients can read the code from the
+    /// connection error with [crate::quinn::close_code].
+    pub fn with_reject_code(mut self, code: u32) -> Self {
+        self.reject_code = code;
+        self
+    }
+
+    /// Check the auth token of every request using `authenticator` before handling it
+    ///
+    /// The token is read from the first message using [ChannelTypes::auth_token], so the client
+    /// and the server need a channel that sends tokens, see [crate::auth]. Rejected requests
+    /// fail with [RpcServerError::Unauthorized] before any handler runs or any update is read,
+    /// and their stream is reset with the code of [RpcServerErrorKind::Unauthorized].
+    ///
+    /// This applies to all methods that read the first message, like [RpcServer::accept_one]
+    /// and the serve loops. Streams from [RpcServer::accept_raw] are not checked. Servers for
+    /// sub-services do not check tokens, since their requests are accepted on the parent.
+    pub fn with_authenticator(mut self, authenticator: impl Authenticator<S>) -> Self {
+        self.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// The auth token the client sent with the request on `chan`, see [ChannelTypes::auth_token]
+    ///
+    /// Handlers can use this to tell clients apart after the token was checked.
+    pub fn auth_token(&self, chan: &ServerSocket<S, C>) -> Option<Bytes> {
+        C::auth_token(&chan.1)
+    }
+
+    /// Span for a call of message `M` on the stream `c`
+    ///
+    /// This also tells the sink of the stream about the pattern, see [ChannelTypes::set_pattern].
+    fn call_span<M: Msg<S>>(&self, c: &mut ServerSocket<S, C>) -> CallSpan {
+        C::set_pattern(&mut c.0, PatternKind::of::<S, M>());
+        let span = CallSpan::for_msg::<S, M>("server", &self.hooks);
+        span.correlate::<C, S::Req>(&c.1);
+        span
+    }
+
+    /// Handle a request that bypasses the typed messages of the service, like the raw calls of
+    /// some transports, in a span for `M` and within the max rpc duration
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) async fn untyped_call<M>(
+        &self,
+        pattern: PatternKind,
+        fut: impl Future<Output = result::Result<(), RpcServerError<C>>>,
+    ) -> result::Result<(), RpcServerError<C>> {
+        self.limit(CallSpan::new::<M>("server", pattern, &self.hooks), fut)
+            .await
+    }
+
+    /// Run the future of a single request in its span, aborting it after the max rpc duration
+    async fn limit(
+        &self,
+        span: CallSpan,
+        fut: impl Future<Output = result::Result<(), RpcServerError<C>>>,
+    ) -> result::Result<(), RpcServerError<C>> {
+        span.call(async move {
+            match self.max_rpc_duration {
+                Some(duration) => self
+                    .runtime
+                    .timeout(duration, fut)
+                    .await
+                    .map_err(|_| RpcServerError::MaxDurationExceeded)?,
+                None => fut.await,
+            }
+        })
+        .await
+    }
 }
 
 impl<S: Service, C: ChannelTypes> RpcServer<S, C> {
@@ -43,16 +272,89 @@ impl<S: Service, C: ChannelTypes> RpcServer<S, C> {
     /// message and the channel for further processing.
     pub async fn accept_one(
         &mut self,
-    ) -> result::Result<(S::Req, (C::SendSink<S::Res>, C::RecvStream<S::Req>)), RpcServerError<C>>
-    where
-        C::RecvStream<S::Req>: Unpin,
-    {
-        let mut channel = self
+    ) -> result::Result<(S::Req, ServerSocket<S, C>), RpcServerError<C>> {
+        let channel = self.accept_raw().await?;
+        self.read_first(channel).await
+    }
+
+    /// Like [RpcServer::accept_one], but also returns the interaction pattern of the request
+    ///
+    /// The pattern is determined using [Service::interaction]. Requests that are not
+    /// classified, e.g. updates, fail with [RpcServerError::UnexpectedStartMessage].
+    pub async fn accept_one_classified(
+        &mut self,
+    ) -> result::Result<(S::Req, PatternKind, ServerSocket<S, C>), RpcServerError<C>> {
+        let (req, chan) = self.accept_one().await?;
+        let kind = S::interaction(&req).ok_or(RpcServerError::UnexpectedStartMessage)?;
+        Ok((req, kind, chan))
+    }
+
+    /// Like [RpcServer::accept_one], but stops waiting for a request when `cancel` completes
+    ///
+    /// Returns `Ok(None)` if cancelled. Requests that were already accepted are not affected, so
+    /// handlers spawned by the caller keep running. This allows a clean shutdown of a dispatch
+    /// loop, e.g. using a `CancellationToken` from tokio-util:
+    ///
+    /// ```ignore
+    /// while let Some((req, chan)) = server.accept_one_or_cancel(token.cancelled()).await? {
+    ///     tokio::spawn(dispatch(server.clone(), req, chan));
+    /// }
+    /// ```
+    pub async fn accept_one_or_cancel(
+        &mut self,
+        cancel: impl Future<Output = ()>,
+    ) -> result::Result<Option<(S::Req, ServerSocket<S, C>)>, RpcServerError<C>> {
+        let accept = self.accept_one();
+        futures::pin_mut!(accept, cancel);
+        match future::select(accept, cancel).await {
+            future::Either::Left((res, _)) => res.map(Some),
+            future::Either::Right(_) => Ok(None),
+        }
+    }
+
+    /// Like [RpcServer::accept_one], but only if `filter` accepts the remote of the connection
+    ///
+    /// The filter gets the [ConnectionInfo] of the connection, e.g. to check the peer address
+    /// against an allowlist or to check the certificate of the peer. If it returns false, the
+    /// connection is closed with the reject code, see [RpcServer::with_reject_code], and this
+    /// fails with [RpcServerError::Rejected] without reading any messages.
+    pub async fn accept_filtered(
+        &mut self,
+        filter: impl Fn(&ConnectionInfo) -> bool,
+    ) -> result::Result<(S::Req, ServerSocket<S, C>), RpcServerError<C>> {
+        if !filter(&self.connection_info()) {
+            self.channel.close(self.reject_code, b"rejected");
+            return Err(RpcServerError::Rejected);
+        }
+        self.accept_one().await
+    }
+
+    /// Information about the connection of this server, see [Channel::connection_info]
+    pub fn connection_info(&self) -> ConnectionInfo {
+        self.channel.connection_info()
+    }
+
+    /// Accept one channel from the client without reading the first request
+    ///
+    /// This is an escape hatch for custom dispatch loops. The first message has to be read from
+    /// the returned stream before handing it to one of the handler methods, like
+    /// [RpcServer::accept_one] does.
+    pub async fn accept_raw(&mut self) -> result::Result<ServerSocket<S, C>, RpcServerError<C>> {
+        let (send, recv) = self
             .channel
             .accept_bi()
             .await
             .map_err(RpcServerError::AcceptBiError)?;
-        // get the first message from the client. This will tell us what it wants to do.
+        Ok((send, Box::pin(recv)))
+    }
+
+    /// Read the first message of a stream and check its auth token
+    ///
+    /// The first message tells us what the client wants to do.
+    async fn read_first(
+        &self,
+        mut channel: ServerSocket<S, C>,
+    ) -> result::Result<(S::Req, ServerSocket<S, C>), RpcServerError<C>> {
         let request: S::Req = channel
             .1
             .next()
@@ -60,17 +362,238 @@ impl<S: Service, C: ChannelTypes> RpcServer<S, C> {
             // no msg => early close
             .ok_or(RpcServerError::EarlyClose)?
             // recv error
-            .map_err(RpcServerError::RecvError)?;
+            .map_err(RpcServerError::recv)?;
+        if let Some(authenticator) = &self.authenticator {
+            let token = C::auth_token(&channel.1);
+            if !authenticator.authenticate(token.as_deref(), &request) {
+                if let Some(code) = RpcServerErrorKind::Unauthorized.reset_code() {
+                    C::reset(&mut channel.0, code);
+                }
+                return Err(RpcServerError::Unauthorized);
+            }
+        }
         Ok((request, channel))
     }
 
+    /// Accept a notification that was sent on a unidirectional stream
+    ///
+    /// Only channel types with native unidirectional streams use them for notifications, see
+    /// [Channel::open_uni_with]. For all other channel types, notifications arrive through
+    /// [RpcServer::accept_one] like any other request, so a server that handles notifications
+    /// should accept from both, e.g. using a clone of the server.
+    pub async fn accept_notification(&self) -> result::Result<S::Req, RpcServerError<C>> {
+        let recv = self
+            .channel
+            .accept_uni()
+            .await
+            .map_err(RpcServerError::AcceptBiError)?;
+        futures::pin_mut!(recv);
+        recv.next()
+            .await
+            .ok_or(RpcServerError::EarlyClose)?
+            .map_err(RpcServerError::recv)
+    }
+
+    /// Push a message to the client, outside of any request
+    ///
+    /// The message is sent on a new stream opened by the server, using a unidirectional stream if
+    /// the channel type supports them, see [Channel::open_uni_with]. The client receives it with
+    /// [crate::RpcClient::accept_push].
+    pub async fn push<M: Into<S::Res>>(&self, msg: M) -> result::Result<(), RpcServerError<C>> {
+        let mut send = self
+            .channel
+            .open_uni_with(msg.into())
+            .await
+            .map_err(|e| match e {
+                OpenBiWithError::Open(e) => RpcServerError::OpenBiError(e),
+                OpenBiWithError::Send(e) => RpcServerError::SendError(e),
+            })?;
+        send.close().await.map_err(RpcServerError::SendError)
+    }
+
+    /// Serve requests until accepting a new channel fails, spawning a task for each request
+    ///
+    /// Tasks are spawned on tokio, unless another spawner is set with [RpcServer::with_spawner].
+    ///
+    /// For each request, `dispatch` is called with a clone of this server, the first message and
+    /// the channel, and is expected to call the handler method for the message. Errors from
+    /// individual requests do not stop the server.
+    pub async fn serve<D, Fut>(self, dispatch: D) -> result::Result<(), RpcServerError<C>>
+    where
+        D: Fn(Self, S::Req, ServerSocket<S, C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = result::Result<(), RpcServerError<C>>> + Send + 'static,
+    {
+        self.serve_with_context(dispatch, |_, fut| fut).await
+    }
+
+    /// Like [RpcServer::serve], but wraps the future of each request using `context`
+    ///
+    /// The wrapped future runs on the spawned task, so this can be used to establish per request
+    /// state such as tokio task locals:
+    ///
+    /// ```ignore
+    /// tokio::task_local! { static REQUEST_ID: u64; }
+    /// server.serve_with_context(dispatch, |ctx, fut| REQUEST_ID.scope(ctx.id, fut)).await
+    /// ```
+    pub async fn serve_with_context<D, Fut, W, WFut>(
+        self,
+        dispatch: D,
+        context: W,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        D: Fn(Self, S::Req, ServerSocket<S, C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = result::Result<(), RpcServerError<C>>> + Send + 'static,
+        W: Fn(&RequestContext, Fut) -> WFut + Send + Sync + 'static,
+        WFut: Future<Output = result::Result<(), RpcServerError<C>>> + Send + 'static,
+    {
+        self.serve_inner(dispatch, context, None, None, None).await
+    }
+
+    /// Like [RpcServer::serve], but with at most `max_concurrent` requests in flight
+    ///
+    /// Once the limit is reached, no further channels are accepted until one of the requests
+    /// completes, so clients get backpressure instead of the server piling up tasks. Like for
+    /// [RpcServer::serve], only failing to accept a channel stops the server.
+    pub async fn serve_concurrent<D, Fut>(
+        self,
+        dispatch: D,
+        max_concurrent: usize,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        D: Fn(Self, S::Req, ServerSocket<S, C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = result::Result<(), RpcServerError<C>>> + Send + 'static,
+    {
+        self.serve_inner(dispatch, |_, fut| fut, Some(max_concurrent), None, None)
+            .await
+    }
+
+    /// Like [RpcServer::serve], but returns `Ok(())` once the server has been idle for `idle`
+    ///
+    /// The server is idle while no request is in flight. The idle time starts when serving starts
+    /// or when the last request completes, and a new request restarts it. This is useful for
+    /// deployments that scale to zero, e.g. to shut down a connection or process that is no
+    /// longer used. The idle time is measured using the timer of the server, see
+    /// [RpcServer::with_timer].
+    pub async fn serve_until_idle<D, Fut>(
+        self,
+        dispatch: D,
+        idle: Duration,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        D: Fn(Self, S::Req, ServerSocket<S, C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = result::Result<(), RpcServerError<C>>> + Send + 'static,
+    {
+        self.serve_inner(dispatch, |_, fut| fut, None, Some(idle), None)
+            .await
+    }
+
+    /// The serve loop shared by all serve methods and by [Drain::serve]
+    ///
+    /// Returns `Ok(())` once the server has been idle for `idle`, or once `drain` started
+    /// draining.
+    async fn serve_inner<D, Fut, W, WFut>(
+        self,
+        dispatch: D,
+        context: W,
+        max_concurrent: Option<usize>,
+        idle: Option<Duration>,
+        drain: Option<DrainHandle>,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        D: Fn(Self, S::Req, ServerSocket<S, C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = result::Result<(), RpcServerError<C>>> + Send + 'static,
+        W: Fn(&RequestContext, Fut) -> WFut + Send + Sync + 'static,
+        WFut: Future<Output = result::Result<(), RpcServerError<C>>> + Send + 'static,
+    {
+        let dispatch = Arc::new(dispatch);
+        let context = Arc::new(context);
+        let limit = max_concurrent.map(|n| Arc::new(Semaphore::new(n.max(1))));
+        let in_flight = match &drain {
+            Some(drain) => drain.in_flight.clone(),
+            None => Arc::new(tokio::sync::watch::channel(0usize).0),
+        };
+        let mut drained = drain.as_ref().map(|drain| drain.signal.subscribe());
+        let mut next_id = 0u64;
+        loop {
+            let accept = async {
+                let permit = match &limit {
+                    Some(limit) => Some(
+                        limit
+                            .clone()
+                            .acquire_owned()
+                            .await
+                            .expect("semaphore is never closed"),
+                    ),
+                    None => None,
+                };
+                let (send, recv) = self.channel.accept_bi().await?;
+                Ok((permit, send, recv))
+            };
+            let stop = async {
+                let idle = async {
+                    match idle {
+                        Some(idle) => wait_idle(in_flight.subscribe(), &self.runtime, idle).await,
+                        None => future::pending().await,
+                    }
+                };
+                let drained = async {
+                    match &mut drained {
+                        Some(drained) => wait_drained(drained).await,
+                        None => future::pending().await,
+                    }
+                };
+                future::select(Box::pin(idle), Box::pin(drained)).await
+            };
+            let (permit, send, recv) = match future::select(Box::pin(stop), Box::pin(accept)).await
+            {
+                future::Either::Left(_) => return Ok(()),
+                future::Either::Right((res, _)) => res.map_err(RpcServerError::AcceptBiError)?,
+            };
+            let id = next_id;
+            next_id += 1;
+            let channel = (send, Box::pin(recv));
+            let server = self.clone();
+            let dispatch = dispatch.clone();
+            let context = context.clone();
+            let counts = drain.as_ref().map(|drain| drain.counts.clone());
+            if let Some(counts) = &counts {
+                counts.accepted.fetch_add(1, Ordering::SeqCst);
+            }
+            let in_flight = InFlight::new(in_flight.clone());
+            self.runtime.spawner.spawn(Box::pin(async move {
+                let _permit = permit;
+                let _in_flight = in_flight;
+                let res = async {
+                    // read the first message on the task, so a slow client does not block
+                    // accepting
+                    let (request, channel) = server.read_first(channel).await?;
+                    let ctx = RequestContext { id };
+                    context(&ctx, dispatch(server, request, channel)).await
+                }
+                .await;
+                if let Err(cause) = &res {
+                    log_request_error(id, cause);
+                }
+                // count before the request stops being in flight, so the summary is complete
+                if let Some(counts) = counts {
+                    let counter = if res.is_ok() {
+                        &counts.completed
+                    } else {
+                        &counts.failed
+                    };
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            }));
+        }
+    }
+
     /// handle the message M using the given function on the target object
     ///
     /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
     pub async fn rpc<M, F, Fut, T>(
         &self,
         req: M,
-        c: (C::SendSink<S::Res>, C::RecvStream<S::Req>),
+        mut c: ServerSocket<S, C>,
         target: T,
         f: F,
     ) -> result::Result<(), RpcServerError<C>>
@@ -80,30 +603,233 @@ impl<S: Service, C: ChannelTypes> RpcServer<S, C> {
         Fut: Future<Output = M::Response>,
         T: Send + 'static,
     {
-        let (mut send, mut recv) = c;
+        let span = self.call_span::<M>(&mut c);
+        let (mut sink, mut recv) = c;
+        let send = &mut sink;
         // cancel if we get an update, no matter what it is
-        let cancel = recv
-            .next()
-            .map(|_| RpcServerError::UnexpectedUpdateMessage::<C>);
+        let cancel = recv.next().map(unexpected_update::<S, C>);
         // race the computation and the cancellation
-        race2(cancel.map(Err), async move {
-            // get the response
-            let res = f(target, req).await;
-            // turn into a S::Res so we can send it
-            let res: S::Res = res.into();
-            // send it and return the error if any
-            send.send(res).await.map_err(RpcServerError::SendError)
-        })
+        let res = self
+            .limit(
+                span,
+                race2(cancel.map(Err), async move {
+                    // get the response
+                    let res = f(target, req).await;
+                    // turn into a S::Res so we can send it
+                    let res: S::Res = res.into();
+                    // send it and return the error if any
+                    send.send(res).await.map_err(RpcServerError::SendError)
+                }),
+            )
+            .await;
+        reset_on_error::<S, C>(&mut sink, res)
+    }
+
+    /// handle the message M and all further requests of type M on the same stream, until the
+    /// client finishes the stream
+    ///
+    /// This is the server side of [crate::RpcClient::rpc_batch]. The requests are processed one
+    /// after the other, so the responses are sent in the order of the requests. Single requests
+    /// sent using [crate::RpcClient::rpc] are handled just like [RpcServer::rpc] does. A
+    /// maximum duration set using [RpcServer::with_max_rpc_duration] applies to the whole
+    /// stream.
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn rpc_pipeline<M, F, Fut, T>(
+        &self,
+        req: M,
+        mut c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = Rpc>,
+        F: Fn(T, M) -> Fut,
+        Fut: Future<Output = M::Response>,
+        T: Clone + Send + 'static,
+    {
+        let span = self.call_span::<M>(&mut c);
+        let (mut sink, mut recv) = c;
+        let send = &mut sink;
+        let res = self
+            .limit(span, async move {
+                let mut req = req;
+                loop {
+                    let res: S::Res = f(target.clone(), req).await.into();
+                    send.send(res).await.map_err(RpcServerError::SendError)?;
+                    req = match recv.next().await {
+                        None => return Ok(()),
+                        Some(Ok(msg)) if S::is_cancel(&msg) => {
+                            return Err(RpcServerError::Cancelled)
+                        }
+                        Some(Ok(msg)) => {
+                            M::try_from(msg).map_err(|_| RpcServerError::UnexpectedUpdateMessage)?
+                        }
+                        Some(Err(cause)) => return Err(RpcServerError::recv(cause)),
+                    };
+                }
+            })
+            .await;
+        reset_on_error::<S, C>(&mut sink, res)
+    }
+
+    /// Like [RpcServer::rpc_pipeline], but processes up to `max_inflight` requests concurrently
+    ///
+    /// This is the server side of [crate::RpcClient::rpc_batch_concurrent]. Responses are sent as
+    /// soon as they are ready, tagged with the index of their request on the stream in an
+    /// [Indexed], so a slow request does not hold back the responses of the requests after it.
+    /// Once `max_inflight` requests are in progress, no further requests are read until one of
+    /// them completes. A maximum duration set using [RpcServer::with_max_rpc_duration] applies to
+    /// the whole stream.
+    pub async fn rpc_pipeline_concurrent<M, F, Fut, T>(
+        &self,
+        req: M,
+        mut c: ServerSocket<S, C>,
+        target: T,
+        max_inflight: usize,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = Rpc>,
+        Indexed<M::Response>: Into<S::Res>,
+        F: Fn(T, M) -> Fut,
+        Fut: Future<Output = M::Response>,
+        T: Clone + Send + 'static,
+    {
+        let span = self.call_span::<M>(&mut c);
+        let (mut sink, mut recv) = c;
+        let send = &mut sink;
+        let max_inflight = max_inflight.max(1);
+        let res = self
+            .limit(span, async move {
+                let mut pending = FuturesUnordered::new();
+                let mut next = Some(req);
+                let mut index = 0;
+                let mut finished = false;
+                loop {
+                    if pending.len() < max_inflight {
+                        if let Some(req) = next.take() {
+                            let i = index;
+                            index += 1;
+                            pending.push(f(target.clone(), req).map(move |item| (i, item)));
+                        }
+                    }
+                    let can_read = !finished && pending.len() < max_inflight;
+                    let event = match (can_read, pending.is_empty()) {
+                        (false, true) => return Ok(()),
+                        (true, true) => future::Either::Left(recv.next().await),
+                        (false, false) => future::Either::Right(pending.next().await),
+                        (true, false) => race_either(recv.next(), pending.next()).await,
+                    };
+                    match event {
+                        future::Either::Left(None) => finished = true,
+                        future::Either::Left(Some(Ok(msg))) if S::is_cancel(&msg) => {
+                            return Err(RpcServerError::Cancelled)
+                        }
+                        future::Either::Left(Some(Ok(msg))) => {
+                            let req = M::try_from(msg)
+                                .map_err(|_| RpcServerError::UnexpectedUpdateMessage)?;
+                            next = Some(req);
+                        }
+                        future::Either::Left(Some(Err(cause))) => {
+                            return Err(RpcServerError::recv(cause))
+                        }
+                        future::Either::Right(Some((index, item))) => {
+                            send.send(Indexed { index, item }.into())
+                                .await
+                                .map_err(RpcServerError::SendError)?;
+                        }
+                        // pending was not empty
+                        future::Either::Right(None) => {}
+                    }
+                }
+            })
+            .await;
+        reset_on_error::<S, C>(&mut sink, res)
+    }
+
+    /// Like [RpcServer::rpc], but also passes the [ConnectionInfo] of the connection to the
+    /// handler, e.g. to authorize or log requests by peer
+    pub async fn rpc_with_info<M, F, Fut, T>(
+        &self,
+        req: M,
+        c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = Rpc>,
+        F: FnOnce(T, M, ConnectionInfo) -> Fut,
+        Fut: Future<Output = M::Response>,
+        T: Send + 'static,
+    {
+        let info = self.connection_info();
+        self.rpc(req, c, target, move |target, req| f(target, req, info))
+            .await
+    }
+
+    /// handle a notification using the given function on the target object
+    ///
+    /// Notifications do not get a response, so there is no socket to pass. If the notification
+    /// was accepted using [RpcServer::accept_one], its socket can just be dropped.
+    pub async fn notify<M, F, Fut, T>(
+        &self,
+        req: M,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: NotifyMsg<S>,
+        F: FnOnce(T, M) -> Fut,
+        Fut: Future<Output = ()>,
+        T: Send + 'static,
+    {
+        self.limit(
+            CallSpan::new::<M>("server", PatternKind::Notify, &self.hooks),
+            f(target, req).map(Ok),
+        )
         .await
     }
 
+    /// handle a request of an [crate::client::OrderedClient] using the given function on the
+    /// target object, after all requests with a lower sequence number are done
+    ///
+    /// Requests that arrive early wait for their predecessors, so ordered requests need to be
+    /// dispatched concurrently, e.g. using [RpcServer::serve]. Requests that are dropped or fail
+    /// do not hold up the ones after them, and missing requests are skipped after the gap
+    /// timeout of the queue, see [OrderedQueue].
+    pub async fn rpc_ordered<M, F, Fut, T>(
+        &self,
+        req: Sequenced<M>,
+        c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+        queue: &OrderedQueue,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        Sequenced<M>: Msg<S, Pattern = Rpc>,
+        F: FnOnce(T, M) -> Fut,
+        Fut: Future<Output = <Sequenced<M> as Msg<S>>::Response>,
+        T: Send + 'static,
+    {
+        let Sequenced { seq, msg } = req;
+        queue
+            .run(seq, &*self.runtime.timer, async move {
+                self.rpc(Sequenced { seq, msg }, c, target, |target, req| {
+                    f(target, req.msg)
+                })
+                .await
+            })
+            .await
+    }
+
     /// handle the message M using the given function on the target object
     ///
     /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
     pub async fn client_streaming<M, F, Fut, T>(
         &self,
         req: M,
-        c: (C::SendSink<S::Res>, C::RecvStream<S::Req>),
+        mut c: ServerSocket<S, C>,
         target: T,
         f: F,
     ) -> result::Result<(), RpcServerError<C>>
@@ -113,26 +839,36 @@ impl<S: Service, C: ChannelTypes> RpcServer<S, C> {
         Fut: Future<Output = M::Response> + Send + 'static,
         T: Send + 'static,
     {
-        let (mut send, recv) = c;
+        let span = self.call_span::<M>(&mut c);
+        let (mut sink, recv) = c;
+        let send = &mut sink;
         let (updates, read_error) = UpdateStream::new(recv);
-        race2(read_error.map(Err), async move {
-            // get the response
-            let res = f(target, req, updates).await;
-            // turn into a S::Res so we can send it
-            let res: S::Res = res.into();
-            // send it and return the error if any
-            send.send(res).await.map_err(RpcServerError::SendError)
-        })
-        .await
+        let res = self
+            .limit(
+                span,
+                race2(read_error.map(Err), async move {
+                    // get the response
+                    let res = f(target, req, updates).await;
+                    // turn into a S::Res so we can send it
+                    let res: S::Res = res.into();
+                    // send it and return the error if any
+                    send.send(res).await.map_err(RpcServerError::SendError)
+                }),
+            )
+            .await;
+        reset_on_error::<S, C>(&mut sink, res)
     }
 
     /// handle the message M using the given function on the target object
     ///
+    /// The call is complete when the response stream ends. The handler does not have to read all
+    /// updates first, the [UpdateStream] ends and stops receiving once the call is complete.
+    ///
     /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
     pub async fn bidi_streaming<M, F, Str, T>(
         &self,
         req: M,
-        c: (C::SendSink<S::Res>, C::RecvStream<S::Req>),
+        mut c: ServerSocket<S, C>,
         target: T,
         f: F,
     ) -> result::Result<(), RpcServerError<C>>
@@ -142,33 +878,141 @@ impl<S: Service, C: ChannelTypes> RpcServer<S, C> {
         Str: Stream<Item = M::Response> + Send + 'static,
         T: Send + 'static,
     {
-        let (mut send, recv) = c;
+        let span = self.call_span::<M>(&mut c);
+        let (mut sink, recv) = c;
+        let send = &mut sink;
         // downcast the updates
         let (updates, read_error) = UpdateStream::new(recv);
         // get the response
         let responses = f(target, req, updates);
-        race2(read_error.map(Err), async move {
-            tokio::pin!(responses);
-            while let Some(response) = responses.next().await {
-                // turn into a S::Res so we can send it
-                let response: S::Res = response.into();
-                // send it and return the error if any
-                send.send(response)
-                    .await
-                    .map_err(RpcServerError::SendError)?;
-            }
-            Ok(())
-        })
-        .await
+        let res = self
+            .limit(
+                span,
+                race2(read_error.map(Err), async move {
+                    futures::pin_mut!(responses);
+                    while let Some(response) = responses.next().await {
+                        // turn into a S::Res so we can send it
+                        let response: S::Res = response.into();
+                        // send it and return the error if any
+                        send.send(response)
+                            .await
+                            .map_err(RpcServerError::SendError)?;
+                    }
+                    Ok(())
+                }),
+            )
+            .await;
+        reset_on_error::<S, C>(&mut sink, res)
+    }
+
+    /// handle the message M using the given function on the target object, sending an
+    /// acknowledgement before the other responses
+    ///
+    /// The handler returns the ack together with the response stream. The ack is sent and
+    /// flushed before the response stream is polled for the first time, so the client gets it
+    /// before any updates are processed. This is useful for subscriptions and other protocols
+    /// where the server confirms the request right away and then streams responses.
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn bidi_streaming_with_ack<M, F, Str, T>(
+        &self,
+        req: M,
+        mut c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = BidiStreaming>,
+        F: FnOnce(T, M, UpdateStream<S, C, M>) -> (M::Response, Str) + Send + 'static,
+        Str: Stream<Item = M::Response> + Send + 'static,
+        T: Send + 'static,
+    {
+        let span = self.call_span::<M>(&mut c);
+        let (mut sink, recv) = c;
+        let send = &mut sink;
+        let (updates, read_error) = UpdateStream::new(recv);
+        let (ack, responses) = f(target, req, updates);
+        let res = self
+            .limit(
+                span,
+                race2(read_error.map(Err), async move {
+                    // send does not return before the ack is flushed
+                    send.send(ack.into())
+                        .await
+                        .map_err(RpcServerError::SendError)?;
+                    futures::pin_mut!(responses);
+                    while let Some(response) = responses.next().await {
+                        let response: S::Res = response.into();
+                        send.send(response)
+                            .await
+                            .map_err(RpcServerError::SendError)?;
+                    }
+                    Ok(())
+                }),
+            )
+            .await;
+        reset_on_error::<S, C>(&mut sink, res)
+    }
+
+    /// handle the message M using the given function on the target object, exchanging
+    /// [ControlFrame]s with the client alongside the updates and responses
+    ///
+    /// The handler gets the updates and the control frames of the client in order as [Frame]s, and
+    /// can send control frames to the client by yielding [Frame::Control].
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn bidi_streaming_with_control<M, F, Str, T>(
+        &self,
+        req: M,
+        mut c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = BidiStreaming>,
+        S::Req: SplitControl,
+        ControlFrame: Into<S::Res>,
+        F: FnOnce(T, M, FrameStream<S, C, M>) -> Str + Send + 'static,
+        Str: Stream<Item = Frame<M::Response>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let span = self.call_span::<M>(&mut c);
+        let (mut sink, recv) = c;
+        let send = &mut sink;
+        let (updates, read_error) = FrameStream::new(recv);
+        let responses = f(target, req, updates);
+        let res = self
+            .limit(
+                span,
+                race2(read_error.map(Err), async move {
+                    futures::pin_mut!(responses);
+                    while let Some(response) = responses.next().await {
+                        let response: S::Res = match response {
+                            Frame::Data(response) => response.into(),
+                            Frame::Control(frame) => frame.into(),
+                        };
+                        send.send(response)
+                            .await
+                            .map_err(RpcServerError::SendError)?;
+                    }
+                    Ok(())
+                }),
+            )
+            .await;
+        reset_on_error::<S, C>(&mut sink, res)
     }
 
     /// handle the message M using the given function on the target object
     ///
+    /// Responses are pulled from the handler stream one at a time, and only after the previous
+    /// response has been accepted by the transport. So at most one response is serialized at a
+    /// time, and a slow client slows down the handler instead of responses piling up in memory.
+    ///
     /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
     pub async fn server_streaming<M, F, Str, T>(
         &self,
         req: M,
-        c: (C::SendSink<S::Res>, C::RecvStream<S::Req>),
+        c: ServerSocket<S, C>,
         target: T,
         f: F,
     ) -> result::Result<(), RpcServerError<C>>
@@ -178,46 +1022,1162 @@ impl<S: Service, C: ChannelTypes> RpcServer<S, C> {
         Str: Stream<Item = M::Response> + Send + 'static,
         T: Send + 'static,
     {
-        let (mut send, mut recv) = c;
-        // cancel if we get an update, no matter what it is
-        let cancel = recv
-            .next()
-            .map(|_| RpcServerError::UnexpectedUpdateMessage::<C>);
+        self.server_streaming_with_policy(req, c, target, f, SlowReaderPolicy::BlockForever)
+            .await
+    }
+
+    /// handle the message M using the given function on the target object, letting the handler
+    /// run up to `buffer` responses ahead of the client
+    ///
+    /// See [SlowReaderPolicy::Buffer].
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn server_streaming_buffered<M, F, Str, T>(
+        &self,
+        req: M,
+        c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+        buffer: usize,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = ServerStreaming>,
+        F: FnOnce(T, M) -> Str + Send + 'static,
+        Str: Stream<Item = M::Response> + Send + 'static,
+        T: Send + 'static,
+    {
+        let policy = SlowReaderPolicy::Buffer(buffer);
+        self.server_streaming_with_policy(req, c, target, f, policy)
+            .await
+    }
+
+    /// handle the message M using the given function on the target object, where the handler can
+    /// end the stream with an application error
+    ///
+    /// The response type of the message is a `Result`, so an error is sent as part of
+    /// [Service::Res] like any other response. The stream ends after the first error, and the
+    /// client gets the error from [crate::RpcClient::try_server_streaming] as `Ok(Err(_))`,
+    /// distinct from a failure to transmit the stream.
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn try_server_streaming<M, F, Str, T, R, E>(
+        &self,
+        req: M,
+        c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = ServerStreaming, Response = result::Result<R, E>>,
+        F: FnOnce(T, M) -> Str + Send + 'static,
+        Str: Stream<Item = result::Result<R, E>> + Send + 'static,
+        R: Send + 'static,
+        E: Send + 'static,
+        T: Send + 'static,
+    {
+        self.server_streaming(req, c, target, move |target, req| {
+            f(target, req).scan(false, |done, item| {
+                let stop = std::mem::replace(done, item.is_err());
+                future::ready((!stop).then_some(item))
+            })
+        })
+        .await
+    }
+
+    /// handle the message M using the given function on the target object, using the given
+    /// policy to deal with a client that does not read the responses fast enough
+    ///
+    /// See [SlowReaderPolicy] for the available policies. With [SlowReaderPolicy::BlockForever]
+    /// this is identical to [RpcServer::server_streaming].
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn server_streaming_with_policy<M, F, Str, T>(
+        &self,
+        req: M,
+        mut c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+        policy: SlowReaderPolicy,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = ServerStreaming>,
+        F: FnOnce(T, M) -> Str + Send + 'static,
+        Str: Stream<Item = M::Response> + Send + 'static,
+        T: Send + 'static,
+    {
+        let span = self.call_span::<M>(&mut c);
+        let (mut sink, mut recv) = c;
+        let send = &mut sink;
+        // cancel if we get an update, no matter what it is, except for keepalives
+        let cancel = async move {
+            loop {
+                match recv.next().await {
+                    Some(Ok(msg)) if S::is_keepalive(&msg) => continue,
+                    update => break unexpected_update::<S, C>(update),
+                }
+            }
+        };
         // race the computation and the cancellation
-        race2(cancel.map(Err), async move {
-            // get the response
-            let responses = f(target, req);
-            tokio::pin!(responses);
-            while let Some(response) = responses.next().await {
-                // turn into a S::Res so we can send it
-                let response: S::Res = response.into();
-                // send it and return the error if any
-                send.send(response)
-                    .await
-                    .map_err(RpcServerError::SendError)?;
+        let res = self
+            .limit(
+                span,
+                race2(cancel.map(Err), async move {
+                    // get the response
+                    let responses = f(target, req).map(Into::<S::Res>::into);
+                    futures::pin_mut!(responses);
+                    match policy {
+                        SlowReaderPolicy::BlockForever => {
+                            // do not poll the handler for the next response before the previous one was sent
+                            while let Some(response) = responses.next().await {
+                                // send it and return the error if any
+                                send.send(response)
+                                    .await
+                                    .map_err(RpcServerError::SendError)?;
+                            }
+                        }
+                        SlowReaderPolicy::TimeoutAfter(timeout) => {
+                            while let Some(response) = responses.next().await {
+                                self.runtime
+                                    .timeout(timeout, send.send(response))
+                                    .await
+                                    .map_err(|_| RpcServerError::ClientTooSlow)?
+                                    .map_err(RpcServerError::SendError)?;
+                            }
+                        }
+                        SlowReaderPolicy::DropOldest(capacity) => {
+                            send_buffered::<S, C, _>(send, responses, capacity.max(1), true)
+                                .await?;
+                        }
+                        SlowReaderPolicy::Buffer(capacity) => {
+                            send_buffered::<S, C, _>(send, responses, capacity.max(1), false)
+                                .await?;
+                        }
+                    }
+                    Ok(())
+                }),
+            )
+            .await;
+        reset_on_error::<S, C>(&mut sink, res)
+    }
+
+    /// handle a subscription, streaming the events of `events` that match the filter of the request
+    ///
+    /// A subscription is a server streaming message that carries a filter, e.g. a topic. `filter`
+    /// is called with the request and each event, and only the events it returns true for are
+    /// sent to the client. Unlike for [RpcServer::server_streaming], the client cancelling the
+    /// call is not an error: the client unsubscribes by dropping the stream returned by
+    /// [crate::RpcClient::subscribe], which completes the subscription with `Ok(())` right away,
+    /// without waiting for the next matching event. The subscription also completes once `events`
+    /// ends.
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn subscription<M, E, F>(
+        &self,
+        req: M,
+        mut c: ServerSocket<S, C>,
+        events: E,
+        mut filter: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = ServerStreaming>,
+        E: Stream<Item = M::Response>,
+        F: FnMut(&M, &M::Response) -> bool,
+    {
+        let span = self.call_span::<M>(&mut c);
+        let (mut sink, mut recv) = c;
+        let send = &mut sink;
+        // the client closes its side of the stream when it unsubscribes
+        let unsubscribed = async move {
+            loop {
+                match recv.next().await {
+                    Some(Ok(msg)) if S::is_keepalive(&msg) => continue,
+                    Some(Ok(msg)) if S::is_cancel(&msg) => break Ok(()),
+                    None => break Ok(()),
+                    update => break Err(unexpected_update::<S, C>(update)),
+                }
             }
-            Ok(())
+        };
+        let res = self
+            .limit(
+                span,
+                race2(unsubscribed, async move {
+                    futures::pin_mut!(events);
+                    while let Some(event) = events.next().await {
+                        if filter(&req, &event) {
+                            send.send(event.into())
+                                .await
+                                .map_err(RpcServerError::SendError)?;
+                        }
+                    }
+                    Ok(())
+                }),
+            )
+            .await;
+        reset_on_error::<S, C>(&mut sink, res)
+    }
+
+    /// handle a resumable server streaming request using the given function on the target object
+    ///
+    /// The handler gets the original request and the offset to resume from, and is responsible
+    /// for skipping ahead to that item. The responses of the handler are tagged with their index
+    /// in the stream, starting at the offset.
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn server_streaming_resumable<M, R, F, Str, T>(
+        &self,
+        req: ResumeFrom<M>,
+        c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        ResumeFrom<M>: Msg<S, Pattern = ServerStreaming, Response = Indexed<R>>,
+        M: Send + 'static,
+        R: Send + 'static,
+        F: FnOnce(T, M, u64) -> Str + Send + 'static,
+        Str: Stream<Item = R> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.server_streaming(req, c, target, move |target, req| {
+            let ResumeFrom { offset, msg } = req;
+            f(target, msg, offset)
+                .zip(futures::stream::iter(offset..))
+                .map(|(item, index)| Indexed { index, item })
         })
         .await
     }
+
+    /// handle the message M using the given function on the target object, allowing the client
+    /// to pause and resume the response stream
+    ///
+    /// The client controls the stream by sending [StreamControl] messages, see
+    /// [crate::RpcClient::server_streaming_controlled]. While paused, `policy` decides whether the
+    /// handler stream is no longer polled or whether its items get dropped. Keepalives are
+    /// ignored and cancels abort the handler, like for [RpcServer::server_streaming].
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn server_streaming_controlled<M, F, Str, T>(
+        &self,
+        req: M,
+        mut c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+        policy: PausePolicy,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = ServerStreaming>,
+        StreamControl: TryFrom<S::Req>,
+        F: FnOnce(T, M) -> Str + Send + 'static,
+        Str: Stream<Item = M::Response> + Send + 'static,
+        T: Send + 'static,
+    {
+        let span = self.call_span::<M>(&mut c);
+        let (mut sink, mut recv) = c;
+        let send = &mut sink;
+        let res = self
+            .limit(span, async move {
+                let responses = f(target, req);
+                futures::pin_mut!(responses);
+                let mut paused = false;
+                loop {
+                    let event = if !paused || policy == PausePolicy::Drop {
+                        race_either(recv.next(), responses.next()).await
+                    } else {
+                        future::Either::Left(recv.next().await)
+                    };
+                    match event {
+                        future::Either::Left(control) => match control {
+                            Some(Ok(msg)) if S::is_keepalive(&msg) => {}
+                            Some(Ok(msg)) if S::is_cancel(&msg) => {
+                                return Err(RpcServerError::Cancelled)
+                            }
+                            Some(Ok(msg)) => match StreamControl::try_from(msg) {
+                                Ok(StreamControl::Pause) => paused = true,
+                                Ok(StreamControl::Resume) => paused = false,
+                                Err(_) => return Err(RpcServerError::UnexpectedUpdateMessage),
+                            },
+                            Some(Err(cause)) => return Err(RpcServerError::recv(cause)),
+                            // the client is no longer interested in the responses
+                            None => return Ok(()),
+                        },
+                        future::Either::Right(response) => match response {
+                            Some(response) if !paused => {
+                                // turn into a S::Res so we can send it
+                                let response: S::Res = response.into();
+                                // send it and return the error if any
+                                send.send(response)
+                                    .await
+                                    .map_err(RpcServerError::SendError)?;
+                            }
+                            // paused with PausePolicy::Drop
+                            Some(_) => {}
+                            None => return Ok(()),
+                        },
+                    }
+                }
+            })
+            .await;
+        reset_on_error::<S, C>(&mut sink, res)
+    }
+
+    /// handle the message M using the given function on the target object, aborting the handler
+    /// if it does not complete within `deadline`
+    ///
+    /// On expiry, the response produced by `on_deadline` is sent to the client, the stream is
+    /// closed, and [RpcServerError::DeadlineExceeded] is returned.
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn rpc_with_deadline<M, F, Fut, T, D>(
+        &self,
+        req: M,
+        c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+        deadline: Duration,
+        on_deadline: D,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = Rpc>,
+        F: FnOnce(T, M) -> Fut,
+        Fut: Future<Output = M::Response>,
+        T: Send + 'static,
+        D: FnOnce() -> M::Response,
+    {
+        let expired = Arc::new(AtomicBool::new(false));
+        let flag = expired.clone();
+        let runtime = self.runtime.clone();
+        self.rpc(req, c, target, |target, req| async move {
+            runtime
+                .timeout(deadline, f(target, req))
+                .await
+                .unwrap_or_else(|_| {
+                    flag.store(true, Ordering::SeqCst);
+                    on_deadline()
+                })
+        })
+        .await?;
+        deadline_result(&expired)
+    }
+
+    /// handle the message M using the given function on the target object, aborting the handler
+    /// if it does not complete within `deadline`
+    ///
+    /// See [RpcServer::rpc_with_deadline]. Errors while receiving the updates still abort the
+    /// request as usual.
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn client_streaming_with_deadline<M, F, Fut, T, D>(
+        &self,
+        req: M,
+        c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+        deadline: Duration,
+        on_deadline: D,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = ClientStreaming>,
+        F: FnOnce(T, M, UpdateStream<S, C, M>) -> Fut + Send + 'static,
+        Fut: Future<Output = M::Response> + Send + 'static,
+        T: Send + 'static,
+        D: FnOnce() -> M::Response + Send + 'static,
+    {
+        let expired = Arc::new(AtomicBool::new(false));
+        let flag = expired.clone();
+        let runtime = self.runtime.clone();
+        self.client_streaming(req, c, target, move |target, req, updates| async move {
+            runtime
+                .timeout(deadline, f(target, req, updates))
+                .await
+                .unwrap_or_else(|_| {
+                    flag.store(true, Ordering::SeqCst);
+                    on_deadline()
+                })
+        })
+        .await?;
+        deadline_result(&expired)
+    }
+
+    /// handle the message M using the given function on the target object, ending the response
+    /// stream if it does not complete within `deadline`
+    ///
+    /// On expiry, the response produced by `on_deadline` is sent as the last item, the stream is
+    /// closed, and [RpcServerError::DeadlineExceeded] is returned.
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn server_streaming_with_deadline<M, F, Str, T, D>(
+        &self,
+        req: M,
+        c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+        deadline: Duration,
+        on_deadline: D,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = ServerStreaming>,
+        F: FnOnce(T, M) -> Str + Send + 'static,
+        Str: Stream<Item = M::Response> + Send + 'static,
+        T: Send + 'static,
+        D: FnOnce() -> M::Response + Send + 'static,
+    {
+        let expired = Arc::new(AtomicBool::new(false));
+        let flag = expired.clone();
+        let sleep = self.runtime.timer.sleep(deadline);
+        self.server_streaming(req, c, target, move |target, req| {
+            DeadlineStream::new(f(target, req), sleep, on_deadline, flag)
+        })
+        .await?;
+        deadline_result(&expired)
+    }
+
+    /// handle the message M using the given function on the target object, ending the response
+    /// stream if it does not complete within `deadline`
+    ///
+    /// See [RpcServer::server_streaming_with_deadline].
+    ///
+    /// If you want to support concurrent requests, you need to spawn this on a tokio task yourself.
+    pub async fn bidi_streaming_with_deadline<M, F, Str, T, D>(
+        &self,
+        req: M,
+        c: ServerSocket<S, C>,
+        target: T,
+        f: F,
+        deadline: Duration,
+        on_deadline: D,
+    ) -> result::Result<(), RpcServerError<C>>
+    where
+        M: Msg<S, Pattern = BidiStreaming>,
+        F: FnOnce(T, M, UpdateStream<S, C, M>) -> Str + Send + 'static,
+        Str: Stream<Item = M::Response> + Send + 'static,
+        T: Send + 'static,
+        D: FnOnce() -> M::Response + Send + 'static,
+    {
+        let expired = Arc::new(AtomicBool::new(false));
+        let flag = expired.clone();
+        let sleep = self.runtime.timer.sleep(deadline);
+        self.bidi_streaming(req, c, target, move |target, req, updates| {
+            DeadlineStream::new(f(target, req, updates), sleep, on_deadline, flag)
+        })
+        .await?;
+        deadline_result(&expired)
+    }
+}
+
+fn deadline_result<C: ChannelTypes>(expired: &AtomicBool) -> result::Result<(), RpcServerError<C>> {
+    if expired.load(Ordering::SeqCst) {
+        Err(RpcServerError::DeadlineExceeded)
+    } else {
+        Ok(())
+    }
+}
+
+/// A stream that ends with a final item produced by a function once a deadline expires
+#[pin_project]
+struct DeadlineStream<St, F> {
+    #[pin]
+    inner: St,
+    sleep: BoxFuture<'static, ()>,
+    on_deadline: Option<F>,
+    expired: Arc<AtomicBool>,
+}
+
+impl<St, F> DeadlineStream<St, F> {
+    fn new(
+        inner: St,
+        sleep: BoxFuture<'static, ()>,
+        on_deadline: F,
+        expired: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            inner,
+            sleep,
+            on_deadline: Some(on_deadline),
+            expired,
+        }
+    }
+}
+
+impl<St: Stream, F: FnOnce() -> St::Item> Stream for DeadlineStream<St, F> {
+    type Item = St::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if this.on_deadline.is_none() {
+            return Poll::Ready(None);
+        }
+        if this.sleep.poll_unpin(cx).is_ready() {
+            this.expired.store(true, Ordering::SeqCst);
+            return Poll::Ready(this.on_deadline.take().map(|f| f()));
+        }
+        let res = this.inner.poll_next(cx);
+        if let Poll::Ready(None) = res {
+            *this.on_deadline = None;
+        }
+        res
+    }
+}
+
+/// What to do when a client does not read the responses of a server streaming request fast enough
+///
+/// See [RpcServer::server_streaming_with_policy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SlowReaderPolicy {
+    /// Wait until the client reads the responses, no matter how long it takes
+    #[default]
+    BlockForever,
+    /// Abort the request with [RpcServerError::ClientTooSlow] if sending a single response takes
+    /// longer than the given duration
+    TimeoutAfter(Duration),
+    /// Keep polling the handler and buffer up to the given number of responses, dropping the
+    /// oldest buffered response when the buffer is full
+    ///
+    /// This is useful for lossy streams such as telemetry, where only recent items matter.
+    DropOldest(usize),
+    /// Keep polling the handler and buffer up to the given number of responses, waiting for the
+    /// client when the buffer is full
+    ///
+    /// This lets a bursty handler run ahead of a slow link without losing responses.
+    Buffer(usize),
+}
+
+/// Send responses from the stream, buffering at most `capacity` responses
+///
+/// When the buffer is full, the oldest buffered response is dropped if `drop_oldest` is set,
+/// otherwise the handler is not polled until the sink accepts a response.
+async fn send_buffered<S: Service, C: ChannelTypes, Str: Stream<Item = S::Res>>(
+    send: &mut C::SendSink<S::Res>,
+    responses: Pin<&mut Str>,
+    capacity: usize,
+    drop_oldest: bool,
+) -> result::Result<(), RpcServerError<C>> {
+    let mut responses = responses;
+    let mut buffer = VecDeque::with_capacity(capacity);
+    let mut done = false;
+    let mut flushed = true;
+    future::poll_fn(|cx| {
+        // pull at most capacity responses per poll, so an always ready handler can not starve us
+        let mut pulled = 0;
+        let mut blocked = false;
+        while !done && pulled < capacity {
+            if !drop_oldest && buffer.len() == capacity {
+                blocked = true;
+                break;
+            }
+            match responses.as_mut().poll_next(cx) {
+                Poll::Ready(Some(response)) => {
+                    if buffer.len() == capacity {
+                        buffer.pop_front();
+                    }
+                    buffer.push_back(response);
+                    pulled += 1;
+                }
+                Poll::Ready(None) => done = true,
+                Poll::Pending => break,
+            }
+        }
+        if pulled == capacity {
+            // the handler might have more responses ready, so make sure we get polled again
+            cx.waker().wake_by_ref();
+        }
+        // move as many buffered responses as possible into the sink
+        while !buffer.is_empty() {
+            match send.poll_ready_unpin(cx) {
+                Poll::Ready(Ok(())) => {
+                    let response = buffer.pop_front().unwrap();
+                    send.start_send_unpin(response)
+                        .map_err(RpcServerError::SendError)?;
+                    flushed = false;
+                }
+                Poll::Ready(Err(cause)) => {
+                    return Poll::Ready(Err(RpcServerError::SendError(cause)))
+                }
+                Poll::Pending => break,
+            }
+        }
+        if blocked && buffer.len() < capacity {
+            // the handler was not polled, so nothing else will wake us to pull the next response
+            cx.waker().wake_by_ref();
+        }
+        if !flushed {
+            match send.poll_flush_unpin(cx) {
+                Poll::Ready(Ok(())) => flushed = true,
+                Poll::Ready(Err(cause)) => {
+                    return Poll::Ready(Err(RpcServerError::SendError(cause)))
+                }
+                Poll::Pending => {}
+            }
+        }
+        if done && buffer.is_empty() && flushed {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Checks the auth token of a request before it is handled, see [RpcServer::with_authenticator]
+///
+/// Implemented for closures taking the token and the first message of the request.
+pub trait Authenticator<S: Service>: Send + Sync + 'static {
+    /// Whether the request `req` with the auth token `token` may be handled
+    ///
+    /// `token` is `None` if the client did not send a token.
+    fn authenticate(&self, token: Option<&[u8]>, req: &S::Req) -> bool;
+}
+
+impl<S, F> Authenticator<S> for F
+where
+    S: Service,
+    F: Fn(Option<&[u8]>, &S::Req) -> bool + Send + Sync + 'static,
+{
+    fn authenticate(&self, token: Option<&[u8]>, req: &S::Req) -> bool {
+        self(token, req)
+    }
+}
+
+/// A request accepted by [MultiServer::accept_any], ready to be run
+///
+/// Running the future reads the first message and calls the dispatch function of the service.
+pub type AcceptedHandler<C> = BoxFuture<'static, result::Result<(), RpcServerError<C>>>;
+
+type PendingAccept<C> =
+    BoxFuture<'static, (usize, result::Result<AcceptedHandler<C>, RpcServerError<C>>)>;
+
+type AcceptFn<C> = Box<dyn Fn(usize) -> PendingAccept<C> + Send + Sync>;
+
+/// Several servers, possibly for different services, driven by a single accept loop
+///
+/// Each server is added with a tag and a dispatch function like for [RpcServer::serve].
+/// [MultiServer::accept_any] waits for a new request on any of the servers and returns the tag
+/// of the server together with a future that handles the request, so the service types do not
+/// show up in the accept loop:
+///
+/// ```ignore
+/// let mut servers = MultiServer::new();
+/// servers.add("compute", compute_server, ComputeService::dispatch);
+/// servers.add("store", store_server, StoreService::dispatch);
+/// while let Some((tag, res)) = servers.accept_any().await {
+///     match res {
+///         Ok(handler) => { tokio::spawn(handler); }
+///         Err(cause) => eprintln!("{tag} stopped accepting: {cause}"),
+///     }
+/// }
+/// ```
+pub struct MultiServer<C: ChannelTypes, T = &'static str> {
+    tags: Vec<T>,
+    accept: Vec<AcceptFn<C>>,
+    pending: FuturesUnordered<PendingAccept<C>>,
+}
+
+impl<C: ChannelTypes, T: Clone> MultiServer<C, T> {
+    /// Create an empty multi server
+    pub fn new() -> Self {
+        Self {
+            tags: Vec::new(),
+            accept: Vec::new(),
+            pending: FuturesUnordered::new(),
+        }
+    }
+
+    /// Add a server, handling its requests using `dispatch`
+    ///
+    /// `dispatch` is called with a clone of the server, the first message and the channel, see
+    /// [RpcServer::serve].
+    pub fn add<S, D, Fut>(&mut self, tag: T, server: RpcServer<S, C>, dispatch: D)
+    where
+        S: Service,
+        D: Fn(RpcServer<S, C>, S::Req, ServerSocket<S, C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = result::Result<(), RpcServerError<C>>> + Send + 'static,
+    {
+        let dispatch = Arc::new(dispatch);
+        let accept: AcceptFn<C> = Box::new(move |index| {
+            let mut server = server.clone();
+            let dispatch = dispatch.clone();
+            async move {
+                let res = server.accept_raw().await.map(|channel| {
+                    async move {
+                        let (request, channel) = server.read_first(channel).await?;
+                        dispatch(server, request, channel).await
+                    }
+                    .boxed()
+                });
+                (index, res)
+            }
+            .boxed()
+        });
+        let index = self.accept.len();
+        self.pending.push(accept(index));
+        self.tags.push(tag);
+        self.accept.push(accept);
+    }
+
+    /// Wait for a request on any of the servers
+    ///
+    /// Returns the tag of the server the request arrived on and a future that handles it. The
+    /// first message is only read when the future is polled, so it should be spawned on a task to
+    /// keep a slow client from blocking the loop.
+    ///
+    /// If accepting fails, the error is returned together with the tag and the server is removed,
+    /// like [RpcServer::serve] stops on such errors. Returns `None` once no servers are left.
+    /// This method is cancel safe, no request is lost if the future is dropped.
+    pub async fn accept_any(
+        &mut self,
+    ) -> Option<(T, result::Result<AcceptedHandler<C>, RpcServerError<C>>)> {
+        let (index, res) = self.pending.next().await?;
+        if res.is_ok() {
+            self.pending.push((self.accept[index])(index));
+        }
+        Some((self.tags[index].clone(), res))
+    }
+
+    /// Number of servers that are still accepting requests
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// True if no server is accepting requests anymore
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<C: ChannelTypes, T: Clone> Default for MultiServer<C, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: ChannelTypes, T: Debug> Debug for MultiServer<C, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiServer")
+            .field("tags", &self.tags)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A serve loop that can be drained, for graceful shutdown
+///
+/// Like [RpcServer::serve_concurrent], this runs at most `max_concurrent` handlers at once. Once
+/// [DrainHandle::drain] is called, no further channels are accepted, and [Drain::serve] completes
+/// when the last handler in flight is done.
+///
+/// ```ignore
+/// let drain = Drain::new(64);
+/// let handle = drain.handle();
+/// tokio::spawn(async move {
+///     shutdown_signal().await;
+///     handle.drain();
+/// });
+/// let summary = drain.serve(server, dispatch).await?;
+/// println!("{} requests completed", summary.completed);
+/// ```
+#[derive(Debug)]
+pub struct Drain {
+    handle: DrainHandle,
+    max_concurrent: usize,
+}
+
+/// Handle to start draining a [Drain] and to watch its requests in flight
+#[derive(Debug, Clone)]
+pub struct DrainHandle {
+    signal: Arc<tokio::sync::watch::Sender<bool>>,
+    in_flight: Arc<tokio::sync::watch::Sender<usize>>,
+    counts: Arc<DrainCounts>,
+}
+
+/// Counters behind [DrainSummary]
+#[derive(Debug, Default)]
+struct DrainCounts {
+    accepted: AtomicU64,
+    completed: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Summary of the requests handled by [Drain::serve]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct DrainSummary {
+    /// Number of accepted channels
+    pub accepted: u64,
+    /// Number of handlers that completed successfully
+    pub completed: u64,
+    /// Number of handlers that failed with an error
+    pub failed: u64,
+}
+
+impl Drain {
+    /// Create a drainable serve loop with at most `max_concurrent` requests in flight
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            handle: DrainHandle {
+                signal: Arc::new(tokio::sync::watch::channel(false).0),
+                in_flight: Arc::new(tokio::sync::watch::channel(0).0),
+                counts: Default::default(),
+            },
+            max_concurrent,
+        }
+    }
+
+    /// Get a handle to start draining
+    pub fn handle(&self) -> DrainHandle {
+        self.handle.clone()
+    }
+
+    /// Serve requests of `server` until drained, spawning a task for each request
+    ///
+    /// Tasks are spawned using the spawner of the server, see [RpcServer::with_spawner].
+    ///
+    /// `dispatch` is called like for [RpcServer::serve]. Once draining starts, the loop stops
+    /// accepting channels and drops its server, then waits for the handlers in flight. Channels
+    /// that are opened after that are never accepted, so clients should be moved to another
+    /// server before draining.
+    ///
+    /// Failing to accept a channel stops the loop with an error, without waiting for the
+    /// handlers in flight.
+    pub async fn serve<S, C, D, Fut>(
+        self,
+        server: RpcServer<S, C>,
+        dispatch: D,
+    ) -> result::Result<DrainSummary, RpcServerError<C>>
+    where
+        S: Service,
+        C: ChannelTypes,
+        D: Fn(RpcServer<S, C>, S::Req, ServerSocket<S, C>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = result::Result<(), RpcServerError<C>>> + Send + 'static,
+    {
+        let Self {
+            handle,
+            max_concurrent,
+        } = self;
+        server
+            .serve_inner(
+                dispatch,
+                |_, fut| fut,
+                Some(max_concurrent),
+                None,
+                Some(handle.clone()),
+            )
+            .await?;
+        // the loop dropped its server, so only the handlers in flight are left
+        let mut in_flight = handle.in_flight.subscribe();
+        while *in_flight.borrow_and_update() > 0 {
+            // the sender is kept alive by the handle, so changed can not fail
+            in_flight.changed().await.ok();
+        }
+        let counts = &handle.counts;
+        Ok(DrainSummary {
+            accepted: counts.accepted.load(Ordering::SeqCst),
+            completed: counts.completed.load(Ordering::SeqCst),
+            failed: counts.failed.load(Ordering::SeqCst),
+        })
+    }
+}
+
+impl DrainHandle {
+    /// Stop accepting new requests
+    pub fn drain(&self) {
+        self.signal.send_replace(true);
+    }
+
+    /// True if draining has started
+    pub fn is_draining(&self) -> bool {
+        *self.signal.borrow()
+    }
+
+    /// Number of handlers currently in flight
+    pub fn in_flight(&self) -> usize {
+        *self.in_flight.borrow()
+    }
+}
+
+/// Wait until draining has started
+async fn wait_drained(drained: &mut tokio::sync::watch::Receiver<bool>) {
+    loop {
+        if *drained.borrow() {
+            return;
+        }
+        // the sender is kept alive by the drain handle, so changed can not fail
+        if drained.changed().await.is_err() {
+            future::pending::<()>().await;
+        }
+    }
+}
+
+/// Wait until no request has been in flight for `idle`, see [RpcServer::serve_until_idle]
+async fn wait_idle(
+    mut in_flight: tokio::sync::watch::Receiver<usize>,
+    runtime: &Runtime,
+    idle: Duration,
+) {
+    loop {
+        while *in_flight.borrow_and_update() > 0 {
+            // the sender is kept alive by the serve loop, so changed can not fail
+            in_flight.changed().await.ok();
+        }
+        let sleep = runtime.timer.sleep(idle);
+        match future::select(sleep, Box::pin(in_flight.changed())).await {
+            future::Either::Left(_) => return,
+            future::Either::Right(_) => continue,
+        }
+    }
+}
+
+/// Counts a request as in flight until dropped, see [RpcServer::serve_until_idle]
+struct InFlight(Arc<tokio::sync::watch::Sender<usize>>);
+
+impl InFlight {
+    fn new(count: Arc<tokio::sync::watch::Sender<usize>>) -> Self {
+        count.send_modify(|n| *n += 1);
+        Self(count)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.send_modify(|n| *n -= 1);
+    }
+}
+
+/// Information about a request, see [RpcServer::serve_with_context]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RequestContext {
+    /// Sequence number of the request within the serve loop
+    pub id: u64,
+}
+
+/// Queue that lets ordered requests of a connection run one after the other, see
+/// [RpcServer::rpc_ordered]
+///
+/// Create one queue per connection and share it between all handlers for that connection.
+///
+/// A request that is dropped or fails, whether it is waiting for its turn or running, releases
+/// its sequence number, so the requests after it still run. A sequence number can also go
+/// missing completely, e.g. if the client dropped the call before the request was sent. Requests
+/// that wait for a missing predecessor skip it after the gap timeout, see
+/// [OrderedQueue::with_gap_timeout]. A skipped request that arrives later runs right away.
+#[derive(Debug, Clone)]
+pub struct OrderedQueue {
+    state: Arc<std::sync::Mutex<QueueState>>,
+    gap_timeout: Option<Duration>,
+}
+
+/// State of an [OrderedQueue]
+#[derive(Debug, Default)]
+struct QueueState {
+    /// The sequence number whose turn it is
+    next: u64,
+    /// Requests that are waiting for their turn or running
+    arrived: std::collections::BTreeSet<u64>,
+    /// Requests after `next` that are already done
+    done: std::collections::BTreeSet<u64>,
+    /// Incremented on every change, to tell waiters to check again
+    version: u64,
+    waiters: Vec<task::Waker>,
+}
+
+impl QueueState {
+    fn changed(&mut self) {
+        self.version += 1;
+        for waker in self.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Skip `next` past all requests that are already done
+    fn advance(&mut self) {
+        while self.done.remove(&self.next) {
+            self.next += 1;
+        }
+    }
+}
+
+/// Releases the sequence number of a request when it is done or dropped
+struct Turn<'a> {
+    state: &'a std::sync::Mutex<QueueState>,
+    seq: u64,
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.arrived.remove(&self.seq);
+        if self.seq >= state.next {
+            state.done.insert(self.seq);
+            state.advance();
+        }
+        state.changed();
+    }
+}
+
+impl Default for OrderedQueue {
+    fn default() -> Self {
+        Self {
+            state: Default::default(),
+            gap_timeout: Some(Self::DEFAULT_GAP_TIMEOUT),
+        }
+    }
+}
+
+impl OrderedQueue {
+    /// Default for [OrderedQueue::with_gap_timeout]
+    pub const DEFAULT_GAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Create a queue that expects the sequence to start at 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skip a missing sequence number once the requests after it waited this long for it
+    ///
+    /// With `None`, a missing sequence number stalls all later ordered requests. The timeout
+    /// only applies while the predecessor has not arrived, a slow predecessor is waited for.
+    pub fn with_gap_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.gap_timeout = timeout;
+        self
+    }
+
+    /// Run `fut` once all requests with a lower sequence number are done or skipped
+    async fn run<F: Future>(&self, seq: u64, timer: &dyn Timer, fut: F) -> F::Output {
+        self.state.lock().unwrap().arrived.insert(seq);
+        let _turn = Turn {
+            state: &self.state,
+            seq,
+        };
+        loop {
+            let (version, gap) = {
+                let state = self.state.lock().unwrap();
+                if state.next >= seq {
+                    break;
+                }
+                (state.version, !state.arrived.contains(&state.next))
+            };
+            let changed = future::poll_fn(|cx| {
+                let mut state = self.state.lock().unwrap();
+                if state.version != version {
+                    Poll::Ready(())
+                } else {
+                    state.waiters.push(cx.waker().clone());
+                    Poll::Pending
+                }
+            });
+            match self.gap_timeout.filter(|_| gap) {
+                Some(timeout) => {
+                    futures::pin_mut!(changed);
+                    if let future::Either::Right(_) =
+                        future::select(changed, timer.sleep(timeout)).await
+                    {
+                        self.skip_gap(seq);
+                    }
+                }
+                None => changed.await,
+            }
+        }
+        fut.await
+    }
+
+    /// Skip the missing sequence numbers before `seq`, up to the next one that arrived
+    fn skip_gap(&self, seq: u64) {
+        let mut state = self.state.lock().unwrap();
+        while state.next < seq && !state.arrived.contains(&state.next) {
+            state.next += 1;
+            state.advance();
+        }
+        state.changed();
+    }
+}
+
+/// Log the error of a request handled by a serve loop, which has no caller to return it to
+///
+/// Errors caused by the client going away are logged at debug level, all others as warnings.
+/// With the `tracing` feature, this emits tracing events instead of log records.
+fn log_request_error<C: ChannelTypes>(id: u64, cause: &RpcServerError<C>) {
+    use RpcServerErrorKind::*;
+    let client_gone = matches!(cause.kind(), EarlyClose | ConnectionLost | Cancelled);
+    #[cfg(feature = "tracing")]
+    if client_gone {
+        tracing::debug!(id, "request ended early: {cause}");
+    } else {
+        tracing::warn!(id, "request failed: {cause}");
+    }
+    #[cfg(not(feature = "tracing"))]
+    if client_gone {
+        log::debug!("request {id} ended early: {cause}");
+    } else {
+        log::warn!("request {id} failed: {cause}");
+    }
+}
+
+/// Reset the stream of a failed call with the code of the error, see
+/// [RpcServerErrorKind::reset_code]
+fn reset_on_error<S: Service, C: ChannelTypes>(
+    send: &mut C::SendSink<S::Res>,
+    res: result::Result<(), RpcServerError<C>>,
+) -> result::Result<(), RpcServerError<C>> {
+    if let Some(code) = res.as_ref().err().and_then(|e| e.kind().reset_code()) {
+        C::reset(send, code);
+    }
+    res
+}
+
+/// The error for an update on an interaction that does not take updates
+///
+/// The stream ending counts as an update, since it means that the client went away. Errors
+/// are passed on, e.g. the client resetting the stream when it drops the call.
+fn unexpected_update<S: Service, C: ChannelTypes>(
+    update: Option<result::Result<S::Req, C::RecvError>>,
+) -> RpcServerError<C> {
+    match update {
+        Some(Ok(msg)) if S::is_cancel(&msg) => RpcServerError::Cancelled,
+        Some(Err(e)) => RpcServerError::recv(e),
+        _ => RpcServerError::UnexpectedUpdateMessage,
+    }
+}
+
+/// The recv side of a [ServerSocket]
+type BoxedRecvStream<S, C> = Pin<Box<<C as ChannelTypes>::RecvStream<<S as Service>::Req>>>;
+
+/// Check if the call of an update stream is over
+///
+/// Returns `None` if the stream has to stall because the call is terminated with an error, so the
+/// handler does not see a regular end. Returns `Some(None)` if the call completed, after dropping
+/// the recv side so the client stops sending updates.
+fn poll_call_done<'a, T, E>(
+    recv: &'a mut Option<T>,
+    error: &mut Option<oneshot::Sender<E>>,
+    cx: &mut task::Context<'_>,
+) -> Option<Option<&'a mut T>> {
+    let tx = error.as_mut()?;
+    // the receiving side is dropped once the call is complete
+    if tx.poll_canceled(cx).is_ready() {
+        *recv = None;
+    }
+    Some(recv.as_mut())
 }
 
 /// A stream of updates
 ///
 /// If there is any error with receiving or with decoding the updates, the stream will stall and the error will
 /// cause a termination of the RPC call.
+///
+/// Once the call is complete, e.g. because the response stream of a bidi streaming handler ended,
+/// the stream ends and drops the recv side, even if the client is still sending updates. So
+/// handlers can stop responding at any time, and clients get an error for further updates
+/// instead of waiting for the server to read them.
 #[pin_project]
 pub struct UpdateStream<S: Service, C: ChannelTypes, M: Msg<S>>(
-    #[pin] C::RecvStream<S::Req>,
+    Option<BoxedRecvStream<S, C>>,
     Option<oneshot::Sender<RpcServerError<C>>>,
     PhantomData<M>,
 );
 
 impl<S: Service, C: ChannelTypes, M: Msg<S>> UpdateStream<S, C, M> {
-    fn new(recv: C::RecvStream<S::Req>) -> (Self, UnwrapToPending<RpcServerError<C>>) {
+    fn new(recv: Pin<Box<C::RecvStream<S::Req>>>) -> (Self, UnwrapToPending<RpcServerError<C>>) {
         let (error_send, error_recv) = oneshot::channel();
         let error_recv = UnwrapToPending(error_recv);
-        (Self(recv, Some(error_send), PhantomData), error_recv)
+        (Self(Some(recv), Some(error_send), PhantomData), error_recv)
     }
 }
 
@@ -225,9 +2185,21 @@ impl<S: Service, C: ChannelTypes, M: Msg<S>> Stream for UpdateStream<S, C, M> {
     type Item = M::Update;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut this = self.project();
-        match this.0.poll_next_unpin(cx) {
+        let this = self.project();
+        let Some(recv) = poll_call_done(this.0, this.1, cx) else {
+            return Poll::Pending;
+        };
+        let Some(recv) = recv else {
+            return Poll::Ready(None);
+        };
+        match recv.poll_next_unpin(cx) {
             Poll::Ready(Some(msg)) => match msg {
+                Ok(msg) if S::is_cancel(&msg) => {
+                    if let Some(tx) = this.1.take() {
+                        let _ = tx.send(RpcServerError::Cancelled);
+                    }
+                    Poll::Pending
+                }
                 Ok(msg) => match M::Update::try_from(msg) {
                     Ok(msg) => Poll::Ready(Some(msg)),
                     Err(_cause) => {
@@ -241,7 +2213,7 @@ impl<S: Service, C: ChannelTypes, M: Msg<S>> Stream for UpdateStream<S, C, M> {
                 Err(cause) => {
                     // we got a recv error, so return pending and send the error
                     if let Some(tx) = this.1.take() {
-                        let _ = tx.send(RpcServerError::RecvError(cause));
+                        let _ = tx.send(RpcServerError::recv(cause));
                     }
                     Poll::Pending
                 }
@@ -252,20 +2224,109 @@ impl<S: Service, C: ChannelTypes, M: Msg<S>> Stream for UpdateStream<S, C, M> {
     }
 }
 
+/// A stream of updates and control frames, see [RpcServer::bidi_streaming_with_control]
+///
+/// Errors and the end of the call are handled like for [UpdateStream].
+#[pin_project]
+pub struct FrameStream<S: Service, C: ChannelTypes, M: Msg<S>>(
+    Option<BoxedRecvStream<S, C>>,
+    Option<oneshot::Sender<RpcServerError<C>>>,
+    PhantomData<M>,
+);
+
+impl<S: Service, C: ChannelTypes, M: Msg<S>> FrameStream<S, C, M> {
+    fn new(recv: Pin<Box<C::RecvStream<S::Req>>>) -> (Self, UnwrapToPending<RpcServerError<C>>) {
+        let (error_send, error_recv) = oneshot::channel();
+        let error_recv = UnwrapToPending(error_recv);
+        (Self(Some(recv), Some(error_send), PhantomData), error_recv)
+    }
+}
+
+impl<S: Service, C: ChannelTypes, M: Msg<S>> Stream for FrameStream<S, C, M>
+where
+    S::Req: SplitControl,
+{
+    type Item = Frame<M::Update>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let Some(recv) = poll_call_done(this.0, this.1, cx) else {
+            return Poll::Pending;
+        };
+        let Some(recv) = recv else {
+            return Poll::Ready(None);
+        };
+        let error = match recv.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(msg))) if S::is_cancel(&msg) => RpcServerError::Cancelled,
+            Poll::Ready(Some(Ok(msg))) => match msg.split_control() {
+                Ok(frame) => return Poll::Ready(Some(Frame::Control(frame))),
+                Err(msg) => match M::Update::try_from(msg) {
+                    Ok(msg) => return Poll::Ready(Some(Frame::Data(msg))),
+                    Err(_cause) => RpcServerError::UnexpectedUpdateMessage,
+                },
+            },
+            Poll::Ready(Some(Err(cause))) => RpcServerError::recv(cause),
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => return Poll::Pending,
+        };
+        // stall the stream and terminate the call with the error
+        if let Some(tx) = this.1.take() {
+            let _ = tx.send(error);
+        }
+        Poll::Pending
+    }
+}
+
 /// Server error. All server DSL methods return a `Result` with this error type.
+#[derive(thiserror::Error)]
 pub enum RpcServerError<C: ChannelTypes> {
     /// Unable to open a new channel
-    AcceptBiError(C::AcceptBiError),
-    /// Recv side for a channel was closed before getting the first message
+    #[error("failed to accept a stream")]
+    AcceptBiError(#[source] C::AcceptBiError),
+    /// Recv side for a channel was finished by the client before getting the first message
+    #[error("the client closed the stream before sending a request")]
     EarlyClose,
+    /// The connection to the client was lost while receiving a message
+    ///
+    /// Unlike [RpcServerError::EarlyClose], this is not an orderly close by the client. Which
+    /// receive errors count as a lost connection is decided by the channel type, see
+    /// [ChannelError::is_connection_lost]. All other receive errors are reported as
+    /// [RpcServerError::RecvError].
+    #[error("the connection to the client was lost")]
+    ConnectionLost(#[source] C::RecvError),
     /// Got an unexpected first message, e.g. an update message
+    #[error("unexpected first message")]
     UnexpectedStartMessage,
     /// Error receiving a message
-    RecvError(C::RecvError),
+    #[error("failed to receive a message")]
+    RecvError(#[source] C::RecvError),
     /// Error sending a response
-    SendError(C::SendError),
+    #[error("failed to send a response")]
+    SendError(#[source] C::SendError),
     /// Got an unexpected update message, e.g. a request message or a non-matching update message
+    #[error("unexpected update message")]
     UnexpectedUpdateMessage,
+    /// The client did not read the responses fast enough, see [SlowReaderPolicy::TimeoutAfter]
+    #[error("the client did not read the responses fast enough")]
+    ClientTooSlow,
+    /// The request took longer than allowed, see [RpcServer::with_max_rpc_duration]
+    #[error("the request took longer than the max rpc duration")]
+    MaxDurationExceeded,
+    /// The handler did not complete in time, see [RpcServer::rpc_with_deadline]
+    #[error("the handler did not complete before the deadline")]
+    DeadlineExceeded,
+    /// The client cancelled the request, see [crate::message::Cancel]
+    #[error("the client cancelled the request")]
+    Cancelled,
+    /// The connection was rejected, see [RpcServer::accept_filtered]
+    #[error("the connection was rejected")]
+    Rejected,
+    /// Unable to open a stream to the client, see [RpcServer::push]
+    #[error("failed to open a stream")]
+    OpenBiError(#[source] C::OpenBiError),
+    /// The auth token of the request was rejected, see [RpcServer::with_authenticator]
+    #[error("the request was not authorized")]
+    Unauthorized,
 }
 
 impl<C: ChannelTypes> fmt::Debug for RpcServerError<C> {
@@ -273,21 +2334,169 @@ impl<C: ChannelTypes> fmt::Debug for RpcServerError<C> {
         match self {
             Self::AcceptBiError(arg0) => f.debug_tuple("AcceptBiError").field(arg0).finish(),
             Self::EarlyClose => write!(f, "EarlyClose"),
+            Self::ConnectionLost(arg0) => f.debug_tuple("ConnectionLost").field(arg0).finish(),
             Self::RecvError(arg0) => f.debug_tuple("RecvError").field(arg0).finish(),
             Self::SendError(arg0) => f.debug_tuple("SendError").field(arg0).finish(),
             Self::UnexpectedStartMessage => f.debug_tuple("UnexpectedStartMessage").finish(),
-            Self::UnexpectedUpdateMessage => f.debug_tuple("UnexpectedStartMessage").finish(),
+            Self::UnexpectedUpdateMessage => f.debug_tuple("UnexpectedUpdateMessage").finish(),
+            Self::ClientTooSlow => f.debug_tuple("ClientTooSlow").finish(),
+            Self::MaxDurationExceeded => f.debug_tuple("MaxDurationExceeded").finish(),
+            Self::DeadlineExceeded => f.debug_tuple("DeadlineExceeded").finish(),
+            Self::Cancelled => f.debug_tuple("Cancelled").finish(),
+            Self::Rejected => f.debug_tuple("Rejected").finish(),
+            Self::OpenBiError(arg0) => f.debug_tuple("OpenBiError").field(arg0).finish(),
+            Self::Unauthorized => f.debug_tuple("Unauthorized").finish(),
         }
     }
 }
 
-impl<C: ChannelTypes> fmt::Display for RpcServerError<C> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        fmt::Debug::fmt(&self, f)
+impl<C: ChannelTypes> RpcServerError<C> {
+    /// The kind of the error, without the error of the channel
+    ///
+    /// This is useful to classify errors in tests and logs without depending on the channel
+    /// type.
+    pub fn kind(&self) -> RpcServerErrorKind {
+        match self {
+            Self::AcceptBiError(_) => RpcServerErrorKind::AcceptBiError,
+            Self::EarlyClose => RpcServerErrorKind::EarlyClose,
+            Self::ConnectionLost(_) => RpcServerErrorKind::ConnectionLost,
+            Self::UnexpectedStartMessage => RpcServerErrorKind::UnexpectedStartMessage,
+            Self::RecvError(_) => RpcServerErrorKind::RecvError,
+            Self::SendError(_) => RpcServerErrorKind::SendError,
+            Self::UnexpectedUpdateMessage => RpcServerErrorKind::UnexpectedUpdateMessage,
+            Self::ClientTooSlow => RpcServerErrorKind::ClientTooSlow,
+            Self::MaxDurationExceeded => RpcServerErrorKind::MaxDurationExceeded,
+            Self::DeadlineExceeded => RpcServerErrorKind::DeadlineExceeded,
+            Self::Cancelled => RpcServerErrorKind::Cancelled,
+            Self::Rejected => RpcServerErrorKind::Rejected,
+            Self::OpenBiError(_) => RpcServerErrorKind::OpenBiError,
+            Self::Unauthorized => RpcServerErrorKind::Unauthorized,
+        }
+    }
+
+    /// Error for a failed receive, telling a lost connection apart from other errors
+    pub(crate) fn recv(cause: C::RecvError) -> Self {
+        if cause.is_connection_lost() {
+            Self::ConnectionLost(cause)
+        } else {
+            Self::RecvError(cause)
+        }
+    }
+
+    /// Convert the error into an io error, independent of the channel type
+    ///
+    /// Errors from the underlying channel are converted by the channel type, see
+    /// [ChannelError].
+    pub fn into_io(self) -> io::Error {
+        let kind = match self {
+            Self::AcceptBiError(e) => return e.into_io(),
+            Self::OpenBiError(e) => return e.into_io(),
+            Self::RecvError(e) | Self::ConnectionLost(e) => return e.into_io(),
+            Self::SendError(e) => return e.into_io(),
+            Self::EarlyClose => io::ErrorKind::UnexpectedEof,
+            Self::UnexpectedStartMessage | Self::UnexpectedUpdateMessage => {
+                io::ErrorKind::InvalidData
+            }
+            Self::ClientTooSlow | Self::MaxDurationExceeded | Self::DeadlineExceeded => {
+                io::ErrorKind::TimedOut
+            }
+            Self::Cancelled => io::ErrorKind::Other,
+            Self::Rejected | Self::Unauthorized => io::ErrorKind::PermissionDenied,
+        };
+        io::Error::new(kind, self)
+    }
+}
+
+impl<C: ChannelTypes> From<RpcServerError<C>> for io::Error {
+    fn from(e: RpcServerError<C>) -> Self {
+        e.into_io()
     }
 }
 
-impl<C: ChannelTypes> error::Error for RpcServerError<C> {}
+/// The kind of a [RpcServerError], see [RpcServerError::kind]
+///
+/// The variants correspond to the variants of the error, without the errors of the channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RpcServerErrorKind {
+    /// See [RpcServerError::AcceptBiError]
+    AcceptBiError,
+    /// See [RpcServerError::EarlyClose]
+    EarlyClose,
+    /// See [RpcServerError::ConnectionLost]
+    ConnectionLost,
+    /// See [RpcServerError::UnexpectedStartMessage]
+    UnexpectedStartMessage,
+    /// See [RpcServerError::RecvError]
+    RecvError,
+    /// See [RpcServerError::SendError]
+    SendError,
+    /// See [RpcServerError::UnexpectedUpdateMessage]
+    UnexpectedUpdateMessage,
+    /// See [RpcServerError::ClientTooSlow]
+    ClientTooSlow,
+    /// See [RpcServerError::MaxDurationExceeded]
+    MaxDurationExceeded,
+    /// See [RpcServerError::DeadlineExceeded]
+    DeadlineExceeded,
+    /// See [RpcServerError::Cancelled]
+    Cancelled,
+    /// See [RpcServerError::Rejected]
+    Rejected,
+    /// See [RpcServerError::OpenBiError]
+    OpenBiError,
+    /// See [RpcServerError::Unauthorized]
+    Unauthorized,
+}
+
+impl RpcServerErrorKind {
+    /// The application error code the stream of a call is reset with when it fails with this
+    /// kind of error
+    ///
+    /// The codes are stable:
+    ///
+    /// | code | kind |
+    /// |------|------|
+    /// | 1 | [RpcServerErrorKind::UnexpectedUpdateMessage] |
+    /// | 2 | [RpcServerErrorKind::RecvError] |
+    /// | 3 | [RpcServerErrorKind::Cancelled] |
+    /// | 4 | [RpcServerErrorKind::ClientTooSlow] |
+    /// | 5 | [RpcServerErrorKind::MaxDurationExceeded] |
+    /// | 6 | [RpcServerErrorKind::Unauthorized] |
+    ///
+    /// The other kinds do not have a code, since they either happen before there is a stream, or
+    /// the stream is broken anyway. Not all channel types support resetting streams, see
+    /// [ChannelTypes::reset].
+    pub fn reset_code(self) -> Option<u32> {
+        Some(match self {
+            Self::UnexpectedUpdateMessage => 1,
+            Self::RecvError => 2,
+            Self::Cancelled => 3,
+            Self::ClientTooSlow => 4,
+            Self::MaxDurationExceeded => 5,
+            Self::Unauthorized => 6,
+            _ => return None,
+        })
+    }
+
+    /// The kind of error for a code returned by [RpcServerErrorKind::reset_code]
+    pub fn from_reset_code(code: u32) -> Option<Self> {
+        Some(match code {
+            1 => Self::UnexpectedUpdateMessage,
+            2 => Self::RecvError,
+            3 => Self::Cancelled,
+            4 => Self::ClientTooSlow,
+            5 => Self::MaxDurationExceeded,
+            6 => Self::Unauthorized,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for RpcServerErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
 
 /// Take an oneshot receiver and just return Pending the underlying future returns `Err(oneshot::Canceled)`
 struct UnwrapToPending<T>(oneshot::Receiver<T>);
@@ -304,9 +2513,24 @@ impl<T> Future for UnwrapToPending<T> {
     }
 }
 
+/// Run `f1` and `f2` until one of them completes
+///
+/// If both are ready, `f2` wins. Callers pass the handler as `f2`, so a call that completed is not
+/// reported as failed because of an update the client sent after the handler was done.
 async fn race2<T, A: Future<Output = T>, B: Future<Output = T>>(f1: A, f2: B) -> T {
-    tokio::select! {
-        x = f1 => x,
-        x = f2 => x,
+    futures::pin_mut!(f1, f2);
+    match future::select(f2, f1).await {
+        future::Either::Left((x, _)) | future::Either::Right((x, _)) => x,
+    }
+}
+
+/// Run `f1` and `f2` until one of them completes, and return which one did
+///
+/// If both are ready, `f1` wins.
+async fn race_either<A: Future, B: Future>(f1: A, f2: B) -> future::Either<A::Output, B::Output> {
+    futures::pin_mut!(f1, f2);
+    match future::select(f1, f2).await {
+        future::Either::Left((x, _)) => future::Either::Left(x),
+        future::Either::Right((x, _)) => future::Either::Right(x),
     }
 }
@@ -0,0 +1,397 @@
+//! Channel that sends the enum variant of every message along with it, to detect protocol drift
+//!
+//! Compact formats like bincode encode enum variants by their index. If the client and the
+//! server were built with different versions of a message enum, e.g. with variants added in the
+//! middle, a message can be decoded as the wrong variant without any error. This channel sends
+//! the variant name next to every message, and fails with [RecvError::VariantMismatch] if the
+//! decoded message has a different variant. Both sides need to use a tagged channel.
+//!
+//! The tag adds a few bytes to every message, so this is opt-in for peers that might run
+//! different builds.
+use crate::{
+    message::PatternKind, ChannelError, ChannelTypes, ConnectionInfo, Retryable, RpcMessage,
+};
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt, Sink, SinkExt, Stream, TryFutureExt};
+use pin_project::pin_project;
+use serde::{ser, Serialize};
+use std::{
+    fmt::{self, Debug},
+    io,
+    marker::PhantomData,
+    pin::Pin,
+    result,
+    task::{Context, Poll},
+};
+
+/// A message together with the name of its enum variant
+pub type Tagged<M> = (Option<String>, M);
+
+/// A channel that tags messages with their variant, wrapping a channel for [Tagged] messages
+pub struct Channel<C: ChannelTypes, In: RpcMessage, Out: RpcMessage>(
+    C::Channel<Tagged<In>, Tagged<Out>>,
+);
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Channel<C, In, Out> {
+    /// Wrap a channel
+    pub fn new(inner: C::Channel<Tagged<In>, Tagged<Out>>) -> Self {
+        Self(inner)
+    }
+
+    fn wrap(
+        (send, recv): (C::SendSink<Tagged<Out>>, C::RecvStream<Tagged<In>>),
+    ) -> Socket<C, In, Out> {
+        (SendSink(send), RecvStream(recv))
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Clone for Channel<C, In, Out> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Debug for Channel<C, In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Channel").finish()
+    }
+}
+
+/// SendSink for tagged channels
+pub struct SendSink<C: ChannelTypes, Out: RpcMessage>(C::SendSink<Tagged<Out>>);
+
+impl<C: ChannelTypes, Out: RpcMessage> Sink<Out> for SendSink<C, Out> {
+    type Error = C::SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        let tag = variant_name(&item).map(String::from);
+        self.0.start_send_unpin((tag, item))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_close_unpin(cx)
+    }
+}
+
+/// RecvStream for tagged channels
+#[pin_project]
+pub struct RecvStream<C: ChannelTypes, In: RpcMessage>(#[pin] C::RecvStream<Tagged<In>>);
+
+impl<C: ChannelTypes, In: RpcMessage> Stream for RecvStream<C, In> {
+    type Item = Result<In, RecvError<C>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(match futures::ready!(self.project().0.poll_next(cx)) {
+            Some(Ok((tag, msg))) => {
+                let got = variant_name(&msg);
+                Some(match tag {
+                    Some(expected) if Some(expected.as_str()) != got => {
+                        Err(RecvError::VariantMismatch { expected, got })
+                    }
+                    _ => Ok(msg),
+                })
+            }
+            Some(Err(e)) => Some(Err(RecvError::Inner(e))),
+            None => None,
+        })
+    }
+}
+
+/// RecvError for tagged channels
+#[derive(Debug, thiserror::Error)]
+pub enum RecvError<C: ChannelTypes> {
+    /// Error of the wrapped channel
+    #[error(transparent)]
+    Inner(C::RecvError),
+    /// The message was decoded as a different variant than the one that was sent
+    ///
+    /// This means that the two sides use incompatible versions of the message type.
+    #[error("the remote sent variant {expected}, but the message was decoded as {got:?}")]
+    VariantMismatch {
+        /// Variant sent by the remote
+        expected: String,
+        /// Variant of the decoded message, if it is an enum
+        got: Option<&'static str>,
+    },
+}
+
+impl<C: ChannelTypes> ChannelError for RecvError<C> {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::Inner(e) => e.into_io(),
+            Self::VariantMismatch { .. } => io::Error::new(io::ErrorKind::InvalidData, self),
+        }
+    }
+
+    fn is_connection_lost(&self) -> bool {
+        match self {
+            Self::Inner(e) => e.is_connection_lost(),
+            Self::VariantMismatch { .. } => false,
+        }
+    }
+}
+
+impl<C: ChannelTypes> Retryable for RecvError<C> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Inner(e) => e.is_retryable(),
+            Self::VariantMismatch { .. } => false,
+        }
+    }
+}
+
+/// A bidirectional stream of a tagged channel: a sink for outgoing and a stream of incoming messages
+pub type Socket<C, In, Out> = (self::SendSink<C, Out>, self::RecvStream<C, In>);
+
+/// Future returned by open_bi
+pub type OpenBiFuture<'a, C, In, Out> =
+    BoxFuture<'a, result::Result<Socket<C, In, Out>, <C as ChannelTypes>::OpenBiError>>;
+
+/// Future returned by accept_bi
+pub type AcceptBiFuture<'a, C, In, Out> =
+    BoxFuture<'a, result::Result<Socket<C, In, Out>, <C as ChannelTypes>::AcceptBiError>>;
+
+/// Channel types for tagged channels
+///
+/// `C` is the channel type of the wrapped channel. Errors are passed through unchanged, except
+/// for messages with a different variant than the one that was sent, see
+/// [RecvError::VariantMismatch].
+#[derive(Debug, Clone, Copy)]
+pub struct TaggedChannelTypes<C: ChannelTypes>(PhantomData<C>);
+
+impl<C: ChannelTypes> ChannelTypes for TaggedChannelTypes<C> {
+    type SendSink<M: RpcMessage> = self::SendSink<C, M>;
+
+    type RecvStream<M: RpcMessage> = self::RecvStream<C, M>;
+
+    type SendError = C::SendError;
+
+    type RecvError = self::RecvError<C>;
+
+    type OpenBiError = C::OpenBiError;
+
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::OpenBiFuture<'a, C, In, Out>;
+
+    type AcceptBiError = C::AcceptBiError;
+
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::AcceptBiFuture<'a, C, In, Out>;
+
+    type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<C, In, Out>;
+
+    fn reset<M: RpcMessage>(send: &mut Self::SendSink<M>, code: u32) {
+        C::reset(&mut send.0, code)
+    }
+
+    fn set_pattern<M: RpcMessage>(send: &mut Self::SendSink<M>, pattern: PatternKind) {
+        C::set_pattern(&mut send.0, pattern)
+    }
+
+    fn set_priority<M: RpcMessage>(send: &mut Self::SendSink<M>, priority: i32) {
+        C::set_priority(&mut send.0, priority)
+    }
+
+    fn correlation_id<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<u64> {
+        C::correlation_id(&recv.0)
+    }
+
+    fn auth_token<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<Bytes> {
+        C::auth_token(&recv.0)
+    }
+}
+
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage>
+    crate::Channel<In, Out, TaggedChannelTypes<C>> for Channel<C, In, Out>
+{
+    fn open_bi(&self) -> OpenBiFuture<'_, C, In, Out> {
+        self.0.open_bi().map_ok(Self::wrap).boxed()
+    }
+
+    fn accept_bi(&self) -> AcceptBiFuture<'_, C, In, Out> {
+        self.0.accept_bi().map_ok(Self::wrap).boxed()
+    }
+
+    fn connection_info(&self) -> ConnectionInfo {
+        self.0.connection_info()
+    }
+
+    fn close(&self, code: u32, reason: &[u8]) {
+        self.0.close(code, reason)
+    }
+}
+
+/// Name of the enum variant of a message, or `None` if it is not an enum
+pub fn variant_name<T: Serialize>(msg: &T) -> Option<&'static str> {
+    match msg.serialize(VariantName) {
+        Ok(()) => None,
+        Err(Found(name)) => name,
+    }
+}
+
+/// Serializer that stops at the first enum variant, reporting its name as the error
+struct VariantName;
+
+#[derive(Debug, thiserror::Error)]
+#[error("found variant {0:?}")]
+struct Found(Option<&'static str>);
+
+impl ser::Error for Found {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Self(None)
+    }
+}
+
+type NotEnum = ser::Impossible<(), Found>;
+
+impl ser::Serializer for VariantName {
+    type Ok = ();
+    type Error = Found;
+    type SerializeSeq = NotEnum;
+    type SerializeTuple = NotEnum;
+    type SerializeTupleStruct = NotEnum;
+    type SerializeTupleVariant = NotEnum;
+    type SerializeMap = NotEnum;
+    type SerializeStruct = NotEnum;
+    type SerializeStructVariant = NotEnum;
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, v: &'static str) -> Result<(), Found> {
+        Err(Found(Some(v)))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: u32,
+        v: &'static str,
+        _: &T,
+    ) -> Result<(), Found> {
+        Err(Found(Some(v)))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        v: &'static str,
+        _: usize,
+    ) -> Result<NotEnum, Found> {
+        Err(Found(Some(v)))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        v: &'static str,
+        _: usize,
+    ) -> Result<NotEnum, Found> {
+        Err(Found(Some(v)))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), Found> {
+        value.serialize(self)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), Found> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _: bool) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_i8(self, _: i8) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_i16(self, _: i16) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_i32(self, _: i32) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_i64(self, _: i64) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_u8(self, _: u8) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_u16(self, _: u16) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_u32(self, _: u32) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_u64(self, _: u64) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_char(self, _: char) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_str(self, _: &str) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_unit(self) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), Found> {
+        Ok(())
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<NotEnum, Found> {
+        Err(Found(None))
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<NotEnum, Found> {
+        Err(Found(None))
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<NotEnum, Found> {
+        Err(Found(None))
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<NotEnum, Found> {
+        Err(Found(None))
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<NotEnum, Found> {
+        Err(Found(None))
+    }
+}
@@ -0,0 +1,778 @@
+//! TCP channel implementation
+//!
+//! This is a fallback for networks where QUIC is not an option, e.g. because UDP is blocked.
+//! All bidirectional streams of a channel are multiplexed over a single TCP connection, using
+//! frames that carry the id of their stream and a length prefix:
+//!
+//! ```text
+//! | stream id: u64 | kind: u8 | length: u32 | payload |
+//! ```
+//!
+//! Streams opened by the connecting side use even ids, streams opened by the accepting side
+//! odd ids. Every stream has its own flow control: a sink may only send as many messages as the
+//! remote has room for, and the remote grants more credit as it reads them. So a stream whose
+//! messages are not consumed only stalls its own sink, not the other streams of the connection.
+//! Likewise, incoming streams wait in a bounded queue until they are accepted, and further
+//! streams are refused instead of holding up the connection.
+use crate::{
+    codec::{BincodeCodec, Codec},
+    ChannelError, ConnectionInfo, Retryable, RpcMessage,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{future::BoxFuture, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    marker::PhantomData,
+    net::SocketAddr,
+    pin::Pin,
+    result,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+use tokio::{
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    task::JoinHandle,
+};
+use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite};
+
+/// Maximum size of the payload of a received frame
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Number of messages that are buffered per stream, which is the credit a sink starts with
+const STREAM_BUFFER: usize = 128;
+
+/// Number of incoming streams that are buffered until they are accepted
+///
+/// This is the same as the default limit of concurrent streams of quinn channels, see
+/// [crate::quinn::QuinnBuilder]. Further incoming streams are refused.
+const ACCEPT_BUFFER: usize = 1024;
+
+/// Frame kind for a message
+const DATA: u8 = 0;
+/// Frame kind for the end of the messages in one direction of a stream
+const FIN: u8 = 1;
+/// Frame kind that allows the remote to send more messages, the payload is their number as u32
+const CREDIT: u8 = 2;
+/// Frame kind telling the remote that its messages on a stream are no longer read
+const STOP: u8 = 3;
+/// Frame kind telling the remote that a stream it opened was refused
+const REFUSED: u8 = 4;
+
+/// Length of the stream id, kind and length prefix of every frame
+const HEADER_LEN: usize = 8 + 1 + 4;
+
+#[derive(Debug)]
+struct Frame {
+    id: u64,
+    kind: u8,
+    payload: Bytes,
+}
+
+impl Frame {
+    /// A frame without payload
+    fn control(id: u64, kind: u8) -> Self {
+        Self {
+            id,
+            kind,
+            payload: Bytes::new(),
+        }
+    }
+
+    fn fin(id: u64) -> Self {
+        Self::control(id, FIN)
+    }
+
+    fn credit(id: u64, n: u32) -> Self {
+        Self {
+            id,
+            kind: CREDIT,
+            payload: Bytes::copy_from_slice(&n.to_be_bytes()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FrameCodec;
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> io::Result<()> {
+        // the size is checked when the message is sent, see SendSink::start_send
+        let len = frame.payload.len() as u32;
+        dst.reserve(HEADER_LEN + frame.payload.len());
+        dst.put_u64(frame.id);
+        dst.put_u8(frame.kind);
+        dst.put_u32(len);
+        dst.put_slice(&frame.payload);
+        Ok(())
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Frame>> {
+        let Some(mut header) = src.get(..HEADER_LEN) else {
+            return Ok(None);
+        };
+        let id = header.get_u64();
+        let kind = header.get_u8();
+        let size = header.get_u32() as usize;
+        if size > MAX_FRAME_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of {size} bytes exceeds the limit of {MAX_FRAME_SIZE} bytes"),
+            ));
+        }
+        if src.len() < HEADER_LEN + size {
+            src.reserve(HEADER_LEN + size - src.len());
+            return Ok(None);
+        }
+        src.advance(HEADER_LEN);
+        let payload = src.split_to(size).freeze();
+        Ok(Some(Frame { id, kind, payload }))
+    }
+}
+
+/// What the connection task hands to the [RecvStream] of a stream
+#[derive(Debug)]
+enum Event {
+    Data(Bytes),
+    Fin,
+    Refused,
+}
+
+/// How many more messages the [SendSink] of a stream may send
+#[derive(Debug)]
+struct Credit {
+    available: usize,
+    /// The remote no longer reads the stream, or refused it
+    stopped: bool,
+    /// The connection is gone
+    lost: bool,
+    /// The sink waiting for credit
+    waker: Option<Waker>,
+}
+
+impl Credit {
+    fn new() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            available: STREAM_BUFFER,
+            stopped: false,
+            lost: false,
+            waker: None,
+        }))
+    }
+
+    /// Update the credit and wake the sink if it is waiting for it
+    fn update(credit: &Mutex<Self>, f: impl FnOnce(&mut Self)) {
+        let mut credit = credit.lock().unwrap();
+        f(&mut credit);
+        if let Some(waker) = credit.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The streams of a connection, shared between the channel and the task reading frames
+#[derive(Debug, Default)]
+struct Streams {
+    senders: HashMap<u64, flume::Sender<Event>>,
+    /// The credit of the sinks, until they are dropped
+    credits: HashMap<u64, Arc<Mutex<Credit>>>,
+    /// For control frames sent by the task reading frames, until the channel is dropped
+    ///
+    /// The task must not keep the connection open on its own, so it does not own a sender.
+    control: Option<flume::Sender<Frame>>,
+    /// The connection is gone, no new streams can be opened
+    closed: bool,
+}
+
+impl Streams {
+    fn close(&mut self) {
+        self.closed = true;
+        // ending the streams without a fin tells them that the connection was lost
+        self.senders.clear();
+        for credit in self.credits.values() {
+            Credit::update(credit, |credit| credit.lost = true);
+        }
+    }
+
+    fn send_control(&self, frame: Frame) {
+        if let Some(control) = &self.control {
+            control.send(frame).ok();
+        }
+    }
+}
+
+type Incoming = (u64, flume::Receiver<Event>, Arc<Mutex<Credit>>);
+
+/// Writes all frames to the connection, until all senders are dropped
+///
+/// Control frames, which manage the streams but do not carry messages, are not limited by
+/// the buffer of the frames, so granting credit never waits for other streams.
+async fn write_frames(
+    mut sink: FramedWrite<OwnedWriteHalf, FrameCodec>,
+    frames: flume::Receiver<Frame>,
+    control: flume::Receiver<Frame>,
+) {
+    let mut frames = futures::stream::select(control.into_stream(), frames.into_stream()).map(Ok);
+    // a write error drops the receivers, so sending further frames fails
+    sink.send_all(&mut frames).await.ok();
+    sink.close().await.ok();
+}
+
+/// Reads frames from the connection and dispatches them to the streams
+///
+/// This never waits for a stream to read its messages or to be accepted, so one stream can not
+/// hold up the others.
+async fn read_frames(
+    mut frames: FramedRead<OwnedReadHalf, FrameCodec>,
+    streams: Arc<Mutex<Streams>>,
+    incoming: flume::Sender<Incoming>,
+    mut next_remote_id: u64,
+) {
+    while let Some(Ok(frame)) = frames.next().await {
+        let mut streams = streams.lock().unwrap();
+        let event = match frame.kind {
+            DATA => Event::Data(frame.payload),
+            FIN => Event::Fin,
+            CREDIT => {
+                let credit = streams.credits.get(&frame.id);
+                if let (Some(credit), Ok(n)) = (credit, <[u8; 4]>::try_from(&frame.payload[..])) {
+                    let n = u32::from_be_bytes(n) as usize;
+                    Credit::update(credit, |credit| credit.available += n);
+                }
+                continue;
+            }
+            STOP | REFUSED => {
+                if let Some(credit) = streams.credits.get(&frame.id) {
+                    Credit::update(credit, |credit| credit.stopped = true);
+                }
+                if frame.kind == REFUSED {
+                    if let Some(sender) = streams.senders.remove(&frame.id) {
+                        sender.try_send(Event::Refused).ok();
+                    }
+                }
+                continue;
+            }
+            _ => break,
+        };
+        let sender = match streams.senders.get(&frame.id) {
+            Some(sender) => sender.clone(),
+            None if frame.id % 2 == next_remote_id % 2 && frame.id >= next_remote_id => {
+                next_remote_id = frame.id + 2;
+                // room for all messages the remote has credit for, and the fin
+                let (send, recv) = flume::bounded(STREAM_BUFFER + 1);
+                let credit = Credit::new();
+                if incoming.try_send((frame.id, recv, credit.clone())).is_err() {
+                    // too many streams are waiting to be accepted, or the channel is gone
+                    streams.send_control(Frame::control(frame.id, REFUSED));
+                    continue;
+                }
+                streams.senders.insert(frame.id, send.clone());
+                streams.credits.insert(frame.id, credit);
+                send
+            }
+            // a stream that we closed already, or a stream that was never opened
+            None => continue,
+        };
+        let fin = matches!(event, Event::Fin);
+        match sender.try_send(event) {
+            Ok(()) if !fin => {}
+            Err(flume::TrySendError::Full(_)) => {
+                // the remote sent more than its credit, stop reading the stream
+                streams.senders.remove(&frame.id);
+                streams.send_control(Frame::control(frame.id, STOP));
+            }
+            // the stream is finished, or its receiver was dropped
+            _ => {
+                streams.senders.remove(&frame.id);
+            }
+        }
+    }
+    streams.lock().unwrap().close();
+}
+
+/// The TCP connection of a channel failed or was closed by the remote
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("the connection was lost")]
+pub struct ConnectionLost;
+
+impl ChannelError for ConnectionLost {
+    fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::ConnectionReset, self)
+    }
+}
+
+impl Retryable for ConnectionLost {
+    fn is_retryable(&self) -> bool {
+        true
+    }
+}
+
+/// Error for sending messages on a TCP channel
+#[derive(Debug, thiserror::Error)]
+pub enum SendError {
+    /// The message could not be serialized, or is too large
+    #[error("failed to serialize the message")]
+    Serialize(#[source] io::Error),
+    /// The connection is gone
+    #[error("the connection was lost")]
+    ConnectionLost,
+    /// The remote no longer reads the messages of the stream, or refused the stream
+    #[error("the remote stopped reading the stream")]
+    Stopped,
+}
+
+impl ChannelError for SendError {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::Serialize(e) => e,
+            Self::ConnectionLost => ConnectionLost.into_io(),
+            Self::Stopped => io::Error::new(io::ErrorKind::BrokenPipe, self),
+        }
+    }
+}
+
+impl Retryable for SendError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Serialize(_) | Self::Stopped => false,
+            Self::ConnectionLost => true,
+        }
+    }
+}
+
+/// Error for receiving messages on a TCP channel
+#[derive(Debug, thiserror::Error)]
+pub enum RecvError {
+    /// The message could not be deserialized
+    #[error("failed to deserialize the message")]
+    Deserialize(#[source] io::Error),
+    /// The connection is gone before the remote finished the stream
+    #[error("the connection was lost before the stream was finished")]
+    ConnectionLost,
+    /// The remote refused the stream, since too many of its incoming streams were not accepted
+    /// yet
+    #[error("the remote refused the stream")]
+    Refused,
+}
+
+impl ChannelError for RecvError {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::Deserialize(e) => e,
+            Self::ConnectionLost => ConnectionLost.into_io(),
+            Self::Refused => io::Error::new(io::ErrorKind::ConnectionRefused, self),
+        }
+    }
+
+    fn is_connection_lost(&self) -> bool {
+        matches!(self, Self::ConnectionLost)
+    }
+}
+
+impl Retryable for RecvError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Deserialize(_) => false,
+            // a refused stream was never handled by the remote
+            Self::ConnectionLost | Self::Refused => true,
+        }
+    }
+}
+
+/// Error for open_bi
+pub type OpenBiError = ConnectionLost;
+
+/// Error for accept_bi
+pub type AcceptBiError = ConnectionLost;
+
+/// SendSink for TCP channels
+///
+/// Closing or dropping the sink tells the remote that no more messages will follow. The sink is
+/// not ready while the remote has no room for further messages of the stream.
+pub struct SendSink<Out, K = BincodeCodec> {
+    id: u64,
+    sink: flume::r#async::SendSink<'static, Frame>,
+    credit: Arc<Mutex<Credit>>,
+    streams: Arc<Mutex<Streams>>,
+    fin_sent: bool,
+    _p: PhantomData<(Out, K)>,
+}
+
+impl<Out, K> SendSink<Out, K> {
+    fn new(id: u64, shared: &Shared, credit: Arc<Mutex<Credit>>) -> Self {
+        Self {
+            id,
+            sink: shared.frames.clone().into_sink(),
+            credit,
+            streams: shared.streams.clone(),
+            fin_sent: false,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<Out, K> fmt::Debug for SendSink<Out, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendSink").field("id", &self.id).finish()
+    }
+}
+
+impl<Out, K> Drop for SendSink<Out, K> {
+    fn drop(&mut self) {
+        self.streams.lock().unwrap().credits.remove(&self.id);
+        if self.fin_sent {
+            return;
+        }
+        let sender = self.sink.sender();
+        if let Err(flume::TrySendError::Full(fin)) = sender.try_send(Frame::fin(self.id)) {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                let sender = sender.clone();
+                handle.spawn(async move { sender.send_async(fin).await.ok() });
+            }
+        }
+    }
+}
+
+impl<Out: RpcMessage, K: Codec> Sink<Out> for SendSink<Out, K> {
+    type Error = SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        {
+            let mut credit = self.credit.lock().unwrap();
+            if credit.lost {
+                return Poll::Ready(Err(SendError::ConnectionLost));
+            }
+            if credit.stopped {
+                return Poll::Ready(Err(SendError::Stopped));
+            }
+            if credit.available == 0 {
+                credit.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+        self.sink
+            .poll_ready_unpin(cx)
+            .map_err(|_| SendError::ConnectionLost)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        let data = K::encode(&item).map_err(SendError::Serialize)?;
+        if data.len() > u32::MAX as usize {
+            return Err(SendError::Serialize(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message too large",
+            )));
+        }
+        let frame = Frame {
+            id: self.id,
+            kind: DATA,
+            payload: data.into(),
+        };
+        self.sink
+            .start_send_unpin(frame)
+            .map_err(|_| SendError::ConnectionLost)?;
+        let mut credit = self.credit.lock().unwrap();
+        credit.available = credit.available.saturating_sub(1);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sink
+            .poll_flush_unpin(cx)
+            .map_err(|_| SendError::ConnectionLost)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if !self.fin_sent {
+            futures::ready!(self.sink.poll_ready_unpin(cx))
+                .map_err(|_| SendError::ConnectionLost)?;
+            let fin = Frame::fin(self.id);
+            self.sink
+                .start_send_unpin(fin)
+                .map_err(|_| SendError::ConnectionLost)?;
+            self.fin_sent = true;
+        }
+        self.sink
+            .poll_flush_unpin(cx)
+            .map_err(|_| SendError::ConnectionLost)
+    }
+}
+
+/// RecvStream for TCP channels
+///
+/// The remote gets credit for more messages as they are read. Dropping the stream before it is
+/// finished tells the remote to stop sending.
+pub struct RecvStream<In, K = BincodeCodec> {
+    id: u64,
+    inner: flume::r#async::RecvStream<'static, Event>,
+    control: flume::Sender<Frame>,
+    /// Messages read since credit for them was granted
+    read: u32,
+    done: bool,
+    _p: PhantomData<(In, K)>,
+}
+
+impl<In, K> RecvStream<In, K> {
+    fn new(id: u64, events: flume::Receiver<Event>, shared: &Shared) -> Self {
+        Self {
+            id,
+            inner: events.into_stream(),
+            control: shared.control.clone(),
+            read: 0,
+            done: false,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In, K> Drop for RecvStream<In, K> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.control.send(Frame::control(self.id, STOP)).ok();
+        }
+    }
+}
+
+impl<In, K> fmt::Debug for RecvStream<In, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecvStream")
+            .field("done", &self.done)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<In: RpcMessage, K: Codec> Stream for RecvStream<In, K> {
+    type Item = result::Result<In, RecvError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        match futures::ready!(self.inner.poll_next_unpin(cx)) {
+            Some(Event::Data(data)) => {
+                // grant credit in batches, to not send a frame for every message
+                self.read += 1;
+                if self.read as usize >= STREAM_BUFFER / 2 {
+                    self.control.send(Frame::credit(self.id, self.read)).ok();
+                    self.read = 0;
+                }
+                Poll::Ready(Some(K::decode(&data).map_err(RecvError::Deserialize)))
+            }
+            Some(Event::Fin) => {
+                self.done = true;
+                Poll::Ready(None)
+            }
+            Some(Event::Refused) => {
+                self.done = true;
+                Poll::Ready(Some(Err(RecvError::Refused)))
+            }
+            None => {
+                self.done = true;
+                Poll::Ready(Some(Err(RecvError::ConnectionLost)))
+            }
+        }
+    }
+}
+
+/// A bidirectional stream of a TCP channel: a sink for outgoing and a stream of incoming messages
+pub type Socket<In, Out, K = BincodeCodec> = (SendSink<Out, K>, RecvStream<In, K>);
+
+#[derive(Debug)]
+struct Shared {
+    frames: flume::Sender<Frame>,
+    control: flume::Sender<Frame>,
+    streams: Arc<Mutex<Streams>>,
+    incoming: flume::Receiver<Incoming>,
+    next_id: AtomicU64,
+    peer: Option<SocketAddr>,
+    /// The tasks reading and writing frames, aborted to close the connection
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // the writer stops once the sinks and receive streams are dropped as well
+        self.streams.lock().unwrap().control = None;
+    }
+}
+
+/// A channel using a single TCP connection
+///
+/// Messages are serialized using the [Codec] `K`.
+pub struct Channel<In: RpcMessage, Out: RpcMessage, K: Codec = BincodeCodec>(
+    Arc<Shared>,
+    PhantomData<(In, Out, K)>,
+);
+
+impl<In: RpcMessage, Out: RpcMessage, K: Codec> Channel<In, Out, K> {
+    /// Create a channel for a connection that was opened by this side
+    pub fn client(stream: TcpStream) -> Self {
+        Self::new(stream, 0)
+    }
+
+    /// Create a channel for a connection that was accepted by this side
+    pub fn server(stream: TcpStream) -> Self {
+        Self::new(stream, 1)
+    }
+
+    /// Spawns the tasks that read and write the frames of the connection
+    ///
+    /// They stop once the connection is closed by the remote and all channels and sinks for
+    /// it are dropped, or once it is closed using [crate::Channel::close].
+    fn new(stream: TcpStream, first_id: u64) -> Self {
+        let peer = stream.peer_addr().ok();
+        // messages are usually small and latency sensitive
+        stream.set_nodelay(true).ok();
+        let (read, write) = stream.into_split();
+        let (frames, frames_recv) = flume::bounded(STREAM_BUFFER);
+        // the number of control frames is limited by the messages and streams they are for
+        let (control, control_recv) = flume::unbounded();
+        let (incoming_send, incoming) = flume::bounded(ACCEPT_BUFFER);
+        let streams = Arc::new(Mutex::new(Streams {
+            control: Some(control.clone()),
+            ..Default::default()
+        }));
+        let writer = tokio::spawn(write_frames(
+            FramedWrite::new(write, FrameCodec),
+            frames_recv,
+            control_recv,
+        ));
+        let reader = tokio::spawn(read_frames(
+            FramedRead::new(read, FrameCodec),
+            streams.clone(),
+            incoming_send,
+            1 - first_id,
+        ));
+        Self(
+            Arc::new(Shared {
+                frames,
+                control,
+                streams,
+                incoming,
+                next_id: AtomicU64::new(first_id),
+                peer,
+                tasks: [writer, reader],
+            }),
+            PhantomData,
+        )
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, K: Codec> Clone for Channel<In, Out, K> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), PhantomData)
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage, K: Codec> fmt::Debug for Channel<In, Out, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel")
+            .field("peer", &self.0.peer)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Types for TCP channels
+///
+/// Messages are serialized using the [Codec] `K`, which defaults to [BincodeCodec].
+#[derive(Debug, Clone, Copy)]
+pub struct TcpChannelTypes<K: Codec = BincodeCodec>(PhantomData<K>);
+
+/// Future returned by open_bi
+pub type OpenBiFuture<'a, In, Out, K = BincodeCodec> =
+    BoxFuture<'a, result::Result<self::Socket<In, Out, K>, self::OpenBiError>>;
+
+/// Future returned by accept_bi
+pub type AcceptBiFuture<'a, In, Out, K = BincodeCodec> =
+    BoxFuture<'a, result::Result<self::Socket<In, Out, K>, self::AcceptBiError>>;
+
+impl<K: Codec> crate::ChannelTypes for TcpChannelTypes<K> {
+    type SendSink<M: RpcMessage> = self::SendSink<M, K>;
+
+    type RecvStream<M: RpcMessage> = self::RecvStream<M, K>;
+
+    type SendError = self::SendError;
+
+    type RecvError = self::RecvError;
+
+    type OpenBiError = self::OpenBiError;
+
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::OpenBiFuture<'a, In, Out, K>;
+
+    type AcceptBiError = self::AcceptBiError;
+
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::AcceptBiFuture<'a, In, Out, K>;
+
+    type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<In, Out, K>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage, K: Codec> crate::Channel<In, Out, TcpChannelTypes<K>>
+    for Channel<In, Out, K>
+{
+    fn open_bi(&self) -> OpenBiFuture<'_, In, Out, K> {
+        async move {
+            let id = self.0.next_id.fetch_add(2, Ordering::Relaxed);
+            // room for all messages the remote has credit for, and the fin
+            let (send, recv) = flume::bounded(STREAM_BUFFER + 1);
+            let credit = Credit::new();
+            {
+                let mut streams = self.0.streams.lock().unwrap();
+                if streams.closed {
+                    return Err(ConnectionLost);
+                }
+                streams.senders.insert(id, send);
+                streams.credits.insert(id, credit.clone());
+            }
+            Ok((
+                SendSink::new(id, &self.0, credit),
+                RecvStream::new(id, recv, &self.0),
+            ))
+        }
+        .boxed()
+    }
+
+    fn accept_bi(&self) -> AcceptBiFuture<'_, In, Out, K> {
+        async move {
+            let (id, recv, credit) = self
+                .0
+                .incoming
+                .recv_async()
+                .await
+                .map_err(|_| ConnectionLost)?;
+            Ok((
+                SendSink::new(id, &self.0, credit),
+                RecvStream::new(id, recv, &self.0),
+            ))
+        }
+        .boxed()
+    }
+
+    fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            peer: self.0.peer,
+            ..Default::default()
+        }
+    }
+
+    fn close(&self, _code: u32, _reason: &[u8]) {
+        // TCP has no application close codes, so the remote sees a lost connection
+        self.0.streams.lock().unwrap().close();
+        // dropping the halves of the socket closes it, and the incoming streams with it
+        for task in &self.0.tasks {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Channel that injects faults into another channel, for testing timeouts and retries
+//!
+//! Faults are applied to the messages received by the wrapped channel, so wrapping the client
+//! channel affects responses and wrapping the server channel affects requests and updates. A
+//! message can be delayed, dropped, or end its stream early, which the client sees as an early
+//! close. All random decisions are made by a generator seeded from [FaultConfig::seed], so a
+//! test that receives its messages in a fixed order sees the same faults on every run.
+//!
+//! The faulty channel needs the `tokio` feature. The module also has helpers to check the
+//! message types of a service, see [assert_roundtrip], which are always available.
+use crate::codec::{BincodeCodec, Codec};
+#[cfg(feature = "tokio")]
+use crate::{message::PatternKind, ChannelTypes, ConnectionInfo, RpcMessage};
+#[cfg(feature = "tokio")]
+use bytes::Bytes;
+#[cfg(feature = "tokio")]
+use futures::{future::BoxFuture, Future, FutureExt, Stream, TryFutureExt};
+#[cfg(feature = "tokio")]
+use pin_project::pin_project;
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::type_name;
+#[cfg(feature = "tokio")]
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+    pin::Pin,
+    result,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// Which faults to inject, see [Channel::new]
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultConfig {
+    /// Seed for the random decisions
+    pub seed: u64,
+    /// Delay for every received message
+    pub delay: Duration,
+    /// Maximum random delay added to [FaultConfig::delay]
+    pub jitter: Duration,
+    /// Probability between 0 and 1 that a received message is dropped
+    pub drop_probability: f64,
+    /// End the stream instead of delivering the nth received message of the channel, counting
+    /// from 1
+    pub close_on: Option<u64>,
+}
+
+#[cfg(feature = "tokio")]
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            drop_probability: 0.0,
+            close_on: None,
+        }
+    }
+}
+
+/// What happens to a received message
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fault {
+    Deliver(Duration),
+    Drop,
+    Close,
+}
+
+/// Fault state shared by all streams of a channel
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+struct Faults {
+    config: FaultConfig,
+    state: Mutex<(u64, u64)>,
+}
+
+#[cfg(feature = "tokio")]
+impl Faults {
+    fn new(config: FaultConfig) -> Self {
+        let seed = config.seed;
+        Self {
+            config,
+            state: Mutex::new((seed, 0)),
+        }
+    }
+
+    /// Decide about the next received message
+    fn next(&self) -> Fault {
+        let mut state = self.state.lock().unwrap();
+        let (rng, count) = &mut *state;
+        *count += 1;
+        if self.config.close_on == Some(*count) {
+            return Fault::Close;
+        }
+        if next_fraction(rng) < self.config.drop_probability {
+            return Fault::Drop;
+        }
+        Fault::Deliver(self.config.delay + self.config.jitter.mul_f64(next_fraction(rng)))
+    }
+}
+
+/// Random number in `[0, 1)`, using splitmix64
+#[cfg(feature = "tokio")]
+fn next_fraction(state: &mut u64) -> f64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A channel that injects faults, wrapping another channel
+#[cfg(feature = "tokio")]
+pub struct Channel<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> {
+    inner: C::Channel<In, Out>,
+    faults: Arc<Faults>,
+}
+
+#[cfg(feature = "tokio")]
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Channel<C, In, Out> {
+    /// Wrap a channel, injecting the faults described by `config`
+    pub fn new(inner: C::Channel<In, Out>, config: FaultConfig) -> Self {
+        Self {
+            inner,
+            faults: Arc::new(Faults::new(config)),
+        }
+    }
+
+    fn wrap(&self, (send, recv): (C::SendSink<Out>, C::RecvStream<In>)) -> Socket<C, In, Out> {
+        (
+            send,
+            RecvStream {
+                inner: recv,
+                faults: self.faults.clone(),
+                delayed: None,
+                closed: false,
+            },
+        )
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Clone for Channel<C, In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            faults: self.faults.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage> Debug for Channel<C, In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel")
+            .field("config", &self.faults.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// RecvStream for faulty channels
+#[cfg(feature = "tokio")]
+#[pin_project]
+pub struct RecvStream<C: ChannelTypes, In: RpcMessage> {
+    #[pin]
+    inner: C::RecvStream<In>,
+    faults: Arc<Faults>,
+    delayed: Option<(In, Pin<Box<tokio::time::Sleep>>)>,
+    closed: bool,
+}
+
+#[cfg(feature = "tokio")]
+impl<C: ChannelTypes, In: RpcMessage> Stream for RecvStream<C, In> {
+    type Item = Result<In, C::RecvError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if *this.closed {
+                return Poll::Ready(None);
+            }
+            if let Some((_, sleep)) = this.delayed {
+                futures::ready!(sleep.as_mut().poll(cx));
+                let (msg, _) = this.delayed.take().unwrap();
+                return Poll::Ready(Some(Ok(msg)));
+            }
+            let msg = match futures::ready!(this.inner.as_mut().poll_next(cx)) {
+                Some(Ok(msg)) => msg,
+                other => return Poll::Ready(other),
+            };
+            match this.faults.next() {
+                Fault::Deliver(delay) if delay.is_zero() => return Poll::Ready(Some(Ok(msg))),
+                Fault::Deliver(delay) => {
+                    *this.delayed = Some((msg, Box::pin(tokio::time::sleep(delay))));
+                }
+                Fault::Drop => {}
+                Fault::Close => *this.closed = true,
+            }
+        }
+    }
+}
+
+/// A bidirectional stream of a faulty channel: a sink for outgoing and a stream of incoming messages
+#[cfg(feature = "tokio")]
+pub type Socket<C, In, Out> = (<C as ChannelTypes>::SendSink<Out>, self::RecvStream<C, In>);
+
+/// Future returned by open_bi
+#[cfg(feature = "tokio")]
+pub type OpenBiFuture<'a, C, In, Out> =
+    BoxFuture<'a, result::Result<Socket<C, In, Out>, <C as ChannelTypes>::OpenBiError>>;
+
+/// Future returned by accept_bi
+#[cfg(feature = "tokio")]
+pub type AcceptBiFuture<'a, C, In, Out> =
+    BoxFuture<'a, result::Result<Socket<C, In, Out>, <C as ChannelTypes>::AcceptBiError>>;
+
+/// Channel types for faulty channels
+///
+/// `C` is the channel type of the wrapped channel. Errors are passed through unchanged.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy)]
+pub struct FaultyChannelTypes<C: ChannelTypes>(PhantomData<C>);
+
+#[cfg(feature = "tokio")]
+impl<C: ChannelTypes> ChannelTypes for FaultyChannelTypes<C> {
+    type SendSink<M: RpcMessage> = C::SendSink<M>;
+
+    type RecvStream<M: RpcMessage> = self::RecvStream<C, M>;
+
+    type SendError = C::SendError;
+
+    type RecvError = C::RecvError;
+
+    type OpenBiError = C::OpenBiError;
+
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::OpenBiFuture<'a, C, In, Out>;
+
+    type AcceptBiError = C::AcceptBiError;
+
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::AcceptBiFuture<'a, C, In, Out>;
+
+    type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<C, In, Out>;
+
+    fn reset<M: RpcMessage>(send: &mut Self::SendSink<M>, code: u32) {
+        C::reset(send, code)
+    }
+
+    fn set_pattern<M: RpcMessage>(send: &mut Self::SendSink<M>, pattern: PatternKind) {
+        C::set_pattern(send, pattern)
+    }
+
+    fn set_priority<M: RpcMessage>(send: &mut Self::SendSink<M>, priority: i32) {
+        C::set_priority(send, priority)
+    }
+
+    fn correlation_id<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<u64> {
+        C::correlation_id(&recv.inner)
+    }
+
+    fn auth_token<M: RpcMessage>(recv: &Self::RecvStream<M>) -> Option<Bytes> {
+        C::auth_token(&recv.inner)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<C: ChannelTypes, In: RpcMessage, Out: RpcMessage>
+    crate::Channel<In, Out, FaultyChannelTypes<C>> for Channel<C, In, Out>
+{
+    fn open_bi(&self) -> OpenBiFuture<'_, C, In, Out> {
+        self.inner.open_bi().map_ok(|s| self.wrap(s)).boxed()
+    }
+
+    fn accept_bi(&self) -> AcceptBiFuture<'_, C, In, Out> {
+        self.inner.accept_bi().map_ok(|s| self.wrap(s)).boxed()
+    }
+
+    fn connection_info(&self) -> ConnectionInfo {
+        self.inner.connection_info()
+    }
+
+    fn close(&self, code: u32, reason: &[u8]) {
+        self.inner.close(code, reason)
+    }
+}
+
+/// Check that `msg` survives the round trip through the enum `E` and the codec `C`
+///
+/// `E` is the request or response enum of a service, and `T` a message, update or response
+/// type that is wrapped in it. A `TryFrom` impl that does not match the `From` impl makes calls
+/// fail with a downcast error at runtime, this catches it in a test. Use the codec of the
+/// channels the service runs on, since codecs differ in the types they support. Panics if the
+/// round trip fails.
+pub fn assert_roundtrip<C, E, T>(msg: T)
+where
+    C: Codec,
+    E: Serialize + DeserializeOwned,
+    T: Into<E> + TryFrom<E>,
+{
+    let wrapped: E = msg.into();
+    let bytes = C::encode(&wrapped).unwrap_or_else(|e| {
+        panic!(
+            "serializing {} with {} failed: {e}",
+            type_name::<E>(),
+            type_name::<C>()
+        )
+    });
+    let wrapped: E = C::decode(&bytes).unwrap_or_else(|e| {
+        panic!(
+            "deserializing {} with {} failed: {e}",
+            type_name::<E>(),
+            type_name::<C>()
+        )
+    });
+    if T::try_from(wrapped).is_err() {
+        panic!(
+            "{} does not convert back to {}",
+            type_name::<E>(),
+            type_name::<T>()
+        );
+    }
+}
+
+/// Like [assert_roundtrip], with a sample value of `T`
+///
+/// The sample is deserialized from zeroed bytes using [BincodeCodec], no matter which codec is
+/// checked. This gives zero numbers, empty collections and the first variant of enums. Panics if
+/// that is not a valid value of `T`, in that case use [assert_roundtrip] with a value.
+pub fn assert_sample_roundtrip<C, E, T>()
+where
+    C: Codec,
+    E: Serialize + DeserializeOwned,
+    T: Into<E> + TryFrom<E> + DeserializeOwned,
+{
+    let sample: T = BincodeCodec::decode(&[0u8; 4096]).unwrap_or_else(|e| {
+        panic!(
+            "can not create a sample of {}, use assert_roundtrip: {e}",
+            type_name::<T>()
+        )
+    });
+    assert_roundtrip::<C, E, T>(sample)
+}
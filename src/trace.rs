@@ -0,0 +1,273 @@
+//! Instrumentation of rpc calls
+//!
+//! With the `tracing` feature, every call gets a span. With the `metrics` feature, the
+//! [crate::metrics::Metrics] of the client or server are notified about every call. Without
+//! either feature, all of this compiles down to nothing.
+use crate::{
+    message::{InteractionPattern, Msg, PatternKind},
+    ChannelTypes, RpcMessage, Service,
+};
+use futures::{Future, Stream};
+use std::result;
+
+/// Instrumentation configured on a client or server
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Hooks {
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Option<std::sync::Arc<dyn crate::metrics::Metrics>>,
+}
+
+#[cfg(any(feature = "tracing", feature = "metrics"))]
+pub(crate) use enabled::CallSpan;
+
+#[cfg(not(any(feature = "tracing", feature = "metrics")))]
+pub(crate) use disabled::CallSpan;
+
+impl CallSpan {
+    /// Instrumentation for a call of message `M` on service `S`
+    pub(crate) fn for_msg<S: Service, M: Msg<S>>(side: &'static str, hooks: &Hooks) -> Self {
+        Self::new::<M>(side, <M::Pattern as InteractionPattern>::KIND, hooks)
+    }
+}
+
+#[cfg(any(feature = "tracing", feature = "metrics"))]
+mod enabled {
+    use super::*;
+    use pin_project::pin_project;
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    /// Instrumentation of a single call, recording the message type, pattern and outcome
+    #[derive(Debug, Clone)]
+    pub(crate) struct CallSpan {
+        #[cfg(feature = "tracing")]
+        span: tracing::Span,
+        #[cfg(feature = "metrics")]
+        metrics: Option<(
+            std::sync::Arc<dyn crate::metrics::Metrics>,
+            &'static str,
+            PatternKind,
+            std::time::Instant,
+        )>,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    enum End {
+        Ok,
+        Err,
+        Cancelled,
+    }
+
+    impl CallSpan {
+        #[allow(unused_variables)]
+        pub(crate) fn new<M>(side: &'static str, pattern: PatternKind, hooks: &Hooks) -> Self {
+            let message = std::any::type_name::<M>();
+            #[cfg(feature = "metrics")]
+            let metrics = hooks.metrics.clone().map(|metrics| {
+                metrics.on_request_start(message, pattern);
+                (metrics, message, pattern, std::time::Instant::now())
+            });
+            Self {
+                #[cfg(feature = "tracing")]
+                span: tracing::debug_span!(
+                    "rpc",
+                    side,
+                    message,
+                    ?pattern,
+                    correlation_id = tracing::field::Empty,
+                    outcome = tracing::field::Empty,
+                ),
+                #[cfg(feature = "metrics")]
+                metrics,
+            }
+        }
+
+        /// Record the id of the stream of the call, see [ChannelTypes::correlation_id]
+        #[allow(unused_variables)]
+        pub(crate) fn correlate<C: ChannelTypes, M: RpcMessage>(&self, recv: &C::RecvStream<M>) {
+            #[cfg(feature = "tracing")]
+            if let Some(id) = C::correlation_id(recv) {
+                self.span.record("correlation_id", id);
+            }
+        }
+
+        #[allow(unused_variables)]
+        fn record(&self, end: End) {
+            #[cfg(feature = "tracing")]
+            self.span.record(
+                "outcome",
+                match end {
+                    End::Ok => "ok",
+                    End::Err => "err",
+                    End::Cancelled => "cancelled",
+                },
+            );
+            #[cfg(feature = "metrics")]
+            if let Some((metrics, message, pattern, start)) = &self.metrics {
+                let outcome = match end {
+                    End::Ok => crate::metrics::Outcome::Ok,
+                    End::Err => crate::metrics::Outcome::Err,
+                    End::Cancelled => crate::metrics::Outcome::Cancelled,
+                };
+                metrics.on_request_end(message, *pattern, start.elapsed(), outcome);
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        fn instrument<F: Future>(&self, fut: F) -> impl Future<Output = F::Output> {
+            tracing::Instrument::instrument(fut, self.span.clone())
+        }
+
+        #[cfg(not(feature = "tracing"))]
+        fn instrument<F: Future>(&self, fut: F) -> F {
+            fut
+        }
+
+        /// Run the whole call in the span, and record whether it succeeded
+        pub(crate) fn call<T, E, F>(&self, fut: F) -> impl Future<Output = result::Result<T, E>>
+        where
+            F: Future<Output = result::Result<T, E>>,
+        {
+            let mut outcome = Outcome::new(self.clone());
+            self.instrument(async move {
+                let res = fut.await;
+                outcome.finish(res.is_ok());
+                res
+            })
+        }
+
+        /// Run the first part of a call in the span, only recording failure
+        ///
+        /// The rest of the call is expected to be wrapped using [CallSpan::call] or
+        /// [CallSpan::stream].
+        pub(crate) fn start<T, E, F>(&self, fut: F) -> impl Future<Output = result::Result<T, E>>
+        where
+            F: Future<Output = result::Result<T, E>>,
+        {
+            let mut outcome = Outcome::new(self.clone());
+            self.instrument(async move {
+                let res = fut.await;
+                match res {
+                    Ok(_) => outcome.hand_over(),
+                    Err(_) => outcome.finish(false),
+                }
+                res
+            })
+        }
+
+        /// Poll a stream of results in the span, recording the first error or the end
+        pub(crate) fn stream<T, E, St>(&self, stream: St) -> Traced<St>
+        where
+            St: Stream<Item = result::Result<T, E>>,
+        {
+            Traced {
+                inner: stream,
+                outcome: Outcome::new(self.clone()),
+            }
+        }
+    }
+
+    /// Records the outcome of a call, or that it was cancelled if dropped early
+    #[derive(Debug)]
+    struct Outcome {
+        span: CallSpan,
+        done: bool,
+    }
+
+    impl Outcome {
+        fn new(span: CallSpan) -> Self {
+            Self { span, done: false }
+        }
+
+        /// Leave recording the outcome to the rest of the call
+        fn hand_over(&mut self) {
+            self.done = true;
+        }
+
+        fn finish(&mut self, ok: bool) {
+            if !self.done {
+                self.done = true;
+                self.span.record(if ok { End::Ok } else { End::Err });
+            }
+        }
+    }
+
+    impl Drop for Outcome {
+        fn drop(&mut self) {
+            if !self.done {
+                self.span.record(End::Cancelled);
+            }
+        }
+    }
+
+    /// A stream that is polled in the span of its call
+    #[pin_project]
+    #[derive(Debug)]
+    pub(crate) struct Traced<St> {
+        #[pin]
+        inner: St,
+        outcome: Outcome,
+    }
+
+    impl<T, E, St> Stream for Traced<St>
+    where
+        St: Stream<Item = result::Result<T, E>>,
+    {
+        type Item = St::Item;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = self.project();
+            let item = {
+                #[cfg(feature = "tracing")]
+                let _entered = this.outcome.span.span.enter();
+                futures::ready!(this.inner.poll_next(cx))
+            };
+            match &item {
+                Some(Ok(_)) => {}
+                Some(Err(_)) => this.outcome.finish(false),
+                None => this.outcome.finish(true),
+            }
+            Poll::Ready(item)
+        }
+    }
+}
+
+#[cfg(not(any(feature = "tracing", feature = "metrics")))]
+mod disabled {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    pub(crate) struct CallSpan;
+
+    impl CallSpan {
+        #[allow(clippy::extra_unused_type_parameters)]
+        pub(crate) fn new<M>(_side: &'static str, _pattern: PatternKind, _hooks: &Hooks) -> Self {
+            Self
+        }
+
+        pub(crate) fn correlate<C: ChannelTypes, M: RpcMessage>(&self, _recv: &C::RecvStream<M>) {}
+
+        pub(crate) fn call<T, E, F>(&self, fut: F) -> F
+        where
+            F: Future<Output = result::Result<T, E>>,
+        {
+            fut
+        }
+
+        pub(crate) fn start<T, E, F>(&self, fut: F) -> F
+        where
+            F: Future<Output = result::Result<T, E>>,
+        {
+            fut
+        }
+
+        pub(crate) fn stream<T, E, St>(&self, stream: St) -> St
+        where
+            St: Stream<Item = result::Result<T, E>>,
+        {
+            stream
+        }
+    }
+}
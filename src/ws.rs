@@ -0,0 +1,483 @@
+//! WebSocket channel implementation based on tokio-tungstenite
+//!
+//! This is useful where raw QUIC is not an option, e.g. behind middleboxes that only let
+//! TCP on port 443 through. Every call opens a new WebSocket connection. Messages are sent as
+//! binary WebSocket messages containing the bincode serialized message.
+//!
+//! Clients use [WsChannelTypes::connect]. Servers either let the channel accept connections
+//! directly with [WsChannelTypes::listen], or hand over WebSocket connections that were
+//! upgraded elsewhere, e.g. in a hyper or axum handler, to an [Acceptor].
+use crate::{channel::CloseSignal, ChannelError, Retryable, RpcMessage};
+use futures::{
+    future::{self, BoxFuture, Either},
+    FutureExt, Sink, SinkExt, Stream, StreamExt,
+};
+use std::{
+    fmt, io,
+    marker::PhantomData,
+    pin::Pin,
+    result,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_tungstenite::{
+    tungstenite::{self, Message},
+    WebSocketStream,
+};
+
+/// Error of the underlying WebSocket connection
+pub type WsError = tungstenite::Error;
+
+/// Frame tag for a message
+const DATA: u8 = 0;
+/// Frame tag for the end of the messages in one direction
+///
+/// WebSocket has no half-close, so closing a sink is signalled with this frame.
+const FIN: u8 = 1;
+
+type RawSink = Pin<Box<dyn Sink<Message, Error = WsError> + Send>>;
+type RawStream = Pin<Box<dyn Stream<Item = result::Result<Message, WsError>> + Send>>;
+
+fn split<S>(ws: WebSocketStream<S>) -> (RawSink, RawStream)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sink, stream) = ws.split();
+    (Box::pin(sink), Box::pin(stream))
+}
+
+impl ChannelError for WsError {
+    fn into_io(self) -> io::Error {
+        match self {
+            tungstenite::Error::Io(e) => e,
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+                io::Error::new(io::ErrorKind::ConnectionAborted, self)
+            }
+            e => io::Error::other(e),
+        }
+    }
+
+    fn is_connection_lost(&self) -> bool {
+        match self {
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => true,
+            tungstenite::Error::Io(e) => e.is_connection_lost(),
+            _ => false,
+        }
+    }
+}
+
+impl Retryable for WsError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => true,
+            tungstenite::Error::Io(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+/// Error for sending messages on a WebSocket channel
+#[derive(Debug, thiserror::Error)]
+pub enum SendError {
+    /// The message could not be serialized
+    #[error("failed to serialize the message")]
+    Serialize(#[source] bincode::Error),
+    /// The WebSocket connection failed
+    #[error("the websocket connection failed")]
+    Ws(#[source] WsError),
+}
+
+impl ChannelError for SendError {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::Serialize(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+            Self::Ws(e) => e.into_io(),
+        }
+    }
+}
+
+impl Retryable for SendError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Serialize(_) => false,
+            Self::Ws(e) => e.is_retryable(),
+        }
+    }
+}
+
+/// Error for receiving messages on a WebSocket channel
+#[derive(Debug, thiserror::Error)]
+pub enum RecvError {
+    /// The message could not be deserialized
+    #[error("failed to deserialize the message")]
+    Deserialize(#[source] bincode::Error),
+    /// The remote sent a message that is not a binary frame of this protocol
+    #[error("the remote sent a message that is not a frame of this protocol")]
+    UnexpectedMessage,
+    /// The WebSocket connection failed
+    #[error("the websocket connection failed")]
+    Ws(#[source] WsError),
+}
+
+impl ChannelError for RecvError {
+    fn into_io(self) -> io::Error {
+        match self {
+            Self::Deserialize(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+            Self::UnexpectedMessage => io::Error::new(io::ErrorKind::InvalidData, self),
+            Self::Ws(e) => e.into_io(),
+        }
+    }
+
+    fn is_connection_lost(&self) -> bool {
+        match self {
+            Self::Deserialize(_) | Self::UnexpectedMessage => false,
+            Self::Ws(e) => e.is_connection_lost(),
+        }
+    }
+}
+
+impl Retryable for RecvError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Deserialize(_) | Self::UnexpectedMessage => false,
+            Self::Ws(e) => e.is_retryable(),
+        }
+    }
+}
+
+/// Error for open_bi, the WebSocket handshake failed
+pub type OpenBiError = WsError;
+
+/// Error for accept_bi
+#[derive(Debug, thiserror::Error)]
+pub enum AcceptBiError {
+    /// All [Acceptor]s of the channel were dropped
+    #[error("all acceptors of the channel were dropped")]
+    AcceptorDropped,
+    /// The channel was closed, see [crate::Channel::close]
+    #[error("the channel was closed")]
+    Closed,
+}
+
+impl ChannelError for AcceptBiError {
+    fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::NotConnected, self)
+    }
+}
+
+/// SendSink for WebSocket channels
+///
+/// Closing or dropping the sink tells the remote that no more messages will follow.
+pub struct SendSink<Out> {
+    inner: Option<RawSink>,
+    fin_sent: bool,
+    _p: PhantomData<Out>,
+}
+
+impl<Out> SendSink<Out> {
+    fn new(inner: RawSink) -> Self {
+        Self {
+            inner: Some(inner),
+            fin_sent: false,
+            _p: PhantomData,
+        }
+    }
+
+    fn inner(&mut self) -> &mut RawSink {
+        self.inner.as_mut().expect("sink is only taken on drop")
+    }
+}
+
+impl<Out> Drop for SendSink<Out> {
+    fn drop(&mut self) {
+        if self.fin_sent {
+            return;
+        }
+        if let (Some(mut inner), Ok(handle)) =
+            (self.inner.take(), tokio::runtime::Handle::try_current())
+        {
+            // the remote may already be gone, in which case there is nobody to tell
+            handle.spawn(async move { inner.send(Message::Binary(vec![FIN])).await.ok() });
+        }
+    }
+}
+
+impl<Out: RpcMessage> Sink<Out> for SendSink<Out> {
+    type Error = SendError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner().poll_ready_unpin(cx).map_err(SendError::Ws)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Out) -> Result<(), Self::Error> {
+        let mut data = vec![DATA];
+        bincode::serialize_into(&mut data, &item).map_err(SendError::Serialize)?;
+        self.inner()
+            .start_send_unpin(Message::Binary(data))
+            .map_err(SendError::Ws)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner().poll_flush_unpin(cx).map_err(SendError::Ws)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if !self.fin_sent {
+            futures::ready!(self.inner().poll_ready_unpin(cx)).map_err(SendError::Ws)?;
+            self.inner()
+                .start_send_unpin(Message::Binary(vec![FIN]))
+                .map_err(SendError::Ws)?;
+            self.fin_sent = true;
+        }
+        self.inner().poll_flush_unpin(cx).map_err(SendError::Ws)
+    }
+}
+
+/// RecvStream for WebSocket channels
+pub struct RecvStream<In> {
+    inner: RawStream,
+    done: bool,
+    _p: PhantomData<In>,
+}
+
+impl<In> RecvStream<In> {
+    fn new(inner: RawStream) -> Self {
+        Self {
+            inner,
+            done: false,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<In: RpcMessage> Stream for RecvStream<In> {
+    type Item = result::Result<In, RecvError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        while !self.done {
+            let msg = match futures::ready!(self.inner.poll_next_unpin(cx)) {
+                Some(Ok(msg)) => msg,
+                Some(Err(e)) => return Poll::Ready(Some(Err(RecvError::Ws(e)))),
+                None => break,
+            };
+            match msg {
+                Message::Binary(data) => match data.split_first() {
+                    Some((&DATA, rest)) => {
+                        return Poll::Ready(Some(
+                            bincode::deserialize(rest).map_err(RecvError::Deserialize),
+                        ))
+                    }
+                    Some((&FIN, [])) => break,
+                    _ => return Poll::Ready(Some(Err(RecvError::UnexpectedMessage))),
+                },
+                Message::Close(_) => break,
+                Message::Ping(_) | Message::Pong(_) => {}
+                Message::Text(_) | Message::Frame(_) => {
+                    return Poll::Ready(Some(Err(RecvError::UnexpectedMessage)))
+                }
+            }
+        }
+        self.done = true;
+        Poll::Ready(None)
+    }
+}
+
+/// A bidirectional stream of a WebSocket channel: a sink for outgoing and a stream of incoming messages
+pub type Socket<In, Out> = (self::SendSink<Out>, self::RecvStream<In>);
+
+enum Inner {
+    Client(String),
+    Server(flume::Receiver<(RawSink, RawStream)>),
+}
+
+/// A WebSocket channel
+pub struct Channel<In: RpcMessage, Out: RpcMessage>(
+    Arc<Inner>,
+    Arc<CloseSignal>,
+    PhantomData<(In, Out)>,
+);
+
+impl<In: RpcMessage, Out: RpcMessage> Clone for Channel<In, Out> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), self.1.clone(), PhantomData)
+    }
+}
+
+impl<In: RpcMessage, Out: RpcMessage> fmt::Debug for Channel<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.as_ref() {
+            Inner::Client(url) => f.debug_tuple("Channel::Client").field(url).finish(),
+            Inner::Server(_) => f.debug_tuple("Channel::Server").finish(),
+        }
+    }
+}
+
+/// Hands WebSocket connections that were accepted elsewhere to a server channel
+///
+/// Every connection becomes one bidirectional stream of the channel.
+pub struct Acceptor(flume::Sender<(RawSink, RawStream)>, Arc<CloseSignal>);
+
+impl Clone for Acceptor {
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), self.1.clone())
+    }
+}
+
+impl fmt::Debug for Acceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Acceptor").finish_non_exhaustive()
+    }
+}
+
+impl Acceptor {
+    fn is_closed(&self) -> bool {
+        self.0.is_disconnected() || self.1.is_closed()
+    }
+
+    /// Pass an established WebSocket connection to the channel
+    ///
+    /// For hyper or axum, upgrade the HTTP connection and wrap it using
+    /// [WebSocketStream::from_raw_socket] with the server role. Returns the connection back if
+    /// the channel was dropped or closed.
+    pub async fn accept<S>(&self, ws: WebSocketStream<S>) -> result::Result<(), WebSocketStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        if self.is_closed() {
+            return Err(ws);
+        }
+        // if the channel goes away in between, the connection is just dropped
+        self.0.send_async(split(ws)).await.ok();
+        Ok(())
+    }
+}
+
+/// Types for WebSocket channels
+#[derive(Debug, Clone, Copy)]
+pub struct WsChannelTypes;
+
+impl WsChannelTypes {
+    /// Create a client channel that opens a WebSocket connection to `url` for every call
+    pub fn connect<In: RpcMessage, Out: RpcMessage>(url: impl Into<String>) -> Channel<In, Out> {
+        Channel(
+            Arc::new(Inner::Client(url.into())),
+            Default::default(),
+            PhantomData,
+        )
+    }
+
+    /// Create a server channel that is fed using the returned [Acceptor]
+    ///
+    /// `buffer` is the number of connections that can be queued before [Acceptor::accept]
+    /// waits for the server to accept them.
+    pub fn server<In: RpcMessage, Out: RpcMessage>(buffer: usize) -> (Channel<In, Out>, Acceptor) {
+        let (send, recv) = flume::bounded(buffer);
+        let closed = Arc::new(CloseSignal::default());
+        (
+            Channel(Arc::new(Inner::Server(recv)), closed.clone(), PhantomData),
+            Acceptor(send, closed),
+        )
+    }
+
+    /// Create a server channel that accepts WebSocket connections on the given listener
+    ///
+    /// This spawns a task that does the WebSocket handshake for incoming TCP connections. The
+    /// task stops at the first connection after the channel was dropped or closed.
+    pub fn listen<In: RpcMessage, Out: RpcMessage>(listener: TcpListener) -> Channel<In, Out> {
+        let (channel, acceptor) = Self::server(16);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if acceptor.is_closed() {
+                    break;
+                }
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    // a failed handshake only affects this connection
+                    if let Ok(ws) = tokio_tungstenite::accept_async(stream).await {
+                        acceptor.accept(ws).await.ok();
+                    }
+                });
+            }
+        });
+        channel
+    }
+}
+
+/// Future returned by open_bi
+pub type OpenBiFuture<'a, In, Out> =
+    BoxFuture<'a, result::Result<self::Socket<In, Out>, self::OpenBiError>>;
+
+/// Future returned by accept_bi
+pub type AcceptBiFuture<'a, In, Out> =
+    BoxFuture<'a, result::Result<self::Socket<In, Out>, self::AcceptBiError>>;
+
+impl crate::ChannelTypes for WsChannelTypes {
+    type SendSink<M: RpcMessage> = self::SendSink<M>;
+
+    type RecvStream<M: RpcMessage> = self::RecvStream<M>;
+
+    type SendError = self::SendError;
+
+    type RecvError = self::RecvError;
+
+    type OpenBiError = self::OpenBiError;
+
+    type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::OpenBiFuture<'a, In, Out>;
+
+    type AcceptBiError = self::AcceptBiError;
+
+    type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> = self::AcceptBiFuture<'a, In, Out>;
+
+    type Channel<In: RpcMessage, Out: RpcMessage> = self::Channel<In, Out>;
+}
+
+impl<In: RpcMessage, Out: RpcMessage> crate::Channel<In, Out, WsChannelTypes> for Channel<In, Out> {
+    fn open_bi(&self) -> OpenBiFuture<'_, In, Out> {
+        async move {
+            let Inner::Client(url) = self.0.as_ref() else {
+                return Err(tungstenite::Error::Io(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "server channels can not open streams",
+                )));
+            };
+            if self.1.is_closed() {
+                return Err(tungstenite::Error::AlreadyClosed);
+            }
+            let connect = tokio_tungstenite::connect_async(url.as_str());
+            let (ws, _) = match future::select(Box::pin(connect), self.1.closed()).await {
+                Either::Left((res, _)) => res?,
+                Either::Right(_) => return Err(tungstenite::Error::AlreadyClosed),
+            };
+            let (send, recv) = split(ws);
+            Ok((SendSink::new(send), RecvStream::new(recv)))
+        }
+        .boxed()
+    }
+
+    fn accept_bi(&self) -> AcceptBiFuture<'_, In, Out> {
+        async move {
+            let Inner::Server(sockets) = self.0.as_ref() else {
+                return futures::future::pending().await;
+            };
+            let (send, recv) = match future::select(sockets.recv_async(), self.1.closed()).await {
+                Either::Left((res, _)) => res.map_err(|_| AcceptBiError::AcceptorDropped)?,
+                Either::Right(_) => return Err(AcceptBiError::Closed),
+            };
+            Ok((SendSink::new(send), RecvStream::new(recv)))
+        }
+        .boxed()
+    }
+
+    fn close(&self, _code: u32, _reason: &[u8]) {
+        // every stream is its own connection, and the ones that are already open are not
+        // affected. Connections that were not accepted yet are dropped
+        self.1.close();
+        if let Inner::Server(sockets) = self.0.as_ref() {
+            sockets.drain();
+        }
+    }
+}
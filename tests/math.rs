@@ -3,7 +3,14 @@ use async_stream::stream;
 use derive_more::{From, TryInto};
 use futures::{SinkExt, Stream, StreamExt, TryStreamExt};
 use quic_rpc::{
-    message::{BidiStreaming, ClientStreaming, Msg, RpcMsg, ServerStreaming},
+    codec::Codec,
+    message::{
+        BidiStreaming, Cancel, ClientStreaming, ControlFrame, Idempotent, Indexed, Keepalive, Msg,
+        NotifyMsg, PatternKind, ResumeFrom, RpcMsg, Sequenced, ServerStreaming, SplitControl,
+        StreamControl,
+    },
+    probe::{Probe, ProbeResponse},
+    reflect::{Reflect, ServiceDescriptor},
     server::RpcServerError,
     ChannelTypes, RpcClient, RpcServer, Service,
 };
@@ -11,62 +18,114 @@ use serde::{Deserialize, Serialize};
 use std::{
     io::{self, Write},
     result,
+    time::Duration,
 };
 use thousands::Separable;
 
 /// compute the square of a number
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sqr(pub u64);
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct SqrResponse(pub u128);
 
 /// sum a stream of numbers
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sum;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SumUpdate(pub u64);
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct SumResponse(pub u128);
 
+/// fire and forget message, ignored by the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification(pub u64);
+
 /// compute the fibonacci sequence as a stream
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Fibonacci(pub u64);
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FibonacciResponse(pub u128);
 
+/// compute the fibonacci sequence as a stream, failing once a number exceeds the maximum
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckedFibonacci {
+    pub n: u64,
+    pub max: u128,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FibonacciOverflow(pub u128);
+
 /// multiply a stream of numbers, returning a stream
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Multiply(pub u64);
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MultiplyUpdate(pub u64);
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MultiplyResponse(pub u128);
 
-/// request enum
-#[derive(Debug, Serialize, Deserialize, From, TryInto)]
-pub enum ComputeRequest {
-    Sqr(Sqr),
-    Sum(Sum),
-    SumUpdate(SumUpdate),
-    Fibonacci(Fibonacci),
-    Multiply(Multiply),
-    MultiplyUpdate(MultiplyUpdate),
+quic_rpc::declare_msg_enum! {
+    /// request enum
+    #[derive(Debug, Clone, Serialize, Deserialize, From, TryInto)]
+    pub enum ComputeRequest {
+        Sqr(Sqr),
+        Sum(Sum),
+        SumUpdate(SumUpdate),
+        Fibonacci(Fibonacci),
+        CheckedFibonacci(CheckedFibonacci),
+        Multiply(Multiply),
+        MultiplyUpdate(MultiplyUpdate),
+        Reflect(Reflect),
+        StreamControl(StreamControl),
+        ResumeFibonacci(ResumeFrom<Fibonacci>),
+        Probe(Probe),
+        OrderedSqr(Sequenced<Sqr>),
+        Control(ControlFrame),
+        Notification(Notification),
+        Cancel(Cancel),
+        Keepalive(Keepalive),
+    }
 }
 
-/// response enum
-#[allow(clippy::enum_variant_names)]
-#[derive(Debug, Serialize, Deserialize, From, TryInto)]
-pub enum ComputeResponse {
-    SqrResponse(SqrResponse),
-    SumResponse(SumResponse),
-    FibonacciResponse(FibonacciResponse),
-    MultiplyResponse(MultiplyResponse),
+quic_rpc::declare_msg_enum! {
+    /// response enum
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Debug, Serialize, Deserialize, From, TryInto)]
+    pub enum ComputeResponse {
+        SqrResponse(SqrResponse),
+        SumResponse(SumResponse),
+        FibonacciResponse(FibonacciResponse),
+        CheckedFibonacciResponse(result::Result<FibonacciResponse, FibonacciOverflow>),
+        MultiplyResponse(MultiplyResponse),
+        ServiceDescriptor(ServiceDescriptor),
+        IndexedFibonacciResponse(Indexed<FibonacciResponse>),
+        ProbeResponse(ProbeResponse),
+        Control(ControlFrame),
+    }
+}
+
+impl SplitControl for ComputeRequest {
+    fn split_control(self) -> result::Result<ControlFrame, Self> {
+        match self {
+            Self::Control(frame) => Ok(frame),
+            msg => Err(msg),
+        }
+    }
+}
+
+impl SplitControl for ComputeResponse {
+    fn split_control(self) -> result::Result<ControlFrame, Self> {
+        match self {
+            Self::Control(frame) => Ok(frame),
+            msg => Err(msg),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -75,12 +134,56 @@ pub struct ComputeService;
 impl Service for ComputeService {
     type Req = ComputeRequest;
     type Res = ComputeResponse;
+
+    fn interaction(req: &ComputeRequest) -> Option<PatternKind> {
+        use ComputeRequest::*;
+        Some(match req {
+            Sqr(_) => PatternKind::of::<Self, self::Sqr>(),
+            Sum(_) => PatternKind::of::<Self, self::Sum>(),
+            Fibonacci(_) => PatternKind::of::<Self, self::Fibonacci>(),
+            CheckedFibonacci(_) => PatternKind::of::<Self, self::CheckedFibonacci>(),
+            Multiply(_) => PatternKind::of::<Self, self::Multiply>(),
+            Reflect(_) => PatternKind::of::<Self, quic_rpc::reflect::Reflect>(),
+            Probe(_) => PatternKind::of::<Self, quic_rpc::probe::Probe>(),
+            ResumeFibonacci(_) => PatternKind::of::<Self, ResumeFrom<self::Fibonacci>>(),
+            OrderedSqr(_) => PatternKind::of::<Self, Sequenced<self::Sqr>>(),
+            Notification(_) => PatternKind::Notify,
+            SumUpdate(_) | MultiplyUpdate(_) | StreamControl(_) | Control(_) | Cancel(_)
+            | Keepalive(_) => return None,
+        })
+    }
+
+    fn is_cancel(req: &ComputeRequest) -> bool {
+        matches!(req, ComputeRequest::Cancel(_))
+    }
+
+    fn is_keepalive(req: &ComputeRequest) -> bool {
+        matches!(req, ComputeRequest::Keepalive(_))
+    }
+}
+
+quic_rpc::declare_client! {
+    /// typed client for the compute service
+    pub ComputeClient for ComputeService;
+    rpc Sqr: sqr;
+    client_streaming Sum: sum;
+    server_streaming Fibonacci: fibonacci;
+    server_streaming ResumeFrom<Fibonacci>: fibonacci_from;
+    bidi_streaming Multiply: multiply;
 }
 
 impl RpcMsg<ComputeService> for Sqr {
     type Response = SqrResponse;
 }
 
+impl Idempotent for Sqr {}
+
+impl RpcMsg<ComputeService> for Sequenced<Sqr> {
+    type Response = SqrResponse;
+}
+
+impl NotifyMsg<ComputeService> for Notification {}
+
 impl Msg<ComputeService> for Sum {
     type Response = SumResponse;
     type Update = SumUpdate;
@@ -93,6 +196,18 @@ impl Msg<ComputeService> for Fibonacci {
     type Pattern = ServerStreaming;
 }
 
+impl Msg<ComputeService> for CheckedFibonacci {
+    type Response = result::Result<FibonacciResponse, FibonacciOverflow>;
+    type Update = Self;
+    type Pattern = ServerStreaming;
+}
+
+impl Msg<ComputeService> for ResumeFrom<Fibonacci> {
+    type Response = Indexed<FibonacciResponse>;
+    type Update = Self;
+    type Pattern = ServerStreaming;
+}
+
 impl Msg<ComputeService> for Multiply {
     type Response = MultiplyResponse;
     type Update = MultiplyUpdate;
@@ -100,6 +215,27 @@ impl Msg<ComputeService> for Multiply {
 }
 
 impl ComputeService {
+    pub fn descriptor() -> ServiceDescriptor {
+        ServiceDescriptor::new::<Self>()
+            .method::<Self, Sqr>("sqr")
+            .method::<Self, Sum>("sum")
+            .method::<Self, Fibonacci>("fibonacci")
+            .method::<Self, Multiply>("multiply")
+            .notify::<Self, Notification>("notification")
+    }
+
+    /// check that all messages of the service survive the round trip through the enums
+    pub fn assert_service_roundtrip<C: Codec>() {
+        use quic_rpc::testing::assert_roundtrip;
+        ComputeRequest::assert_variants_roundtrip::<C>();
+        ComputeResponse::assert_variants_roundtrip::<C>();
+        // values that differ from the samples
+        assert_roundtrip::<C, ComputeResponse, result::Result<FibonacciResponse, FibonacciOverflow>>(
+            Err(FibonacciOverflow(1)),
+        );
+        assert_roundtrip::<C, ComputeResponse, _>(Self::descriptor());
+    }
+
     async fn sqr(self, req: Sqr) -> SqrResponse {
         SqrResponse(req.0 as u128 * req.0 as u128)
     }
@@ -113,7 +249,7 @@ impl ComputeService {
         SumResponse(sum)
     }
 
-    fn fibonacci(self, req: Fibonacci) -> impl Stream<Item = FibonacciResponse> {
+    pub fn fibonacci(self, req: Fibonacci) -> impl Stream<Item = FibonacciResponse> {
         let mut a = 0u128;
         let mut b = 1u128;
         let mut n = req.0;
@@ -128,6 +264,21 @@ impl ComputeService {
         }
     }
 
+    fn checked_fibonacci(
+        self,
+        req: CheckedFibonacci,
+    ) -> impl Stream<Item = result::Result<FibonacciResponse, FibonacciOverflow>> {
+        let max = req.max;
+        self.fibonacci(Fibonacci(req.n)).map(move |x| match x.0 {
+            n if n > max => Err(FibonacciOverflow(n)),
+            _ => Ok(x),
+        })
+    }
+
+    fn fibonacci_from(self, req: Fibonacci, offset: u64) -> impl Stream<Item = FibonacciResponse> {
+        self.fibonacci(req).skip(offset as usize)
+    }
+
     fn multiply(
         self,
         req: Multiply,
@@ -156,9 +307,19 @@ impl ComputeService {
                 Sqr(msg) => s.rpc(msg, chan, service, ComputeService::sqr).await,
                 Sum(msg) => s.client_streaming(msg, chan, service, ComputeService::sum).await,
                 Fibonacci(msg) => s.server_streaming(msg, chan, service, ComputeService::fibonacci).await,
+                CheckedFibonacci(msg) => s.try_server_streaming(msg, chan, service, ComputeService::checked_fibonacci).await,
                 Multiply(msg) => s.bidi_streaming(msg, chan, service, ComputeService::multiply).await,
+                Reflect(msg) => s.reflect(msg, chan, ComputeService::descriptor()).await,
+                Probe(msg) => s.probe(msg, chan).await,
+                Notification(msg) => s.notify(msg, service, |_, _| async {}).await,
+                ResumeFibonacci(msg) => s.server_streaming_resumable(msg, chan, service, ComputeService::fibonacci_from).await,
                 SumUpdate(_) => Err(RpcServerError::UnexpectedStartMessage)?,
                 MultiplyUpdate(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                StreamControl(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                OrderedSqr(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                Control(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                Cancel(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                Keepalive(_) => Err(RpcServerError::UnexpectedStartMessage)?,
             }?;
         }
     }
@@ -186,9 +347,19 @@ impl ComputeService {
                     Sqr(msg) => s.rpc(msg, chan, service, ComputeService::sqr).await,
                     Sum(msg) => s.client_streaming(msg, chan, service, ComputeService::sum).await,
                     Fibonacci(msg) => s.server_streaming(msg, chan, service, ComputeService::fibonacci).await,
+                    CheckedFibonacci(msg) => s.try_server_streaming(msg, chan, service, ComputeService::checked_fibonacci).await,
                     Multiply(msg) => s.bidi_streaming(msg, chan, service, ComputeService::multiply).await,
+                    Reflect(msg) => s.reflect(msg, chan, ComputeService::descriptor()).await,
+                    Probe(msg) => s.probe(msg, chan).await,
+                    Notification(msg) => s.notify(msg, service, |_, _| async {}).await,
+                    ResumeFibonacci(msg) => s.server_streaming_resumable(msg, chan, service, ComputeService::fibonacci_from).await,
                     SumUpdate(_) => Err(RpcServerError::UnexpectedStartMessage)?,
                     MultiplyUpdate(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                    StreamControl(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                    OrderedSqr(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                    Control(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                    Cancel(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                    Keepalive(_) => Err(RpcServerError::UnexpectedStartMessage)?,
                 }?;
                 Ok::<_, RpcServerError<C>>(())
             }
@@ -239,6 +410,50 @@ pub async fn smoke_test<C: ChannelTypes>(
     });
     let res = recv.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
     assert_eq!(res, vec![2, 4, 6]);
+
+    // resuming a server streaming call after dropping it
+    let mut s = client
+        .server_streaming(ResumeFrom::start(Fibonacci(10)))
+        .await?;
+    let mut res = Vec::new();
+    let mut next = 0;
+    while let Some(Indexed { index, item }) = s.try_next().await? {
+        assert_eq!(index, next);
+        res.push(item.0);
+        next = index + 1;
+        if next == 4 {
+            break;
+        }
+    }
+    drop(s);
+    let s = client
+        .server_streaming(ResumeFrom {
+            offset: next,
+            msg: Fibonacci(10),
+        })
+        .await?;
+    let rest = s.try_collect::<Vec<_>>().await?;
+    assert_eq!(rest[0].index, 4);
+    res.extend(rest.into_iter().map(|x| x.item.0));
+    assert_eq!(res, vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34]);
+
+    // reflection call
+    let descriptor = client.reflect().await?;
+    assert_eq!(descriptor, ComputeService::descriptor());
+    let sum = descriptor.get("sum").unwrap();
+    assert_eq!(sum.pattern, PatternKind::ClientStreaming);
+    assert!(sum.update.as_ref().unwrap().ends_with("SumUpdate"));
+    assert_eq!(
+        descriptor.get("notification").unwrap().pattern,
+        PatternKind::Notify
+    );
+
+    // notification
+    client.notify(Notification(1)).await?;
+
+    // probe call
+    let probe = client.probe().await?;
+    assert!(probe.rtt < Duration::from_secs(5));
     Ok(())
 }
 
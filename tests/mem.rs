@@ -1,9 +1,32 @@
 mod math;
+use futures::{future::BoxFuture, stream::BoxStream, FutureExt, SinkExt, StreamExt, TryStreamExt};
 use math::*;
 use quic_rpc::{
+    client::{
+        bidi_drain, OrderedClient, PollStream, PushError, ResponseStreamExt, RetryPolicy,
+        RetryingClient, RpcClientError, StreamingResponseItemError, TryRecvError,
+    },
+    codec::{BincodeCodec, PostcardCodec},
+    logging::{self, DebugPayload, LoggingChannelTypes},
     mem::{self, MemChannelTypes},
-    server::RpcServerError,
-    RpcClient, RpcServer,
+    message::{
+        Cancel, ClientStreaming, ControlFrame, Frame, Indexed, Keepalive, Msg, PatternKind,
+        PausePolicy, ResumeFrom, Rpc, ServerStreaming,
+    },
+    router::{
+        BidiStreamingHandler, ClientStreamingHandler, FunctionRouter, RpcHandler,
+        ServerStreamingHandler,
+    },
+    server::{Drain, OrderedQueue, RpcServerError, SlowReaderPolicy},
+    testing::{self, FaultConfig, FaultyChannelTypes},
+    ChannelTypes, RpcClient, RpcServer, Service,
+};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
 #[tokio::test]
@@ -39,3 +62,2800 @@ async fn mem_channel_smoke() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+/// pausing and resuming a server streaming response does not lose items
+#[tokio::test]
+async fn mem_channel_server_streaming_controlled() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        match req {
+            ComputeRequest::Fibonacci(msg) => {
+                server
+                    .server_streaming_controlled(
+                        msg,
+                        chan,
+                        ComputeService,
+                        ComputeService::fibonacci,
+                        PausePolicy::Buffer,
+                    )
+                    .await?
+            }
+            _ => anyhow::bail!("unexpected request {:?}", req),
+        }
+        anyhow::Ok(())
+    });
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let (controller, recv) = client.server_streaming_controlled(Fibonacci(10)).await?;
+    controller.pause().await?;
+    controller.resume().await?;
+    let res = recv.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(res, vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34]);
+    server_handle.await??;
+    Ok(())
+}
+
+/// keepalives and cancels are not mistaken for pause or resume requests
+#[tokio::test]
+async fn mem_channel_server_streaming_controlled_updates() -> anyhow::Result<()> {
+    use quic_rpc::Channel;
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(async move {
+        let mut results = Vec::new();
+        for _ in 0..2 {
+            let (req, chan) = server.accept_one().await?;
+            let ComputeRequest::Fibonacci(msg) = req else {
+                anyhow::bail!("unexpected request {:?}", req);
+            };
+            let res = server
+                .server_streaming_controlled(
+                    msg,
+                    chan,
+                    ComputeService,
+                    |_, _| futures::stream::pending::<FibonacciResponse>(),
+                    PausePolicy::Buffer,
+                )
+                .await;
+            results.push(res);
+        }
+        anyhow::Ok(results)
+    });
+    // a keepalive does not end the call, the client closing the stream does
+    let (mut send, _recv) = client.open_bi_with(Fibonacci(3).into()).await?;
+    send.send(Keepalive.into()).await?;
+    send.close().await?;
+    // a cancel aborts the handler
+    let (mut send, _recv) = client.open_bi_with(Fibonacci(3).into()).await?;
+    send.send(Cancel.into()).await?;
+    let results = server_handle.await??;
+    assert!(results[0].is_ok(), "{:?}", results[0]);
+    assert!(matches!(results[1], Err(RpcServerError::Cancelled)));
+    Ok(())
+}
+
+/// client errors are classified into retryable and fatal ones
+#[tokio::test]
+async fn mem_channel_retryable_errors() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    // the server accepts the request but closes the stream without answering
+    let server_handle = tokio::task::spawn(async move {
+        let (_req, chan) = server.accept_one().await?;
+        drop(chan);
+        anyhow::Ok(())
+    });
+    let err = client.rpc(Sqr(2)).await.unwrap_err();
+    assert!(matches!(err, RpcClientError::EarlyClose));
+    assert!(err.is_retryable());
+    server_handle.await??;
+    // the server is gone for good
+    let err = client.rpc(Sqr(2)).await.unwrap_err();
+    assert!(matches!(err, RpcClientError::Open(_)));
+    assert!(!err.is_retryable());
+    Ok(())
+}
+
+/// a slow client limits how far the server streaming handler runs ahead
+#[tokio::test]
+async fn mem_channel_server_streaming_lazy() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let produced = Arc::new(AtomicU64::new(0));
+
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let produced2 = produced.clone();
+    tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        match req {
+            ComputeRequest::Fibonacci(msg) => {
+                let handler = move |_, _| {
+                    futures::stream::repeat(()).map(move |_| {
+                        FibonacciResponse(produced2.fetch_add(1, Ordering::SeqCst) as u128)
+                    })
+                };
+                server
+                    .server_streaming(msg, chan, ComputeService, handler)
+                    .await?
+            }
+            _ => anyhow::bail!("unexpected request {:?}", req),
+        }
+        anyhow::Ok(())
+    });
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let mut recv = client.server_streaming(Fibonacci(0)).await?;
+    recv.next().await.unwrap()?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    // the per stream buffer of the mem channel is 128 items
+    let produced = produced.load(Ordering::SeqCst);
+    assert!(produced <= 130, "handler ran ahead by {} items", produced);
+    Ok(())
+}
+
+tokio::task_local! {
+    static REQUEST_ID: u64;
+}
+
+/// task locals established by the context hook are visible in the spawned handlers
+#[tokio::test]
+async fn mem_channel_serve_with_context() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let dispatch = |s: RpcServer<ComputeService, MemChannelTypes>, req, chan| async move {
+        match req {
+            ComputeRequest::Sqr(msg) => {
+                s.rpc(msg, chan, (), |_, _| async move {
+                    SqrResponse(REQUEST_ID.get() as u128)
+                })
+                .await
+            }
+            _ => Err(RpcServerError::UnexpectedStartMessage),
+        }
+    };
+    let server_handle = tokio::task::spawn(
+        server.serve_with_context(dispatch, |ctx, fut| REQUEST_ID.scope(ctx.id, fut)),
+    );
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    for i in 0..3 {
+        assert_eq!(client.rpc(Sqr(0)).await?, SqrResponse(i));
+    }
+    drop(client);
+    match server_handle.await? {
+        Err(RpcServerError::AcceptBiError(_)) => {}
+        e => panic!("unexpected termination result {:?}", e),
+    }
+    Ok(())
+}
+
+/// serve_concurrent never runs more than the given number of handlers at once
+#[tokio::test]
+async fn mem_channel_serve_concurrent() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let active = Arc::new(AtomicU64::new(0));
+    let max_active = Arc::new(AtomicU64::new(0));
+    let counters = (active.clone(), max_active.clone());
+    let dispatch = move |s: RpcServer<ComputeService, MemChannelTypes>, req, chan| {
+        let (active, max_active) = counters.clone();
+        async move {
+            match req {
+                ComputeRequest::Sqr(msg) => {
+                    s.rpc(msg, chan, (), |_, Sqr(x)| async move {
+                        let n = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_active.fetch_max(n, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        active.fetch_sub(1, Ordering::SeqCst);
+                        SqrResponse(x as u128 * x as u128)
+                    })
+                    .await
+                }
+                _ => Err(RpcServerError::UnexpectedStartMessage),
+            }
+        }
+    };
+    let server_handle = tokio::task::spawn(server.serve_concurrent(dispatch, 2));
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let results = futures::future::join_all((0..8u64).map(|i| client.rpc(Sqr(i)))).await;
+    for (i, res) in results.into_iter().enumerate() {
+        assert_eq!(res?, SqrResponse((i * i) as u128));
+    }
+    assert_eq!(max_active.load(Ordering::SeqCst), 2);
+    drop(client);
+    match server_handle.await? {
+        Err(RpcServerError::AcceptBiError(_)) => {}
+        e => panic!("unexpected termination result {:?}", e),
+    }
+    Ok(())
+}
+
+/// serve_until_idle returns once no request was in flight for the idle time
+#[tokio::test]
+async fn mem_channel_serve_until_idle() -> anyhow::Result<()> {
+    let (client, server) = mem::service_connection::<ComputeService>(1);
+    let dispatch = |s: RpcServer<ComputeService, MemChannelTypes>, req, chan| async move {
+        match req {
+            ComputeRequest::Sqr(msg) => {
+                s.rpc(msg, chan, (), |_, Sqr(x)| async move {
+                    // longer than the idle time, which must not count while in flight
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    SqrResponse(x as u128 * x as u128)
+                })
+                .await
+            }
+            _ => Err(RpcServerError::UnexpectedStartMessage),
+        }
+    };
+    let idle = Duration::from_millis(100);
+    let server_handle = tokio::task::spawn(server.serve_until_idle(dispatch, idle));
+    assert_eq!(client.rpc(Sqr(2)).await?, SqrResponse(4));
+    // a pause shorter than the idle time
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    // the client goes quiet, but keeps the connection open
+    let quiet = std::time::Instant::now();
+    tokio::time::timeout(Duration::from_secs(5), server_handle).await???;
+    assert!(quiet.elapsed() >= Duration::from_millis(50));
+    drop(client);
+    Ok(())
+}
+
+/// all messages of the compute service convert back from the request and response enums
+#[test]
+fn compute_service_roundtrip() {
+    ComputeService::assert_service_roundtrip::<BincodeCodec>();
+    ComputeService::assert_service_roundtrip::<PostcardCodec>();
+}
+
+/// a TryFrom impl that does not match the From impl fails the round trip check
+#[test]
+#[should_panic(expected = "does not convert back")]
+fn roundtrip_mismatch() {
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    enum Request {
+        A(u64),
+        B(u64),
+    }
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct A(u64);
+    impl From<A> for Request {
+        fn from(A(x): A) -> Self {
+            Request::A(x)
+        }
+    }
+    impl TryFrom<Request> for A {
+        type Error = Request;
+        fn try_from(req: Request) -> Result<Self, Request> {
+            match req {
+                Request::B(x) => Ok(A(x)),
+                req => Err(req),
+            }
+        }
+    }
+    testing::assert_sample_roundtrip::<BincodeCodec, Request, A>();
+}
+
+/// draining stops accepting requests but lets the requests in flight complete
+#[tokio::test]
+async fn mem_channel_drain() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let (release_tx, release_rx) = flume::bounded::<()>(1);
+    let dispatch = move |s: RpcServer<ComputeService, MemChannelTypes>, req, chan| {
+        let release = release_rx.clone();
+        async move {
+            match req {
+                ComputeRequest::Sqr(msg) => {
+                    s.rpc(msg, chan, (), |_, Sqr(x)| async move {
+                        release.recv_async().await.ok();
+                        SqrResponse(x as u128 * x as u128)
+                    })
+                    .await
+                }
+                _ => Err(RpcServerError::UnexpectedStartMessage),
+            }
+        }
+    };
+    let drain = Drain::new(4);
+    let handle = drain.handle();
+    let server_handle = tokio::task::spawn(drain.serve(server, dispatch));
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let call = tokio::task::spawn({
+        let client = client.clone();
+        async move { client.rpc(Sqr(3)).await }
+    });
+    while handle.in_flight() == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    handle.drain();
+    assert!(handle.is_draining());
+    // the loop waits for the handler in flight
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!server_handle.is_finished());
+    release_tx.send_async(()).await?;
+    assert_eq!(call.await??, SqrResponse(9));
+    let summary = server_handle.await??;
+    assert_eq!(
+        (summary.accepted, summary.completed, summary.failed),
+        (1, 1, 0)
+    );
+    assert_eq!(handle.in_flight(), 0);
+    Ok(())
+}
+
+/// the serve loop and the max rpc duration work without a tokio runtime
+#[test]
+fn mem_channel_custom_runtime() -> anyhow::Result<()> {
+    let pool = futures::executor::ThreadPool::new()?;
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server)
+        .with_spawner({
+            let pool = pool.clone();
+            move |fut| pool.spawn_ok(fut)
+        })
+        .with_timer(|duration| {
+            let (tx, rx) = futures::channel::oneshot::channel();
+            std::thread::spawn(move || {
+                std::thread::sleep(duration);
+                tx.send(()).ok();
+            });
+            rx.map(|_| ()).boxed()
+        })
+        .with_max_rpc_duration(Duration::from_millis(50));
+    let dispatch = |s: RpcServer<ComputeService, MemChannelTypes>, req, chan| async move {
+        match req {
+            ComputeRequest::Sqr(msg) => {
+                s.rpc(msg, chan, (), |_, Sqr(x)| async move {
+                    if x == 0 {
+                        futures::future::pending::<()>().await;
+                    }
+                    SqrResponse(x as u128 * x as u128)
+                })
+                .await
+            }
+            _ => Err(RpcServerError::UnexpectedStartMessage),
+        }
+    };
+    pool.spawn_ok(server.serve(dispatch).map(|_| ()));
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    futures::executor::block_on(async move {
+        assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+        // the handler never completes, so the call is aborted after the max rpc duration
+        assert!(client.rpc(Sqr(0)).await.is_err());
+        anyhow::Ok(())
+    })
+}
+
+/// spawn a server that answers a single fibonacci request with the numbers 0..n
+fn spawn_counting_server(
+    server: mem::Channel<ComputeRequest, ComputeResponse>,
+    n: u128,
+    policy: SlowReaderPolicy,
+) -> tokio::task::JoinHandle<Result<(), RpcServerError<MemChannelTypes>>> {
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        match req {
+            ComputeRequest::Fibonacci(msg) => {
+                let handler = move |_, _| futures::stream::iter((0..n).map(FibonacciResponse));
+                server
+                    .server_streaming_with_policy(msg, chan, ComputeService, handler, policy)
+                    .await
+            }
+            _ => Err(RpcServerError::UnexpectedStartMessage),
+        }
+    })
+}
+
+/// a client that does not read is detected with the timeout policy
+#[tokio::test]
+async fn mem_channel_slow_reader_timeout() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let policy = SlowReaderPolicy::TimeoutAfter(Duration::from_millis(50));
+    let server_handle = spawn_counting_server(server, u128::MAX, policy);
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let _recv = client.server_streaming(Fibonacci(0)).await?;
+    match server_handle.await? {
+        Err(RpcServerError::ClientTooSlow) => {}
+        e => panic!("unexpected termination result {:?}", e),
+    }
+    Ok(())
+}
+
+/// a client that does not read gets the most recent items with the drop oldest policy
+#[tokio::test]
+async fn mem_channel_slow_reader_drop_oldest() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server_handle = spawn_counting_server(server, 1000, SlowReaderPolicy::DropOldest(4));
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let recv = client.server_streaming(Fibonacci(0)).await?;
+    // give the handler time to run to completion while nobody is reading
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let res = recv.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert!(res.len() < 1000);
+    assert_eq!(res[res.len() - 4..], [996, 997, 998, 999]);
+    server_handle.await??;
+    Ok(())
+}
+
+/// the handler runs ahead of a client that does not read by at most the buffer size
+#[tokio::test]
+async fn mem_channel_server_streaming_buffered() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let pulled = Arc::new(AtomicU64::new(0));
+    let server_handle = tokio::task::spawn({
+        let pulled = pulled.clone();
+        async move {
+            let (req, chan) = server.accept_one().await?;
+            let ComputeRequest::Fibonacci(msg) = req else {
+                return Err(RpcServerError::UnexpectedStartMessage);
+            };
+            let handler = move |_, _| {
+                futures::stream::iter(0..1000).map(move |i| {
+                    pulled.fetch_add(1, Ordering::SeqCst);
+                    FibonacciResponse(i)
+                })
+            };
+            server
+                .server_streaming_buffered(msg, chan, ComputeService, handler, 8)
+                .await
+        }
+    });
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let recv = client.server_streaming(Fibonacci(0)).await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // the stream of the mem channel holds 128 responses, plus the buffer
+    let ahead = pulled.load(Ordering::SeqCst);
+    assert!((136..=140).contains(&ahead), "{ahead}");
+    // nothing is lost once the client reads
+    let res = recv.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(res, (0..1000).collect::<Vec<_>>());
+    server_handle.await??;
+    Ok(())
+}
+
+/// a struct holding a client and a server, for any channel type
+///
+/// The fields are only read by the Debug impl.
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Endpoints<C: ChannelTypes> {
+    client: RpcClient<ComputeService, C>,
+    server: RpcServer<ComputeService, C>,
+}
+
+/// Debug does not require the channel to implement Debug
+fn debug_endpoints<C: ChannelTypes>(endpoints: &Endpoints<C>) -> String {
+    format!("{endpoints:?}")
+}
+
+#[test]
+fn client_server_debug() {
+    let (client, server) = mem::service_connection::<ComputeService>(1);
+    let endpoints = Endpoints { client, server };
+    let debug = debug_endpoints(&endpoints);
+    // the service is shown by its type name, whose module path is not stable
+    assert!(debug.contains("RpcClient { service: \""));
+    assert!(debug.contains("RpcServer { service: \""));
+    assert!(debug.contains("ComputeService"));
+}
+
+/// errors of the server keep the error of the channel as their source
+#[tokio::test]
+async fn mem_channel_error_source() -> anyhow::Result<()> {
+    let (client, mut server) = mem::service_connection::<ComputeService>(1);
+    drop(client);
+    let Err(err) = server.accept_one().await else {
+        panic!("accepted a request without a client");
+    };
+    assert!(matches!(err, RpcServerError::AcceptBiError(_)));
+    assert!(std::error::Error::source(&err).is_some());
+    let chain = anyhow::Error::from(err)
+        .chain()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(chain.len(), 2);
+    assert_eq!(chain[0], "failed to accept a stream");
+    Ok(())
+}
+
+/// without unidirectional streams, pushes arrive on bidirectional streams opened by the server
+#[tokio::test]
+async fn mem_channel_push() -> anyhow::Result<()> {
+    let (client, server) = mem::service_connection::<ComputeService>(1);
+    server.push(SqrResponse(4)).await?;
+    assert_eq!(client.accept_push::<SqrResponse>().await?, SqrResponse(4));
+    // a push of another message is an error
+    server.push(SqrResponse(5)).await?;
+    let res = client.accept_push::<SumResponse>().await;
+    assert!(matches!(res, Err(PushError::DowncastError(_))));
+    Ok(())
+}
+
+/// a service can be served from a set of handler functions
+#[tokio::test]
+async fn mem_channel_function_router() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let router =
+        FunctionRouter::new().on(|Sqr(x)| async move { SqrResponse(x as u128 * x as u128) });
+    let server_handle = tokio::task::spawn(router.serve(server));
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    for i in 0..3u64 {
+        assert_eq!(client.rpc(Sqr(i)).await?, SqrResponse((i * i) as u128));
+    }
+    // no handler for this message, so the server closes the stream
+    let err = client.reflect().await.unwrap_err();
+    assert!(matches!(err, RpcClientError::EarlyClose));
+    drop(client);
+    match server_handle.await? {
+        Err(RpcServerError::AcceptBiError(_)) => {}
+        e => panic!("unexpected termination result {:?}", e),
+    }
+    Ok(())
+}
+
+/// a handler type for all patterns of the compute service
+struct ComputeHandler {
+    calls: AtomicU64,
+}
+
+impl RpcHandler<ComputeService, Sqr> for ComputeHandler {
+    fn handle_rpc(self: Arc<Self>, Sqr(x): Sqr) -> BoxFuture<'static, SqrResponse> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        async move { SqrResponse(x as u128 * x as u128) }.boxed()
+    }
+}
+
+impl ServerStreamingHandler<ComputeService, Fibonacci> for ComputeHandler {
+    fn handle_server_streaming(
+        self: Arc<Self>,
+        Fibonacci(n): Fibonacci,
+    ) -> BoxStream<'static, FibonacciResponse> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let fib = (0..n).scan((0u128, 1u128), |(a, b), _| {
+            let x = *a;
+            (*a, *b) = (*b, x + *b);
+            Some(FibonacciResponse(x))
+        });
+        futures::stream::iter(fib).boxed()
+    }
+}
+
+impl ClientStreamingHandler<ComputeService, Sum> for ComputeHandler {
+    fn handle_client_streaming(
+        self: Arc<Self>,
+        _: Sum,
+        updates: BoxStream<'static, SumUpdate>,
+    ) -> BoxFuture<'static, SumResponse> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        updates
+            .fold(0, |sum, SumUpdate(x)| async move { sum + x as u128 })
+            .map(SumResponse)
+            .boxed()
+    }
+}
+
+impl BidiStreamingHandler<ComputeService, Multiply> for ComputeHandler {
+    fn handle_bidi_streaming(
+        self: Arc<Self>,
+        Multiply(factor): Multiply,
+        updates: BoxStream<'static, MultiplyUpdate>,
+    ) -> BoxStream<'static, MultiplyResponse> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        updates
+            .map(move |MultiplyUpdate(x)| MultiplyResponse(factor as u128 * x as u128))
+            .boxed()
+    }
+}
+
+/// one handler type registered for messages of all patterns
+#[tokio::test]
+async fn mem_channel_handler_router() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let handler = Arc::new(ComputeHandler {
+        calls: AtomicU64::new(0),
+    });
+    let router = FunctionRouter::new()
+        .rpc_handler::<Sqr, _>(handler.clone())
+        .server_streaming_handler::<Fibonacci, _>(handler.clone())
+        .client_streaming_handler::<Sum, _>(handler.clone())
+        .bidi_streaming_handler::<Multiply, _>(handler.clone());
+    let server_handle = tokio::task::spawn(router.serve(server));
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    let fib = client.server_streaming(Fibonacci(5)).await?;
+    let fib = fib.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(fib, [0, 1, 1, 2, 3]);
+    let (mut send, res) = client.client_streaming(Sum).await?;
+    send.send(SumUpdate(1)).await?;
+    send.send(SumUpdate(2)).await?;
+    send.close().await?;
+    assert_eq!(res.await?, SumResponse(3));
+    let (mut send, recv) = client.bidi(Multiply(2)).await?;
+    send.send(MultiplyUpdate(3)).await?;
+    send.close().await?;
+    let res = recv.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(res, [6]);
+    assert_eq!(handler.calls.load(Ordering::SeqCst), 4);
+    server_handle.abort();
+    Ok(())
+}
+
+/// logger that captures the log lines of the logging channel and the server
+struct CaptureLogger(Mutex<Vec<String>>);
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        matches!(metadata.target(), "quic_rpc::logging" | "quic_rpc::server")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLogger = CaptureLogger(Mutex::new(Vec::new()));
+
+/// install [LOGGER], once for all tests
+fn capture_logs() -> &'static CaptureLogger {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+    });
+    &LOGGER
+}
+
+/// the logging channel logs requests and responses, using the redactors for the payload
+#[tokio::test]
+async fn mem_channel_logging() -> anyhow::Result<()> {
+    type C = LoggingChannelTypes<MemChannelTypes>;
+    let logger = capture_logs();
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let redact = |req: &ComputeRequest| match req {
+        ComputeRequest::Sqr(_) => Some("Sqr(<redacted>)".to_string()),
+        _ => None,
+    };
+    let server = logging::Channel::<MemChannelTypes, _, _>::with_redactors(
+        server,
+        redact,
+        DebugPayload { max_len: 16 },
+    );
+    let server = RpcServer::<ComputeService, C>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    let client = RpcClient::<ComputeService, C>::new(logging::Channel::new(client));
+    assert_eq!(
+        client.rpc(Sqr(12345678)).await?,
+        SqrResponse(152415765279684)
+    );
+    drop(client);
+    server_handle.await?.ok();
+    let lines = logger.0.lock().unwrap().clone();
+    assert!(lines.contains(&"stream 0 send ? (12 bytes)".to_string()));
+    assert!(lines.contains(&"stream 0 recv ? (12 bytes): Sqr(<redacted>)".to_string()));
+    assert!(lines.contains(&"stream 0 send SqrResponse (20 bytes): SqrResponse(SqrR…".to_string()));
+    assert!(lines.iter().all(|line| !line.contains("12345678")));
+    Ok(())
+}
+
+/// errors of requests handled by a serve loop are logged instead of being dropped
+#[cfg(not(feature = "tracing"))]
+#[tokio::test]
+async fn mem_channel_serve_logs_errors() -> anyhow::Result<()> {
+    let logger = capture_logs();
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(
+        server.serve(|_, _, _| async { Err(RpcServerError::UnexpectedStartMessage) }),
+    );
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    assert!(client.rpc(Sqr(2)).await.is_err());
+    // the client might see the closed stream before the task logged the error
+    let line = "request 0 failed: unexpected first message".to_string();
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while !logger.0.lock().unwrap().contains(&line) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await?;
+    server_handle.abort();
+    Ok(())
+}
+
+/// a response that arrives after the caller gave up is never delivered to a later call
+///
+/// Every rpc uses its own stream, so the late response goes to the stream of the cancelled call.
+#[tokio::test]
+async fn mem_channel_late_response_after_timeout() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let router = FunctionRouter::new().on(|Sqr(x)| async move {
+        if x == 0 {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        SqrResponse(x as u128 * x as u128)
+    });
+    let server_handle = tokio::task::spawn(router.serve(server));
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let res = tokio::time::timeout(Duration::from_millis(50), client.rpc(Sqr(0))).await;
+    assert!(res.is_err());
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    // wait until the slow handler has answered the cancelled call
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(client.rpc(Sqr(4)).await?, SqrResponse(16));
+    drop(client);
+    match server_handle.await? {
+        Err(RpcServerError::AcceptBiError(_)) => {}
+        e => panic!("unexpected termination result {:?}", e),
+    }
+    Ok(())
+}
+
+/// draining a bidi call closes the update sink and collects the responses
+#[tokio::test]
+async fn mem_channel_bidi_drain() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let (mut send, recv) = client.bidi(Multiply(3)).await?;
+    for i in 1..=3 {
+        send.send(MultiplyUpdate(i)).await?;
+    }
+    let res = bidi_drain(send, recv).await?;
+    assert_eq!(res.into_iter().map(|x| x.0).collect::<Vec<_>>(), [3, 6, 9]);
+    drop(client);
+    match server_handle.await? {
+        Err(RpcServerError::AcceptBiError(_)) => {}
+        e => panic!("unexpected termination result {:?}", e),
+    }
+    Ok(())
+}
+
+/// ordered requests are processed in the order they were issued, even if they run concurrently
+#[tokio::test]
+async fn mem_channel_ordered() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(16);
+
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let queue = OrderedQueue::new();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let server_log = log.clone();
+    let server_handle = tokio::task::spawn(server.serve(move |server, req, chan| {
+        let queue = queue.clone();
+        let log = server_log.clone();
+        async move {
+            match req {
+                ComputeRequest::OrderedSqr(msg) => {
+                    let handler = |log: Arc<Mutex<Vec<u64>>>, Sqr(x)| async move {
+                        // later requests are faster, so without ordering they would overtake
+                        tokio::time::sleep(Duration::from_millis(50 - x * 5)).await;
+                        log.lock().unwrap().push(x);
+                        SqrResponse(x as u128 * x as u128)
+                    };
+                    server.rpc_ordered(msg, chan, log, handler, &queue).await
+                }
+                _ => Err(RpcServerError::UnexpectedStartMessage),
+            }
+        }
+    }));
+    let client = OrderedClient::new(RpcClient::<ComputeService, MemChannelTypes>::new(client));
+    let res = futures::future::try_join_all((0..10).map(|i| client.rpc(Sqr(i)))).await?;
+    let res = res.into_iter().map(|x| x.0).collect::<Vec<_>>();
+    assert_eq!(res, (0..10u128).map(|x| x * x).collect::<Vec<_>>());
+    assert_eq!(*log.lock().unwrap(), (0..10).collect::<Vec<_>>());
+    drop(client);
+    match server_handle.await? {
+        Err(RpcServerError::AcceptBiError(_)) => {}
+        e => panic!("unexpected termination result {:?}", e),
+    }
+    Ok(())
+}
+
+/// ordered requests that are dropped or never arrive do not stall the requests after them
+#[tokio::test]
+async fn mem_channel_ordered_gaps() -> anyhow::Result<()> {
+    type C = FaultyChannelTypes<MemChannelTypes>;
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(16);
+    // the request of the first call never reaches the queue
+    let config = FaultConfig {
+        close_on: Some(1),
+        ..Default::default()
+    };
+    let server = RpcServer::<ComputeService, C>::new(testing::Channel::new(server, config));
+    let queue = OrderedQueue::new().with_gap_timeout(Some(Duration::from_millis(50)));
+    tokio::task::spawn(server.serve(move |server, req, chan| {
+        let queue = queue.clone();
+        async move {
+            match req {
+                ComputeRequest::OrderedSqr(msg) => {
+                    let handler = |_, Sqr(x)| async move {
+                        if x == 2 {
+                            // runs until the client drops the call
+                            futures::future::pending::<()>().await;
+                        }
+                        SqrResponse(x as u128 * x as u128)
+                    };
+                    server.rpc_ordered(msg, chan, (), handler, &queue).await
+                }
+                _ => Err(RpcServerError::UnexpectedStartMessage),
+            }
+        }
+    }));
+    let client = OrderedClient::new(RpcClient::<ComputeService, MemChannelTypes>::new(client));
+    assert!(client.rpc(Sqr(0)).await.is_err());
+    // waits for the gap timeout
+    assert_eq!(client.rpc(Sqr(1)).await?, SqrResponse(1));
+    // dropped mid flight
+    let dropped = tokio::time::timeout(Duration::from_millis(50), client.rpc(Sqr(2))).await;
+    assert!(dropped.is_err());
+    let res = tokio::time::timeout(Duration::from_secs(1), client.rpc(Sqr(3))).await?;
+    assert_eq!(res?, SqrResponse(9));
+    Ok(())
+}
+
+/// a streaming request that keeps sending is aborted after the max rpc duration
+#[tokio::test]
+async fn mem_channel_max_rpc_duration() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server)
+        .with_max_rpc_duration(Duration::from_millis(100));
+    let server_handle = tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Fibonacci(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        let res = server
+            .server_streaming(msg, chan, (), |_, _| {
+                // trickle responses forever
+                futures::stream::unfold(0, |i| async move {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    Some((FibonacciResponse(i), i + 1))
+                })
+            })
+            .await;
+        assert!(matches!(res, Err(RpcServerError::MaxDurationExceeded)));
+        anyhow::Ok(())
+    });
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let res = client
+        .server_streaming(Fibonacci(0))
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert!(!res.is_empty() && res.len() < 20);
+    server_handle.await??;
+    Ok(())
+}
+
+/// control frames are exchanged in order with the updates and responses of a bidi call
+#[tokio::test]
+async fn mem_channel_bidi_control_frames() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Multiply(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        server
+            .bidi_streaming_with_control(msg, chan, (), |_, Multiply(x), frames| {
+                // multiply the updates and acknowledge control frames
+                frames.map(move |frame| match frame {
+                    Frame::Data(MultiplyUpdate(y)) => {
+                        Frame::Data(MultiplyResponse((x * y) as u128))
+                    }
+                    Frame::Control(frame) => Frame::Control(ControlFrame {
+                        code: frame.code + 1,
+                        payload: frame.payload,
+                    }),
+                })
+            })
+            .await?;
+        anyhow::Ok(())
+    });
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let (mut send, recv) = client.bidi_with_control(Multiply(2)).await?;
+    send.send(MultiplyUpdate(1)).await?;
+    send.send_control(ControlFrame {
+        code: 7,
+        payload: b"ping".to_vec(),
+    })
+    .await?;
+    send.send(MultiplyUpdate(2)).await?;
+    drop(send);
+    let res = recv.try_collect::<Vec<_>>().await?;
+    assert_eq!(res.len(), 3);
+    assert!(matches!(res[0], Frame::Data(MultiplyResponse(2))));
+    assert!(
+        matches!(&res[1], Frame::Control(ControlFrame { code: 8, payload }) if payload == b"ping")
+    );
+    assert!(matches!(res[2], Frame::Data(MultiplyResponse(4))));
+    server_handle.await??;
+    Ok(())
+}
+
+/// a typed client and server pair can be created directly from the service type
+#[tokio::test]
+async fn mem_channel_service_connection() -> anyhow::Result<()> {
+    let (client, server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    // several clones of the client share the connection to the single server
+    let res = futures::future::try_join_all((0..10u64).map(|i| {
+        let client = client.clone();
+        async move { client.rpc(Sqr(i)).await }
+    }))
+    .await?;
+    assert_eq!(res[9], SqrResponse(81));
+    drop(client);
+    match server_handle.await? {
+        Err(RpcServerError::AcceptBiError(_)) => {}
+        e => panic!("unexpected termination result {:?}", e),
+    }
+    Ok(())
+}
+
+/// a call to a hung server fails with a timeout error
+#[tokio::test]
+async fn mem_channel_rpc_with_timeout() -> anyhow::Result<()> {
+    let (client, server) = mem::service_connection::<ComputeService>(1);
+    let router = FunctionRouter::new().on(|Sqr(x)| async move {
+        if x == 0 {
+            futures::future::pending::<()>().await;
+        }
+        SqrResponse(x as u128 * x as u128)
+    });
+    let server_handle = tokio::task::spawn(router.serve(server));
+    let timeout = Duration::from_millis(50);
+    let err = client.rpc_with_timeout(Sqr(0), timeout).await.unwrap_err();
+    assert!(matches!(err, RpcClientError::Timeout));
+    assert!(err.is_retryable());
+    assert_eq!(
+        client.rpc_with_timeout(Sqr(2), timeout).await?,
+        SqrResponse(4)
+    );
+    drop(client);
+    match server_handle.await? {
+        Err(RpcServerError::AcceptBiError(_)) => {}
+        e => panic!("unexpected termination result {:?}", e),
+    }
+    Ok(())
+}
+
+/// a custom dispatch loop can accept the raw stream and read the first message itself
+#[tokio::test]
+async fn mem_channel_accept_raw() -> anyhow::Result<()> {
+    let (client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let (send, mut recv) = server.accept_raw().await?;
+        match recv.next().await {
+            Some(Ok(ComputeRequest::Sqr(msg))) => {
+                server
+                    .rpc(msg, (send, recv), (), |_, Sqr(x)| async move {
+                        SqrResponse(x as u128 * x as u128)
+                    })
+                    .await?
+            }
+            req => anyhow::bail!("unexpected request {:?}", req),
+        }
+        anyhow::Ok(())
+    });
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    server_handle.await??;
+    Ok(())
+}
+
+/// the typed client calls the methods with the pattern of their message
+#[tokio::test]
+async fn mem_channel_typed_client() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    let mut client = ComputeClient::new(RpcClient::<ComputeService, MemChannelTypes>::new(client));
+    assert_eq!(client.sqr(Sqr(3)).await?, SqrResponse(9));
+    let (mut send, recv) = client.sum(Sum).await?;
+    send.send(SumUpdate(1)).await?;
+    send.send(SumUpdate(2)).await?;
+    drop(send);
+    assert_eq!(recv.await?, SumResponse(3));
+    let res = client.fibonacci(Fibonacci(5)).await?;
+    let res = res.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(res, vec![0, 1, 1, 2, 3]);
+    let res = client
+        .fibonacci_from(ResumeFrom::start(Fibonacci(5)))
+        .await?;
+    assert_eq!(res.try_collect::<Vec<_>>().await?.len(), 5);
+    let (mut send, recv) = client.multiply(Multiply(2)).await?;
+    send.send(MultiplyUpdate(3)).await?;
+    drop(send);
+    let res = recv.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(res, vec![6]);
+    drop(client);
+    server_handle.abort();
+    Ok(())
+}
+
+/// service declared with the macro, reusing the messages of the compute service
+#[derive(Debug, Clone)]
+struct DeclaredService;
+
+quic_rpc::declare_service! {
+    DeclaredService: ComputeRequest => ComputeResponse;
+    rpc Sqr -> SqrResponse: sqr;
+    server_streaming Fibonacci -> FibonacciResponse: fibonacci;
+    bidi_streaming Multiply(MultiplyUpdate) -> MultiplyResponse: multiply;
+}
+
+impl DeclaredService {
+    async fn sqr(self, Sqr(x): Sqr) -> SqrResponse {
+        SqrResponse(x as u128 * x as u128)
+    }
+
+    fn fibonacci(self, req: Fibonacci) -> impl futures::Stream<Item = FibonacciResponse> {
+        ComputeService.fibonacci(req)
+    }
+
+    fn multiply(
+        self,
+        Multiply(x): Multiply,
+        updates: impl futures::Stream<Item = MultiplyUpdate>,
+    ) -> impl futures::Stream<Item = MultiplyResponse> {
+        updates.map(move |MultiplyUpdate(y)| MultiplyResponse(x as u128 * y as u128))
+    }
+}
+
+/// the dispatch function generated by the macro calls the right handler
+#[tokio::test]
+async fn mem_channel_declare_service() -> anyhow::Result<()> {
+    let (mut client, server) = mem::service_connection::<DeclaredService>(1);
+    let server_handle = tokio::task::spawn(server.serve(|server, req, chan| async move {
+        DeclaredService.dispatch(&server, req, chan).await
+    }));
+    assert_eq!(client.rpc(Sqr(5)).await?, SqrResponse(25));
+    let res = client
+        .server_streaming(Fibonacci(5))
+        .await?
+        .map_ok(|x| x.0)
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert_eq!(res, [0, 1, 1, 2, 3]);
+    let (mut send, recv) = client.bidi(Multiply(3)).await?;
+    send.send(MultiplyUpdate(2)).await?;
+    let res = bidi_drain(send, recv).await?;
+    assert_eq!(res[0].0, 6);
+    // not declared, so the server closes the stream
+    let err = client.reflect().await.unwrap_err();
+    assert!(matches!(err, RpcClientError::EarlyClose));
+    drop(client);
+    match server_handle.await? {
+        Err(RpcServerError::AcceptBiError(_)) => {}
+        e => panic!("unexpected termination result {:?}", e),
+    }
+    Ok(())
+}
+
+/// the macro derives the interaction patterns from the declared messages
+#[test]
+fn declare_service_interaction() {
+    type S = DeclaredService;
+    let kind = |req: ComputeRequest| <S as Service>::interaction(&req);
+    assert_eq!(kind(Sqr(1).into()), Some(PatternKind::Rpc));
+    assert_eq!(
+        kind(Fibonacci(1).into()),
+        Some(PatternKind::ServerStreaming)
+    );
+    assert_eq!(kind(Multiply(1).into()), Some(PatternKind::BidiStreaming));
+    // updates and undeclared requests do not start an interaction
+    assert_eq!(kind(MultiplyUpdate(1).into()), None);
+    assert_eq!(kind(Sum.into()), None);
+}
+
+/// hand-written services opt out of classification by keeping the default
+#[tokio::test]
+async fn mem_channel_interaction_opt_out() -> anyhow::Result<()> {
+    #[derive(Debug, Clone)]
+    struct Unclassified;
+    impl Service for Unclassified {
+        type Req = ComputeRequest;
+        type Res = ComputeResponse;
+    }
+    impl quic_rpc::message::RpcMsg<Unclassified> for Sqr {
+        type Response = SqrResponse;
+    }
+    let (client, mut server) = mem::service_connection::<Unclassified>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let res = server.accept_one_classified().await;
+        assert!(matches!(res, Err(RpcServerError::UnexpectedStartMessage)));
+    });
+    assert!(client.rpc(Sqr(2)).await.is_err());
+    server_handle.await?;
+    Ok(())
+}
+
+/// a dispatch loop stops cleanly when cancelled, while accepted requests keep running
+#[tokio::test]
+async fn mem_channel_accept_one_or_cancel() -> anyhow::Result<()> {
+    let (client, mut server) = mem::service_connection::<ComputeService>(1);
+    let (cancel_send, cancel_recv) = futures::channel::oneshot::channel::<()>();
+    let server_handle = tokio::task::spawn(async move {
+        let cancel = cancel_recv.map(|_| ()).shared();
+        let mut handlers = Vec::new();
+        while let Some((req, chan)) = server.accept_one_or_cancel(cancel.clone()).await? {
+            let ComputeRequest::Sqr(msg) = req else {
+                anyhow::bail!("unexpected request {:?}", req);
+            };
+            let server = server.clone();
+            handlers.push(tokio::task::spawn(async move {
+                server
+                    .rpc(msg, chan, (), |_, Sqr(x)| async move {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        SqrResponse(x as u128 * x as u128)
+                    })
+                    .await
+            }));
+        }
+        anyhow::Ok(handlers)
+    });
+    let call = tokio::task::spawn(async move { client.rpc(Sqr(7)).await });
+    // wait until the request has been accepted, then shut down
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    cancel_send.send(()).ok();
+    let handlers = server_handle.await??;
+    assert_eq!(handlers.len(), 1);
+    // the in flight request still completes
+    assert_eq!(call.await??, SqrResponse(49));
+    Ok(())
+}
+
+/// a response of the wrong type is reported with the expected type and the variant that arrived
+#[tokio::test]
+async fn mem_channel_downcast_error() -> anyhow::Result<()> {
+    let (client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let (mut send, _recv) = server.accept_one().await?.1;
+        send.send(SumResponse(0).into()).await?;
+        anyhow::Ok(())
+    });
+    let err = client.rpc(Sqr(3)).await.unwrap_err();
+    let RpcClientError::DowncastError(unexpected) = &err else {
+        panic!("unexpected error {:?}", err);
+    };
+    assert!(unexpected.expected.ends_with("SqrResponse"));
+    assert_eq!(unexpected.received, Some("SumResponse"));
+    assert!(err.to_string().contains("SqrResponse"));
+    assert!(err.to_string().contains("unexpected response SumResponse"));
+    server_handle.await??;
+    Ok(())
+}
+
+/// a handler that misses its deadline is replaced by the designated response
+#[tokio::test]
+async fn mem_channel_server_deadline() -> anyhow::Result<()> {
+    let (mut client, mut server) = mem::service_connection::<ComputeService>(1);
+    let deadline = Duration::from_millis(50);
+    let server_handle = tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Sqr(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        let res = server
+            .rpc_with_deadline(
+                msg,
+                chan,
+                (),
+                |_, _| futures::future::pending(),
+                deadline,
+                || SqrResponse(0),
+            )
+            .await;
+        assert!(matches!(res, Err(RpcServerError::DeadlineExceeded)));
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Fibonacci(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        let res = server
+            .server_streaming_with_deadline(
+                msg,
+                chan,
+                (),
+                |_, _| {
+                    futures::stream::once(async { FibonacciResponse(1) })
+                        .chain(futures::stream::pending())
+                },
+                deadline,
+                || FibonacciResponse(u128::MAX),
+            )
+            .await;
+        assert!(matches!(res, Err(RpcServerError::DeadlineExceeded)));
+        anyhow::Ok(())
+    });
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(0));
+    let res = client
+        .server_streaming(Fibonacci(10))
+        .await?
+        .map_ok(|x| x.0)
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert_eq!(res, [1, u128::MAX]);
+    server_handle.await??;
+    Ok(())
+}
+
+/// items of a response stream can be read without waiting, and the request stays alive
+#[tokio::test]
+async fn mem_channel_poll_stream() -> anyhow::Result<()> {
+    let (mut client, mut server) = mem::service_connection::<ComputeService>(1);
+    let (items, items_rx) = flume::bounded::<FibonacciResponse>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Fibonacci(req) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        server
+            .server_streaming(req, chan, ComputeService, move |_, _| {
+                items_rx.into_stream()
+            })
+            .await?;
+        anyhow::Ok(())
+    });
+    let mut s = PollStream::new(client.server_streaming(Fibonacci(0)).await?);
+    assert_eq!(s.try_recv().unwrap_err(), TryRecvError::Empty);
+    items.send_async(FibonacciResponse(1)).await?;
+    let item = loop {
+        match s.try_recv() {
+            Ok(item) => break item?,
+            Err(TryRecvError::Empty) => tokio::time::sleep(Duration::from_millis(1)).await,
+            Err(TryRecvError::Closed) => anyhow::bail!("stream closed early"),
+        }
+    };
+    assert_eq!(item.0, 1);
+    // awaiting still works after non-blocking reads
+    items.send_async(FibonacciResponse(2)).await?;
+    assert_eq!(s.next().await.transpose()?.map(|x| x.0), Some(2));
+    drop(items);
+    assert!(s.next().await.is_none());
+    assert_eq!(s.try_recv().unwrap_err(), TryRecvError::Closed);
+    server_handle.await??;
+    Ok(())
+}
+
+/// notifications on mem channels arrive through accept_one and don't get a response
+#[tokio::test]
+async fn mem_channel_notify() -> anyhow::Result<()> {
+    let (client, mut server) = mem::service_connection::<ComputeService>(1);
+    let (received, received_rx) = flume::unbounded();
+    let server_handle = tokio::task::spawn(async move {
+        let (req, _chan) = server.accept_one().await?;
+        let ComputeRequest::Notification(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        server
+            .notify(msg, (), |_, Notification(x)| async move {
+                received.send(x).ok();
+            })
+            .await?;
+        anyhow::Ok(())
+    });
+    client.notify(Notification(42)).await?;
+    server_handle.await??;
+    assert_eq!(received_rx.recv_async().await?, 42);
+    Ok(())
+}
+
+/// idempotent rpcs are retried if the server closes the stream without answering
+#[tokio::test]
+async fn mem_channel_retrying_client() -> anyhow::Result<()> {
+    let (client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        // drop the first two requests without answering
+        for _ in 0..2 {
+            server.accept_one().await?;
+        }
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Sqr(req) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        server
+            .rpc(req, chan, (), |_, Sqr(x)| async move {
+                SqrResponse(x as u128 * x as u128)
+            })
+            .await?;
+        // and the one after that, too
+        server.accept_one().await?;
+        anyhow::Ok(())
+    });
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(1),
+        jitter: Duration::from_millis(1),
+    };
+    let retrying = RetryingClient::new(client.clone(), policy);
+    assert_eq!(retrying.rpc(Sqr(3)).await?, SqrResponse(9));
+    // a single attempt is not retried
+    let policy = RetryPolicy {
+        max_attempts: 1,
+        ..policy
+    };
+    let res = RetryingClient::new(client, policy).rpc(Sqr(3)).await;
+    assert!(matches!(res, Err(RpcClientError::EarlyClose)));
+    server_handle.await??;
+    Ok(())
+}
+
+/// errors of a response stream can be mapped to an application error type
+#[tokio::test]
+async fn mem_channel_into_app_errors() -> anyhow::Result<()> {
+    #[derive(Debug, PartialEq)]
+    enum AppError {
+        Unexpected,
+        Transport,
+    }
+
+    let (mut client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let (_, (mut send, _recv)) = server.accept_one().await?;
+        send.send(FibonacciResponse(1).into()).await?;
+        // not a valid response for a fibonacci request
+        send.send(SqrResponse(4).into()).await?;
+        anyhow::Ok(())
+    });
+    let items = client
+        .server_streaming(Fibonacci(2))
+        .await?
+        .into_app_errors(|e| match e {
+            StreamingResponseItemError::DowncastError(_) => AppError::Unexpected,
+            StreamingResponseItemError::RecvError(_)
+            | StreamingResponseItemError::KeepaliveTimeout
+            | StreamingResponseItemError::Timeout => AppError::Transport,
+        })
+        .map(|item| item.map(|x| x.0))
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(items, vec![Ok(1), Err(AppError::Unexpected)]);
+    server_handle.await??;
+    Ok(())
+}
+
+/// a bounded update sink stops accepting updates when the server does not read them
+#[tokio::test]
+async fn mem_channel_client_streaming_bounded() -> anyhow::Result<()> {
+    let (mut client, mut server) = mem::service_connection::<ComputeService>(1);
+    let (start, start_rx) = tokio::sync::oneshot::channel::<()>();
+    let server_handle = tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Sum(req) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        // don't read any updates until told to
+        start_rx.await?;
+        server
+            .client_streaming(req, chan, (), |_, _, updates| async move {
+                SumResponse(
+                    updates
+                        .fold(0, |sum, SumUpdate(x)| async move { sum + x as u128 })
+                        .await,
+                )
+            })
+            .await?;
+        anyhow::Ok(())
+    });
+    let (mut send, recv) = client.client_streaming_bounded(Sum, 4).await?;
+    let mut sent = 0;
+    while tokio::time::timeout(Duration::from_millis(50), send.feed(SumUpdate(1)))
+        .await
+        .is_ok()
+    {
+        sent += 1;
+        assert!(sent <= 1000, "no backpressure");
+    }
+    // the channel buffer of the mem stream, one update being sent, and the buffer of the sink
+    assert!(sent <= 128 + 1 + 4);
+    start.send(()).ok();
+    for _ in sent..1000 {
+        send.feed(SumUpdate(1)).await?;
+    }
+    send.close().await?;
+    drop(send);
+    assert_eq!(recv.await?, SumResponse(1000));
+    server_handle.await??;
+    Ok(())
+}
+
+/// closing the update sink ends the updates, without having to drop the sink
+#[tokio::test]
+async fn mem_channel_client_streaming_close() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let (mut send, recv) = client.client_streaming(Sum).await?;
+    send.send(SumUpdate(1)).await?;
+    send.send(SumUpdate(2)).await?;
+    send.close().await?;
+    let res = tokio::time::timeout(Duration::from_secs(1), recv).await??;
+    assert_eq!(res, SumResponse(3));
+    assert!(send.send(SumUpdate(3)).await.is_err());
+    server_handle.abort();
+    Ok(())
+}
+
+/// accept_one_classified dispatches on the pattern of the request
+#[tokio::test]
+async fn mem_channel_accept_one_classified() -> anyhow::Result<()> {
+    let (mut client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let mut kinds = Vec::new();
+        loop {
+            let (req, kind, chan) = match server.accept_one_classified().await {
+                Ok(x) => x,
+                Err(RpcServerError::AcceptBiError(_)) => break,
+                Err(e) => return Err(e.into()),
+            };
+            kinds.push(kind);
+            match (req, kind) {
+                (ComputeRequest::Sqr(msg), PatternKind::Rpc) => {
+                    server
+                        .rpc(msg, chan, (), |_, Sqr(x)| async move {
+                            SqrResponse(x as u128 * x as u128)
+                        })
+                        .await?
+                }
+                (ComputeRequest::Fibonacci(msg), PatternKind::ServerStreaming) => {
+                    server
+                        .server_streaming(msg, chan, (), |_, Fibonacci(n)| {
+                            futures::stream::iter((0..n).map(|i| FibonacciResponse(i as u128)))
+                        })
+                        .await?
+                }
+                (req, kind) => anyhow::bail!("unexpected request {:?} {:?}", req, kind),
+            }
+        }
+        anyhow::Ok(kinds)
+    });
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    let fib = client.server_streaming(Fibonacci(3)).await?;
+    assert_eq!(fib.try_collect::<Vec<_>>().await?.len(), 3);
+    drop(client);
+    let kinds = server_handle.await??;
+    assert_eq!(kinds, [PatternKind::Rpc, PatternKind::ServerStreaming]);
+    assert_eq!(
+        ComputeService::interaction(&SumUpdate(1).into()),
+        None,
+        "updates don't start an interaction"
+    );
+    Ok(())
+}
+
+/// cancelling a streaming interaction aborts the handler on the server
+#[tokio::test]
+async fn mem_channel_cancel() -> anyhow::Result<()> {
+    let (mut client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let mut results = Vec::new();
+        for _ in 0..2 {
+            let (req, chan) = server.accept_one().await?;
+            let res = match req {
+                ComputeRequest::Multiply(msg) => {
+                    server
+                        .bidi_streaming(msg, chan, (), |_, Multiply(x), updates| {
+                            updates.map(move |MultiplyUpdate(y)| {
+                                MultiplyResponse(x as u128 * y as u128)
+                            })
+                        })
+                        .await
+                }
+                ComputeRequest::Fibonacci(msg) => {
+                    server
+                        .server_streaming(msg, chan, (), |_, _| {
+                            futures::stream::repeat_with(|| FibonacciResponse(1))
+                        })
+                        .await
+                }
+                req => anyhow::bail!("unexpected request {:?}", req),
+            };
+            results.push(res);
+        }
+        anyhow::Ok(results)
+    });
+    let (mut updates, mut responses) = client.bidi(Multiply(2)).await?;
+    updates.send(MultiplyUpdate(3)).await?;
+    assert_eq!(responses.next().await.unwrap()?.0, 6);
+    updates.cancel().await?;
+    assert!(responses.next().await.is_none());
+    // the server would send fibonacci responses forever
+    let (controller, mut responses) = client.server_streaming_controlled(Fibonacci(0)).await?;
+    responses.next().await.unwrap()?;
+    controller.cancel().await?;
+    while let Some(item) = responses.next().await {
+        item?;
+    }
+    let results = server_handle.await??;
+    assert!(matches!(
+        results[..],
+        [
+            Err(RpcServerError::Cancelled),
+            Err(RpcServerError::Cancelled)
+        ]
+    ));
+    Ok(())
+}
+
+/// call picks the interaction pattern from the message type
+#[tokio::test]
+async fn mem_channel_call() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    assert_eq!(client.call(Sqr(4)).await?, SqrResponse(16));
+    let fib = client.call(Fibonacci(5)).await?;
+    let fib = fib.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(fib, [0, 1, 1, 2, 3]);
+    let (mut send, res) = client.call(Sum).await?;
+    send.send(SumUpdate(1)).await?;
+    send.send(SumUpdate(2)).await?;
+    drop(send);
+    assert_eq!(res.await?, SumResponse(3));
+    let (mut send, recv) = client.call(Multiply(2)).await?;
+    send.send(MultiplyUpdate(3)).await?;
+    let res = bidi_drain(send, recv).await?;
+    assert_eq!(res.into_iter().map(|x| x.0).collect::<Vec<_>>(), [6]);
+    server_handle.abort();
+    Ok(())
+}
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn mem_channel_metrics() -> anyhow::Result<()> {
+    use quic_rpc::metrics::AtomicMetrics;
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    let metrics = Arc::new(AtomicMetrics::new());
+    let mut client =
+        RpcClient::<ComputeService, MemChannelTypes>::new(client).with_metrics(metrics.clone());
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    assert_eq!(client.rpc(Sqr(4)).await?, SqrResponse(16));
+    let fib = client.server_streaming(Fibonacci(3)).await?;
+    let fib = fib.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(fib, [0, 1, 1]);
+    let (_controller, fib) = client.server_streaming_controlled(Fibonacci(2)).await?;
+    let fib = fib.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(fib, [0, 1]);
+    // dropping the stream before it ends counts as cancelled
+    let mut fib = client.server_streaming(Fibonacci(10)).await?;
+    fib.next().await.unwrap()?;
+    drop(fib);
+    let rpc = metrics.get(PatternKind::Rpc);
+    assert_eq!((rpc.started, rpc.ok, rpc.err, rpc.cancelled), (2, 2, 0, 0));
+    let streaming = metrics.get(PatternKind::ServerStreaming);
+    assert_eq!(
+        (
+            streaming.started,
+            streaming.ok,
+            streaming.err,
+            streaming.cancelled
+        ),
+        (3, 2, 0, 1)
+    );
+    let (mut send, recv) = client.bidi_with_control(Multiply(2)).await?;
+    send.send(MultiplyUpdate(3)).await?;
+    drop(send);
+    assert_eq!(recv.try_collect::<Vec<_>>().await?.len(), 1);
+    let bidi = metrics.get(PatternKind::BidiStreaming);
+    assert_eq!(
+        (bidi.started, bidi.ok, bidi.err, bidi.cancelled),
+        (1, 1, 0, 0)
+    );
+    server_handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn mem_channel_into_io() -> anyhow::Result<()> {
+    async fn sqr(client: &RpcClient<ComputeService, MemChannelTypes>) -> std::io::Result<u128> {
+        Ok(client.rpc(Sqr(3)).await?.0)
+    }
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    assert_eq!(sqr(&client).await?, 9);
+    server_handle.abort();
+    let _ = server_handle.await;
+    let err = sqr(&client).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotConnected);
+    Ok(())
+}
+
+/// the faulty channel delays, drops and closes responses as configured
+#[tokio::test]
+async fn mem_channel_faulty() -> anyhow::Result<()> {
+    type C = FaultyChannelTypes<MemChannelTypes>;
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    let faulty =
+        |config| RpcClient::<ComputeService, C>::new(testing::Channel::new(client.clone(), config));
+    // delays
+    let config = FaultConfig {
+        delay: Duration::from_millis(50),
+        jitter: Duration::from_millis(10),
+        ..Default::default()
+    };
+    let t0 = std::time::Instant::now();
+    assert_eq!(faulty(config).rpc(Sqr(3)).await?, SqrResponse(9));
+    assert!(t0.elapsed() >= Duration::from_millis(50));
+    // dropped responses, the server closes the stream after the response
+    let config = FaultConfig {
+        drop_probability: 1.0,
+        ..Default::default()
+    };
+    let res = faulty(config).rpc(Sqr(3)).await;
+    assert!(matches!(res, Err(RpcClientError::EarlyClose)));
+    // the first response ends the stream, so a retry succeeds
+    let config = FaultConfig {
+        close_on: Some(1),
+        ..Default::default()
+    };
+    let res = faulty(config.clone()).rpc(Sqr(3)).await;
+    assert!(matches!(res, Err(RpcClientError::EarlyClose)));
+    let policy = RetryPolicy {
+        max_attempts: 2,
+        base_delay: Duration::from_millis(1),
+        jitter: Duration::from_millis(1),
+    };
+    let retrying = RetryingClient::new(faulty(config), policy);
+    assert_eq!(retrying.rpc(Sqr(3)).await?, SqrResponse(9));
+    // the same seed drops the same responses
+    let dropped = |seed| {
+        let client = faulty(FaultConfig {
+            seed,
+            drop_probability: 0.5,
+            ..Default::default()
+        });
+        async move {
+            let mut dropped = Vec::new();
+            for i in 0..16 {
+                dropped.push(client.rpc(Sqr(i)).await.is_err());
+            }
+            dropped
+        }
+    };
+    let a = dropped(1).await;
+    assert_eq!(a, dropped(1).await);
+    assert!(a.contains(&true) && a.contains(&false));
+    server_handle.abort();
+    Ok(())
+}
+
+/// an application error ends the stream and is distinct from transport errors
+#[tokio::test]
+async fn mem_channel_try_server_streaming() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let items = client
+        .try_server_streaming(CheckedFibonacci { n: 5, max: 10 })
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    let items = items
+        .into_iter()
+        .map(|x| x.map(|x| x.0))
+        .collect::<Vec<_>>();
+    assert_eq!(items, [Ok(0), Ok(1), Ok(1), Ok(2), Ok(3)]);
+    let items = client
+        .try_server_streaming(CheckedFibonacci { n: 20, max: 10 })
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    let items = items
+        .into_iter()
+        .map(|x| x.map(|x| x.0))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        items,
+        [
+            Ok(0),
+            Ok(1),
+            Ok(1),
+            Ok(2),
+            Ok(3),
+            Ok(5),
+            Ok(8),
+            Err(FibonacciOverflow(13))
+        ]
+    );
+    server_handle.abort();
+    Ok(())
+}
+
+/// a gateway service that embeds the compute service
+#[derive(Debug, Clone)]
+struct GatewayService;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+enum GatewayRequest {
+    Compute(ComputeRequest),
+    Ping,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+enum GatewayResponse {
+    Compute(ComputeResponse),
+    Pong,
+}
+
+impl Service for GatewayService {
+    type Req = GatewayRequest;
+    type Res = GatewayResponse;
+}
+
+/// the math service can be routed through an outer service on both sides
+#[tokio::test]
+async fn mem_channel_map_service() -> anyhow::Result<()> {
+    use quic_rpc::mapped::{self, MappedChannelTypes};
+    let (client, server) = mem::connection::<GatewayResponse, GatewayRequest>(1);
+    let server = mapped::Channel::<MemChannelTypes, _, _, _, _>::new(
+        server,
+        GatewayResponse::Compute,
+        |req| match req {
+            GatewayRequest::Compute(req) => Some(req),
+            GatewayRequest::Ping => None,
+        },
+    );
+    let server =
+        RpcServer::<ComputeService, MappedChannelTypes<MemChannelTypes, _, _>>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    let client = RpcClient::<GatewayService, MemChannelTypes>::new(client);
+    let mut client =
+        client.map_service::<ComputeService>(GatewayRequest::Compute, |res| match res {
+            GatewayResponse::Compute(res) => Some(res),
+            GatewayResponse::Pong => None,
+        });
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    let fib = client.server_streaming(Fibonacci(5)).await?;
+    let fib = fib.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(fib, [0, 1, 1, 2, 3]);
+    server_handle.abort();
+    Ok(())
+}
+
+/// a batch of requests on one stream gets the responses in order
+#[tokio::test]
+async fn mem_channel_rpc_batch() -> anyhow::Result<()> {
+    let (client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        for _ in 0..3 {
+            let (req, chan) = server.accept_one().await?;
+            let ComputeRequest::Sqr(req) = req else {
+                anyhow::bail!("unexpected request {:?}", req);
+            };
+            server
+                .rpc_pipeline(req, chan, (), |_, Sqr(x)| async move {
+                    SqrResponse(x as u128 * x as u128)
+                })
+                .await?;
+        }
+        anyhow::Ok(())
+    });
+    // more requests than fit into the buffers of the stream
+    let res = client.rpc_batch((0..1000).map(Sqr).collect()).await?;
+    let expected = (0..1000u128)
+        .map(|x| SqrResponse(x * x))
+        .collect::<Vec<_>>();
+    assert_eq!(res, expected);
+    assert_eq!(client.rpc_batch(vec![Sqr(2)]).await?, [SqrResponse(4)]);
+    // a single rpc call is handled as well
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    assert!(client.rpc_batch(Vec::<Sqr>::new()).await?.is_empty());
+    server_handle.await??;
+    Ok(())
+}
+
+/// A channel type whose streams are not [Unpin], wrapping mem channels
+mod pinned {
+    use futures::{future::BoxFuture, FutureExt, Stream, TryFutureExt};
+    use pin_project::pin_project;
+    use quic_rpc::{mem, ChannelTypes, RpcMessage};
+    use std::{
+        marker::PhantomPinned,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    #[pin_project]
+    pub struct RecvStream<M: RpcMessage> {
+        #[pin]
+        inner: mem::RecvStream<M>,
+        _pinned: PhantomPinned,
+    }
+
+    impl<M: RpcMessage> Stream for RecvStream<M> {
+        type Item = Result<M, mem::RecvError>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            self.project().inner.poll_next(cx)
+        }
+    }
+
+    type Socket<In, Out> = (mem::SendSink<Out>, RecvStream<In>);
+
+    fn wrap<In: RpcMessage, Out: RpcMessage>(
+        (send, recv): (mem::SendSink<Out>, mem::RecvStream<In>),
+    ) -> Socket<In, Out> {
+        let recv = RecvStream {
+            inner: recv,
+            _pinned: PhantomPinned,
+        };
+        (send, recv)
+    }
+
+    pub struct Channel<In: RpcMessage, Out: RpcMessage>(pub mem::Channel<In, Out>);
+
+    impl<In: RpcMessage, Out: RpcMessage> Clone for Channel<In, Out> {
+        fn clone(&self) -> Self {
+            Self(self.0.clone())
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct PinnedChannelTypes;
+
+    impl ChannelTypes for PinnedChannelTypes {
+        type SendSink<M: RpcMessage> = mem::SendSink<M>;
+        type RecvStream<M: RpcMessage> = RecvStream<M>;
+        type SendError = mem::SendError;
+        type RecvError = mem::RecvError;
+        type OpenBiError = mem::OpenBiError;
+        type OpenBiFuture<'a, In: RpcMessage, Out: RpcMessage> =
+            BoxFuture<'a, Result<Socket<In, Out>, mem::OpenBiError>>;
+        type AcceptBiError = mem::AcceptBiError;
+        type AcceptBiFuture<'a, In: RpcMessage, Out: RpcMessage> =
+            BoxFuture<'a, Result<Socket<In, Out>, mem::AcceptBiError>>;
+        type Channel<In: RpcMessage, Out: RpcMessage> = Channel<In, Out>;
+    }
+
+    impl<In: RpcMessage, Out: RpcMessage> quic_rpc::Channel<In, Out, PinnedChannelTypes>
+        for Channel<In, Out>
+    {
+        fn open_bi(&self) -> BoxFuture<'_, Result<Socket<In, Out>, mem::OpenBiError>> {
+            self.0.open_bi().map_ok(wrap).boxed()
+        }
+
+        fn accept_bi(&self) -> BoxFuture<'_, Result<Socket<In, Out>, mem::AcceptBiError>> {
+            self.0.accept_bi().map_ok(wrap).boxed()
+        }
+
+        fn close(&self, code: u32, reason: &[u8]) {
+            self.0.close(code, reason)
+        }
+    }
+}
+
+#[tokio::test]
+async fn mem_channel_not_unpin() -> anyhow::Result<()> {
+    type C = pinned::PinnedChannelTypes;
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, C>::new(pinned::Channel(server));
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    smoke_test::<C>(pinned::Channel(client)).await?;
+    server_handle.abort();
+    Ok(())
+}
+
+/// servers for different services are driven by one accept loop
+#[tokio::test]
+async fn mem_channel_multi_server() -> anyhow::Result<()> {
+    use quic_rpc::{server::MultiServer, Channel};
+    let (compute_client, compute_server) = mem::service_connection::<ComputeService>(1);
+    let (gateway_client, gateway_server) = mem::connection::<GatewayResponse, GatewayRequest>(1);
+    let gateway_server = RpcServer::<GatewayService, MemChannelTypes>::new(gateway_server);
+    let mut servers = MultiServer::<MemChannelTypes>::new();
+    servers.add("compute", compute_server, |server, req, chan| async move {
+        let ComputeRequest::Sqr(req) = req else {
+            return Err(RpcServerError::UnexpectedStartMessage);
+        };
+        server
+            .rpc(req, chan, (), |_, Sqr(x)| async move {
+                SqrResponse(x as u128 * x as u128)
+            })
+            .await
+    });
+    servers.add(
+        "gateway",
+        gateway_server,
+        |_, req, (mut send, _)| async move {
+            match req {
+                GatewayRequest::Ping => send
+                    .send(GatewayResponse::Pong)
+                    .await
+                    .map_err(RpcServerError::SendError),
+                GatewayRequest::Compute(_) => Err(RpcServerError::UnexpectedStartMessage),
+            }
+        },
+    );
+    assert_eq!(servers.len(), 2);
+    let tags = Arc::new(Mutex::new(Vec::new()));
+    let server_handle = tokio::task::spawn({
+        let tags = tags.clone();
+        async move {
+            while let Some((tag, res)) = servers.accept_any().await {
+                tags.lock().unwrap().push(tag);
+                if let Ok(handler) = res {
+                    tokio::spawn(handler);
+                }
+            }
+        }
+    });
+    assert_eq!(compute_client.rpc(Sqr(3)).await?, SqrResponse(9));
+    let (_send, mut recv) = gateway_client.open_bi_with(GatewayRequest::Ping).await?;
+    assert!(matches!(recv.next().await, Some(Ok(GatewayResponse::Pong))));
+    assert_eq!(compute_client.rpc(Sqr(4)).await?, SqrResponse(16));
+    // dropping the clients removes the servers, which ends the loop
+    drop(compute_client);
+    drop(gateway_client);
+    server_handle.await?;
+    let mut tags = tags.lock().unwrap().clone();
+    tags.sort();
+    assert_eq!(
+        tags,
+        ["compute", "compute", "compute", "gateway", "gateway"]
+    );
+    Ok(())
+}
+
+/// a deadline covers all calls made within its scope
+#[tokio::test]
+async fn mem_channel_deadline() -> anyhow::Result<()> {
+    use quic_rpc::client::Deadline;
+    let (client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        while let Ok((req, chan)) = server.accept_one().await {
+            let ComputeRequest::Sqr(req) = req else {
+                anyhow::bail!("unexpected request {:?}", req);
+            };
+            let server = server.clone();
+            tokio::spawn(async move {
+                server
+                    .rpc(req, chan, (), |_, Sqr(x)| async move {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        SqrResponse(x as u128 * x as u128)
+                    })
+                    .await
+            });
+        }
+        anyhow::Ok(())
+    });
+    assert!(Deadline::current().is_none());
+    let res = Deadline::scope(Duration::from_secs(5), client.rpc(Sqr(3))).await?;
+    assert_eq!(res, SqrResponse(9));
+    // an expired deadline fails without making the call
+    let res = Deadline::scope(Duration::ZERO, client.rpc(Sqr(3))).await;
+    assert!(matches!(res, Err(RpcClientError::Timeout)));
+    // the budget shrinks as the chain progresses, and nested scopes can't extend it
+    let res = Deadline::scope(Duration::from_millis(100), async {
+        let first = client.rpc(Sqr(2)).await?;
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let remaining = Deadline::current().unwrap().remaining();
+        assert!(remaining < Duration::from_millis(50));
+        let second = Deadline::scope(Duration::from_secs(5), client.rpc(Sqr(first.0 as u64))).await;
+        anyhow::Ok(second)
+    })
+    .await?;
+    assert!(matches!(res, Err(RpcClientError::Timeout)));
+    // an explicit timeout is limited by the deadline as well
+    let res = Deadline::scope(
+        Duration::from_millis(10),
+        client.rpc_with_timeout(Sqr(3), Duration::from_secs(5)),
+    )
+    .await;
+    assert!(matches!(res, Err(RpcClientError::Timeout)));
+    // so are calls with a priority, which is ignored by mem channels
+    let res = Deadline::scope(Duration::from_secs(5), client.rpc_with_priority(Sqr(4), 1)).await?;
+    assert_eq!(res, SqrResponse(16));
+    let res = Deadline::scope(Duration::ZERO, client.rpc_with_priority(Sqr(4), 1)).await;
+    assert!(matches!(res, Err(RpcClientError::Timeout)));
+    // a deadline beyond what the clock can represent never expires
+    let res = Deadline::scope(Duration::MAX, client.rpc(Sqr(5))).await?;
+    assert_eq!(res, SqrResponse(25));
+    let res = client.rpc_with_timeout(Sqr(6), Duration::MAX).await?;
+    assert_eq!(res, SqrResponse(36));
+    let res = Deadline::scope(
+        Duration::from_millis(10),
+        client.rpc_with_timeout(Sqr(3), Duration::MAX),
+    )
+    .await;
+    assert!(matches!(res, Err(RpcClientError::Timeout)));
+    server_handle.abort();
+    Ok(())
+}
+
+/// tagging messages is transparent if both sides agree on the message types
+#[tokio::test]
+async fn mem_channel_tagged() -> anyhow::Result<()> {
+    use quic_rpc::tagged::{self, TaggedChannelTypes};
+    type C = TaggedChannelTypes<MemChannelTypes>;
+    let (client, server) = mem::connection(1);
+    let server = RpcServer::<ComputeService, C>::new(tagged::Channel::new(server));
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    smoke_test::<C>(tagged::Channel::new(client)).await?;
+    server_handle.abort();
+    Ok(())
+}
+
+/// every stream gets the next id of the client, which the server learns from the first message
+#[cfg(feature = "tracing")]
+#[tokio::test]
+async fn mem_channel_correlated() -> anyhow::Result<()> {
+    use quic_rpc::{
+        correlated::{self, CorrelatedChannelTypes},
+        Channel,
+    };
+    type C = CorrelatedChannelTypes<MemChannelTypes>;
+    let (client, server) = mem::connection(1);
+    let client = correlated::Channel::<MemChannelTypes, u64, u64>::new(client);
+    let server = correlated::Channel::<MemChannelTypes, u64, u64>::new(server);
+    for expected in 0..2 {
+        let (mut send, mut recv) = client.open_bi().await?;
+        assert_eq!(C::correlation_id(&recv), Some(expected));
+        send.send(expected).await?;
+        let (mut server_send, mut server_recv) = server.accept_bi().await?;
+        assert_eq!(C::correlation_id(&server_recv), None);
+        assert_eq!(server_recv.next().await.transpose()?, Some(expected));
+        assert_eq!(server_recv.id(), Some(expected));
+        server_send.send(expected * 2).await?;
+        assert_eq!(recv.next().await.transpose()?, Some(expected * 2));
+    }
+    // rpc calls work as usual
+    let (client, server) = mem::connection(1);
+    let server = RpcServer::<ComputeService, C>::new(correlated::Channel::new(server));
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    smoke_test::<C>(correlated::Channel::new(client)).await?;
+    server_handle.abort();
+    Ok(())
+}
+
+/// requests with a wrong or missing token are rejected before the handler runs
+#[tokio::test]
+async fn mem_channel_auth_token() -> anyhow::Result<()> {
+    use quic_rpc::{
+        auth::{self, AuthChannelTypes},
+        server::RpcServerErrorKind,
+    };
+    type C = AuthChannelTypes<MemChannelTypes>;
+    let (client, server) = mem::connection(1);
+    let mut server = RpcServer::<ComputeService, C>::new(auth::Channel::new(server))
+        .with_authenticator(|token: Option<&[u8]>, _: &ComputeRequest| {
+            token == Some(&b"secret"[..])
+        });
+    let server_handle = tokio::task::spawn(async move {
+        let mut results = Vec::new();
+        for _ in 0..3 {
+            match server.accept_one().await {
+                Ok((req, chan)) => {
+                    let token = server.auth_token(&chan).map(|token| token.to_vec());
+                    let ComputeRequest::Sqr(msg) = req else {
+                        anyhow::bail!("unexpected request {:?}", req);
+                    };
+                    server
+                        .rpc(msg, chan, (), |_, Sqr(x)| async move {
+                            SqrResponse(x as u128 * x as u128)
+                        })
+                        .await?;
+                    results.push(Ok(token));
+                }
+                Err(cause) => results.push(Err(cause.kind())),
+            }
+        }
+        anyhow::Ok(results)
+    });
+    let client = auth::Channel::new(client);
+    let valid = RpcClient::<ComputeService, C>::new(client.clone().with_token(&b"secret"[..]));
+    assert_eq!(valid.rpc(Sqr(3)).await?, SqrResponse(9));
+    let wrong = RpcClient::<ComputeService, C>::new(client.clone().with_token(&b"guess"[..]));
+    assert!(wrong.rpc(Sqr(3)).await.is_err());
+    let missing = RpcClient::<ComputeService, C>::new(client);
+    assert!(missing.rpc(Sqr(3)).await.is_err());
+    assert_eq!(
+        server_handle.await??,
+        vec![
+            Ok(Some(b"secret".to_vec())),
+            Err(RpcServerErrorKind::Unauthorized),
+            Err(RpcServerErrorKind::Unauthorized),
+        ]
+    );
+    Ok(())
+}
+
+/// every call of a client with a default timeout inherits it, unless it is overridden
+#[tokio::test]
+async fn mem_channel_default_timeout() -> anyhow::Result<()> {
+    use quic_rpc::client::StreamingResponseItemError;
+    let (client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        loop {
+            let (req, chan) = server.accept_one().await?;
+            let server = server.clone();
+            tokio::task::spawn(async move {
+                match req {
+                    // answers slowly
+                    ComputeRequest::Sqr(msg) => {
+                        server
+                            .rpc(msg, chan, (), |_, Sqr(x)| async move {
+                                tokio::time::sleep(Duration::from_millis(200)).await;
+                                SqrResponse(x as u128 * x as u128)
+                            })
+                            .await
+                    }
+                    // stalls after the first response
+                    ComputeRequest::Fibonacci(msg) => {
+                        server
+                            .server_streaming(msg, chan, (), |_, _| {
+                                futures::stream::once(async { FibonacciResponse(1) })
+                                    .chain(futures::stream::pending())
+                            })
+                            .await
+                    }
+                    _ => Err(RpcServerError::UnexpectedStartMessage),
+                }
+            });
+        }
+        #[allow(unreachable_code)]
+        anyhow::Ok(())
+    });
+    let mut client = client
+        .with_default_timeout(Duration::from_millis(50))
+        .with_item_timeout(Duration::from_millis(50));
+    assert!(matches!(
+        client.rpc(Sqr(3)).await,
+        Err(RpcClientError::Timeout)
+    ));
+    assert_eq!(
+        client
+            .rpc_with_timeout(Sqr(3), Duration::from_secs(10))
+            .await?,
+        SqrResponse(9)
+    );
+    let items = client
+        .server_streaming(Fibonacci(10))
+        .await?
+        .map(|item| item.map(|x| x.0))
+        .collect::<Vec<_>>()
+        .await;
+    assert!(matches!(
+        items.as_slice(),
+        [Ok(1), Err(StreamingResponseItemError::Timeout)]
+    ));
+    server_handle.abort();
+    Ok(())
+}
+
+/// a message whose Serialize impl forgets a field, so it can not be deserialized
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+struct ForgetfulMessage {
+    a: u64,
+    b: u64,
+}
+
+impl serde::Serialize for ForgetfulMessage {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("ForgetfulMessage", 1)?;
+        state.serialize_field("a", &self.a)?;
+        state.end()
+    }
+}
+
+/// the serialized mem channel round-trips messages through the codec, so it catches broken
+/// serialization that the plain mem channel passes through
+#[tokio::test]
+async fn mem_channel_serialized() -> anyhow::Result<()> {
+    use quic_rpc::{
+        mem::{SerializedMemChannelTypes, SerializedRecvError},
+        Channel,
+    };
+    let msg = ForgetfulMessage { a: 1, b: 2 };
+    // the plain mem channel does not notice
+    let (server, client) = mem::connection::<ForgetfulMessage, ForgetfulMessage>(1);
+    let (mut send, _recv) = client.open_bi().await?;
+    send.send(msg.clone()).await?;
+    let (_send, mut recv) = server.accept_bi().await?;
+    assert_eq!(recv.next().await.transpose()?, Some(msg.clone()));
+    // the serialized mem channel fails like a network transport would
+    let (server, client) =
+        mem::serialized_connection::<ForgetfulMessage, ForgetfulMessage, BincodeCodec>(1);
+    let (mut send, _recv) = client.open_bi().await?;
+    send.send(msg).await?;
+    let (_send, mut recv) = server.accept_bi().await?;
+    let err = recv.next().await.unwrap().unwrap_err();
+    assert!(matches!(err, SerializedRecvError::Decode(_)));
+    assert_eq!(err.to_string(), "failed to deserialize the message");
+    assert!(std::error::Error::source(&err).is_some());
+    // rpc calls work as usual
+    type C = SerializedMemChannelTypes;
+    let (server, client) = mem::serialized_connection::<_, _, BincodeCodec>(1);
+    let server = RpcServer::<ComputeService, C>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    smoke_test::<C>(client).await?;
+    server_handle.abort();
+    Ok(())
+}
+
+/// a bidi handler can stop responding while the client is still sending updates
+#[tokio::test]
+async fn mem_channel_bidi_early_end() -> anyhow::Result<()> {
+    let (mut client, mut server) = mem::service_connection::<ComputeService>(1);
+    let (leak_send, leak_recv) = tokio::sync::oneshot::channel();
+    let server_handle = tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Multiply(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        server
+            .bidi_streaming(msg, chan, (), |_, Multiply(x), mut updates| {
+                async_stream::stream! {
+                    for _ in 0..3 {
+                        if let Some(MultiplyUpdate(y)) = updates.next().await {
+                            yield MultiplyResponse(x as u128 * y as u128);
+                        }
+                    }
+                    // keep the updates alive after the call
+                    let _ = leak_send.send(updates);
+                }
+            })
+            .await?;
+        anyhow::Ok(())
+    });
+    let (mut updates, responses) = client.bidi(Multiply(2)).await?;
+    let sender = tokio::task::spawn(async move {
+        let mut i = 0;
+        while updates.send(MultiplyUpdate(i)).await.is_ok() {
+            i += 1;
+        }
+        i
+    });
+    let responses = responses
+        .map(|x| x.map(|x| x.0))
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert_eq!(responses, [0, 2, 4]);
+    server_handle.await??;
+    // the updates of a completed call end, and the client can no longer send
+    let mut leaked = leak_recv.await?;
+    assert!(leaked.next().await.is_none());
+    assert!(sender.await? >= 3);
+    Ok(())
+}
+
+/// a second service, to compose with the compute service
+#[derive(Debug, Clone)]
+pub struct EchoService;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Echo(String);
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct EchoResponse(String);
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, derive_more::From, derive_more::TryInto)]
+pub enum EchoRequest {
+    Echo(Echo),
+}
+
+quic_rpc::declare_service! {
+    EchoService: EchoRequest => EchoResponse;
+    rpc Echo -> EchoResponse: echo;
+}
+
+impl EchoService {
+    async fn echo(self, Echo(text): Echo) -> EchoResponse {
+        EchoResponse(text)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AppService;
+
+quic_rpc::compose_service! {
+    AppService: AppRequest => AppResponse;
+    Compute(ComputeService),
+    Echo(EchoService),
+}
+
+/// requests of sub-services are wrapped in the envelope of the composed service
+#[tokio::test]
+async fn mem_channel_sub_service() -> anyhow::Result<()> {
+    let (client, mut server) = mem::service_connection::<AppService>(1);
+    let server_handle: tokio::task::JoinHandle<anyhow::Result<()>> =
+        tokio::task::spawn(async move {
+            loop {
+                let (req, chan) = server.accept_one().await?;
+                let (req, chan) = match server.route::<EchoService>(req, chan) {
+                    Ok((server, req, chan)) => {
+                        EchoService.dispatch(&server, req, chan).await?;
+                        continue;
+                    }
+                    Err(unrouted) => unrouted,
+                };
+                let Ok((server, req, chan)) = server.route::<ComputeService>(req, chan) else {
+                    unreachable!("all sub-services are routed");
+                };
+                match req {
+                    ComputeRequest::Sqr(msg) => {
+                        server
+                            .rpc(msg, chan, (), |_, Sqr(x)| async move {
+                                SqrResponse(x as u128 * x as u128)
+                            })
+                            .await?
+                    }
+                    ComputeRequest::Fibonacci(msg) => {
+                        server
+                            .server_streaming(msg, chan, ComputeService, ComputeService::fibonacci)
+                            .await?
+                    }
+                    req => anyhow::bail!("unexpected request {:?}", req),
+                }
+            }
+        });
+    let compute = client.sub::<ComputeService>();
+    let echo = client.sub::<EchoService>();
+    assert_eq!(compute.rpc(Sqr(3)).await?, SqrResponse(9));
+    let fib = client
+        .sub::<ComputeService>()
+        .server_streaming(Fibonacci(5))
+        .await?;
+    let fib = fib.map_ok(|x| x.0).try_collect::<Vec<_>>().await?;
+    assert_eq!(fib, [0, 1, 1, 2, 3]);
+    let res = echo.rpc(Echo("hello".to_string())).await?;
+    assert_eq!(res, EchoResponse("hello".to_string()));
+    drop((client, compute, echo));
+    assert!(server_handle.await?.is_err());
+    Ok(())
+}
+
+/// keepalives are ignored by the server, and report a dead server on a quiet stream
+#[tokio::test]
+async fn mem_channel_server_streaming_keepalive() -> anyhow::Result<()> {
+    let (mut client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Fibonacci(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        // a stream that produces data rarely
+        server
+            .server_streaming(msg, chan, (), |_, Fibonacci(n)| {
+                futures::stream::iter(0..n).then(|i| async move {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    FibonacciResponse(i as u128)
+                })
+            })
+            .await?;
+        // a zombie stream: the server neither responds nor reads
+        let (_send, recv) = server.accept_raw().await?;
+        drop(recv);
+        server.accept_raw().await?;
+        anyhow::Ok(())
+    });
+    let interval = Duration::from_millis(10);
+    let items = client
+        .server_streaming_with_keepalive(Fibonacci(3), interval)
+        .await?
+        .map_ok(|x| x.0)
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert_eq!(items, [0, 1, 2]);
+    let mut responses = client
+        .server_streaming_with_keepalive(Fibonacci(3), interval)
+        .await?;
+    assert!(matches!(
+        responses.next().await,
+        Some(Err(StreamingResponseItemError::KeepaliveTimeout))
+    ));
+    assert!(responses.next().await.is_none());
+    drop((responses, client));
+    assert!(server_handle.await?.is_err());
+    Ok(())
+}
+
+/// errors of different channel types can be classified by their kind
+#[tokio::test]
+async fn mem_channel_server_error_kind() -> anyhow::Result<()> {
+    use quic_rpc::{server::RpcServerErrorKind, Channel};
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    // an update is not a valid first message
+    let (mut send, _recv) = client.open_bi().await?;
+    send.send(ComputeRequest::SumUpdate(SumUpdate(1))).await?;
+    drop(client);
+    let err = server_handle.await?.unwrap_err();
+    assert_eq!(err.kind(), RpcServerErrorKind::UnexpectedStartMessage);
+    let err = RpcServerError::<LoggingChannelTypes<MemChannelTypes>>::EarlyClose;
+    assert_eq!(err.kind(), RpcServerErrorKind::EarlyClose);
+    assert_eq!(err.kind().to_string(), "EarlyClose");
+    Ok(())
+}
+
+/// the ack of a bidi call arrives before the client sends any updates
+#[tokio::test]
+async fn mem_channel_bidi_ack() -> anyhow::Result<()> {
+    let (mut client, mut server) = mem::service_connection::<ComputeService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Multiply(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        server
+            .bidi_streaming_with_ack(msg, chan, (), |_, Multiply(x), updates| {
+                let responses =
+                    updates.map(move |MultiplyUpdate(y)| MultiplyResponse(x as u128 * y as u128));
+                (MultiplyResponse(x as u128), responses)
+            })
+            .await?;
+        anyhow::Ok(())
+    });
+    let (mut send, mut recv) = client.bidi(Multiply(3)).await?;
+    assert_eq!(recv.next().await.unwrap()?.0, 3);
+    send.send(MultiplyUpdate(2)).await?;
+    assert_eq!(recv.next().await.unwrap()?.0, 6);
+    let rest = bidi_drain(send, recv).await?;
+    assert!(rest.is_empty());
+    server_handle.await??;
+    Ok(())
+}
+
+/// a service with a subscription to the events of a topic
+#[derive(Debug, Clone)]
+struct EventService;
+
+/// subscribe to the events whose topic starts with the given prefix
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct SubscribeTopic(String);
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct TopicEvent {
+    topic: String,
+    value: u64,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, derive_more::From, derive_more::TryInto)]
+enum EventRequest {
+    Subscribe(SubscribeTopic),
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, derive_more::From, derive_more::TryInto)]
+enum EventResponse {
+    Event(TopicEvent),
+}
+
+impl Service for EventService {
+    type Req = EventRequest;
+    type Res = EventResponse;
+}
+
+impl Msg<EventService> for SubscribeTopic {
+    type Update = Self;
+    type Response = TopicEvent;
+    type Pattern = ServerStreaming;
+}
+
+/// the server only sends matching events, and dropping the stream unsubscribes
+#[tokio::test]
+async fn mem_channel_subscription() -> anyhow::Result<()> {
+    let (mut client, mut server) = mem::service_connection::<EventService>(1);
+    let (publish, events) = flume::unbounded();
+    let server_handle = tokio::task::spawn(async move {
+        let (EventRequest::Subscribe(req), chan) = server.accept_one().await?;
+        server
+            .subscription(req, chan, events.into_stream(), |filter, event| {
+                event.topic.starts_with(&filter.0)
+            })
+            .await
+    });
+    let mut stream = client.subscribe(SubscribeTopic("a/".into())).await?;
+    for (i, topic) in ["a/x", "b/x", "a/y"].into_iter().enumerate() {
+        let topic = topic.to_string();
+        publish.send(TopicEvent {
+            topic,
+            value: i as u64,
+        })?;
+    }
+    let first = stream.next().await.unwrap()?;
+    let second = stream.next().await.unwrap()?;
+    assert_eq!((first.topic.as_str(), first.value), ("a/x", 0));
+    assert_eq!((second.topic.as_str(), second.value), ("a/y", 2));
+    // unsubscribe, the server does not wait for the next matching event
+    drop(stream);
+    tokio::time::timeout(Duration::from_secs(1), server_handle).await???;
+    drop(publish);
+    Ok(())
+}
+
+/// mem channels follow the contract of the channel traits
+#[tokio::test]
+async fn mem_channel_conformance() {
+    quic_rpc::channel::test_suite::<MemChannelTypes, _, _>(|| async { mem::connection(1) }).await;
+}
+
+/// a service that receives an upload as a sequence of chunks
+#[derive(Debug, Clone)]
+struct UploadService;
+
+/// start an upload, the data is sent as updates
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct StartUpload;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct UploadChunk(Vec<u8>);
+
+impl From<bytes::Bytes> for UploadChunk {
+    fn from(data: bytes::Bytes) -> Self {
+        Self(data.to_vec())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct UploadSummary {
+    len: u64,
+    chunks: u64,
+    sum: u64,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, derive_more::From, derive_more::TryInto)]
+enum UploadRequest {
+    Start(StartUpload),
+    Chunk(UploadChunk),
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, derive_more::From, derive_more::TryInto)]
+enum UploadResponse {
+    Summary(UploadSummary),
+}
+
+impl Service for UploadService {
+    type Req = UploadRequest;
+    type Res = UploadResponse;
+}
+
+impl Msg<UploadService> for StartUpload {
+    type Update = UploadChunk;
+    type Response = UploadSummary;
+    type Pattern = ClientStreaming;
+}
+
+/// short reads are combined into full chunks, only the last chunk is smaller
+#[tokio::test]
+async fn mem_channel_upload() -> anyhow::Result<()> {
+    let (mut client, mut server) = mem::service_connection::<UploadService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let (UploadRequest::Start(req), chan) = server.accept_one().await? else {
+            anyhow::bail!("unexpected request");
+        };
+        server
+            .client_streaming(req, chan, (), |_, _, updates| async move {
+                let mut summary = UploadSummary {
+                    len: 0,
+                    chunks: 0,
+                    sum: 0,
+                };
+                tokio::pin!(updates);
+                while let Some(UploadChunk(data)) = updates.next().await {
+                    summary.len += data.len() as u64;
+                    summary.chunks += 1;
+                    summary.sum += data.iter().map(|x| *x as u64).sum::<u64>();
+                }
+                summary
+            })
+            .await?;
+        anyhow::Ok(())
+    });
+    let data = (0..1024 * 1024 + 100)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    let expected = UploadSummary {
+        len: data.len() as u64,
+        chunks: 65,
+        sum: data.iter().map(|x| *x as u64).sum(),
+    };
+    // a small duplex buffer makes every read return less than a chunk
+    let (mut writer, reader) = tokio::io::duplex(64);
+    let write_handle =
+        tokio::task::spawn(
+            async move { tokio::io::AsyncWriteExt::write_all(&mut writer, &data).await },
+        );
+    let summary = client.upload(StartUpload, reader, 16 * 1024).await?;
+    assert_eq!(summary, expected);
+    write_handle.await??;
+    server_handle.await??;
+    Ok(())
+}
+
+/// a service whose handler takes a client defined time, for pipelining
+#[derive(Debug, Clone)]
+struct DelayService;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Delayed {
+    millis: u64,
+    value: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct DelayedResponse(u64);
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, derive_more::From, derive_more::TryInto)]
+enum DelayRequest {
+    Delayed(Delayed),
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, derive_more::From, derive_more::TryInto)]
+enum DelayResponse {
+    Delayed(DelayedResponse),
+    Indexed(Indexed<DelayedResponse>),
+}
+
+impl Service for DelayService {
+    type Req = DelayRequest;
+    type Res = DelayResponse;
+}
+
+impl Msg<DelayService> for Delayed {
+    type Update = Self;
+    type Response = DelayedResponse;
+    type Pattern = Rpc;
+}
+
+/// slow requests do not hold back the responses after them, which are still returned in order
+#[tokio::test]
+async fn mem_channel_rpc_pipeline_concurrent() -> anyhow::Result<()> {
+    let (client, mut server) = mem::service_connection::<DelayService>(1);
+    let server_handle = tokio::task::spawn(async move {
+        let (DelayRequest::Delayed(req), chan) = server.accept_one().await?;
+        server
+            .rpc_pipeline_concurrent(req, chan, (), 4, |_, req| async move {
+                tokio::time::sleep(Duration::from_millis(req.millis)).await;
+                DelayedResponse(req.value)
+            })
+            .await?;
+        anyhow::Ok(())
+    });
+    let delays = [150, 50, 100, 10, 10];
+    let reqs = delays
+        .iter()
+        .enumerate()
+        .map(|(value, &millis)| Delayed {
+            millis,
+            value: value as u64,
+        })
+        .collect();
+    let t0 = std::time::Instant::now();
+    let res = client.rpc_batch_concurrent(reqs).await?;
+    // processed one after the other, this would take 320ms
+    assert!(t0.elapsed() < Duration::from_millis(250));
+    let expected = (0..5).map(DelayedResponse).collect::<Vec<_>>();
+    assert_eq!(res, expected);
+    server_handle.await??;
+    Ok(())
+}
+
+/// rejecting the remote closes the connection, so its calls fail instead of waiting forever
+#[tokio::test]
+async fn mem_channel_accept_filtered() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    // a call that was opened before the remote was rejected
+    let pending = tokio::task::spawn({
+        let client = client.clone();
+        async move { client.rpc(Sqr(2)).await }
+    });
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let res = server.accept_filtered(|_| false).await;
+    assert!(matches!(res, Err(RpcServerError::Rejected)));
+    assert!(matches!(
+        server.accept_one().await,
+        Err(RpcServerError::AcceptBiError(mem::AcceptBiError::Closed))
+    ));
+    let res = tokio::time::timeout(Duration::from_secs(1), pending).await??;
+    assert!(res.is_err());
+    let err = client.rpc(Sqr(3)).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RpcClientError::Open(mem::OpenBiError::Closed)
+    ));
+    // the channel error is the source of the client error
+    let source = std::error::Error::source(&err).map(|e| e.to_string());
+    assert_eq!(source.as_deref(), Some("the connection was closed"));
+    Ok(())
+}
+
+/// strict rpc clients notice servers that send more than one response
+#[tokio::test]
+async fn mem_channel_strict_rpc() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    // the server streams two responses into each rpc call
+    let server_handle = tokio::task::spawn(async move {
+        for _ in 0..2 {
+            let (req, (mut send, _recv)) = server.accept_one().await?;
+            let ComputeRequest::Sqr(Sqr(x)) = req else {
+                anyhow::bail!("unexpected request {:?}", req);
+            };
+            for _ in 0..2 {
+                send.send(SqrResponse(x as u128 * x as u128).into()).await?;
+            }
+        }
+        anyhow::Ok(())
+    });
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    // by default, the extra response is ignored
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    let client = client.with_strict_rpc(true);
+    let err = client.rpc(Sqr(3)).await.unwrap_err();
+    assert!(matches!(err, RpcClientError::UnexpectedExtraResponse));
+    assert!(!err.is_retryable());
+    server_handle.await??;
+    // a well behaved server closes the stream after the response
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    tokio::task::spawn(ComputeService::server(server));
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client).with_strict_rpc(true);
+    assert_eq!(client.rpc(Sqr(4)).await?, SqrResponse(16));
+    // a server that keeps the stream open until the client closed its side
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let mut server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(async move {
+        let (req, (mut send, mut recv)) = server.accept_one().await?;
+        let ComputeRequest::Sqr(Sqr(x)) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        send.send(SqrResponse(x as u128 * x as u128).into()).await?;
+        while recv.next().await.is_some() {}
+        anyhow::Ok(())
+    });
+    let client = RpcClient::<ComputeService, MemChannelTypes>::new(client).with_strict_rpc(true);
+    let res = tokio::time::timeout(Duration::from_secs(1), client.rpc(Sqr(5))).await?;
+    assert_eq!(res?, SqrResponse(25));
+    server_handle.await??;
+    Ok(())
+}
+
+/// a duplex is both the update sink and the responses of a bidi call
+#[tokio::test]
+async fn mem_channel_bidi_duplex() -> anyhow::Result<()> {
+    let (client, server) = mem::connection::<ComputeResponse, ComputeRequest>(1);
+    let server = RpcServer::<ComputeService, MemChannelTypes>::new(server);
+    let server_handle = tokio::task::spawn(ComputeService::server(server));
+    let mut client = RpcClient::<ComputeService, MemChannelTypes>::new(client);
+    let mut duplex = client.bidi_duplex(Multiply(2)).await?;
+    duplex.send(MultiplyUpdate(3)).await?;
+    assert_eq!(duplex.next().await.unwrap()?.0, 6);
+    // send and receive at the same time
+    let (updates, responses) = duplex.parts_mut();
+    let (sent, received) = tokio::join!(updates.send(MultiplyUpdate(4)), responses.next());
+    sent?;
+    assert_eq!(received.unwrap()?.0, 8);
+    let (mut updates, responses) = duplex.split();
+    updates.close().await?;
+    assert_eq!(responses.count().await, 0);
+    server_handle.abort();
+    Ok(())
+}
@@ -3,8 +3,16 @@ use std::{
     sync::Arc,
 };
 
+use std::time::Duration;
+
 use anyhow::Context;
-use quic_rpc::{quinn::QuinnChannelTypes, RpcClient, RpcServer};
+use futures::{SinkExt, StreamExt};
+use quic_rpc::{
+    codec::{BincodeCodec, Codec, PostcardCodec},
+    message::ControlFrame,
+    quinn::{ConnectionEvent, DatagramError, QuinnChannelTypes, RecvError},
+    Channel, RpcClient, RpcServer,
+};
 use quinn::{ClientConfig, Endpoint, ServerConfig};
 use tokio::task::JoinHandle;
 
@@ -66,10 +74,8 @@ fn configure_server() -> anyhow::Result<(ServerConfig, Vec<u8>)> {
     let priv_key = rustls::PrivateKey(priv_key);
     let cert_chain = vec![rustls::Certificate(cert_der.clone())];
 
-    let mut server_config = ServerConfig::with_single_cert(cert_chain, priv_key)?;
-    Arc::get_mut(&mut server_config.transport)
-        .unwrap()
-        .max_concurrent_uni_streams(0_u8.into());
+    // unidirectional streams are used for notifications
+    let server_config = ServerConfig::with_single_cert(cert_chain, priv_key)?;
 
     Ok((server_config, cert_der))
 }
@@ -81,8 +87,10 @@ pub struct Endpoints {
 }
 
 pub fn make_endpoints() -> anyhow::Result<Endpoints> {
-    let server_addr: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 12345));
-    let (server, server_certs) = make_server_endpoint(server_addr)?;
+    // bind to a random port so tests can run in parallel
+    let bind_addr: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let (server, server_certs) = make_server_endpoint(bind_addr)?;
+    let server_addr = server.local_addr()?;
     let client = make_client_endpoint("0.0.0.0:0".parse()?, &[&server_certs])?;
     Ok(Endpoints {
         client,
@@ -135,3 +143,1587 @@ async fn quinn_channel_smoke() -> anyhow::Result<()> {
     check_termination_anyhow::<C>(server_handle).await?;
     Ok(())
 }
+
+/// Runs the smoke test over a quinn channel that uses the codec `K` on both sides
+async fn codec_roundtrip<K: Codec>() -> anyhow::Result<()> {
+    type C<K> = QuinnChannelTypes<K>;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = quic_rpc::quinn::Channel::<_, _, K>::new(
+            server.accept().await.context("accept failed")?.await?,
+        );
+        let server = RpcServer::<ComputeService, C<K>>::new(connection);
+        ComputeService::server(server).await?;
+        anyhow::Ok(())
+    });
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    smoke_test::<C<K>>(quic_rpc::quinn::Channel::new(client_connection)).await?;
+    check_termination_anyhow::<C<K>>(server_handle).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_codecs() -> anyhow::Result<()> {
+    codec_roundtrip::<BincodeCodec>().await?;
+    codec_roundtrip::<PostcardCodec>().await?;
+    Ok(())
+}
+
+/// bincode with every byte xored with a key, so each direction needs the matching instance
+#[derive(Debug, Clone)]
+struct MaskCodec(u8);
+
+impl quic_rpc::codec::ChannelCodec for MaskCodec {
+    fn encode_into<T: serde::Serialize>(
+        &self,
+        item: &T,
+        buf: &mut bytes::BytesMut,
+    ) -> std::io::Result<()> {
+        let data = BincodeCodec::encode(item)?;
+        buf.extend(data.into_iter().map(|b| b ^ self.0));
+        Ok(())
+    }
+
+    fn decode_from<T: serde::de::DeserializeOwned>(
+        &self,
+        buf: &mut bytes::BytesMut,
+    ) -> std::io::Result<T> {
+        let data = buf.iter().map(|b| b ^ self.0).collect::<Vec<_>>();
+        BincodeCodec::decode(&data)
+    }
+}
+
+#[tokio::test]
+async fn quinn_channel_codec_instances() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes<MaskCodec>;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = server.accept().await.context("accept failed")?.await?;
+        let connection =
+            quic_rpc::quinn::Channel::new_with_codec(connection, MaskCodec(1), MaskCodec(2));
+        let server = RpcServer::<ComputeService, C>::new(connection);
+        ComputeService::server(server).await?;
+        anyhow::Ok(())
+    });
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    let client_connection = QuinnChannelTypes::<BincodeCodec>::builder()
+        .with_codec(MaskCodec(2), MaskCodec(1))
+        .channel(client_connection);
+    smoke_test::<C>(client_connection).await?;
+    check_termination_anyhow::<C>(server_handle).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_datagram_rpc() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection =
+            quic_rpc::quinn::Channel::new(server.accept().await.context("accept failed")?.await?);
+        let server = RpcServer::<ComputeService, C>::new(connection);
+        loop {
+            let (req, responder) = match server.accept_datagram().await {
+                Ok(x) => x,
+                Err(DatagramError::Recv(_)) => break,
+                Err(e) => return Err(e.into()),
+            };
+            match req {
+                ComputeRequest::Sqr(msg) => {
+                    server
+                        .rpc_datagram(msg, responder, ComputeService, |_, Sqr(x)| async move {
+                            SqrResponse(x as u128 * x as u128)
+                        })
+                        .await?
+                }
+                _ => anyhow::bail!("unexpected request {:?}", req),
+            }
+        }
+        anyhow::Ok(())
+    });
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    let client_connection = quic_rpc::quinn::Channel::new(client_connection);
+    let client = RpcClient::<ComputeService, C>::new(client_connection);
+    let timeout = Duration::from_secs(5);
+    let res = client.rpc_datagram(Sqr(1234), timeout).await?;
+    assert_eq!(res, SqrResponse(1522756));
+    // several concurrent requests get matched to the right responses
+    let futs = (0..10u64).map(|i| client.rpc_datagram(Sqr(i), timeout));
+    let res = futures::future::try_join_all(futs).await?;
+    for (i, res) in res.into_iter().enumerate() {
+        assert_eq!(res, SqrResponse((i * i) as u128));
+    }
+    drop(client);
+    server_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_notify() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let (received, received_rx) = flume::unbounded();
+    let server_handle = tokio::task::spawn(async move {
+        let connection =
+            quic_rpc::quinn::Channel::new(server.accept().await.context("accept failed")?.await?);
+        let server = RpcServer::<ComputeService, C>::new(connection);
+        for _ in 0..3 {
+            // notifications use unidirectional streams, so they don't show up in accept_one
+            let req = server.accept_notification().await?;
+            let ComputeRequest::Notification(msg) = req else {
+                anyhow::bail!("unexpected request {:?}", req);
+            };
+            let received = received.clone();
+            server
+                .notify(msg, (), |_, Notification(x)| async move {
+                    received.send(x).ok();
+                })
+                .await?;
+        }
+        anyhow::Ok(())
+    });
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    let client_connection = quic_rpc::quinn::Channel::new(client_connection);
+    let client = RpcClient::<ComputeService, C>::new(client_connection);
+    for i in 0..3 {
+        client.notify(Notification(i)).await?;
+    }
+    server_handle.await??;
+    let mut received = received_rx.drain().collect::<Vec<_>>();
+    received.sort();
+    assert_eq!(received, vec![0, 1, 2]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_push() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = server.accept().await.context("accept failed")?.await?;
+        let server =
+            RpcServer::<ComputeService, C>::new(quic_rpc::quinn::Channel::new(connection.clone()));
+        for i in 0..3u128 {
+            server.push(SqrResponse(i)).await?;
+        }
+        // keep the connection open until the client got all pushes
+        connection.closed().await;
+        anyhow::Ok(())
+    });
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    let channel = quic_rpc::quinn::Channel::new(client_connection);
+    let client = RpcClient::<ComputeService, C>::new(channel.clone());
+    let (pushed, pushed_rx) = flume::unbounded();
+    let push_loop = tokio::task::spawn(async move {
+        client
+            .accept_push_loop(move |SqrResponse(x)| {
+                let pushed = pushed.clone();
+                async move {
+                    pushed.send(x).ok();
+                }
+            })
+            .await
+    });
+    let mut received = Vec::new();
+    for _ in 0..3 {
+        let x = tokio::time::timeout(Duration::from_secs(5), pushed_rx.recv_async()).await??;
+        received.push(x);
+    }
+    received.sort();
+    assert_eq!(received, vec![0, 1, 2]);
+    // the loop ends once the connection is closed
+    channel.close(0, b"done");
+    assert!(push_loop.await?.is_err());
+    server_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_max_frame_size() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = quic_rpc::quinn::Channel::<ComputeRequest, ComputeResponse>::new(
+            server.accept().await.context("accept failed")?.await?,
+        )
+        .with_max_frame_size(1024);
+        let (_send, mut recv) = connection.accept_bi().await?;
+        // a small message is fine
+        assert!(matches!(
+            recv.next().await,
+            Some(Ok(ComputeRequest::Sqr(_)))
+        ));
+        // a large one is rejected based on the length prefix
+        match recv.next().await {
+            Some(Err(RecvError::FrameTooLarge { size, limit })) => {
+                assert!(size > 4096);
+                assert_eq!(limit, 1024);
+            }
+            res => anyhow::bail!("unexpected result {:?}", res),
+        }
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let connection = quic_rpc::quinn::Channel::<ComputeResponse, ComputeRequest>::new(connection);
+    let (mut send, _recv) = Channel::<_, _, C>::open_bi(&connection).await?;
+    send.send(ComputeRequest::Sqr(Sqr(2))).await?;
+    let payload = vec![0; 4096];
+    send.send(ComputeRequest::Control(ControlFrame { code: 0, payload }))
+        .await?;
+    server_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_max_message_size() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = quic_rpc::quinn::Channel::<ComputeRequest, ComputeResponse>::new(
+            server.accept().await.context("accept failed")?.await?,
+        )
+        .with_max_frame_size(1024)
+        .with_max_message_size(16 * 1024);
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        // a response that spans several frames
+        let Some(Ok(ComputeRequest::Control(frame))) = recv.next().await else {
+            anyhow::bail!("unexpected request");
+        };
+        send.send(ComputeResponse::Control(frame)).await?;
+        // a message larger than the limit is rejected, even though its frames are small
+        match recv.next().await {
+            Some(Err(RecvError::MessageTooLarge { size, limit })) => {
+                assert!(size > limit);
+                assert_eq!(limit, 16 * 1024);
+            }
+            res => anyhow::bail!("unexpected result {:?}", res),
+        }
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let connection = quic_rpc::quinn::Channel::<ComputeResponse, ComputeRequest>::new(connection)
+        .with_max_frame_size(1024)
+        .with_max_message_size(16 * 1024);
+    let (mut send, mut recv) = Channel::<_, _, C>::open_bi(&connection).await?;
+    let payload = (0..10000).map(|i| i as u8).collect::<Vec<_>>();
+    send.send(ComputeRequest::Control(ControlFrame {
+        code: 1,
+        payload: payload.clone(),
+    }))
+    .await?;
+    match recv.next().await {
+        Some(Ok(ComputeResponse::Control(frame))) => assert_eq!(frame.payload, payload),
+        res => anyhow::bail!("unexpected result {:?}", res),
+    }
+    send.send(ComputeRequest::Control(ControlFrame {
+        code: 2,
+        payload: vec![0; 20000],
+    }))
+    .await?;
+    server_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_truncated_message() -> anyhow::Result<()> {
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = quic_rpc::quinn::Channel::<ComputeRequest, ComputeResponse>::new(
+            server.accept().await.context("accept failed")?.await?,
+        )
+        .with_max_frame_size(1024)
+        .with_max_message_size(16 * 1024);
+        let (_send, mut recv) = connection.accept_bi().await?;
+        // the first chunk of a message, but the stream ends before the final one
+        match recv.next().await {
+            Some(Err(RecvError::Io(e))) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof)
+            }
+            res => anyhow::bail!("unexpected result {:?}", res),
+        }
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let (mut send, _recv) = connection.open_bi().await?;
+    // length prefix, header of a continued chunk and the start of the message
+    send.write_all(&[0, 0, 0, 3, 1, 0, 0]).await?;
+    send.finish().await?;
+    server_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_connection_info() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let client_port = client.local_addr()?.port();
+    let server_handle = tokio::task::spawn(async move {
+        let connection =
+            quic_rpc::quinn::Channel::new(server.accept().await.context("accept failed")?.await?);
+        let mut server = RpcServer::<ComputeService, C>::new(connection);
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Sqr(req) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        server
+            .rpc_with_info(req, chan, (), move |_, Sqr(x), info| async move {
+                assert_eq!(info.peer.map(|addr| addr.port()), Some(client_port));
+                assert!(info.rtt.is_some());
+                // the client did not authenticate
+                assert_eq!(info.peer_certificates, None);
+                SqrResponse(x as u128 * x as u128)
+            })
+            .await?;
+        // keep the connection open until the client is done
+        server.accept_one().await.ok();
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let connection = quic_rpc::quinn::Channel::<ComputeResponse, ComputeRequest>::new(connection);
+    let info = Channel::<_, _, C>::connection_info(&connection);
+    assert_eq!(info.peer, Some(server_addr));
+    assert_eq!(info.peer_certificates.map(|certs| certs.len()), Some(1));
+    let client = RpcClient::<ComputeService, C>::new(connection);
+    assert_eq!(client.rpc(Sqr(4)).await?, SqrResponse(16));
+    drop(client);
+    server_handle.await??;
+    Ok(())
+}
+
+/// Builds a client and server endpoint pair that negotiate application protocols using ALPN
+fn make_alpn_endpoints(server_alpn: &[&[u8]], client_alpn: &[&[u8]]) -> anyhow::Result<Endpoints> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    let cert_der = cert.serialize_der()?;
+    let priv_key = rustls::PrivateKey(cert.serialize_private_key_der());
+    let mut server_crypto = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![rustls::Certificate(cert_der.clone())], priv_key)?;
+    server_crypto.alpn_protocols = server_alpn.iter().map(|p| p.to_vec()).collect();
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&rustls::Certificate(cert_der))?;
+    let mut client_crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client_crypto.alpn_protocols = client_alpn.iter().map(|p| p.to_vec()).collect();
+
+    let bind_addr: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let server = Endpoint::server(
+        ServerConfig::with_crypto(Arc::new(server_crypto)),
+        bind_addr,
+    )?;
+    let server_addr = server.local_addr()?;
+    let mut client = Endpoint::client("0.0.0.0:0".parse()?)?;
+    client.set_default_client_config(ClientConfig::new(Arc::new(client_crypto)));
+    Ok(Endpoints {
+        client,
+        server,
+        server_addr,
+    })
+}
+
+#[tokio::test]
+async fn quinn_channel_alpn() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_alpn_endpoints(&[b"quic-rpc", b"other"], &[b"other"])?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection =
+            quic_rpc::quinn::Channel::new(server.accept().await.context("accept failed")?.await?);
+        let server = RpcServer::<ComputeService, C>::new(connection);
+        assert_eq!(server.alpn(), Some(b"other".to_vec()));
+        server.require_alpn(&[b"other"])?;
+        let err = server.require_alpn(&[b"quic-rpc"]).unwrap_err();
+        assert_eq!(err.negotiated, Some(b"other".to_vec()));
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    assert_eq!(
+        quic_rpc::quinn::Channel::<ComputeResponse, ComputeRequest>::new(connection.clone()).alpn(),
+        Some(b"other".to_vec())
+    );
+    server_handle.await??;
+    match connection.closed().await {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(close.error_code, quic_rpc::quinn::ALPN_MISMATCH)
+        }
+        e => anyhow::bail!("unexpected close reason {}", e),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_pool() -> anyhow::Result<()> {
+    use quic_rpc::pool::{ClientPool, PoolConfig};
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let accepted = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let server_handle = tokio::task::spawn({
+        let accepted = accepted.clone();
+        async move {
+            while let Some(connecting) = server.accept().await {
+                let conn = connecting.await?;
+                accepted.lock().unwrap().push(conn.clone());
+                let server = RpcServer::<ComputeService, QuinnChannelTypes>::new(
+                    quic_rpc::quinn::Channel::new(conn),
+                );
+                tokio::task::spawn(ComputeService::server(server));
+            }
+            anyhow::Ok(())
+        }
+    });
+    let config = PoolConfig {
+        max_connections: 2,
+        idle_timeout: Duration::from_millis(500),
+    };
+    let pool = ClientPool::<ComputeService>::new(client, server_addr, "localhost", config);
+    for i in 0..4 {
+        let client = pool.get().await?;
+        assert_eq!(
+            client.rpc(Sqr(i)).await?,
+            SqrResponse(i as u128 * i as u128)
+        );
+    }
+    assert_eq!(pool.len(), 2);
+    assert_eq!(accepted.lock().unwrap().len(), 2);
+    // closed connections are replaced
+    for conn in accepted.lock().unwrap().iter() {
+        conn.close(0u32.into(), b"bye");
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let client = pool.get().await?;
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    assert_eq!(pool.len(), 1);
+    assert_eq!(accepted.lock().unwrap().len(), 3);
+    // idle connections are removed
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let client = pool.get().await?;
+    assert_eq!(client.rpc(Sqr(4)).await?, SqrResponse(16));
+    assert_eq!(accepted.lock().unwrap().len(), 4);
+    // concurrent callers connect in parallel, but do not exceed the limit
+    for conn in accepted.lock().unwrap().iter() {
+        conn.close(0u32.into(), b"bye");
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (a, b, c) = tokio::join!(pool.get(), pool.get(), pool.get());
+    for client in [a?, b?, c?] {
+        assert_eq!(client.rpc(Sqr(5)).await?, SqrResponse(25));
+    }
+    assert_eq!(pool.len(), 2);
+    assert_eq!(accepted.lock().unwrap().len(), 6);
+    server_handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_rpc_raw() -> anyhow::Result<()> {
+    use bytes::Bytes;
+    use quic_rpc::{
+        client::{Deadline, RpcClientError},
+        server::RpcServerError,
+    };
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection =
+            quic_rpc::quinn::Channel::new(server.accept().await.context("accept failed")?.await?);
+        let server = RpcServer::<ComputeService, C>::new(connection);
+        loop {
+            let (req, socket) = match server.accept_bytes().await {
+                Ok(x) => x,
+                Err(RpcServerError::AcceptBiError(_)) => break,
+                Err(e) => return Err(e.into()),
+            };
+            server
+                .rpc_raw(req, socket, (), |_, req| async move {
+                    req.iter().rev().copied().collect::<Bytes>()
+                })
+                .await?;
+        }
+        anyhow::Ok(())
+    });
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    let client_connection = quic_rpc::quinn::Channel::new(client_connection);
+    let client = RpcClient::<ComputeService, C>::new(client_connection);
+    let res = client.rpc_raw(Bytes::from_static(b"hello")).await?;
+    assert_eq!(res, Bytes::from_static(b"olleh"));
+    // payloads are framed like messages, so they can be larger than a single packet
+    let large = (0..1024 * 1024).map(|i| i as u8).collect::<Bytes>();
+    let res = client.rpc_raw(large.clone()).await?;
+    assert_eq!(res, large.iter().rev().copied().collect::<Bytes>());
+    assert!(client.rpc_raw(Bytes::new()).await?.is_empty());
+    // raw calls respect the deadline like typed calls
+    let res = Deadline::scope(Duration::ZERO, client.rpc_raw(Bytes::new())).await;
+    assert!(matches!(res, Err(RpcClientError::Timeout)));
+    drop(client);
+    server_handle.await??;
+    Ok(())
+}
+
+/// a message type without serde support
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Point {
+    x: u32,
+    y: u32,
+}
+
+/// hand written encoding of [Point], as two big endian integers
+struct PointCodec;
+
+impl quic_rpc::codec::MessageCodec<Point> for PointCodec {
+    fn encode(&self, item: &Point, buf: &mut bytes::BytesMut) -> std::io::Result<()> {
+        use bytes::BufMut;
+        buf.put_u32(item.x);
+        buf.put_u32(item.y);
+        Ok(())
+    }
+
+    fn decode(&self, buf: &mut bytes::BytesMut) -> std::io::Result<Point> {
+        use bytes::Buf;
+        if buf.len() != 8 {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        Ok(Point {
+            x: buf.get_u32(),
+            y: buf.get_u32(),
+        })
+    }
+}
+
+#[tokio::test]
+async fn quinn_channel_message_codec() -> anyhow::Result<()> {
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = quic_rpc::quinn::Channel::<(), ()>::new(
+            server.accept().await.context("accept failed")?.await?,
+        );
+        let (mut send, mut recv) = connection.accept_with_codec(PointCodec, PointCodec).await?;
+        while let Some(p) = recv.next().await {
+            let p = p?;
+            send.send(Point { x: p.y, y: p.x }).await?;
+        }
+        send.close().await?;
+        anyhow::Ok(())
+    });
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    let client_connection = quic_rpc::quinn::Channel::<(), ()>::new(client_connection);
+    let (mut send, mut recv) = client_connection
+        .open_with_codec(PointCodec, PointCodec)
+        .await?;
+    for i in 0..10 {
+        send.send(Point { x: i, y: i * 2 }).await?;
+        let p = recv.next().await.context("early close")??;
+        assert_eq!(p, Point { x: i * 2, y: i });
+    }
+    send.close().await?;
+    assert!(recv.next().await.is_none());
+    server_handle.await??;
+    Ok(())
+}
+
+/// size of a message on the wire, including the length prefix
+fn frame_len<T: serde::Serialize>(msg: &T) -> u64 {
+    BincodeCodec::encode(msg).unwrap().len() as u64 + 4
+}
+
+#[tokio::test]
+async fn quinn_channel_byte_counts() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = run_server(server);
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    let client_connection = quic_rpc::quinn::Channel::new(client_connection);
+    let mut client = RpcClient::<ComputeService, C>::new(client_connection);
+    let (mut responses, counts) = client.server_streaming_counted(Fibonacci(10)).await?;
+    let mut expected = 0;
+    while let Some(res) = responses.next().await {
+        expected += frame_len(&ComputeResponse::from(res?));
+        assert_eq!(counts.bytes_received(), expected);
+    }
+    let sent = frame_len(&ComputeRequest::from(Fibonacci(10)));
+    assert_eq!(counts.bytes_sent(), sent);
+    drop(responses);
+    // updates are counted on the sink of client streaming calls
+    let (mut send, recv) = client.client_streaming(Sum).await?;
+    let counts = send.byte_counts();
+    let mut expected = frame_len(&ComputeRequest::from(Sum));
+    for i in 0..5 {
+        send.send(SumUpdate(i)).await?;
+        expected += frame_len(&ComputeRequest::from(SumUpdate(i)));
+        assert_eq!(counts.bytes_sent(), expected);
+    }
+    send.close().await?;
+    assert_eq!(recv.await?, SumResponse(10));
+    assert!(counts.bytes_received() > 0);
+    drop(send);
+    drop(client);
+    check_termination_anyhow::<C>(server_handle).await?;
+    Ok(())
+}
+
+/// the same messages as [V2Message], with the variants in a different order
+#[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq)]
+enum V1Message {
+    Ping(u64),
+    Pong(u64),
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq)]
+enum V2Message {
+    Pong(u64),
+    Ping(u64),
+}
+
+#[tokio::test]
+async fn quinn_channel_tagged() -> anyhow::Result<()> {
+    use quic_rpc::tagged::{self, RecvError, Tagged, TaggedChannelTypes};
+    type C = TaggedChannelTypes<QuinnChannelTypes>;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = server.accept().await.context("accept failed")?.await?;
+        let channel = tagged::Channel::<QuinnChannelTypes, V1Message, V1Message>::new(
+            quic_rpc::quinn::Channel::<Tagged<_>, Tagged<_>>::new(connection),
+        );
+        let (mut send, _recv) = Channel::<_, _, C>::accept_bi(&channel).await?;
+        send.send(V1Message::Pong(1)).await?;
+        send.send(V1Message::Pong(2)).await?;
+        send.close().await?;
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let channel = tagged::Channel::<QuinnChannelTypes, V2Message, V2Message>::new(
+        quic_rpc::quinn::Channel::<Tagged<_>, Tagged<_>>::new(connection),
+    );
+    let (mut send, mut recv) = Channel::<_, _, C>::open_bi(&channel).await?;
+    send.send(V2Message::Ping(0)).await?;
+    // without the tag, this would be decoded as a Ping
+    match recv.next().await {
+        Some(Err(RecvError::VariantMismatch { expected, got })) => {
+            assert_eq!(expected, "Pong");
+            assert_eq!(got, Some("Ping"));
+        }
+        other => panic!("unexpected result {other:?}"),
+    }
+    assert_eq!(tagged::variant_name(&V2Message::Pong(0)), Some("Pong"));
+    assert_eq!(tagged::variant_name(&Sqr(2)), None);
+    server_handle.await??;
+    Ok(())
+}
+
+async fn sum_updates(
+    _: ComputeService,
+    _: Sum,
+    updates: impl futures::Stream<Item = SumUpdate>,
+) -> SumResponse {
+    SumResponse(
+        updates
+            .fold(0, |sum, SumUpdate(n)| async move { sum + n as u128 })
+            .await,
+    )
+}
+
+#[tokio::test]
+async fn quinn_channel_fair_scheduler() -> anyhow::Result<()> {
+    use quic_rpc::quinn::FairScheduler;
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection =
+            quic_rpc::quinn::Channel::new(server.accept().await.context("accept failed")?.await?);
+        let mut server = RpcServer::<ComputeService, C>::new(connection);
+        // handle requests concurrently, so the rpc calls don't wait for the sum
+        loop {
+            let (req, chan) = server.accept_one().await?;
+            let server = server.clone();
+            tokio::task::spawn(async move {
+                let service = ComputeService;
+                match req {
+                    ComputeRequest::Sqr(msg) => {
+                        server
+                            .rpc(msg, chan, service, |_, Sqr(x)| async move {
+                                SqrResponse(x as u128 * x as u128)
+                            })
+                            .await
+                    }
+                    ComputeRequest::Sum(msg) => {
+                        server
+                            .client_streaming(msg, chan, service, sum_updates)
+                            .await
+                    }
+                    _ => Err(quic_rpc::server::RpcServerError::UnexpectedStartMessage),
+                }
+            });
+        }
+        #[allow(unreachable_code)]
+        anyhow::Ok(())
+    });
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    // a tiny chunk size, so every frame is written in several turns
+    let scheduler = FairScheduler::new(4);
+    assert_eq!(scheduler.chunk_size(), 4);
+    let client_connection =
+        quic_rpc::quinn::Channel::new(client_connection).with_fair_scheduler(scheduler);
+    let client = RpcClient::<ComputeService, C>::new(client_connection);
+    let bulk = {
+        let mut client = client.clone();
+        async move {
+            let (mut send, recv) = client.client_streaming(Sum).await?;
+            for i in 0..1000 {
+                send.send(SumUpdate(i)).await?;
+            }
+            send.close().await?;
+            anyhow::Ok(recv.await?)
+        }
+    };
+    let interactive = {
+        let client = client.clone();
+        async move {
+            for i in 0..100u64 {
+                assert_eq!(
+                    client.rpc(Sqr(i)).await?,
+                    SqrResponse(i as u128 * i as u128)
+                );
+            }
+            anyhow::Ok(())
+        }
+    };
+    let (sum, ()) = tokio::try_join!(bulk, interactive)?;
+    assert_eq!(sum, SumResponse(999 * 1000 / 2));
+    drop(client);
+    check_termination_anyhow::<C>(server_handle).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_accept_filtered() -> anyhow::Result<()> {
+    use quic_rpc::server::RpcServerError;
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        // the first connection is allowed, the second one is rejected
+        for allowed in [true, false] {
+            let connection = server.accept().await.context("accept failed")?.await?;
+            let connection = quic_rpc::quinn::Channel::new(connection);
+            let mut server = RpcServer::<ComputeService, C>::new(connection).with_reject_code(42);
+            let filter = |info: &quic_rpc::ConnectionInfo| {
+                allowed && info.peer.is_some_and(|addr| addr.ip().is_loopback())
+            };
+            match server.accept_filtered(filter).await {
+                Ok((ComputeRequest::Sqr(msg), chan)) => {
+                    assert!(allowed);
+                    server
+                        .rpc(msg, chan, ComputeService, |_, Sqr(x)| async move {
+                            SqrResponse(x as u128 * x as u128)
+                        })
+                        .await?;
+                    // wait for the client to close the connection
+                    assert!(matches!(
+                        server.accept_filtered(filter).await,
+                        Err(RpcServerError::AcceptBiError(_))
+                    ));
+                }
+                Err(RpcServerError::Rejected) => assert!(!allowed),
+                res => panic!("unexpected result {:?}", res.map(|(req, _)| req)),
+            }
+        }
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let rpc_client = RpcClient::<ComputeService, C>::new(quic_rpc::quinn::Channel::new(connection));
+    assert_eq!(rpc_client.rpc(Sqr(3)).await?, SqrResponse(9));
+    drop(rpc_client);
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let error = connection.closed().await;
+    assert_eq!(quic_rpc::quinn::close_code(&error), Some(42));
+    server_handle.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_priority() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let (order_send, order_recv) = tokio::sync::oneshot::channel();
+    let server_handle = tokio::task::spawn(async move {
+        let connection = server.accept().await.context("accept failed")?.await?;
+        // record the order in which data of the two streams starts to arrive. The end of a frame
+        // can arrive in the same packet as the start of the next one, so the order in which
+        // frames are complete is not reliable.
+        let (first_send, mut first_recv) = tokio::sync::mpsc::channel(2);
+        for _ in 0..2 {
+            let (send, mut recv) = connection.accept_bi().await?;
+            let first_send = first_send.clone();
+            tokio::task::spawn(async move {
+                let chunk = recv.read_chunk(usize::MAX, true).await.unwrap().unwrap();
+                let len = u32::from_be_bytes(chunk.bytes[..4].try_into().unwrap()) as usize;
+                first_send.send(len).await.unwrap();
+                // read the rest, so the client can flush the frame
+                let mut read = chunk.bytes.len();
+                while read < 4 + len {
+                    read += recv
+                        .read_chunk(usize::MAX, true)
+                        .await
+                        .unwrap()
+                        .unwrap()
+                        .bytes
+                        .len();
+                }
+                drop(send);
+            });
+        }
+        let order = [first_recv.recv().await, first_recv.recv().await];
+        order_send.send(order).ok();
+        // serve a regular rpc that is sent with a priority
+        let channel = quic_rpc::quinn::Channel::new(connection);
+        let server = RpcServer::<ComputeService, C>::new(channel);
+        ComputeService::server(server).await?;
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let channel = quic_rpc::quinn::Channel::new(connection);
+    // both frames are queued before the connection gets to send anything, since the test runs on
+    // a single thread. Without priorities, quinn would send them round robin, so data of both
+    // streams would arrive right away.
+    let (mut small, small_recv) = channel.open_raw().await?;
+    let (mut large, large_recv) = channel.open_raw().await?;
+    small.set_priority(-1)?;
+    large.set_priority(1)?;
+    small.send(vec![0u8; 1000].into()).await?;
+    large.send(vec![0u8; 500_000].into()).await?;
+    assert_eq!(order_recv.await?, [Some(500_000), Some(1000)]);
+    let client = RpcClient::<ComputeService, C>::new(channel);
+    assert_eq!(client.rpc_with_priority(Sqr(4), 10).await?, SqrResponse(16));
+    drop((small, small_recv, large, large_recv, client));
+    check_termination_anyhow::<C>(server_handle).await?;
+    Ok(())
+}
+
+/// dropping an rpc call resets the stream, so the server stops the handler
+#[tokio::test]
+async fn quinn_channel_rpc_cancel() -> anyhow::Result<()> {
+    use quic_rpc::server::{RpcServerError, RpcServerErrorKind};
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = server.accept().await.context("accept failed")?.await?;
+        let connection = quic_rpc::quinn::Channel::new(connection);
+        let mut server = RpcServer::<ComputeService, C>::new(connection);
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Sqr(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        // the handler never completes, so only the cancellation can end the call
+        let res = server
+            .rpc(msg, chan, (), |_, _| futures::future::pending())
+            .await;
+        let Err(RpcServerError::RecvError(err)) = res else {
+            anyhow::bail!("unexpected result {:?}", res);
+        };
+        assert_eq!(err.server_error_kind(), Some(RpcServerErrorKind::Cancelled));
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let client = RpcClient::<ComputeService, C>::new(quic_rpc::quinn::Channel::new(connection));
+    tokio::select! {
+        res = client.rpc(Sqr(2)) => panic!("unexpected result {:?}", res),
+        _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+    }
+    // the client stays connected, so the server sees the reset and not the connection closing
+    tokio::time::timeout(Duration::from_secs(5), server_handle).await???;
+    drop(client);
+    Ok(())
+}
+
+/// a server that fails a call because of a protocol error resets the stream with its code
+#[tokio::test]
+async fn quinn_channel_reset_code() -> anyhow::Result<()> {
+    use quic_rpc::server::{RpcServerError, RpcServerErrorKind};
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = server.accept().await.context("accept failed")?.await?;
+        let connection = quic_rpc::quinn::Channel::new(connection);
+        let mut server = RpcServer::<ComputeService, C>::new(connection);
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Sum(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        let res = server
+            .client_streaming(msg, chan, ComputeService, sum_updates)
+            .await;
+        assert!(matches!(res, Err(RpcServerError::UnexpectedUpdateMessage)));
+        // keep the connection open until the client got the reset
+        server.accept_one().await.ok();
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let channel = quic_rpc::quinn::Channel::<ComputeResponse, ComputeRequest>::new(connection);
+    let (mut send, mut recv) = channel.open_bi().await?;
+    send.send(ComputeRequest::Sum(Sum)).await?;
+    // a request instead of an update
+    send.send(ComputeRequest::Sqr(Sqr(2))).await?;
+    let err = match recv.next().await {
+        Some(Err(err)) => err,
+        res => panic!("unexpected result {:?}", res),
+    };
+    assert!(matches!(err, RecvError::PeerReset { code: 1 }));
+    assert_eq!(
+        err.server_error_kind(),
+        Some(RpcServerErrorKind::UnexpectedUpdateMessage)
+    );
+    drop((send, recv, channel));
+    server_handle.await??;
+    Ok(())
+}
+
+/// a resumable server streaming call continues after the server gave up on a stream
+#[tokio::test]
+async fn quinn_channel_server_streaming_resumable() -> anyhow::Result<()> {
+    use quic_rpc::{
+        client::{ResumableItem, RetryPolicy},
+        message::{Indexed, ResumeFrom},
+        server::RpcServerError,
+    };
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection = server.accept().await.context("accept failed")?.await?;
+        let connection = quic_rpc::quinn::Channel::new(connection);
+        // every stream is reset after a few items
+        let mut server = RpcServer::<ComputeService, C>::new(connection)
+            .with_max_rpc_duration(Duration::from_millis(100));
+        while let Ok((req, chan)) = server.accept_one().await {
+            let ComputeRequest::ResumeFibonacci(msg) = req else {
+                anyhow::bail!("unexpected request {:?}", req);
+            };
+            let res = server
+                .server_streaming_resumable(msg, chan, ComputeService, |s, req, offset| {
+                    s.fibonacci(req)
+                        .skip(offset as usize)
+                        .then(|item| async move {
+                            tokio::time::sleep(Duration::from_millis(30)).await;
+                            item
+                        })
+                })
+                .await;
+            match res {
+                Ok(()) | Err(RpcServerError::MaxDurationExceeded) => {}
+                Err(cause) => return Err(cause.into()),
+            }
+        }
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let mut client = RpcClient::<ComputeService, C>::new(quic_rpc::quinn::Channel::new(connection));
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(10),
+        jitter: Duration::ZERO,
+    };
+    let mut stream = client
+        .server_streaming_resumable(
+            ResumeFrom::start(Fibonacci(10)),
+            policy,
+            |req, last: &Indexed<FibonacciResponse>| ResumeFrom {
+                offset: last.index + 1,
+                msg: req.msg.clone(),
+            },
+        )
+        .await?;
+    let mut items = Vec::new();
+    let mut reconnects = 0;
+    while let Some(item) = stream.next().await {
+        match item? {
+            ResumableItem::Item(Indexed { index, item }) => {
+                assert_eq!(index, items.len() as u64);
+                items.push(item.0);
+            }
+            ResumableItem::Reconnected => reconnects += 1,
+        }
+    }
+    assert_eq!(items, vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34]);
+    assert!(reconnects > 0);
+    drop((stream, client));
+    server_handle.await??;
+    Ok(())
+}
+
+/// the server sees the client moving to another address, idle periods and closing
+#[tokio::test]
+async fn quinn_channel_events() -> anyhow::Result<()> {
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection =
+            quic_rpc::quinn::Channel::new(server.accept().await.context("accept failed")?.await?);
+        let server = RpcServer::<ComputeService, QuinnChannelTypes>::new(connection);
+        let events = server.events(Duration::from_millis(20));
+        tokio::task::spawn(ComputeService::server(server));
+        anyhow::Ok(events.collect::<Vec<_>>().await)
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let client_addr = client.local_addr()?;
+    let client_channel = quic_rpc::quinn::Channel::new(connection.clone());
+    let client_rpc = RpcClient::<ComputeService, QuinnChannelTypes>::new(client_channel);
+    assert_eq!(client_rpc.rpc(Sqr(2)).await?, SqrResponse(4));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    // move the client to another socket, like a switch from wifi to cellular
+    client.rebind(std::net::UdpSocket::bind("127.0.0.1:0")?)?;
+    assert_eq!(client_rpc.rpc(Sqr(3)).await?, SqrResponse(9));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    connection.close(0u32.into(), b"done");
+    let events = server_handle.await??;
+    assert!(events.contains(&ConnectionEvent::Idle));
+    let new = client.local_addr()?;
+    assert!(events.iter().any(|event| matches!(
+        event,
+        ConnectionEvent::PathChanged { old, new: addr } if old.port() == client_addr.port() && *addr == new
+    )));
+    assert!(matches!(events.last(), Some(ConnectionEvent::Closed(_))));
+    Ok(())
+}
+
+/// a flushed update reaches the server while the sink is still open
+#[tokio::test]
+async fn quinn_channel_flush_update() -> anyhow::Result<()> {
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = run_server(server);
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let channel = quic_rpc::quinn::Channel::new(connection);
+    let mut client = RpcClient::<ComputeService, QuinnChannelTypes>::new(channel);
+    let (mut send, mut recv) = client.bidi(Multiply(2)).await?;
+    send.feed(MultiplyUpdate(3)).await?;
+    send.flush().await?;
+    // the server reacts to the update before the sink is closed
+    let res = tokio::time::timeout(Duration::from_secs(1), recv.next())
+        .await
+        .context("update was not flushed")?
+        .context("stream closed")??;
+    assert_eq!(res.0, 6);
+    send.close().await?;
+    assert!(recv.next().await.is_none());
+    drop((send, recv, client));
+    check_termination_anyhow::<QuinnChannelTypes>(server_handle).await?;
+    Ok(())
+}
+
+/// frames are compressed by the side that enables it, and decompressed transparently
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn quinn_channel_compression() -> anyhow::Result<()> {
+    use quic_rpc::{quinn::Compression, ByteCounted};
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let large = bytes::Bytes::from(vec![7u8; 100_000]);
+    let expected = large.clone();
+    let server_handle = tokio::task::spawn(async move {
+        let connection = server.accept().await.context("accept failed")?.await?;
+        let channel = quic_rpc::quinn::Channel::<ComputeRequest, ComputeResponse>::new(connection)
+            .with_compression(Compression::default());
+        let (mut send, mut recv) = channel.accept_raw().await?;
+        // echo the frames, the large one gets compressed on the way back
+        while let Some(frame) = recv.next().await {
+            send.send(frame?).await?;
+        }
+        send.close().await?;
+        anyhow::Ok(recv.byte_counts().bytes_received())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    // the client only sends small messages, so it does not compress
+    let channel = quic_rpc::quinn::Channel::<ComputeResponse, ComputeRequest>::new(connection)
+        .with_compression(Compression::default().receive_only());
+    let (mut send, mut recv) = channel.open_raw().await?;
+    send.send(bytes::Bytes::from_static(b"small")).await?;
+    send.send(large).await?;
+    send.close().await?;
+    assert_eq!(&recv.next().await.unwrap()?[..], b"small");
+    assert_eq!(recv.next().await.unwrap()?, expected);
+    assert!(recv.next().await.is_none());
+    // the large frame was sent uncompressed, but received compressed
+    let received = server_handle.await??;
+    assert!(received > 100_000);
+    assert!(recv.byte_counts().bytes_received() < 1000);
+    Ok(())
+}
+
+/// compression can be limited to the responses of some interaction patterns
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn quinn_channel_compression_for_patterns() -> anyhow::Result<()> {
+    use quic_rpc::{message::PatternKind, quinn::Compression, ByteCounted, ChannelTypes};
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let payload = vec![7u8; 100_000];
+    let expected = payload.clone();
+    let server_handle = tokio::task::spawn(async move {
+        let connection = server.accept().await.context("accept failed")?.await?;
+        let channel = quic_rpc::quinn::Channel::<ComputeRequest, ComputeResponse>::new(connection)
+            .with_compression(Compression::default().for_patterns(&[PatternKind::ServerStreaming]));
+        for pattern in [PatternKind::ServerStreaming, PatternKind::Rpc] {
+            let (mut send, mut recv) = Channel::<_, _, C>::accept_bi(&channel).await?;
+            recv.next().await.context("no request")??;
+            C::set_pattern(&mut send, pattern);
+            let payload = payload.clone();
+            send.send(ComputeResponse::Control(ControlFrame { code: 0, payload }))
+                .await?;
+            send.close().await?;
+        }
+        anyhow::Ok(())
+    });
+    let connection = client.connect(server_addr, "localhost")?.await?;
+    let channel = quic_rpc::quinn::Channel::<ComputeResponse, ComputeRequest>::new(connection)
+        .with_compression(Compression::default().receive_only());
+    let mut received = Vec::new();
+    for _ in 0..2 {
+        let (mut send, mut recv) = Channel::<_, _, C>::open_bi(&channel).await?;
+        send.send(ComputeRequest::Sqr(Sqr(1))).await?;
+        match recv.next().await {
+            Some(Ok(ComputeResponse::Control(frame))) => assert_eq!(frame.payload, expected),
+            res => anyhow::bail!("unexpected result {:?}", res),
+        }
+        received.push(recv.byte_counts().bytes_received());
+    }
+    server_handle.await??;
+    // only the server streaming response was compressed
+    assert!(received[0] < 1000);
+    assert!(received[1] > 100_000);
+    Ok(())
+}
+
+/// quinn channels follow the contract of the channel traits
+#[tokio::test]
+async fn quinn_channel_conformance() {
+    quic_rpc::channel::test_suite::<QuinnChannelTypes, _, _>(|| async {
+        let Endpoints {
+            client,
+            server,
+            server_addr,
+        } = make_endpoints().unwrap();
+        let connect = async { client.connect(server_addr, "localhost").unwrap().await };
+        let accept = async { server.accept().await.unwrap().await };
+        let (client, server) = tokio::try_join!(connect, accept).unwrap();
+        (
+            quic_rpc::quinn::Channel::new(client),
+            quic_rpc::quinn::Channel::new(server),
+        )
+    })
+    .await;
+}
+
+/// a reconnect uses the session ticket of the first connection to send an rpc as 0-RTT data
+#[tokio::test]
+async fn quinn_channel_connect_0rtt() -> anyhow::Result<()> {
+    type C = QuinnChannelTypes;
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    let cert_der = cert.serialize_der()?;
+    let priv_key = rustls::PrivateKey(cert.serialize_private_key_der());
+    let mut server_crypto = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![rustls::Certificate(cert_der.clone())], priv_key)?;
+    server_crypto.max_early_data_size = u32::MAX;
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&rustls::Certificate(cert_der))?;
+    let mut client_crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client_crypto.enable_early_data = true;
+    let bind_addr: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let server = Endpoint::server(
+        ServerConfig::with_crypto(Arc::new(server_crypto)),
+        bind_addr,
+    )?;
+    let server_addr = server.local_addr()?;
+    let mut client = Endpoint::client("0.0.0.0:0".parse()?)?;
+    client.set_default_client_config(ClientConfig::new(Arc::new(client_crypto)));
+    let server_handle = tokio::task::spawn(async move {
+        while let Some(connecting) = server.accept().await {
+            let channel = quic_rpc::quinn::Channel::new(connecting.await?);
+            let server = RpcServer::<ComputeService, C>::new(channel);
+            tokio::task::spawn(ComputeService::server(server));
+        }
+        anyhow::Ok(())
+    });
+    // no session ticket yet, so this is a regular handshake
+    let early = C::connect_0rtt::<ComputeService>(&client, server_addr, "localhost").await?;
+    assert!(!early.is_0rtt());
+    assert_eq!(early.rpc(Sqr(2)).await?, SqrResponse(4));
+    let (first, accepted) = early.confirm().await;
+    assert!(accepted);
+    drop(first);
+    // the reconnect sends the request before the handshake is complete
+    let early = C::connect_0rtt::<ComputeService>(&client, server_addr, "localhost").await?;
+    assert!(early.is_0rtt());
+    assert_eq!(early.rpc(Sqr(3)).await?, SqrResponse(9));
+    let (second, accepted) = early.confirm().await;
+    assert!(accepted);
+    assert_eq!(second.rpc(Sqr(4)).await?, SqrResponse(16));
+    server_handle.abort();
+    Ok(())
+}
+
+/// Feed `n` sum updates without flushing over a channel with the given coalescing threshold
+async fn feed_sum_updates(
+    write_coalescing: usize,
+    scheduler: Option<quic_rpc::quinn::FairScheduler>,
+    n: u64,
+) -> anyhow::Result<Duration> {
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = run_server(server);
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    let mut client_connection =
+        quic_rpc::quinn::Channel::new(client_connection).with_write_coalescing(write_coalescing);
+    if let Some(scheduler) = scheduler {
+        client_connection = client_connection.with_fair_scheduler(scheduler);
+    }
+    let mut client = RpcClient::<ComputeService, C>::new(client_connection);
+    let t0 = std::time::Instant::now();
+    {
+        let (mut send, recv) = client.client_streaming(Sum).await?;
+        for i in 0..n {
+            send.feed(SumUpdate(i)).await?;
+        }
+        send.close().await?;
+        assert_eq!(recv.await?, SumResponse((n as u128 - 1) * n as u128 / 2));
+    }
+    let elapsed = t0.elapsed();
+    drop(client);
+    check_termination_anyhow::<C>(server_handle).await?;
+    Ok(elapsed)
+}
+
+#[tokio::test]
+async fn quinn_channel_write_coalescing() -> anyhow::Result<()> {
+    use quic_rpc::quinn::FairScheduler;
+    // a tiny chunk size, so the coalesced writes are only partially accepted
+    feed_sum_updates(100, Some(FairScheduler::new(7)), 10000).await?;
+    // every frame is written on its own
+    feed_sum_updates(0, Some(FairScheduler::new(7)), 1000).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_bench_write_coalescing() -> anyhow::Result<()> {
+    use thousands::Separable;
+    let n = 20000;
+    for (name, threshold) in [
+        ("uncoalesced", 0),
+        ("coalesced", quic_rpc::quinn::DEFAULT_WRITE_COALESCING),
+    ] {
+        let elapsed = feed_sum_updates(threshold, None, n).await?;
+        let ups = ((n as f64) / elapsed.as_secs_f64()).round();
+        println!("Sum {} {} updates/s", name, ups.separate_with_underscores());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_stats() -> anyhow::Result<()> {
+    use quic_rpc::quinn::QuinnChannelExt;
+    type C = QuinnChannelTypes;
+    let Endpoints {
+        client,
+        server,
+        server_addr,
+    } = make_endpoints()?;
+    let server_handle = tokio::task::spawn(async move {
+        let connection =
+            quic_rpc::quinn::Channel::new(server.accept().await.context("accept failed")?.await?);
+        let mut server = RpcServer::<ComputeService, C>::new(connection);
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Sqr(msg) = req else {
+            anyhow::bail!("unexpected request");
+        };
+        server
+            .rpc(msg, chan, ComputeService, |_, Sqr(x)| async move {
+                SqrResponse(x as u128 * x as u128)
+            })
+            .await?;
+        let stats = server.stats();
+        // keep the connection open until the client is done
+        assert!(server.accept_one().await.is_err());
+        anyhow::Ok(stats)
+    });
+    let client_connection = client.connect(server_addr, "localhost")?.await?;
+    let client =
+        RpcClient::<ComputeService, C>::new(quic_rpc::quinn::Channel::new(client_connection));
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    let client_stats = client.stats();
+    assert!(client.rtt() > Duration::ZERO);
+    drop(client);
+    let server_stats = server_handle.await??;
+    assert!(client_stats.udp_tx.bytes > 0);
+    assert!(client_stats.udp_rx.bytes > 0);
+    assert!(client_stats.frame_tx.stream > 0);
+    assert!(server_stats.udp_rx.bytes > 0);
+    assert!(server_stats.frame_rx.stream > 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_balancer() -> anyhow::Result<()> {
+    use quic_rpc::balancer::{BalancePolicy, BalancedClient};
+    let mut clients = Vec::new();
+    let mut server_conns = Vec::new();
+    let mut client_conns = Vec::new();
+    for _ in 0..2 {
+        let Endpoints {
+            client,
+            server,
+            server_addr,
+        } = make_endpoints()?;
+        let (client, server) = tokio::join!(
+            async {
+                client
+                    .connect(server_addr, "localhost")?
+                    .await
+                    .map_err(anyhow::Error::from)
+            },
+            async {
+                server
+                    .accept()
+                    .await
+                    .context("accept failed")?
+                    .await
+                    .map_err(anyhow::Error::from)
+            },
+        );
+        let (client, server) = (client?, server?);
+        let rpc_server = RpcServer::<ComputeService, QuinnChannelTypes>::new(
+            quic_rpc::quinn::Channel::new(server.clone()),
+        );
+        tokio::task::spawn(ComputeService::server(rpc_server));
+        clients.push(RpcClient::new(quic_rpc::quinn::Channel::new(
+            client.clone(),
+        )));
+        server_conns.push(server);
+        client_conns.push(client);
+    }
+    // a server whose connection failed is skipped for good
+    let client = BalancedClient::<ComputeService>::new(clients, BalancePolicy::RoundRobin)
+        .with_cooldown(Duration::MAX);
+    for i in 0..4 {
+        assert_eq!(
+            client.rpc(Sqr(i)).await?,
+            SqrResponse(i as u128 * i as u128)
+        );
+    }
+    // the first server goes away, calls fail over to the second one
+    server_conns[0].close(0u32.into(), b"bye");
+    client_conns[0].closed().await;
+    for i in 0..4 {
+        assert_eq!(
+            client.rpc(Sqr(i)).await?,
+            SqrResponse(i as u128 * i as u128)
+        );
+    }
+    assert!(!client.is_healthy(0));
+    assert!(client.is_healthy(1));
+    assert_eq!(client.outstanding(1), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn quinn_channel_builder() -> anyhow::Result<()> {
+    let builder = QuinnChannelTypes::<BincodeCodec>::builder()
+        .with_max_idle_timeout(Some(Duration::from_millis(300)))
+        .with_keep_alive_interval(Some(Duration::from_millis(100)));
+    let (server_config, server_cert) = configure_server()?;
+    // only the client sends keep alive packets
+    let server_config = builder
+        .clone()
+        .with_keep_alive_interval(None)
+        .server_config(server_config);
+    let bind_addr: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let server = Endpoint::server(server_config, bind_addr)?;
+    let server_addr = server.local_addr()?;
+    let server_handle = tokio::task::spawn({
+        let builder = builder.clone();
+        async move {
+            while let Some(connecting) = server.accept().await {
+                let channel = builder.channel(connecting.await?);
+                let server = RpcServer::<ComputeService, QuinnChannelTypes>::new(channel);
+                tokio::task::spawn(ComputeService::server(server));
+            }
+            anyhow::Ok(())
+        }
+    });
+    let endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
+    let client_config = || configure_client(&[&server_cert]);
+    // keep alive packets keep the idle connection open
+    let channel = builder
+        .connect(&endpoint, client_config()?, server_addr, "localhost")
+        .await?;
+    let client = RpcClient::<ComputeService, QuinnChannelTypes>::new(channel);
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    assert_eq!(client.rpc(Sqr(3)).await?, SqrResponse(9));
+    // without them, it times out
+    let channel = builder
+        .clone()
+        .with_keep_alive_interval(None)
+        .connect(&endpoint, client_config()?, server_addr, "localhost")
+        .await?;
+    let client = RpcClient::<ComputeService, QuinnChannelTypes>::new(channel);
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    assert!(client.rpc(Sqr(3)).await.is_err());
+    server_handle.abort();
+    Ok(())
+}
+
+/// a slow client limits how far the server streaming handler runs ahead to the flow control
+/// window of the stream, since responses are only serialized once the previous one was sent
+#[tokio::test]
+async fn quinn_channel_server_streaming_lazy() -> anyhow::Result<()> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    const WINDOW: u32 = 4096;
+    let bind_addr: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let (server, server_cert) = make_server_endpoint(bind_addr)?;
+    let server_addr = server.local_addr()?;
+    let produced = Arc::new(AtomicU64::new(0));
+    let produced2 = produced.clone();
+    let server_handle = tokio::task::spawn(async move {
+        let connection =
+            quic_rpc::quinn::Channel::new(server.accept().await.context("accept failed")?.await?);
+        let mut server = RpcServer::<ComputeService, QuinnChannelTypes>::new(connection);
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Fibonacci(msg) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        let handler = move |_, _| {
+            futures::stream::repeat(())
+                .map(move |_| FibonacciResponse(produced2.fetch_add(1, Ordering::SeqCst) as u128))
+        };
+        server
+            .server_streaming(msg, chan, ComputeService, handler)
+            .await?;
+        anyhow::Ok(())
+    });
+    let mut transport = quinn::TransportConfig::default();
+    transport.stream_receive_window(WINDOW.into());
+    let mut client_config = configure_client(&[&server_cert])?;
+    client_config.transport_config(Arc::new(transport));
+    let client = Endpoint::client("0.0.0.0:0".parse()?)?;
+    let connection = client
+        .connect_with(client_config, server_addr, "localhost")?
+        .await?;
+    let mut client = RpcClient::<ComputeService, QuinnChannelTypes>::new(
+        quic_rpc::quinn::Channel::new(connection),
+    );
+    let mut recv = client.server_streaming(Fibonacci(0)).await?;
+    recv.next().await.unwrap()?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // the responses in the flow control window, those the client read into its decode buffer,
+    // which is limited by the window as well, and the one the send loop is waiting to send
+    let produced = produced.load(Ordering::SeqCst);
+    let frame = frame_len(&ComputeResponse::from(FibonacciResponse(0)));
+    let limit = 2 * WINDOW as u64 / frame + 1;
+    assert!(
+        produced <= limit,
+        "handler ran ahead by {produced} items, expected at most {limit}"
+    );
+    server_handle.abort();
+    Ok(())
+}
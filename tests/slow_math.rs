@@ -106,6 +106,16 @@ impl ComputeService {
                 Sum(msg) => s.client_streaming(msg, chan, service, ComputeService::sum).await,
                 Fibonacci(msg) => s.server_streaming(msg, chan, service, ComputeService::fibonacci).await,
                 Multiply(msg) => s.bidi_streaming(msg, chan, service, ComputeService::multiply).await,
+                Reflect(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                Probe(msg) => s.probe(msg, chan).await,
+                Notification(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                Cancel(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                Keepalive(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                CheckedFibonacci(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                StreamControl(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                OrderedSqr(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                Control(_) => Err(RpcServerError::UnexpectedStartMessage)?,
+                ResumeFibonacci(_) => Err(RpcServerError::UnexpectedStartMessage)?,
                 SumUpdate(_) => Err(RpcServerError::UnexpectedStartMessage)?,
                 MultiplyUpdate(_) => Err(RpcServerError::UnexpectedStartMessage)?,
             }?;
@@ -0,0 +1,235 @@
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use futures::{future, SinkExt, StreamExt, TryStreamExt};
+use quic_rpc::{
+    codec::{BincodeCodec, Codec, PostcardCodec},
+    server::{RpcServerError, RpcServerErrorKind},
+    tcp::{self, TcpChannelTypes},
+    Channel, RpcClient, RpcServer,
+};
+use std::time::Duration;
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+mod math;
+use math::*;
+mod util;
+
+/// Binds a listener on a random port and serves [ComputeService] on the first connection
+async fn run_server<K: Codec>() -> anyhow::Result<(SocketAddr, JoinHandle<anyhow::Result<()>>)> {
+    // bind to a random port so tests can run in parallel
+    let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let listener = TcpListener::bind(bind_addr).await?;
+    let addr = listener.local_addr()?;
+    let handle = tokio::task::spawn(async move {
+        let (stream, _) = listener.accept().await?;
+        let channel = tcp::Channel::<_, _, K>::server(stream);
+        let server = RpcServer::<ComputeService, TcpChannelTypes<K>>::new(channel);
+        ComputeService::server(server).await?;
+        anyhow::Ok(())
+    });
+    Ok((addr, handle))
+}
+
+async fn codec_roundtrip<K: Codec>() -> anyhow::Result<()> {
+    type C<K> = TcpChannelTypes<K>;
+    let (addr, server_handle) = run_server::<K>().await?;
+    let client = tcp::Channel::client(TcpStream::connect(addr).await?);
+    smoke_test::<C<K>>(client).await?;
+    util::check_termination_anyhow::<C<K>>(server_handle).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tcp_channel_smoke() -> anyhow::Result<()> {
+    codec_roundtrip::<BincodeCodec>().await?;
+    codec_roundtrip::<PostcardCodec>().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tcp_channel_bench() -> anyhow::Result<()> {
+    type C = TcpChannelTypes;
+    let (addr, server_handle) = run_server::<BincodeCodec>().await?;
+    let client = tcp::Channel::client(TcpStream::connect(addr).await?);
+    let client = RpcClient::<ComputeService, C>::new(client);
+    bench(client, 50000).await?;
+    server_handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn tcp_channel_connection_lost() -> anyhow::Result<()> {
+    type C = TcpChannelTypes;
+    let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let listener = TcpListener::bind(bind_addr).await?;
+    let addr = listener.local_addr()?;
+    let server_handle = tokio::task::spawn(async move {
+        // read the request, then go away without answering
+        let (mut stream, _) = listener.accept().await?;
+        let n = stream.read(&mut [0u8; 1024]).await?;
+        anyhow::Ok(n)
+    });
+    let client = tcp::Channel::client(TcpStream::connect(addr).await?);
+    let client = RpcClient::<ComputeService, C>::new(client);
+    let res = client.rpc(Sqr(2)).await;
+    assert!(server_handle.await?? > 0);
+    let err = res.unwrap_err();
+    assert!(matches!(
+        err,
+        quic_rpc::client::RpcClientError::RecvError(tcp::RecvError::ConnectionLost)
+    ));
+    Ok(())
+}
+
+/// the server tells a client that finished a stream apart from a client that went away
+#[tokio::test]
+async fn tcp_channel_server_connection_lost() -> anyhow::Result<()> {
+    type C = TcpChannelTypes;
+    let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let listener = TcpListener::bind(bind_addr).await?;
+    let addr = listener.local_addr()?;
+    let server_handle = tokio::task::spawn(async move {
+        let (stream, _) = listener.accept().await?;
+        let mut server = RpcServer::<ComputeService, C>::new(tcp::Channel::server(stream));
+        // the first stream is finished without a request
+        let err = server.accept_one().await.unwrap_err();
+        assert!(matches!(err, RpcServerError::EarlyClose));
+        // the connection is dropped while the second request is processed
+        let (req, chan) = server.accept_one().await?;
+        let ComputeRequest::Sqr(req) = req else {
+            anyhow::bail!("unexpected request {:?}", req);
+        };
+        let err = server
+            .rpc(req, chan, (), |_, _| future::pending::<SqrResponse>())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                RpcServerError::ConnectionLost(tcp::RecvError::ConnectionLost)
+            ),
+            "{err:?}"
+        );
+        assert_eq!(err.kind(), RpcServerErrorKind::ConnectionLost);
+        anyhow::Ok(())
+    });
+    // connect through a proxy, so the connection can be cut without finishing the streams
+    let proxy = TcpListener::bind(bind_addr).await?;
+    let proxy_addr = proxy.local_addr()?;
+    let proxy_handle = tokio::task::spawn(async move {
+        let (mut inbound, _) = proxy.accept().await?;
+        let mut outbound = TcpStream::connect(addr).await?;
+        tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await
+    });
+    let client = tcp::Channel::<ComputeResponse, ComputeRequest>::client(
+        TcpStream::connect(proxy_addr).await?,
+    );
+    let (mut send, _recv) = client.open_bi().await?;
+    send.close().await?;
+    let _socket = client.open_bi_with(Sqr(2).into()).await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    proxy_handle.abort();
+    server_handle.await??;
+    Ok(())
+}
+
+/// a rejected client is disconnected instead of waiting for a response forever
+#[tokio::test]
+async fn tcp_channel_accept_filtered() -> anyhow::Result<()> {
+    type C = TcpChannelTypes;
+    let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let listener = TcpListener::bind(bind_addr).await?;
+    let addr = listener.local_addr()?;
+    let server_handle = tokio::task::spawn(async move {
+        let (stream, _) = listener.accept().await?;
+        let mut server = RpcServer::<ComputeService, C>::new(tcp::Channel::server(stream));
+        let err = server.accept_filtered(|_| false).await.unwrap_err();
+        assert!(matches!(err, RpcServerError::Rejected));
+        // the channel stays closed
+        assert!(server.accept_one().await.is_err());
+        anyhow::Ok(())
+    });
+    let client = tcp::Channel::client(TcpStream::connect(addr).await?);
+    let client = RpcClient::<ComputeService, C>::new(client);
+    let res = tokio::time::timeout(Duration::from_secs(5), client.rpc(Sqr(2))).await?;
+    assert!(res.is_err());
+    server_handle.await??;
+    Ok(())
+}
+
+/// tcp channels follow the contract of the channel traits
+#[tokio::test]
+async fn tcp_channel_conformance() {
+    quic_rpc::channel::test_suite::<TcpChannelTypes, _, _>(|| async {
+        let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+        let listener = TcpListener::bind(bind_addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, server) =
+            tokio::try_join!(TcpStream::connect(addr), listener.accept()).unwrap();
+        (tcp::Channel::client(client), tcp::Channel::server(server.0))
+    })
+    .await;
+}
+
+/// a stream that is not read only holds up its own sink, not the other calls of the connection
+#[tokio::test]
+async fn tcp_channel_unread_stream() -> anyhow::Result<()> {
+    type C = TcpChannelTypes;
+    let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let listener = TcpListener::bind(bind_addr).await?;
+    let addr = listener.local_addr()?;
+    let server_handle = tokio::task::spawn(async move {
+        let (stream, _) = listener.accept().await?;
+        let server = RpcServer::<ComputeService, C>::new(tcp::Channel::server(stream));
+        ComputeService::server_par(server, 2).await?;
+        anyhow::Ok(())
+    });
+    let client = tcp::Channel::client(TcpStream::connect(addr).await?);
+    let mut client = RpcClient::<ComputeService, C>::new(client);
+    // more responses than the stream buffers
+    let s = client.server_streaming(Fibonacci(180)).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let res = tokio::time::timeout(Duration::from_secs(5), client.rpc(Sqr(3))).await??;
+    assert_eq!(res, SqrResponse(9));
+    // the stream continues once it is read
+    let res = s.try_collect::<Vec<_>>().await?;
+    assert_eq!(res.len(), 180);
+    server_handle.abort();
+    Ok(())
+}
+
+/// streams beyond the accept buffer are refused instead of holding up the connection
+#[tokio::test]
+async fn tcp_channel_refused() -> anyhow::Result<()> {
+    let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let listener = TcpListener::bind(bind_addr).await?;
+    let addr = listener.local_addr()?;
+    let (client, server) = tokio::try_join!(TcpStream::connect(addr), listener.accept())?;
+    let client = tcp::Channel::<ComputeResponse, ComputeRequest>::client(client);
+    let server = tcp::Channel::<ComputeRequest, ComputeResponse>::server(server.0);
+    // the accept buffer holds 1024 streams
+    let mut sockets = Vec::new();
+    for i in 0..1024 {
+        sockets.push(client.open_bi_with(Sqr(i).into()).await?);
+    }
+    let (_send, mut recv) = client.open_bi_with(Sqr(1024).into()).await?;
+    let err = tokio::time::timeout(Duration::from_secs(5), recv.next())
+        .await?
+        .unwrap()
+        .unwrap_err();
+    assert!(matches!(err, tcp::RecvError::Refused), "{err:?}");
+    // the streams that were not refused are still served
+    let (mut send, mut recv) = server.accept_bi().await?;
+    assert!(matches!(
+        recv.next().await.unwrap()?,
+        ComputeRequest::Sqr(Sqr(0))
+    ));
+    send.send(SqrResponse(0).into()).await?;
+    let res = sockets[0].1.next().await.unwrap()?;
+    assert!(matches!(res, ComputeResponse::SqrResponse(SqrResponse(0))));
+    Ok(())
+}
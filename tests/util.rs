@@ -1,5 +1,8 @@
 use anyhow::Context;
-use quic_rpc::{quinn::QuinnChannelTypes, server::RpcServerError, ChannelTypes};
+use quic_rpc::{
+    server::{RpcServerError, RpcServerErrorKind},
+    ChannelTypes,
+};
 
 pub async fn check_termination_anyhow<C: ChannelTypes>(
     server_handle: tokio::task::JoinHandle<anyhow::Result<()>>,
@@ -7,12 +10,13 @@ pub async fn check_termination_anyhow<C: ChannelTypes>(
     // dropping the client will cause the server to terminate
     match server_handle.await? {
         Err(e) => {
-            let err: RpcServerError<QuinnChannelTypes> =
-                e.downcast().context("unexpected termination result")?;
-            match err {
-                RpcServerError::AcceptBiError(_) => {}
-                e => panic!("unexpected termination error {:?}", e),
-            }
+            let err: RpcServerError<C> = e.downcast().context("unexpected termination result")?;
+            assert_eq!(
+                err.kind(),
+                RpcServerErrorKind::AcceptBiError,
+                "unexpected termination error {:?}",
+                err
+            );
         }
         e => panic!("server should have terminated with an error {:?}", e),
     }
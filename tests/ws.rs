@@ -0,0 +1,100 @@
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use quic_rpc::{
+    ws::{self, WsChannelTypes},
+    RpcClient, RpcServer,
+};
+use std::time::Duration;
+use tokio::{net::TcpListener, task::JoinHandle};
+
+mod math;
+use math::*;
+
+/// Binds a listener on a random port and serves [ComputeService] on it
+async fn run_server() -> anyhow::Result<(String, JoinHandle<anyhow::Result<()>>)> {
+    // bind to a random port so tests can run in parallel
+    let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let listener = TcpListener::bind(bind_addr).await?;
+    let url = format!("ws://{}", listener.local_addr()?);
+    let channel = WsChannelTypes::listen(listener);
+    let handle = tokio::task::spawn(async move {
+        let server = RpcServer::<ComputeService, WsChannelTypes>::new(channel);
+        ComputeService::server(server).await?;
+        anyhow::Ok(())
+    });
+    Ok((url, handle))
+}
+
+#[tokio::test]
+async fn ws_channel_smoke() -> anyhow::Result<()> {
+    type C = WsChannelTypes;
+    let (url, server_handle) = run_server().await?;
+    smoke_test::<C>(WsChannelTypes::connect(url)).await?;
+    server_handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn ws_channel_bench() -> anyhow::Result<()> {
+    type C = WsChannelTypes;
+    let (url, server_handle) = run_server().await?;
+    let client = RpcClient::<ComputeService, C>::new(WsChannelTypes::connect(url));
+    bench(client, 1000).await?;
+    server_handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn ws_channel_acceptor() -> anyhow::Result<()> {
+    type C = WsChannelTypes;
+    let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let listener = TcpListener::bind(bind_addr).await?;
+    let url = format!("ws://{}", listener.local_addr()?);
+    let (channel, acceptor) = WsChannelTypes::server(1);
+    // stands in for a http server that upgrades the connection itself
+    tokio::task::spawn(async move {
+        let (stream, _) = listener.accept().await?;
+        let ws = tokio_tungstenite::accept_async(stream).await?;
+        acceptor.accept(ws).await.ok();
+        anyhow::Ok(())
+    });
+    let server_handle = tokio::task::spawn(async move {
+        let server = RpcServer::<ComputeService, C>::new(channel);
+        ComputeService::server(server).await?;
+        anyhow::Ok(())
+    });
+    let client = RpcClient::<ComputeService, C>::new(WsChannelTypes::connect(url));
+    assert_eq!(client.rpc(Sqr(12)).await?, SqrResponse(144));
+    // the acceptor is gone after the first connection, so the server terminates
+    let err = server_handle.await?.unwrap_err();
+    let err: quic_rpc::server::RpcServerError<C> = err.downcast()?;
+    assert!(matches!(
+        err,
+        quic_rpc::server::RpcServerError::AcceptBiError(ws::AcceptBiError::AcceptorDropped)
+    ));
+    Ok(())
+}
+
+/// a closed server channel drops the connections it did not accept yet
+#[tokio::test]
+async fn ws_channel_close() -> anyhow::Result<()> {
+    use quic_rpc::Channel;
+    type C = WsChannelTypes;
+    let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let listener = TcpListener::bind(bind_addr).await?;
+    let url = format!("ws://{}", listener.local_addr()?);
+    let channel = WsChannelTypes::listen::<ComputeRequest, ComputeResponse>(listener);
+    channel.close(0, b"bye");
+    assert!(matches!(
+        channel.accept_bi().await,
+        Err(ws::AcceptBiError::Closed)
+    ));
+    let client = RpcClient::<ComputeService, C>::new(WsChannelTypes::connect(url.clone()));
+    let res = tokio::time::timeout(Duration::from_secs(5), client.rpc(Sqr(2))).await?;
+    assert!(res.is_err());
+    // closing the client channel fails further calls without connecting
+    let client_channel = WsChannelTypes::connect::<ComputeResponse, ComputeRequest>(url);
+    client_channel.close(0, b"bye");
+    assert!(client_channel.open_bi().await.is_err());
+    Ok(())
+}